@@ -1,16 +1,19 @@
-use quinn::{ClientConfig, Endpoint};
-use ring::signature::Ed25519KeyPair;
 use simple_pbft_demo::{
-    crypto::primitives::Crypto,
-    message::message_types::{PBFTMessage, Request},
+    client::pbft_client::PbftClient,
+    config::{membership::Membership, node::load_cluster_config},
+    crypto::primitives::{load_private_key, load_public_keys},
+    message::message_types::{AdminQuery, AdminResult, ClientInfo, Reconfigure, ReplicaInfo},
+    state::{events::to_hex, ledger::verify_chain},
 };
-use std::{collections::HashMap, net::SocketAddr, sync::Arc};
+use std::{collections::HashMap, path::Path, time::Duration};
 
-fn make_insecure_client_config() -> rustls::ClientConfig {
-    rustls::ClientConfig::builder()
-        .dangerous()
-        .with_custom_certificate_verifier(Arc::new(SkipVerification))
-        .with_no_client_auth()
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+enum Command {
+    /// Ordered through consensus.
+    Invoke(Vec<u8>),
+    /// Asked of one replica directly.
+    Admin(u32, AdminQuery),
 }
 
 #[tokio::main]
@@ -20,122 +23,324 @@ async fn main() {
         .expect("Failed to install rustls crypto provider");
 
     let args: Vec<String> = std::env::args().collect();
-    if args.len() != 2 {
-        eprintln!("Usage: {} <operation>", args[0]);
-        eprintln!("  Example: {} 'PUT:name:Alice'", args[0]);
-        std::process::exit(1);
-    }
-
-    let operation = args[1].as_bytes().to_vec();
-
-    let primary_addr: SocketAddr = "127.0.0.1:5000".parse().unwrap();
-
-    let client_pkcs8 = Crypto::generate_keypair();
-    let kp = Ed25519KeyPair::from_pkcs8(client_pkcs8.as_ref()).unwrap();
-    let client_crypto = Crypto::new(kp, 999, HashMap::new());
-
-    let request = Request {
-        operation,
-        timestamp: std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_micros() as u64,
-        client_id: 999,
+    let command = match args.get(1).map(String::as_str) {
+        Some("add-replica") if args.len() == 5 => {
+            let public_key = std::fs::read(&args[4]).expect("Failed to read public key");
+            let operation = Reconfigure {
+                add: vec![ReplicaInfo {
+                    id: args[2].parse().expect("Invalid replica id"),
+                    public_key,
+                    addr: args[3].parse().expect("Invalid replica address"),
+                }],
+                ..Default::default()
+            }
+            .to_operation();
+            Command::Invoke(operation)
+        }
+        Some("remove-replica") if args.len() == 3 => Command::Invoke(
+            Reconfigure {
+                remove: vec![args[2].parse().expect("Invalid replica id")],
+                ..Default::default()
+            }
+            .to_operation(),
+        ),
+        Some("add-client") if args.len() == 4 => {
+            let public_key = std::fs::read(&args[3]).expect("Failed to read public key");
+            let operation = Reconfigure {
+                add_clients: vec![ClientInfo {
+                    id: args[2].parse().expect("Invalid client id"),
+                    public_key,
+                }],
+                ..Default::default()
+            }
+            .to_operation();
+            Command::Invoke(operation)
+        }
+        Some("remove-client") if args.len() == 3 => Command::Invoke(
+            Reconfigure {
+                remove_clients: vec![args[2].parse().expect("Invalid client id")],
+                ..Default::default()
+            }
+            .to_operation(),
+        ),
+        Some("evidence") if args.len() == 3 => Command::Admin(
+            args[2].parse().expect("Invalid replica id"),
+            AdminQuery::Evidence,
+        ),
+        Some("certificate") if args.len() == 4 => Command::Admin(
+            args[2].parse().expect("Invalid replica id"),
+            AdminQuery::Certificate {
+                seq_num: args[3].parse().expect("Invalid sequence number"),
+            },
+        ),
+        Some("ledger") if args.len() == 4 || args.len() == 5 => {
+            let from = args[3].parse().expect("Invalid sequence number");
+            let to = match args.get(4) {
+                Some(to) => to.parse().expect("Invalid sequence number"),
+                None => from,
+            };
+            Command::Admin(
+                args[2].parse().expect("Invalid replica id"),
+                AdminQuery::Ledger { from, to },
+            )
+        }
+        Some("proof") if args.len() == 4 => Command::Admin(
+            args[2].parse().expect("Invalid replica id"),
+            AdminQuery::Proof {
+                key: args[3].clone(),
+            },
+        ),
+        Some("status") if args.len() == 3 => Command::Admin(
+            args[2].parse().expect("Invalid replica id"),
+            AdminQuery::Status,
+        ),
+        Some("view-change") if args.len() == 3 => Command::Admin(
+            args[2].parse().expect("Invalid replica id"),
+            AdminQuery::ForceViewChange,
+        ),
+        Some("snapshot") if args.len() == 3 => Command::Admin(
+            args[2].parse().expect("Invalid replica id"),
+            AdminQuery::Snapshot,
+        ),
+        Some(op) if args.len() == 2 => Command::Invoke(op.as_bytes().to_vec()),
+        _ => {
+            eprintln!("Usage: {} <operation>", args[0]);
+            eprintln!(
+                "       {} add-replica <id> <addr> <public key file>",
+                args[0]
+            );
+            eprintln!("       {} remove-replica <id>", args[0]);
+            eprintln!("       {} add-client <id> <public key file>", args[0]);
+            eprintln!("       {} remove-client <id>", args[0]);
+            eprintln!("       {} evidence <replica id>", args[0]);
+            eprintln!("       {} certificate <replica id> <seq>", args[0]);
+            eprintln!(
+                "       {} ledger <replica id> <from seq> [<to seq>]",
+                args[0]
+            );
+            eprintln!("       {} proof <replica id> <key>", args[0]);
+            eprintln!("       {} status <replica id>", args[0]);
+            eprintln!("       {} view-change <replica id>", args[0]);
+            eprintln!("       {} snapshot <replica id>", args[0]);
+            eprintln!("  Example: {} 'PUT:name:Alice'", args[0]);
+            eprintln!(
+                "  Example: {} add-replica 4 127.0.0.1:5004 keys/node_4.pub",
+                args[0]
+            );
+            std::process::exit(1);
+        }
     };
 
-    let signed_request = client_crypto.create_signed_message(request);
-    let message = PBFTMessage::Request(signed_request);
-
-    println!("Sending request to primary at {}...", primary_addr);
-
-    let rustls_client_cfg = make_insecure_client_config();
-    let mut endpoint = Endpoint::client("0.0.0.0:0".parse().unwrap()).unwrap();
-    let quinn_client_cfg = ClientConfig::new(Arc::new(
-        quinn::crypto::rustls::QuicClientConfig::try_from(rustls_client_cfg).unwrap(),
-    ));
-
-    endpoint.set_default_client_config(quinn_client_cfg);
-
-    let connection = endpoint
-        .connect(primary_addr, "peer")
-        .expect("Failed to initiate connection")
-        .await
-        .expect("Failed to connect to primary");
-
-    println!("Connected! Sending request...");
+    let cluster = load_cluster_config(Path::new("cluster.toml"));
 
-    let mut send_stream = connection.open_uni().await.unwrap();
-    let serialized = postcard::to_allocvec(&message).unwrap();
-    let len = serialized.len() as u32;
-
-    send_stream
-        .write_all(&len.to_be_bytes())
-        .await
-        .expect("Failed to write length");
-    send_stream
-        .write_all(&serialized)
-        .await
-        .expect("Failed to write message");
-    send_stream.finish().expect("Failed to finish stream");
-
-    println!("Request sent");
-
-    tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
-
-    connection.close(0u32.into(), b"Done");
-    endpoint.wait_idle().await;
-}
+    let client_id = match std::env::var("PBFT_CLIENT_ID") {
+        Ok(id) => id.parse().expect("Invalid PBFT_CLIENT_ID"),
+        Err(_) => cluster.clients[0],
+    };
+    let client_pkcs8 = load_private_key(&format!("client_{}", client_id)).await;
+    let replica_keys = load_public_keys("node", &cluster.replica_ids()).await;
+    let membership = Membership::new(
+        cluster
+            .replicas
+            .iter()
+            .map(|r| ReplicaInfo {
+                id: r.id,
+                public_key: replica_keys[&r.id].clone(),
+                addr: r.addr,
+            })
+            .collect(),
+        Vec::new(),
+    )
+    .expect("Invalid cluster config");
 
-use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified};
-use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
-use rustls::{DigitallySignedStruct, Error, SignatureScheme};
+    let mut client =
+        PbftClient::connect(client_id, &client_pkcs8, &cluster, replica_keys.clone()).await;
 
-#[derive(Debug)]
-struct SkipVerification;
+    println!(
+        "Connected to replicas {:?}, sending request...",
+        client.connected_replicas()
+    );
 
-impl rustls::client::danger::ServerCertVerifier for SkipVerification {
-    fn verify_server_cert(
-        &self,
-        _end_entity_cert: &CertificateDer<'_>,
-        _intermediates: &[CertificateDer<'_>],
-        _server_name: &ServerName<'_>,
-        _ocsp: &[u8],
-        _now: UnixTime,
-    ) -> Result<ServerCertVerified, Error> {
-        Ok(ServerCertVerified::assertion())
-    }
+    let answered = match command {
+        Command::Invoke(operation) => match client.invoke(operation, REQUEST_TIMEOUT).await {
+            Some(result) => {
+                println!("Result: {}", String::from_utf8_lossy(&result));
+                true
+            }
+            None => false,
+        },
+        Command::Admin(replica_id, query) => {
+            match client.admin(replica_id, query, REQUEST_TIMEOUT).await {
+                Some(result) => {
+                    print_admin_result(replica_id, &result, &replica_keys, &membership);
+                    true
+                }
+                None => false,
+            }
+        }
+    };
 
-    fn verify_tls12_signature(
-        &self,
-        _message: &[u8],
-        _cert: &CertificateDer<'_>,
-        _sig: &DigitallySignedStruct,
-    ) -> Result<HandshakeSignatureValid, Error> {
-        Ok(HandshakeSignatureValid::assertion())
+    client.close().await;
+    if !answered {
+        eprintln!("No result within {:?}", REQUEST_TIMEOUT);
+        std::process::exit(1);
     }
+}
 
-    fn verify_tls13_signature(
-        &self,
-        _message: &[u8],
-        _cert: &CertificateDer<'_>,
-        _sig: &DigitallySignedStruct,
-    ) -> Result<HandshakeSignatureValid, Error> {
-        Ok(HandshakeSignatureValid::assertion())
-    }
+fn print_admin_result(
+    replica_id: u32,
+    result: &AdminResult,
+    replica_keys: &HashMap<u32, Vec<u8>>,
+    membership: &Membership,
+) {
+    match result {
+        AdminResult::Evidence(evidence) => {
+            println!(
+                "Replica {} holds {} piece(s) of evidence",
+                replica_id,
+                evidence.len()
+            );
+            for e in evidence {
+                println!(
+                    "  replica {} equivocated at view {} seq {}: {} vs {}",
+                    e.culprit(),
+                    e.view(),
+                    e.seq_num(),
+                    &to_hex(&e.first.message.digest)[..16],
+                    &to_hex(&e.second.message.digest)[..16],
+                );
+            }
+        }
+        AdminResult::Certificate(None) => {
+            println!(
+                "Replica {} has not executed that sequence number",
+                replica_id
+            );
+        }
+        AdminResult::Certificate(Some(entry)) => {
+            let certificate = &entry.certificate;
+            println!(
+                "Seq {} committed in view {}: {}",
+                certificate.seq_num,
+                certificate.view,
+                String::from_utf8_lossy(&entry.request.operation)
+            );
+            println!(
+                "  client {} timestamp {}, digest {}",
+                entry.request.client_id,
+                entry.request.timestamp,
+                to_hex(&certificate.digest)
+            );
+            let signers: Vec<u32> = certificate
+                .signatures
+                .iter()
+                .map(|s| s.replica_id)
+                .collect();
+            println!("  signed by replicas {:?}", signers);
 
-    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
-        vec![
-            SignatureScheme::RSA_PKCS1_SHA256,
-            SignatureScheme::RSA_PKCS1_SHA384,
-            SignatureScheme::RSA_PKCS1_SHA512,
-            SignatureScheme::ECDSA_NISTP256_SHA256,
-            SignatureScheme::ECDSA_NISTP384_SHA384,
-            SignatureScheme::ECDSA_NISTP521_SHA512,
-            SignatureScheme::RSA_PSS_SHA256,
-            SignatureScheme::RSA_PSS_SHA384,
-            SignatureScheme::RSA_PSS_SHA512,
-            SignatureScheme::ED25519,
-            SignatureScheme::ED448,
-        ]
+            let quorum = membership.quorum() as usize;
+            if entry.verify(replica_keys, quorum) {
+                println!("  certificate valid (quorum {})", quorum);
+            } else {
+                println!("  certificate INVALID (quorum {})", quorum);
+            }
+        }
+        AdminResult::Ledger(entries) => {
+            println!(
+                "Replica {} returned {} ledger entr{}",
+                replica_id,
+                entries.len(),
+                if entries.len() == 1 { "y" } else { "ies" }
+            );
+            for e in entries {
+                println!(
+                    "  seq {} view {}: request {} result {} state {} prev {} hash {}",
+                    e.seq_num,
+                    e.view,
+                    &to_hex(&e.request_digest)[..16],
+                    &to_hex(&e.result_digest)[..16],
+                    &to_hex(&e.state_digest)[..16],
+                    &to_hex(&e.prev_hash)[..16],
+                    &to_hex(&e.hash())[..16],
+                );
+            }
+            match verify_chain(entries) {
+                Ok(()) => println!("  chain intact"),
+                Err(seq_num) => println!("  chain BROKEN at seq {}", seq_num),
+            }
+        }
+        AdminResult::Proof { seq_num, proof } => {
+            match &proof.value {
+                Some(value) => println!(
+                    "Replica {} after seq {}: {} = {}",
+                    replica_id, seq_num, proof.key, value
+                ),
+                None => println!(
+                    "Replica {} after seq {}: {} not found",
+                    replica_id, seq_num, proof.key
+                ),
+            }
+            for leaf in &proof.leaves {
+                println!(
+                    "  leaf {} of {}: {} = {} ({} sibling hashes)",
+                    leaf.proof.index,
+                    leaf.proof.leaf_count,
+                    leaf.key,
+                    leaf.value,
+                    leaf.proof.siblings.len()
+                );
+            }
+        }
+        AdminResult::Status(status) => {
+            println!("Replica {}", replica_id);
+            println!("  view {}, primary {}", status.view, status.primary);
+            match status.pending_view {
+                Some(view) => println!("  changing to view {}", view),
+                None => println!("  not changing views"),
+            }
+            println!(
+                "  next seq {}, last executed {}",
+                status.next_seq_num, status.last_executed
+            );
+            match status.last_snapshot {
+                Some(seq_num) => println!("  last snapshot at seq {}", seq_num),
+                None => println!("  no snapshot"),
+            }
+            for (peer, connected) in &status.peers {
+                println!(
+                    "  peer {}: {}",
+                    peer,
+                    if *connected {
+                        "connected"
+                    } else {
+                        "disconnected"
+                    }
+                );
+            }
+            println!(
+                "  message log {}, pending requests {}, deferred {}, evidence {}",
+                status.message_log, status.pending_requests, status.deferred, status.evidence
+            );
+        }
+        AdminResult::ViewChange { new_view } => {
+            println!(
+                "Replica {} asked for view {}; the view changes once a quorum asks",
+                replica_id, new_view
+            );
+        }
+        AdminResult::Snapshot {
+            seq_num,
+            state_digest,
+        } => {
+            println!(
+                "Replica {} saved a snapshot at seq {}, state {}",
+                replica_id,
+                seq_num,
+                to_hex(state_digest)
+            );
+        }
+        AdminResult::Failed(reason) => {
+            println!("Replica {} failed: {}", replica_id, reason);
+        }
     }
 }
@@ -0,0 +1,454 @@
+use serde::Serialize;
+use simple_pbft_demo::{
+    client::pbft_client::PbftClient,
+    config::node::{ClusterConfig, PeerConfig, load_cluster_config},
+    crypto::primitives::{generate_key_files, load_private_key, load_public_keys},
+    message::message_types::{AdminQuery, AdminResult, ReplicaStatus},
+};
+use std::{
+    collections::BTreeMap,
+    env,
+    fs::OpenOptions,
+    io,
+    path::{Path, PathBuf},
+    process::{self, ExitStatus},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    process::{Child, Command},
+    time::Instant,
+};
+
+const CONFIG_PATH: &str = "cluster.toml";
+const READY_TIMEOUT: Duration = Duration::from_secs(30);
+const QUERY_TIMEOUT: Duration = Duration::from_secs(1);
+/// How long a stopped node gets to exit before it is killed.
+const STOP_GRACE: Duration = Duration::from_secs(3);
+
+struct Options {
+    nodes: Option<u32>,
+    dir: PathBuf,
+    trace: bool,
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "Usage: cluster [--nodes N] [--dir <dir>] [--trace]\n\
+         Runs a local cluster from <dir> (default: the current directory). Writes\n\
+         cluster.toml for N replicas (default 4) unless one exists, generates any\n\
+         missing keys, starts every replica with its output in logs/node_<id>.log\n\
+         and waits until they all answer a status query. --trace records protocol\n\
+         traces to traces/node_<id>.bin.\n\
+         \n\
+         Then reads commands from stdin:\n  \
+           status                 state of every replica\n  \
+           stop <id>              terminate a replica, killing it if it hangs\n  \
+           kill <id>              kill a replica immediately\n  \
+           start <id>             start a stopped replica\n  \
+           restart <id>           stop and start a replica\n  \
+           quit                   stop every replica and exit (also Ctrl-C or EOF)"
+    );
+    process::exit(2);
+}
+
+fn parse_options() -> Options {
+    let mut options = Options {
+        nodes: None,
+        dir: PathBuf::from("."),
+        trace: false,
+    };
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "--nodes" => options.nodes = Some(value().parse().unwrap_or_else(|_| usage())),
+            "--dir" => options.dir = PathBuf::from(value()),
+            "--trace" => options.trace = true,
+            _ => usage(),
+        }
+    }
+    options
+}
+
+/// The part of `cluster.toml` the launcher writes; everything else keeps its
+/// defaults so it can be added by hand later.
+#[derive(Serialize)]
+struct Layout<'a> {
+    clients: &'a [u32],
+    admins: &'a [u32],
+    replicas: &'a [PeerConfig],
+}
+
+/// Loads `cluster.toml`, writing a localhost layout for `nodes` replicas first
+/// if there is none.
+fn prepare_config(nodes: Option<u32>) -> ClusterConfig {
+    let path = Path::new(CONFIG_PATH);
+    if path.exists() {
+        let cluster = load_cluster_config(path);
+        if let Some(nodes) = nodes
+            && nodes as usize != cluster.replicas.len()
+        {
+            eprintln!(
+                "{} already has {} replicas; remove it to start {}",
+                CONFIG_PATH,
+                cluster.replicas.len(),
+                nodes
+            );
+            process::exit(1);
+        }
+        println!("Using existing {}", CONFIG_PATH);
+        return cluster;
+    }
+
+    let cluster = ClusterConfig::local(nodes.unwrap_or(4));
+    let layout = Layout {
+        clients: &cluster.clients,
+        admins: &cluster.admins,
+        replicas: &cluster.replicas,
+    };
+    let contents = toml::to_string(&layout).expect("Failed to serialize cluster config");
+    std::fs::write(path, contents).expect("Failed to write cluster config");
+    println!(
+        "Wrote {} for {} replicas",
+        CONFIG_PATH,
+        cluster.replicas.len()
+    );
+    cluster
+}
+
+async fn prepare_keys(cluster: &ClusterConfig) {
+    let names = cluster
+        .replica_ids()
+        .into_iter()
+        .map(|id| format!("node_{}", id))
+        .chain(cluster.clients.iter().map(|id| format!("client_{}", id)));
+    for name in names {
+        match generate_key_files(Path::new("keys"), &name).await {
+            Ok(true) => println!("Generated keys for {}", name),
+            Ok(false) => {}
+            Err(e) => panic!("Failed to write keys for {}: {}", name, e),
+        }
+    }
+}
+
+struct Launcher {
+    cluster: ClusterConfig,
+    node_exe: PathBuf,
+    trace: bool,
+    nodes: BTreeMap<u32, Option<Child>>,
+}
+
+impl Launcher {
+    fn new(cluster: ClusterConfig, node_exe: PathBuf, trace: bool) -> Self {
+        let nodes = cluster
+            .replica_ids()
+            .into_iter()
+            .map(|id| (id, None))
+            .collect();
+        Launcher {
+            cluster,
+            node_exe,
+            trace,
+            nodes,
+        }
+    }
+
+    fn contains(&self, id: u32) -> bool {
+        if self.nodes.contains_key(&id) {
+            return true;
+        }
+        println!("No replica {} in {}", id, CONFIG_PATH);
+        false
+    }
+
+    fn is_running(&self, id: u32) -> bool {
+        matches!(self.nodes.get(&id), Some(Some(_)))
+    }
+
+    /// Starts `node <id>` in its own process group, so Ctrl-C reaches only the
+    /// launcher, which then stops the replicas itself. The first start of a
+    /// session truncates the node's log; restarts append to it.
+    fn start(&mut self, id: u32, truncate: bool) -> io::Result<()> {
+        std::fs::create_dir_all("logs")?;
+        let log = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(truncate)
+            .append(!truncate)
+            .open(log_path(id))?;
+
+        let mut command = Command::new(&self.node_exe);
+        command
+            .arg(id.to_string())
+            .stdout(log.try_clone()?)
+            .stderr(log)
+            .process_group(0)
+            .kill_on_drop(true);
+        if self.trace {
+            std::fs::create_dir_all("traces")?;
+            command.env("PBFT_TRACE", format!("traces/node_{}.bin", id));
+        }
+
+        let child = command.spawn()?;
+        println!(
+            "Started replica {} (pid {}), logging to {}",
+            id,
+            child.id().unwrap_or_default(),
+            log_path(id).display()
+        );
+        self.nodes.insert(id, Some(child));
+        Ok(())
+    }
+
+    /// Stops replica `id`: with `graceful` it is sent SIGTERM and only killed
+    /// if it is still running after `STOP_GRACE`.
+    async fn stop(&mut self, id: u32, graceful: bool) {
+        let Some(mut child) = self.nodes.get_mut(&id).and_then(Option::take) else {
+            println!("Replica {} is not running", id);
+            return;
+        };
+
+        if graceful && let Some(pid) = child.id() {
+            let _ = std::process::Command::new("kill")
+                .args(["-TERM", &pid.to_string()])
+                .status();
+            if let Ok(Ok(status)) = tokio::time::timeout(STOP_GRACE, child.wait()).await {
+                println!("Replica {} stopped ({})", id, status);
+                return;
+            }
+            println!(
+                "Replica {} did not exit in {:?}, killing it",
+                id, STOP_GRACE
+            );
+        }
+
+        let _ = child.kill().await;
+        println!("Replica {} killed", id);
+    }
+
+    /// Forgets replicas that exited on their own, reporting each one.
+    fn reap(&mut self) {
+        for (id, slot) in self.nodes.iter_mut() {
+            let exited: Option<ExitStatus> = slot.as_mut().and_then(|c| c.try_wait().ok()?);
+            if let Some(status) = exited {
+                println!(
+                    "Replica {} exited ({}), see {}",
+                    id,
+                    status,
+                    log_path(*id).display()
+                );
+                *slot = None;
+            }
+        }
+    }
+
+    /// A throwaway client for admin queries. Replicas route replies to the
+    /// latest connection of a client id, so a long-lived one here would steal
+    /// replies from a `client` run by the operator.
+    async fn connect(&self) -> Option<PbftClient> {
+        let client_id = *self.cluster.clients.first()?;
+        let pkcs8 = load_private_key(&format!("client_{}", client_id)).await;
+        let replica_keys = load_public_keys("node", &self.cluster.replica_ids()).await;
+        Some(PbftClient::connect(client_id, &pkcs8, &self.cluster, replica_keys).await)
+    }
+
+    async fn query_status(client: &mut PbftClient, id: u32) -> Option<ReplicaStatus> {
+        match client.admin(id, AdminQuery::Status, QUERY_TIMEOUT).await {
+            Some(AdminResult::Status(status)) => Some(status),
+            _ => None,
+        }
+    }
+
+    /// Waits until every replica in `ids` answers a status query, which it
+    /// only does once it is processing messages. Returns the ones that never
+    /// did.
+    async fn wait_ready(&mut self, mut ids: Vec<u32>) -> Vec<u32> {
+        let Some(mut client) = self.connect().await else {
+            println!("No clients in {}, not waiting for readiness", CONFIG_PATH);
+            return Vec::new();
+        };
+
+        let deadline = Instant::now() + READY_TIMEOUT;
+        while !ids.is_empty() && Instant::now() < deadline {
+            let mut waiting = Vec::new();
+            for id in ids {
+                if !self.is_running(id) {
+                    continue;
+                }
+                match Self::query_status(&mut client, id).await {
+                    Some(status) => println!(
+                        "Replica {} ready in view {} (primary {})",
+                        id, status.view, status.primary
+                    ),
+                    None => waiting.push(id),
+                }
+            }
+            ids = waiting;
+            self.reap();
+        }
+
+        client.close().await;
+        ids
+    }
+
+    async fn print_status(&mut self) {
+        self.reap();
+        let mut client = self.connect().await;
+        let ids: Vec<u32> = self.nodes.keys().copied().collect();
+        for id in ids {
+            let Some(pid) = self.nodes[&id].as_ref().and_then(Child::id) else {
+                println!("replica {}: stopped", id);
+                continue;
+            };
+            let status = match client.as_mut() {
+                Some(client) => Self::query_status(client, id).await,
+                None => None,
+            };
+            match status {
+                Some(status) => println!(
+                    "replica {}: running (pid {}), view {}{}, primary {}, last executed {}, {}/{} peers connected",
+                    id,
+                    pid,
+                    status.view,
+                    if status.in_view_change {
+                        " (changing view)"
+                    } else {
+                        ""
+                    },
+                    status.primary,
+                    status.last_executed,
+                    status.peers.iter().filter(|(_, up)| *up).count(),
+                    status.peers.len()
+                ),
+                None => println!("replica {}: running (pid {}), not answering", id, pid),
+            }
+        }
+        if let Some(client) = client {
+            client.close().await;
+        }
+    }
+
+    async fn shutdown(&mut self) {
+        let ids: Vec<u32> = self.nodes.keys().copied().collect();
+        for id in ids {
+            if self.is_running(id) {
+                self.stop(id, true).await;
+            }
+        }
+        println!("All replicas stopped; logs are in logs/");
+    }
+
+    /// Runs one command line. Returns false when the launcher should exit.
+    async fn command(&mut self, line: &str) -> bool {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let id = match words.get(1).map(|id| id.parse::<u32>()) {
+            Some(Ok(id)) if words.len() == 2 => Some(id),
+            _ => None,
+        };
+
+        match (words.first().copied(), id) {
+            (None, _) => {}
+            (Some("status"), _) if words.len() == 1 => self.print_status().await,
+            (Some("stop"), Some(id)) if self.contains(id) => self.stop(id, true).await,
+            (Some("kill"), Some(id)) if self.contains(id) => self.stop(id, false).await,
+            (Some(cmd @ ("start" | "restart")), Some(id)) if self.contains(id) => {
+                if self.is_running(id) {
+                    if cmd == "restart" {
+                        self.stop(id, true).await;
+                    } else {
+                        println!("Replica {} is already running", id);
+                        return true;
+                    }
+                }
+                match self.start(id, false) {
+                    Ok(()) => {
+                        if !self.wait_ready(vec![id]).await.is_empty() {
+                            println!("Replica {} is not ready after {:?}", id, READY_TIMEOUT);
+                        }
+                    }
+                    Err(e) => println!("Failed to start replica {}: {}", id, e),
+                }
+            }
+            (Some("quit" | "exit"), _) => return false,
+            (Some("stop" | "kill" | "start" | "restart"), Some(_)) => {}
+            _ => println!("Commands: status, stop <id>, kill <id>, start <id>, restart <id>, quit"),
+        }
+        true
+    }
+}
+
+fn log_path(id: u32) -> PathBuf {
+    Path::new("logs").join(format!("node_{}.log", id))
+}
+
+#[tokio::main]
+async fn main() {
+    rustls::crypto::ring::default_provider()
+        .install_default()
+        .expect("Failed to install rustls crypto provider");
+
+    let options = parse_options();
+    let node_exe = env::current_exe()
+        .expect("Cannot locate cluster binary")
+        .with_file_name("node");
+    if !node_exe.exists() {
+        eprintln!(
+            "{} not found; build it with 'cargo build --bin node'",
+            node_exe.display()
+        );
+        process::exit(1);
+    }
+    std::fs::create_dir_all(&options.dir).expect("Failed to create cluster directory");
+    env::set_current_dir(&options.dir).expect("Failed to enter cluster directory");
+
+    let cluster = prepare_config(options.nodes);
+    prepare_keys(&cluster).await;
+
+    let mut launcher = Launcher::new(cluster, node_exe, options.trace);
+    for id in launcher.cluster.replica_ids() {
+        if let Err(e) = launcher.start(id, true) {
+            eprintln!("Failed to start replica {}: {}", id, e);
+            launcher.shutdown().await;
+            process::exit(1);
+        }
+    }
+
+    let ids = launcher.cluster.replica_ids();
+    let not_ready = tokio::select! {
+        not_ready = launcher.wait_ready(ids) => not_ready,
+        _ = tokio::signal::ctrl_c() => {
+            launcher.shutdown().await;
+            return;
+        }
+    };
+    if not_ready.is_empty() {
+        println!("Cluster ready. Type 'help' for commands.");
+    } else {
+        println!(
+            "Replicas {:?} are not ready after {:?}; check their logs",
+            not_ready, READY_TIMEOUT
+        );
+    }
+
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    let mut reap = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        tokio::select! {
+            line = lines.next_line() => match line {
+                Ok(Some(line)) => {
+                    if !launcher.command(&line).await {
+                        break;
+                    }
+                }
+                Ok(None) | Err(_) => break,
+            },
+            _ = reap.tick() => launcher.reap(),
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+
+    launcher.shutdown().await;
+}
@@ -0,0 +1,39 @@
+use simple_pbft_demo::{
+    config::{membership::quorum_size, node::load_cluster_config},
+    trace::{diagram::Diagram, record::read_trace, timeline::to_html},
+};
+use std::{env, path::Path, process};
+
+/// Draws the message flow recorded in the traces of nodes started with
+/// `PBFT_TRACE`, one trace per replica. The quorum size comes from the
+/// replicas listed in `cluster.toml`.
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let format = args.get(1).map(String::as_str);
+    if args.len() < 3 || !matches!(format, Some("mermaid" | "plantuml" | "html")) {
+        eprintln!("Usage: diagram <mermaid|plantuml|html> <trace file>...");
+        eprintln!("  Example: diagram html t0.bin t1.bin t2.bin t3.bin > flow.html");
+        process::exit(2);
+    }
+
+    let traces: Vec<_> = args[2..]
+        .iter()
+        .map(|path| {
+            read_trace(Path::new(path)).unwrap_or_else(|e| {
+                eprintln!("Failed to read {}: {}", path, e);
+                process::exit(2);
+            })
+        })
+        .collect();
+
+    let cluster = load_cluster_config(Path::new("cluster.toml"));
+    let quorum = quorum_size(cluster.replicas.len() as u32) as usize;
+    let diagram = Diagram::build(&traces, quorum);
+
+    let output = match format {
+        Some("mermaid") => diagram.to_mermaid(),
+        Some("plantuml") => diagram.to_plantuml(),
+        _ => to_html(&diagram),
+    };
+    print!("{}", output);
+}
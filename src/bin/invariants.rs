@@ -0,0 +1,46 @@
+use simple_pbft_demo::state::{events::parse_event_line, invariants::InvariantChecker};
+use std::{env, fs, process};
+
+/// Checks PBFT safety over the event logs written by nodes started with
+/// `PBFT_EVENT_LOG`. Only pass logs of replicas that are meant to be correct.
+fn main() {
+    let paths: Vec<String> = env::args().skip(1).collect();
+    if paths.is_empty() {
+        eprintln!("Usage: invariants <event log>...");
+        process::exit(2);
+    }
+
+    let mut checker = InvariantChecker::new();
+    let mut events = 0;
+
+    for path in &paths {
+        let contents = fs::read_to_string(path).unwrap_or_else(|e| {
+            eprintln!("Failed to read {}: {}", path, e);
+            process::exit(2);
+        });
+
+        for (line_no, line) in contents.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            match parse_event_line(line) {
+                Ok((node_id, event)) => {
+                    checker.observe(node_id, &event);
+                    events += 1;
+                }
+                // A node killed mid-write can leave a partial last line.
+                Err(e) => eprintln!("{}:{}: skipping: {}", path, line_no + 1, e),
+            }
+        }
+    }
+
+    match checker.check() {
+        Ok(()) => println!("{} events from {} logs, no violations", events, paths.len()),
+        Err(violations) => {
+            for violation in &violations {
+                println!("VIOLATION: {}", violation);
+            }
+            process::exit(1);
+        }
+    }
+}
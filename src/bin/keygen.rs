@@ -1,40 +1,31 @@
-use ring::signature::{Ed25519KeyPair, KeyPair};
+use simple_pbft_demo::{config::node::load_cluster_config, crypto::primitives::generate_key_files};
 use std::path::Path;
 
+async fn generate_keys(keys_dir: &Path, name: &str) {
+    match generate_key_files(keys_dir, name).await {
+        Ok(true) => println!("Generated keys for {}", name),
+        Ok(false) => println!("{} keys already exist, skipping", name),
+        Err(e) => panic!("Failed to write keys for {}: {}", name, e),
+    }
+}
+
 #[tokio::main]
 async fn main() {
     let keys_dir = Path::new("keys");
+    let node_count: u32 = std::env::args()
+        .nth(1)
+        .map(|n| n.parse().expect("Invalid node count"))
+        .unwrap_or(4);
+    let cluster = load_cluster_config(Path::new("cluster.toml"));
 
-    tokio::fs::create_dir_all(keys_dir)
-        .await
-        .expect("Failed to create keys directory");
-
-    println!("Generating keys for 4 nodes...");
-
-    for node_id in 0..4 {
-        let key_path = keys_dir.join(format!("node_{}.key", node_id));
-        let pub_path = keys_dir.join(format!("node_{}.pub", node_id));
+    println!("Generating keys for {} nodes...", node_count);
 
-        if key_path.exists() {
-            println!("Node {} keys already exist, skipping", node_id);
-            continue;
-        }
-
-        let rng = ring::rand::SystemRandom::new();
-        let pkcs8_bytes = Ed25519KeyPair::generate_pkcs8(&rng).expect("Key generation failed");
-
-        let keypair =
-            Ed25519KeyPair::from_pkcs8(pkcs8_bytes.as_ref()).expect("Failed to parse keypair");
-        let pub_key = keypair.public_key().as_ref();
-
-        tokio::fs::write(&key_path, pkcs8_bytes.as_ref())
-            .await
-            .expect("Failed to write private key");
-        tokio::fs::write(&pub_path, pub_key)
-            .await
-            .expect("Failed to write public key");
+    for node_id in 0..node_count {
+        generate_keys(keys_dir, &format!("node_{}", node_id)).await;
+    }
 
-        println!("Generated keys for node {}", node_id);
+    for client_id in &cluster.clients {
+        generate_keys(keys_dir, &format!("client_{}", client_id)).await;
     }
 
     println!("\nAll keys generated successfully!");
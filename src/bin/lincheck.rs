@@ -0,0 +1,212 @@
+use rand::RngExt;
+use simple_pbft_demo::{
+    client::{
+        history::{HistoryRecorder, KvOp, KvResult},
+        linearizability,
+        pbft_client::PbftClient,
+    },
+    config::node::{ClusterConfig, load_cluster_config},
+    crypto::primitives::{load_private_key, load_public_keys},
+};
+use std::{collections::HashMap, env, path::Path, process, time::Duration};
+use tokio::process::{Child, Command};
+
+const OP_TIMEOUT: Duration = Duration::from_secs(10);
+const STARTUP_WAIT: Duration = Duration::from_secs(7);
+
+struct Options {
+    ops_per_client: usize,
+    keys: usize,
+    /// Replica to kill, and when, in milliseconds after the clients start.
+    crash: Option<(u32, u64)>,
+    /// Restart the crashed replica this long after killing it.
+    restart_after_ms: Option<u64>,
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "Usage: lincheck [--ops N] [--keys N] [--crash <id>@<ms>] [--restart-after <ms>]\n\
+         Starts every replica in cluster.toml as a child process (keys must exist),\n\
+         runs one client per configured client id doing random PUT/GET/CAS, and\n\
+         checks the recorded history for linearizability."
+    );
+    process::exit(2);
+}
+
+fn parse_options() -> Options {
+    let mut options = Options {
+        ops_per_client: 50,
+        keys: 3,
+        crash: None,
+        restart_after_ms: None,
+    };
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "--ops" => options.ops_per_client = value().parse().unwrap_or_else(|_| usage()),
+            "--keys" => options.keys = value().parse().unwrap_or_else(|_| usage()),
+            "--crash" => {
+                let (id, at) = value().split_once('@').unwrap_or_else(|| usage());
+                options.crash = Some((
+                    id.parse().unwrap_or_else(|_| usage()),
+                    at.parse().unwrap_or_else(|_| usage()),
+                ));
+            }
+            "--restart-after" => {
+                options.restart_after_ms = Some(value().parse().unwrap_or_else(|_| usage()))
+            }
+            _ => usage(),
+        }
+    }
+    options
+}
+
+/// Starts `node <id>` next to this binary, logging to `lincheck-node-<id>.log`.
+fn spawn_node(id: u32) -> Child {
+    let exe = env::current_exe().expect("Cannot locate lincheck binary");
+    let node = exe.with_file_name("node");
+    let log = std::fs::File::create(format!("lincheck-node-{}.log", id))
+        .expect("Failed to create node log");
+
+    Command::new(node)
+        .arg(id.to_string())
+        .env("PBFT_EVENT_LOG", format!("lincheck-node-{}.events", id))
+        .stdout(log.try_clone().unwrap())
+        .stderr(log)
+        .kill_on_drop(true)
+        .spawn()
+        .unwrap_or_else(|e| panic!("Failed to start node {}: {}", id, e))
+}
+
+async fn run_client(
+    client_id: u32,
+    cluster: ClusterConfig,
+    replica_keys: HashMap<u32, Vec<u8>>,
+    history: HistoryRecorder,
+    ops: usize,
+    keys: usize,
+) {
+    let pkcs8 = load_private_key(&format!("client_{}", client_id)).await;
+    let mut client = PbftClient::connect(client_id, &pkcs8, &cluster, replica_keys).await;
+    let mut last_seen: HashMap<String, String> = HashMap::new();
+
+    for n in 0..ops {
+        let key = format!("k{}", rand::rng().random_range(0..keys));
+        let value = format!("c{}n{}", client_id, n);
+        let op = match rand::rng().random_range(0..3) {
+            0 => KvOp::Put { key, value },
+            1 => KvOp::Get { key },
+            _ => KvOp::Cas {
+                expected: last_seen.get(&key).cloned().unwrap_or_default(),
+                key,
+                new: value,
+            },
+        };
+
+        let handle = history.invoke(client_id, op.clone());
+        let Some(result) = client.invoke(op.to_operation(), OP_TIMEOUT).await else {
+            println!("client {}: {} timed out", client_id, op);
+            continue;
+        };
+        let result = op.parse_result(&result);
+        history.complete(handle, result.clone());
+
+        match (&op, result) {
+            (KvOp::Get { key }, KvResult::Value(Some(v))) => {
+                last_seen.insert(key.clone(), v);
+            }
+            (
+                KvOp::Put { key, value }
+                | KvOp::Cas {
+                    key, new: value, ..
+                },
+                _,
+            ) => {
+                last_seen.insert(key.clone(), value.clone());
+            }
+            _ => {}
+        }
+    }
+
+    client.close().await;
+}
+
+#[tokio::main]
+async fn main() {
+    rustls::crypto::ring::default_provider()
+        .install_default()
+        .expect("Failed to install rustls crypto provider");
+
+    let options = parse_options();
+    let cluster = load_cluster_config(Path::new("cluster.toml"));
+    let replica_keys = load_public_keys("node", &cluster.replica_ids()).await;
+
+    let mut nodes: HashMap<u32, Child> = cluster
+        .replica_ids()
+        .into_iter()
+        .map(|id| (id, spawn_node(id)))
+        .collect();
+    println!(
+        "Started {} replicas, waiting {:?} for them to connect",
+        nodes.len(),
+        STARTUP_WAIT
+    );
+    tokio::time::sleep(STARTUP_WAIT).await;
+
+    let history = HistoryRecorder::new();
+    let clients: Vec<_> = cluster
+        .clients
+        .iter()
+        .map(|&client_id| {
+            tokio::spawn(run_client(
+                client_id,
+                cluster.clone(),
+                replica_keys.clone(),
+                history.clone(),
+                options.ops_per_client,
+                options.keys,
+            ))
+        })
+        .collect();
+
+    if let Some((id, at_ms)) = options.crash {
+        tokio::time::sleep(Duration::from_millis(at_ms)).await;
+        if let Some(child) = nodes.get_mut(&id) {
+            println!("Fault: killing replica {}", id);
+            let _ = child.kill().await;
+        }
+        if let Some(after_ms) = options.restart_after_ms {
+            tokio::time::sleep(Duration::from_millis(after_ms)).await;
+            println!("Fault: restarting replica {}", id);
+            nodes.insert(id, spawn_node(id));
+        }
+    }
+
+    for client in clients {
+        let _ = client.await;
+    }
+
+    for child in nodes.values_mut() {
+        let _ = child.kill().await;
+    }
+
+    let entries = history.entries();
+    let completed = entries.iter().filter(|e| e.complete.is_some()).count();
+    println!(
+        "{} operations, {} completed, {} without a result",
+        entries.len(),
+        completed,
+        entries.len() - completed
+    );
+
+    match linearizability::check(&entries) {
+        Ok(()) => println!("History is linearizable"),
+        Err(e) => {
+            print!("{}", e);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use simple_pbft_demo::{
+    config::{membership::Membership, node::load_cluster_config},
+    crypto::primitives::{load_public_keys, setup_crypto_for_node},
+    message::message_types::{ClientInfo, ReplicaInfo},
+    state::{events::to_hex, replica::Replica},
+    trace::{
+        record::{TraceEvent, read_trace},
+        replay::{SentMessages, replay},
+    },
+};
+use std::{env, path::Path, process};
+
+/// Replays a trace recorded by a node started with `PBFT_TRACE` into a
+/// fresh replica, using the same `cluster.toml` and `keys/` as that node, and
+/// reports where the replayed replica acted differently.
+#[tokio::main]
+async fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 2 && args.len() != 3 {
+        eprintln!("Usage: replay <trace file> [<last record index>]");
+        process::exit(2);
+    }
+
+    let mut records = read_trace(Path::new(&args[1])).unwrap_or_else(|e| {
+        eprintln!("Failed to read {}: {}", args[1], e);
+        process::exit(2);
+    });
+    if let Some(last) = args.get(2) {
+        let last: usize = last.parse().expect("Invalid record index");
+        records.truncate(last + 1);
+    }
+    let Some(TraceEvent::Start { node_id }) = records.first().map(|r| &r.event) else {
+        eprintln!("{} is not a trace", args[1]);
+        process::exit(2);
+    };
+    let node_id = *node_id;
+
+    let cluster = load_cluster_config(Path::new("cluster.toml"));
+    let (crypto, peer_pk) = setup_crypto_for_node(node_id, &cluster.replica_ids()).await;
+    let mut membership = Membership::new(
+        cluster
+            .replicas
+            .iter()
+            .map(|r| ReplicaInfo {
+                id: r.id,
+                public_key: peer_pk
+                    .get(&r.id)
+                    .cloned()
+                    .unwrap_or_else(|| crypto.get_pub_key()),
+                addr: r.addr,
+            })
+            .collect(),
+        load_public_keys("client", &cluster.clients)
+            .await
+            .into_iter()
+            .map(|(id, public_key)| ClientInfo { id, public_key })
+            .collect(),
+    )
+    .unwrap_or_else(|e| {
+        eprintln!("Invalid cluster config: {}", e);
+        process::exit(2);
+    });
+    membership.set_admins(cluster.admins.iter().copied());
+
+    let mut replica = Replica::new(node_id, membership, crypto);
+    let report = replay(&mut replica, &records).await;
+
+    println!(
+        "Replayed {} events of {} records for replica {}",
+        report.events,
+        records.len(),
+        node_id
+    );
+    let state = replica
+        .ledger_entry(replica.last_executed())
+        .map_or("none".to_string(), |e| to_hex(&e.state_digest));
+    println!(
+        "  view {}, last executed {}, state {}",
+        replica.view(),
+        replica.last_executed(),
+        state
+    );
+
+    if report.divergences.is_empty() {
+        println!("  sent exactly what was recorded");
+        return;
+    }
+    for divergence in &report.divergences {
+        println!("DIVERGED at record {}", divergence.index);
+        println!("  recorded: {}", describe(&divergence.recorded));
+        println!("  replayed: {}", describe(&divergence.replayed));
+    }
+    process::exit(1);
+}
+
+fn describe(sent: &SentMessages) -> String {
+    if sent.is_empty() {
+        return "nothing".to_string();
+    }
+    let messages: Vec<String> = sent
+        .iter()
+        .map(|(to, message)| match to {
+            Some(to) => format!("{} to {}", message.kind().name(), to),
+            None => format!("{} to all", message.kind().name()),
+        })
+        .collect();
+    messages.join(", ")
+}
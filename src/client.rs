@@ -0,0 +1,4 @@
+pub mod history;
+pub mod linearizability;
+pub mod pbft_client;
+pub mod verifier;
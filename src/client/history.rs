@@ -0,0 +1,144 @@
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+};
+use tokio::time::Instant;
+
+/// Key-value operation understood by `AppState`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum KvOp {
+    Put {
+        key: String,
+        value: String,
+    },
+    Get {
+        key: String,
+    },
+    Cas {
+        key: String,
+        expected: String,
+        new: String,
+    },
+}
+
+impl KvOp {
+    pub fn key(&self) -> &str {
+        match self {
+            KvOp::Put { key, .. } | KvOp::Get { key } | KvOp::Cas { key, .. } => key,
+        }
+    }
+
+    pub fn to_operation(&self) -> Vec<u8> {
+        match self {
+            KvOp::Put { key, value } => format!("PUT:{}:{}", key, value),
+            KvOp::Get { key } => format!("GET:{}", key),
+            KvOp::Cas { key, expected, new } => format!("CAS:{}:{}:{}", key, expected, new),
+        }
+        .into_bytes()
+    }
+
+    /// Interprets the result `AppState` returned for this operation.
+    pub fn parse_result(&self, result: &[u8]) -> KvResult {
+        match (self, result) {
+            (KvOp::Put { .. } | KvOp::Cas { .. }, b"OK") => KvResult::Ok,
+            (KvOp::Cas { .. }, b"CAS_FAILED") => KvResult::CasFailed,
+            (KvOp::Get { .. }, b"NOT_FOUND") => KvResult::Value(None),
+            (KvOp::Get { .. }, value) => {
+                KvResult::Value(Some(String::from_utf8_lossy(value).into_owned()))
+            }
+            (_, other) => KvResult::Other(String::from_utf8_lossy(other).into_owned()),
+        }
+    }
+}
+
+impl fmt::Display for KvOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", String::from_utf8_lossy(&self.to_operation()))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum KvResult {
+    Ok,
+    CasFailed,
+    Value(Option<String>),
+    Other(String),
+}
+
+/// One operation as a client saw it. Times are microseconds since the
+/// recorder was created; `complete` is `None` if no result ever came back.
+#[derive(Clone, Debug)]
+pub struct HistoryEntry {
+    pub client_id: u32,
+    pub op: KvOp,
+    pub invoke_us: u64,
+    pub complete: Option<(u64, KvResult)>,
+}
+
+impl fmt::Display for HistoryEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.complete {
+            Some((complete_us, result)) => write!(
+                f,
+                "client {} [{} .. {}] {} -> {:?}",
+                self.client_id, self.invoke_us, complete_us, self.op, result
+            ),
+            None => write!(
+                f,
+                "client {} [{} .. ?] {} -> (no result)",
+                self.client_id, self.invoke_us, self.op
+            ),
+        }
+    }
+}
+
+/// Records invocations and completions from any number of concurrent
+/// clients. Clones share the same history.
+#[derive(Clone)]
+pub struct HistoryRecorder {
+    origin: Instant,
+    entries: Arc<Mutex<Vec<HistoryEntry>>>,
+}
+
+impl Default for HistoryRecorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HistoryRecorder {
+    pub fn new() -> Self {
+        HistoryRecorder {
+            origin: Instant::now(),
+            entries: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    fn now_us(&self) -> u64 {
+        self.origin.elapsed().as_micros() as u64
+    }
+
+    /// Records the start of `op` and returns a handle for `complete`.
+    pub fn invoke(&self, client_id: u32, op: KvOp) -> usize {
+        let invoke_us = self.now_us();
+        let mut entries = self.entries.lock().unwrap();
+        entries.push(HistoryEntry {
+            client_id,
+            op,
+            invoke_us,
+            complete: None,
+        });
+        entries.len() - 1
+    }
+
+    /// Records the result of the operation `handle`. Leave operations that
+    /// timed out uncompleted: they may still take effect later.
+    pub fn complete(&self, handle: usize, result: KvResult) {
+        let complete_us = self.now_us();
+        self.entries.lock().unwrap()[handle].complete = Some((complete_us, result));
+    }
+
+    pub fn entries(&self) -> Vec<HistoryEntry> {
+        self.entries.lock().unwrap().clone()
+    }
+}
@@ -0,0 +1,120 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt,
+};
+
+use crate::client::history::{HistoryEntry, KvOp, KvResult};
+
+/// A key whose operations admit no linearization.
+#[derive(Debug)]
+pub struct LinearizabilityError {
+    pub key: String,
+    pub entries: Vec<HistoryEntry>,
+}
+
+impl fmt::Display for LinearizabilityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "history of key {:?} is not linearizable:", self.key)?;
+        for entry in &self.entries {
+            writeln!(f, "  {}", entry)?;
+        }
+        Ok(())
+    }
+}
+
+/// Sequential specification of a single key.
+fn apply(op: &KvOp, state: &Option<String>) -> (Option<String>, KvResult) {
+    match op {
+        KvOp::Put { value, .. } => (Some(value.clone()), KvResult::Ok),
+        KvOp::Get { .. } => (state.clone(), KvResult::Value(state.clone())),
+        KvOp::Cas { expected, new, .. } => {
+            if state.as_ref() == Some(expected) {
+                (Some(new.clone()), KvResult::Ok)
+            } else {
+                (state.clone(), KvResult::CasFailed)
+            }
+        }
+    }
+}
+
+/// Checks that `history` is linearizable against a key-value store that
+/// starts out empty. Keys are independent, so each is checked on its own.
+///
+/// Operations without a result may have taken effect at any point after
+/// they were invoked, or not at all.
+pub fn check(history: &[HistoryEntry]) -> Result<(), LinearizabilityError> {
+    let mut by_key: BTreeMap<&str, Vec<&HistoryEntry>> = BTreeMap::new();
+    for entry in history {
+        by_key.entry(entry.op.key()).or_default().push(entry);
+    }
+
+    for (key, mut entries) in by_key {
+        entries.sort_by_key(|e| e.invoke_us);
+        let mut search = Search {
+            entries: &entries,
+            linearized: vec![false; entries.len()],
+            seen: HashSet::new(),
+        };
+        if !search.run(None) {
+            return Err(LinearizabilityError {
+                key: key.to_string(),
+                entries: entries.into_iter().cloned().collect(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Wing & Gong search with Lowe's memoization: repeatedly pick an operation
+/// that could take effect next (no unlinearized operation finished before it
+/// started), apply it to the model if its result matches, and backtrack on
+/// dead ends. Configurations already explored are cached by the set of
+/// linearized operations plus the model state.
+struct Search<'a> {
+    entries: &'a [&'a HistoryEntry],
+    linearized: Vec<bool>,
+    seen: HashSet<(Vec<bool>, Option<String>)>,
+}
+
+impl Search<'_> {
+    fn run(&mut self, state: Option<String>) -> bool {
+        let pending_completed = self
+            .entries
+            .iter()
+            .zip(&self.linearized)
+            .filter(|(e, done)| !**done && e.complete.is_some());
+        let Some(frontier) = pending_completed
+            .map(|(e, _)| e.complete.as_ref().unwrap().0)
+            .min()
+        else {
+            // Every completed operation is placed; the rest may never have
+            // happened.
+            return true;
+        };
+
+        if !self.seen.insert((self.linearized.clone(), state.clone())) {
+            return false;
+        }
+
+        for i in 0..self.entries.len() {
+            let entry = self.entries[i];
+            if self.linearized[i] || entry.invoke_us > frontier {
+                continue;
+            }
+
+            let (next, result) = apply(&entry.op, &state);
+            if let Some((_, observed)) = &entry.complete
+                && *observed != result
+            {
+                continue;
+            }
+
+            self.linearized[i] = true;
+            if self.run(next) {
+                return true;
+            }
+            self.linearized[i] = false;
+        }
+        false
+    }
+}
@@ -0,0 +1,304 @@
+use quinn::{ClientConfig, Connection, Endpoint, TransportConfig};
+use ring::signature::Ed25519KeyPair;
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    time::Instant,
+};
+
+use crate::{
+    config::node::ClusterConfig,
+    crypto::primitives::Crypto,
+    message::message_types::{AdminQuery, AdminRequest, AdminResult, PBFTMessage, Request},
+    network::{
+        cert::{NodeCert, PinnedKeys, make_client_config, replica_server_name},
+        framing::{Envelope, FrameLimits, FrameStats, read_frame, write_frame},
+        network_layer::{IDLE_TIMEOUT, KEEP_ALIVE_INTERVAL},
+    },
+};
+
+/// How long a single dial may take before the replica is treated as down.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+type Connections = Arc<Mutex<HashMap<u32, Connection>>>;
+
+/// Client side of the protocol: sends each request to every replica and
+/// accepts a result once f + 1 replicas returned the same signed reply,
+/// resending until then.
+pub struct PbftClient {
+    client_id: u32,
+    crypto: Crypto,
+    endpoint: Endpoint,
+    replicas: Vec<(u32, SocketAddr)>,
+    connections: Connections,
+    connecting: Arc<Mutex<HashSet<u32>>>,
+    /// Replies and admin responses from every replica.
+    reply_tx: UnboundedSender<PBFTMessage>,
+    replies: UnboundedReceiver<PBFTMessage>,
+    retry_interval: Duration,
+    last_timestamp: u64,
+    /// The cluster's id and our newest wire version, on every request.
+    envelope: Envelope,
+}
+
+impl PbftClient {
+    /// Connects to every replica in `cluster` that is reachable.
+    /// `replica_keys` are the replicas' Ed25519 public keys, used both to pin
+    /// their TLS certificates and to verify their replies.
+    pub async fn connect(
+        client_id: u32,
+        pkcs8: &[u8],
+        cluster: &ClusterConfig,
+        replica_keys: HashMap<u32, Vec<u8>>,
+    ) -> Self {
+        let pinned = PinnedKeys::new(replica_keys.clone(), HashMap::new());
+        let certs = NodeCert::from_pkcs8(format!("client-{}", client_id), pkcs8);
+
+        let mut transport = TransportConfig::default();
+        transport.keep_alive_interval(Some(KEEP_ALIVE_INTERVAL));
+        transport.max_idle_timeout(Some(IDLE_TIMEOUT.try_into().unwrap()));
+        let mut client_cfg = ClientConfig::new(Arc::new(
+            quinn::crypto::rustls::QuicClientConfig::try_from(make_client_config(&certs, pinned))
+                .expect("Failed to create QUIC client config"),
+        ));
+        client_cfg.transport_config(Arc::new(transport));
+
+        let mut endpoint = Endpoint::client("0.0.0.0:0".parse().unwrap())
+            .expect("Failed to create client endpoint");
+        endpoint.set_default_client_config(client_cfg);
+
+        let keypair = Ed25519KeyPair::from_pkcs8(pkcs8).expect("Invalid client key");
+        let crypto = Crypto::new(keypair, client_id, replica_keys);
+        let (reply_tx, replies) = mpsc::unbounded_channel();
+
+        let client = PbftClient {
+            client_id,
+            crypto,
+            endpoint,
+            replicas: cluster.replicas.iter().map(|r| (r.id, r.addr)).collect(),
+            connections: Arc::new(Mutex::new(HashMap::new())),
+            connecting: Arc::new(Mutex::new(HashSet::new())),
+            reply_tx,
+            replies,
+            retry_interval: Duration::from_secs(2),
+            last_timestamp: 0,
+            envelope: Envelope::new(cluster.network.cluster_id),
+        };
+
+        let dials: Vec<_> = client
+            .replicas
+            .iter()
+            .map(|&(id, addr)| client.dial(id, addr))
+            .collect();
+        for dial in dials {
+            let _ = dial.await;
+        }
+
+        client
+    }
+
+    pub fn client_id(&self) -> u32 {
+        self.client_id
+    }
+
+    pub fn set_retry_interval(&mut self, retry_interval: Duration) {
+        self.retry_interval = retry_interval;
+    }
+
+    pub fn connected_replicas(&self) -> Vec<u32> {
+        let mut ids: Vec<u32> = self.connections.lock().unwrap().keys().copied().collect();
+        ids.sort();
+        ids
+    }
+
+    /// Dials `id` in the background unless a live connection or a dial is
+    /// already there, and starts reading replies off the new connection.
+    fn dial(&self, id: u32, addr: SocketAddr) -> tokio::task::JoinHandle<()> {
+        let live = self
+            .connections
+            .lock()
+            .unwrap()
+            .get(&id)
+            .is_some_and(|c| c.close_reason().is_none());
+        if live || !self.connecting.lock().unwrap().insert(id) {
+            return tokio::spawn(async {});
+        }
+
+        let endpoint = self.endpoint.clone();
+        let connections = self.connections.clone();
+        let connecting = self.connecting.clone();
+        let reply_tx = self.reply_tx.clone();
+        let cluster_id = self.envelope.cluster_id;
+
+        tokio::spawn(async move {
+            let connecting_attempt = endpoint.connect(addr, &replica_server_name(id));
+            let connection = match connecting_attempt {
+                Ok(connecting_attempt) => {
+                    match tokio::time::timeout(CONNECT_TIMEOUT, connecting_attempt).await {
+                        Ok(Ok(connection)) => Some(connection),
+                        _ => None,
+                    }
+                }
+                Err(_) => None,
+            };
+
+            if let Some(connection) = connection {
+                connections.lock().unwrap().insert(id, connection.clone());
+                tokio::spawn(Self::read_replies(connection, cluster_id, reply_tx));
+            }
+            connecting.lock().unwrap().remove(&id);
+        })
+    }
+
+    async fn read_replies(
+        connection: Connection,
+        cluster_id: u32,
+        reply_tx: UnboundedSender<PBFTMessage>,
+    ) {
+        let limits = Arc::new(FrameLimits::default());
+        let stats = Arc::new(FrameStats::default());
+
+        while let Ok(mut stream) = connection.accept_uni().await {
+            let reply_tx = reply_tx.clone();
+            let limits = limits.clone();
+            let stats = stats.clone();
+            // Replicas keep one stream open and write every reply to it.
+            tokio::spawn(async move {
+                while let Ok(message) = read_frame(&mut stream, cluster_id, &limits, &stats).await {
+                    if let PBFTMessage::Reply(_) | PBFTMessage::AdminResponse(_) = message {
+                        let _ = reply_tx.send(message);
+                    }
+                }
+            });
+        }
+    }
+
+    async fn send_all(&self, message: &PBFTMessage) {
+        for &(id, addr) in &self.replicas {
+            self.dial(id, addr);
+        }
+
+        let connections: Vec<Connection> =
+            self.connections.lock().unwrap().values().cloned().collect();
+        for connection in connections {
+            if let Ok(mut stream) = connection.open_uni().await {
+                let _ = write_frame(&mut stream, self.envelope, message).await;
+                let _ = stream.finish();
+            }
+        }
+    }
+
+    /// Timestamps only ever grow, also across client restarts.
+    fn next_timestamp(&mut self) -> u64 {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_micros() as u64;
+        self.last_timestamp = now.max(self.last_timestamp + 1);
+        self.last_timestamp
+    }
+
+    /// Submits `operation` and waits for its result. Returns `None` if no
+    /// f + 1 matching replies arrived within `timeout`; the operation may or
+    /// may not have been executed in that case.
+    pub async fn invoke(&mut self, operation: Vec<u8>, timeout: Duration) -> Option<Vec<u8>> {
+        let timestamp = self.next_timestamp();
+        let request = Request {
+            operation,
+            timestamp,
+            client_id: self.client_id as u64,
+        };
+        let message = PBFTMessage::Request(self.crypto.create_signed_message(request));
+
+        let f = (self.replicas.len().max(1) - 1) / 3;
+        let deadline = Instant::now() + timeout;
+        let mut votes: HashMap<Vec<u8>, HashSet<u32>> = HashMap::new();
+
+        loop {
+            self.send_all(&message).await;
+            let retry_at = (Instant::now() + self.retry_interval).min(deadline);
+
+            loop {
+                let reply = match tokio::time::timeout_at(retry_at, self.replies.recv()).await {
+                    Ok(Some(PBFTMessage::Reply(reply))) => reply,
+                    Ok(Some(_)) => continue,
+                    Ok(None) => return None,
+                    Err(_) => break,
+                };
+
+                if reply.signer_id != reply.message.replica_id
+                    || reply.message.client_id != self.client_id as u64
+                    || reply.message.timestamp != timestamp
+                    || !self.crypto.verify_signed_message(&reply)
+                {
+                    continue;
+                }
+
+                let voters = votes.entry(reply.message.result.clone()).or_default();
+                voters.insert(reply.message.replica_id);
+                if voters.len() > f {
+                    return Some(reply.message.result);
+                }
+            }
+
+            if Instant::now() >= deadline {
+                return None;
+            }
+        }
+    }
+
+    /// Asks a single replica `query`. The answer is signed by that replica
+    /// but, unlike `invoke`, not agreed on by the others.
+    pub async fn admin(
+        &mut self,
+        replica_id: u32,
+        query: AdminQuery,
+        timeout: Duration,
+    ) -> Option<AdminResult> {
+        let &(_, addr) = self.replicas.iter().find(|(id, _)| *id == replica_id)?;
+        let nonce = self.next_timestamp();
+        let request = AdminRequest {
+            client_id: self.client_id as u64,
+            nonce,
+            query,
+        };
+        let message = PBFTMessage::AdminRequest(self.crypto.create_signed_message(request));
+
+        let _ = self.dial(replica_id, addr).await;
+        let connection = self.connections.lock().unwrap().get(&replica_id).cloned()?;
+        let mut stream = connection.open_uni().await.ok()?;
+        write_frame(&mut stream, self.envelope, &message)
+            .await
+            .ok()?;
+        let _ = stream.finish();
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            let response = match tokio::time::timeout_at(deadline, self.replies.recv()).await {
+                Ok(Some(PBFTMessage::AdminResponse(response))) => response,
+                Ok(Some(_)) => continue,
+                Ok(None) | Err(_) => return None,
+            };
+
+            if response.signer_id == replica_id
+                && response.message.replica_id == replica_id
+                && response.message.nonce == nonce
+                && self.crypto.verify_signed_message(&response)
+            {
+                return Some(response.message.result);
+            }
+        }
+    }
+
+    pub async fn close(self) {
+        for connection in self.connections.lock().unwrap().values() {
+            connection.close(0u32.into(), b"Done");
+        }
+        self.endpoint.wait_idle().await;
+    }
+}
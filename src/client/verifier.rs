@@ -0,0 +1,125 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    config::membership::Membership,
+    crypto::{merkle::KeyProof, primitives::Crypto},
+    message::message_types::{CertifiedRequest, Reply, SignedMessage},
+};
+
+/// Checks what replicas hand out against the cluster's public keys alone, so
+/// a client that hears from a single relay can still tell whether the
+/// cluster agreed on it.
+pub struct Verifier {
+    replica_keys: HashMap<u32, Vec<u8>>,
+    f: usize,
+    quorum: usize,
+}
+
+/// What f + 1 replicas agreed on in their replies. At least one of them is
+/// correct, so the request really was executed with this outcome.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct AgreedReply {
+    pub client_id: u64,
+    pub timestamp: u64,
+    pub seq_num: u64,
+    pub result: Vec<u8>,
+    pub state_digest: [u8; 32],
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VerifyError {
+    /// No outcome was vouched for by enough distinct, validly signed replies.
+    NotEnoughReplies {
+        matching: usize,
+        needed: usize,
+    },
+    InvalidCertificate,
+    /// The key proof was built over a different state than the one the
+    /// replies agreed on.
+    ProofForOtherState,
+    /// The key proof does not hold under the agreed state digest.
+    InvalidProof,
+}
+
+impl Verifier {
+    pub fn new(replica_keys: HashMap<u32, Vec<u8>>, f: usize, quorum: usize) -> Self {
+        Verifier {
+            replica_keys,
+            f,
+            quorum,
+        }
+    }
+
+    pub fn from_membership(membership: &Membership) -> Self {
+        Self::new(
+            membership.replica_public_keys(),
+            membership.f() as usize,
+            membership.quorum() as usize,
+        )
+    }
+
+    /// The outcome at least f + 1 distinct replicas signed. Replies with a
+    /// bad signature, from unknown replicas, or signed by someone other than
+    /// the replica they name are ignored.
+    pub fn verify_replies(
+        &self,
+        replies: &[SignedMessage<Reply>],
+    ) -> Result<AgreedReply, VerifyError> {
+        let needed = self.f + 1;
+        let mut votes: HashMap<AgreedReply, HashSet<u32>> = HashMap::new();
+
+        for reply in replies {
+            let Some(pk) = self.replica_keys.get(&reply.signer_id) else {
+                continue;
+            };
+            if reply.signer_id != reply.message.replica_id || !Crypto::verify_with_key(pk, reply) {
+                continue;
+            }
+            let outcome = AgreedReply {
+                client_id: reply.message.client_id,
+                timestamp: reply.message.timestamp,
+                seq_num: reply.message.seq_num,
+                result: reply.message.result.clone(),
+                state_digest: reply.message.state_digest,
+            };
+            votes.entry(outcome).or_default().insert(reply.signer_id);
+        }
+
+        let best = votes.into_iter().max_by_key(|(_, signers)| signers.len());
+        match best {
+            Some((outcome, signers)) if signers.len() >= needed => Ok(outcome),
+            best => Err(VerifyError::NotEnoughReplies {
+                matching: best.map_or(0, |(_, signers)| signers.len()),
+                needed,
+            }),
+        }
+    }
+
+    /// Checks that `entry` was committed at its sequence number by a quorum.
+    pub fn verify_certificate(&self, entry: &CertifiedRequest) -> Result<(), VerifyError> {
+        if entry.verify(&self.replica_keys, self.quorum) {
+            Ok(())
+        } else {
+            Err(VerifyError::InvalidCertificate)
+        }
+    }
+
+    /// Reads a key from the state the cluster reached after the request
+    /// `replies` answer: the replies vouch for the state digest and `proof`
+    /// shows the key's value, or its absence, under it.
+    pub fn verify_read(
+        &self,
+        replies: &[SignedMessage<Reply>],
+        proof: &KeyProof,
+    ) -> Result<Option<String>, VerifyError> {
+        let agreed = self.verify_replies(replies)?;
+        if proof.root != agreed.state_digest {
+            return Err(VerifyError::ProofForOtherState);
+        }
+        if proof.verify(&agreed.state_digest) {
+            Ok(proof.value.clone())
+        } else {
+            Err(VerifyError::InvalidProof)
+        }
+    }
+}
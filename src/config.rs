@@ -1 +1,3 @@
+pub mod logging;
+pub mod membership;
 pub mod node;
@@ -0,0 +1,81 @@
+use serde::{Deserialize, Serialize};
+use std::io::IsTerminal;
+use tracing_subscriber::EnvFilter;
+
+/// Overrides `LoggingConfig::level`, in `EnvFilter` syntax
+/// (`info,simple_pbft_demo::network=debug`).
+pub const LOG_LEVEL_ENV: &str = "RUST_LOG";
+
+/// Overrides `LoggingConfig::format` with `text` or `json`.
+pub const LOG_FORMAT_ENV: &str = "PBFT_LOG_FORMAT";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    /// One JSON object per line, with the fields of every enclosing span.
+    Json,
+}
+
+/// The `[logging]` section of `cluster.toml`:
+///
+/// ```toml
+/// [logging]
+/// level = "info,simple_pbft_demo::network=debug"
+/// format = "json"
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LoggingConfig {
+    pub level: String,
+    pub format: LogFormat,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig {
+            level: "info".to_string(),
+            format: LogFormat::Text,
+        }
+    }
+}
+
+impl LoggingConfig {
+    /// This config with the environment overrides applied.
+    pub fn from_env(mut self) -> Self {
+        if let Ok(level) = std::env::var(LOG_LEVEL_ENV) {
+            self.level = level;
+        }
+        match std::env::var(LOG_FORMAT_ENV).as_deref() {
+            Ok("json") => self.format = LogFormat::Json,
+            Ok("text") => self.format = LogFormat::Text,
+            Ok(other) => eprintln!("Ignoring unknown {}={}", LOG_FORMAT_ENV, other),
+            Err(_) => {}
+        }
+        self
+    }
+
+    /// Parses `level`, failing on any directive `EnvFilter` can't read.
+    pub fn filter(&self) -> Result<EnvFilter, String> {
+        EnvFilter::try_new(&self.level)
+            .map_err(|e| format!("invalid log level {:?}: {}", self.level, e))
+    }
+
+    /// Installs the global subscriber. Logs go to stdout.
+    pub fn init(&self) {
+        let filter = self.filter().unwrap_or_else(|e| {
+            eprintln!("{}, using info", e);
+            EnvFilter::new("info")
+        });
+        let builder = tracing_subscriber::fmt().with_env_filter(filter);
+        match self.format {
+            LogFormat::Text => builder.with_ansi(std::io::stdout().is_terminal()).init(),
+            LogFormat::Json => builder
+                .json()
+                .with_current_span(true)
+                .with_span_list(true)
+                .init(),
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::message::message_types::{ClientInfo, Reconfigure, ReplicaInfo};
+
+/// The replica set and client registry for one epoch. Every reconfiguration
+/// executed through consensus produces a new `Membership` with the epoch
+/// bumped by one.
+#[derive(Clone, Debug)]
+pub struct Membership {
+    epoch: u64,
+    replicas: BTreeMap<u32, ReplicaInfo>,
+    clients: BTreeMap<u32, Vec<u8>>,
+    /// Clients allowed to reconfigure the cluster and run admin commands.
+    admins: BTreeSet<u32>,
+}
+
+impl Membership {
+    /// Fails if an id is listed twice, whether as two replicas, two clients
+    /// or a replica and a client, or if there are too few replicas to
+    /// tolerate a fault.
+    pub fn new(replicas: Vec<ReplicaInfo>, clients: Vec<ClientInfo>) -> Result<Self, String> {
+        let mut membership = Membership {
+            epoch: 0,
+            replicas: BTreeMap::new(),
+            clients: BTreeMap::new(),
+            admins: BTreeSet::new(),
+        };
+
+        for info in replicas {
+            if membership.replicas.insert(info.id, info.clone()).is_some() {
+                return Err(format!("replica {} is listed twice", info.id));
+            }
+        }
+        check_size(&membership.replicas)?;
+
+        for info in clients {
+            if membership.replicas.contains_key(&info.id)
+                || membership
+                    .clients
+                    .insert(info.id, info.public_key)
+                    .is_some()
+            {
+                return Err(format!("id {} is already registered", info.id));
+            }
+        }
+
+        Ok(membership)
+    }
+
+    pub fn set_admins(&mut self, admins: impl IntoIterator<Item = u32>) {
+        self.admins = admins.into_iter().collect();
+    }
+
+    /// Whether `client_id` is a registered client allowed to administer the
+    /// cluster.
+    pub fn is_admin(&self, client_id: u32) -> bool {
+        self.admins.contains(&client_id) && self.clients.contains_key(&client_id)
+    }
+
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    pub fn contains(&self, id: u32) -> bool {
+        self.replicas.contains_key(&id)
+    }
+
+    pub fn get(&self, id: u32) -> Option<&ReplicaInfo> {
+        self.replicas.get(&id)
+    }
+
+    pub fn replicas(&self) -> impl Iterator<Item = &ReplicaInfo> {
+        self.replicas.values()
+    }
+
+    pub fn replica_public_keys(&self) -> HashMap<u32, Vec<u8>> {
+        self.replicas
+            .values()
+            .map(|r| (r.id, r.public_key.clone()))
+            .collect()
+    }
+
+    pub fn client_public_keys(&self) -> HashMap<u32, Vec<u8>> {
+        self.clients
+            .iter()
+            .map(|(id, pk)| (*id, pk.clone()))
+            .collect()
+    }
+
+    pub fn total_nodes(&self) -> u32 {
+        self.replicas.len() as u32
+    }
+
+    pub fn f(&self) -> u32 {
+        (self.total_nodes() - 1) / 3
+    }
+
+    /// Smallest set size such that any two quorums intersect in at least one
+    /// correct replica, i.e. ceil((n + f + 1) / 2). Equal to 2f + 1 when
+    /// n = 3f + 1.
+    pub fn quorum(&self) -> u32 {
+        quorum_size(self.total_nodes())
+    }
+
+    pub fn primary(&self, view: u64) -> u32 {
+        let idx = (view % self.total_nodes() as u64) as usize;
+        *self.replicas.keys().nth(idx).unwrap()
+    }
+
+    /// Public keys of every member except `own_id`.
+    pub fn peer_public_keys(&self, own_id: u32) -> HashMap<u32, Vec<u8>> {
+        self.replicas
+            .values()
+            .filter(|r| r.id != own_id)
+            .map(|r| (r.id, r.public_key.clone()))
+            .collect()
+    }
+
+    pub fn apply(&self, reconfigure: &Reconfigure) -> Result<Membership, String> {
+        let mut replicas = self.replicas.clone();
+
+        for id in &reconfigure.remove {
+            if replicas.remove(id).is_none() {
+                return Err(format!("replica {} is not a member", id));
+            }
+        }
+
+        for info in &reconfigure.add {
+            if replicas.contains_key(&info.id) {
+                return Err(format!("replica {} is already a member", info.id));
+            }
+            replicas.insert(info.id, info.clone());
+        }
+
+        check_size(&replicas)?;
+
+        let mut clients = self.clients.clone();
+
+        for id in &reconfigure.remove_clients {
+            if clients.remove(id).is_none() {
+                return Err(format!("client {} is not registered", id));
+            }
+        }
+
+        for info in &reconfigure.add_clients {
+            if clients.contains_key(&info.id) || replicas.contains_key(&info.id) {
+                return Err(format!("id {} is already registered", info.id));
+            }
+            clients.insert(info.id, info.public_key.clone());
+        }
+
+        if let Some(id) = replicas.keys().find(|id| clients.contains_key(id)) {
+            return Err(format!("id {} is registered as a client", id));
+        }
+
+        Ok(Membership {
+            epoch: self.epoch + 1,
+            replicas,
+            clients,
+            admins: self.admins.clone(),
+        })
+    }
+}
+
+/// Fewer than 3f + 1 = 4 replicas cannot tolerate a single fault.
+fn check_size(replicas: &BTreeMap<u32, ReplicaInfo>) -> Result<(), String> {
+    if replicas.len() < 4 {
+        return Err(format!(
+            "membership of {} replicas cannot tolerate a fault",
+            replicas.len()
+        ));
+    }
+    Ok(())
+}
+
+/// `Membership::quorum` for a cluster of `total_nodes` replicas.
+pub fn quorum_size(total_nodes: u32) -> u32 {
+    let f = (total_nodes - 1) / 3;
+    (total_nodes + f) / 2 + 1
+}
@@ -1,42 +1,113 @@
-use std::net::SocketAddr;
+use serde::{Deserialize, Serialize};
+use std::{net::SocketAddr, path::Path};
+
+use crate::{
+    config::logging::LoggingConfig,
+    network::{byzantine::ByzantineConfig, network_layer::NetworkConfig},
+};
 
 #[derive(Clone)]
 pub struct NodeConfig {
-    id: u32,
+    pub id: u32,
     pub bind_addr: SocketAddr,
+    pub metrics_addr: Option<SocketAddr>,
     pub peers: Vec<PeerConfig>,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct PeerConfig {
     pub id: u32,
     pub addr: SocketAddr,
+    /// Where the replica serves `/metrics`; no endpoint when unset.
+    #[serde(default)]
+    pub metrics_addr: Option<SocketAddr>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ClusterConfig {
+    pub replicas: Vec<PeerConfig>,
+    /// Ids of the clients whose keys (`keys/client_<id>.pub`) are trusted.
+    #[serde(default = "default_clients")]
+    pub clients: Vec<u32>,
+    /// Clients allowed to reconfigure the cluster, force view changes and
+    /// take snapshots. Read-only admin queries are open to every client.
+    #[serde(default)]
+    pub admins: Vec<u32>,
+    #[serde(default)]
+    pub network: NetworkConfig,
+    /// Replicas that misbehave on purpose, for demos and fault testing.
+    #[serde(default)]
+    pub byzantine: Vec<ByzantineConfig>,
+    #[serde(default)]
+    pub logging: LoggingConfig,
+}
+
+fn default_clients() -> Vec<u32> {
+    vec![100]
+}
+
+impl Default for ClusterConfig {
+    fn default() -> Self {
+        ClusterConfig::local(4)
+    }
+}
+
+impl ClusterConfig {
+    /// `nodes` replicas on localhost, replica `i` listening on port 5000 + i
+    /// and serving metrics on 9000 + i, administered by the default client.
+    pub fn local(nodes: u32) -> Self {
+        let replicas = (0..nodes)
+            .map(|id| PeerConfig {
+                id,
+                addr: SocketAddr::from(([127, 0, 0, 1], 5000 + id as u16)),
+                metrics_addr: Some(SocketAddr::from(([127, 0, 0, 1], 9000 + id as u16))),
+            })
+            .collect();
+
+        ClusterConfig {
+            replicas,
+            clients: default_clients(),
+            admins: default_clients(),
+            network: NetworkConfig::default(),
+            byzantine: Vec::new(),
+            logging: LoggingConfig::default(),
+        }
+    }
+
+    pub fn replica_ids(&self) -> Vec<u32> {
+        self.replicas.iter().map(|r| r.id).collect()
+    }
+
+    pub fn byzantine_config(&self, node_id: u32) -> Option<&ByzantineConfig> {
+        self.byzantine.iter().find(|b| b.id == node_id)
+    }
 }
 
-pub fn get_node_config(node_id: u32) -> NodeConfig {
-    // hardcoded 4 addrs for 4 nodes setup
-    let all_addrs = vec![
-        "127.0.0.1:5000",
-        "127.0.0.1:5001",
-        "127.0.0.1:5002",
-        "127.0.0.1:5003",
-    ];
+/// Loads the cluster layout from `path`, falling back to the 4 node localhost
+/// setup when the file doesn't exist.
+pub fn load_cluster_config(path: &Path) -> ClusterConfig {
+    if !path.exists() {
+        return ClusterConfig::default();
+    }
 
-    let bind_addr: SocketAddr = all_addrs[node_id as usize].parse().unwrap();
+    let contents = std::fs::read_to_string(path).expect("Failed to read cluster config");
+    toml::from_str(&contents).expect("Failed to parse cluster config")
+}
 
-    let peers: Vec<PeerConfig> = all_addrs
+pub fn get_node_config(node_id: u32, cluster: &ClusterConfig) -> Option<NodeConfig> {
+    let own = cluster.replicas.iter().find(|r| r.id == node_id)?;
+
+    let peers: Vec<PeerConfig> = cluster
+        .replicas
         .iter()
-        .enumerate()
-        .filter(|(i, _)| *i != node_id as usize)
-        .map(|(i, addr)| PeerConfig {
-            id: i as u32,
-            addr: addr.parse().unwrap(),
-        })
+        .filter(|r| r.id != node_id)
+        .cloned()
         .collect();
 
-    NodeConfig {
+    Some(NodeConfig {
         id: node_id,
-        bind_addr,
+        bind_addr: own.addr,
+        metrics_addr: own.metrics_addr,
         peers,
-    }
+    })
 }
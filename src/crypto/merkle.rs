@@ -0,0 +1,185 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Root of a tree with no leaves.
+pub const EMPTY_ROOT: [u8; 32] = [0; 32];
+
+/// Leaves and inner nodes are hashed with different prefixes, so a node can
+/// never be passed off as a leaf.
+pub fn leaf_hash(key: &str, value: &str) -> [u8; 32] {
+    let serialized = postcard::to_allocvec(&(key, value)).unwrap();
+    let mut hasher = Sha256::new();
+    hasher.update([0]);
+    hasher.update(serialized);
+    hasher.finalize().into()
+}
+
+fn node_hash(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update([1]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// Hashes one level into the next. A node without a sibling moves up
+/// unchanged.
+fn next_level(level: &[[u8; 32]]) -> Vec<[u8; 32]> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => node_hash(left, right),
+            [single] => *single,
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+pub fn root(leaves: &[[u8; 32]]) -> [u8; 32] {
+    if leaves.is_empty() {
+        return EMPTY_ROOT;
+    }
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+    level[0]
+}
+
+/// Path from one leaf to the root.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MerkleProof {
+    pub index: u64,
+    pub leaf_count: u64,
+    /// Sibling hashes from the leaf level up, skipping levels where the node
+    /// has no sibling.
+    pub siblings: Vec<[u8; 32]>,
+}
+
+impl MerkleProof {
+    /// Proof for `leaves[index]`; `index` must be in range.
+    pub fn build(leaves: &[[u8; 32]], index: usize) -> Self {
+        let mut siblings = Vec::new();
+        let mut level = leaves.to_vec();
+        let mut i = index;
+        while level.len() > 1 {
+            if let Some(sibling) = level.get(i ^ 1) {
+                siblings.push(*sibling);
+            }
+            level = next_level(&level);
+            i /= 2;
+        }
+        MerkleProof {
+            index: index as u64,
+            leaf_count: leaves.len() as u64,
+            siblings,
+        }
+    }
+
+    /// The root `leaf` hashes up to along this path, or `None` if the path
+    /// does not fit the tree shape.
+    pub fn root(&self, leaf: [u8; 32]) -> Option<[u8; 32]> {
+        if self.index >= self.leaf_count {
+            return None;
+        }
+        let mut siblings = self.siblings.iter();
+        let mut hash = leaf;
+        let mut index = self.index;
+        let mut width = self.leaf_count;
+        while width > 1 {
+            if index % 2 == 1 {
+                hash = node_hash(siblings.next()?, &hash);
+            } else if index + 1 < width {
+                hash = node_hash(&hash, siblings.next()?);
+            }
+            index /= 2;
+            width = width.div_ceil(2);
+        }
+        if siblings.next().is_some() {
+            return None;
+        }
+        Some(hash)
+    }
+}
+
+/// A key-value pair in the tree and its path to the root.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProvenLeaf {
+    pub key: String,
+    pub value: String,
+    pub proof: MerkleProof,
+}
+
+impl ProvenLeaf {
+    fn root(&self) -> Option<[u8; 32]> {
+        self.proof.root(leaf_hash(&self.key, &self.value))
+    }
+}
+
+/// Proof of a key's value, or of its absence, in a tree whose leaves are
+/// sorted by key. An absent key is proven by the leaves on either side of
+/// where it would be.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyProof {
+    pub key: String,
+    pub value: Option<String>,
+    /// Root of the tree the proof was built over.
+    pub root: [u8; 32],
+    pub leaves: Vec<ProvenLeaf>,
+}
+
+impl KeyProof {
+    /// Proof for `key` in a tree over `entries`, which must be sorted by key.
+    pub fn build<'a>(entries: impl Iterator<Item = (&'a str, &'a str)>, key: &str) -> Self {
+        let entries: Vec<(&str, &str)> = entries.collect();
+        let leaves: Vec<[u8; 32]> = entries.iter().map(|(k, v)| leaf_hash(k, v)).collect();
+        let proven = |index: usize| ProvenLeaf {
+            key: entries[index].0.to_string(),
+            value: entries[index].1.to_string(),
+            proof: MerkleProof::build(&leaves, index),
+        };
+        let tree_root = root(&leaves);
+
+        match entries.binary_search_by(|(k, _)| (*k).cmp(key)) {
+            Ok(index) => KeyProof {
+                key: key.to_string(),
+                value: Some(entries[index].1.to_string()),
+                root: tree_root,
+                leaves: vec![proven(index)],
+            },
+            Err(index) => KeyProof {
+                key: key.to_string(),
+                value: None,
+                root: tree_root,
+                leaves: (index.saturating_sub(1)..(index + 1).min(entries.len()))
+                    .map(proven)
+                    .collect(),
+            },
+        }
+    }
+
+    /// Whether the proof shows `value` for `key` under `root`.
+    pub fn verify(&self, root: &[u8; 32]) -> bool {
+        if self.root != *root || self.leaves.iter().any(|l| l.root().as_ref() != Some(root)) {
+            return false;
+        }
+
+        if let Some(value) = &self.value {
+            return matches!(self.leaves.as_slice(), [leaf] if leaf.key == self.key && &leaf.value == value);
+        }
+
+        let key = self.key.as_str();
+        match self.leaves.as_slice() {
+            [] => *root == EMPTY_ROOT,
+            [left, right] => {
+                right.proof.index == left.proof.index + 1
+                    && right.proof.leaf_count == left.proof.leaf_count
+                    && left.key.as_str() < key
+                    && key < right.key.as_str()
+            }
+            [only] if only.proof.index == 0 && key < only.key.as_str() => true,
+            [only] => only.proof.index + 1 == only.proof.leaf_count && only.key.as_str() < key,
+            _ => false,
+        }
+    }
+}
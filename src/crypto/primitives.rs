@@ -4,17 +4,17 @@ use ring::{
     signature::{ED25519, Ed25519KeyPair, KeyPair, UnparsedPublicKey},
 };
 use serde::Serialize;
-use std::{collections::HashMap, path::Path};
+use std::{collections::HashMap, io, path::Path};
 use tokio::fs;
+use tracing::warn;
 
-use crate::message::message_types::{
-    Commit, PBFTMessage, PrePrepare, Prepare, Request, SignedMessage,
-};
+use crate::message::message_types::{Evidence, PBFTMessage, SignedMessage};
 
 pub struct Crypto {
     keypair: Ed25519KeyPair,
     id: u32,
     peer_public_keys: HashMap<u32, Vec<u8>>,
+    client_public_keys: HashMap<u32, Vec<u8>>,
 }
 
 impl Crypto {
@@ -23,6 +23,7 @@ impl Crypto {
             keypair,
             id,
             peer_public_keys,
+            client_public_keys: HashMap::new(),
         }
     }
 
@@ -51,12 +52,25 @@ impl Crypto {
         }
     }
 
+    pub fn set_peer_public_keys(&mut self, peer_public_keys: HashMap<u32, Vec<u8>>) {
+        self.peer_public_keys = peer_public_keys;
+    }
+
+    pub fn set_client_public_keys(&mut self, client_public_keys: HashMap<u32, Vec<u8>>) {
+        self.client_public_keys = client_public_keys;
+    }
+
     pub fn verify_signed_message<T: Serialize>(&self, signed_msg: &SignedMessage<T>) -> bool {
-        let pk_bytes = match self.peer_public_keys.get(&signed_msg.signer_id) {
-            Some(pk) => pk,
-            None => return false,
-        };
+        if signed_msg.signer_id == self.id {
+            return Self::verify_with_key(&self.get_pub_key(), signed_msg);
+        }
+        match self.peer_public_keys.get(&signed_msg.signer_id) {
+            Some(pk) => Self::verify_with_key(pk, signed_msg),
+            None => false,
+        }
+    }
 
+    pub fn verify_with_key<T: Serialize>(pk_bytes: &[u8], signed_msg: &SignedMessage<T>) -> bool {
         let serialized = match postcard::to_allocvec(&signed_msg.message) {
             Ok(data) => data,
             Err(_) => return false,
@@ -68,31 +82,42 @@ impl Crypto {
 }
 
 impl Crypto {
-    fn sign_request(&self, request: Request) -> SignedMessage<Request> {
-        self.create_signed_message(request)
-    }
-
-    fn sign_pre_prepare(&self, pre_prepare: PrePrepare) -> SignedMessage<PrePrepare> {
-        self.create_signed_message(pre_prepare)
-    }
-
-    fn sign_prepare(&self, prepare: Prepare) -> SignedMessage<Prepare> {
-        self.create_signed_message(prepare)
+    /// Checks a message a client signed on its own behalf.
+    pub fn verify_client_message<T: Serialize>(
+        &self,
+        signed_msg: &SignedMessage<T>,
+        client_id: u64,
+    ) -> bool {
+        let Some(pk) = self.client_public_keys.get(&signed_msg.signer_id) else {
+            warn!(
+                client = signed_msg.signer_id,
+                "Rejecting request from unknown client"
+            );
+            return false;
+        };
+        if client_id != signed_msg.signer_id as u64 {
+            warn!(
+                client = client_id,
+                signer = signed_msg.signer_id,
+                "Rejecting request signed by another client"
+            );
+            return false;
+        }
+        Self::verify_with_key(pk, signed_msg)
     }
 
-    fn sign_commit(&self, commit: Commit) -> SignedMessage<Commit> {
-        self.create_signed_message(commit)
+    /// Whether `evidence` really shows a replica signing two conflicting
+    /// pre-prepares.
+    pub fn verify_evidence(&self, evidence: &Evidence) -> bool {
+        evidence.is_conflicting()
+            && self.verify_signed_message(&evidence.first)
+            && self.verify_signed_message(&evidence.second)
     }
 
     pub fn verify_pbft_message(&self, message: &PBFTMessage) -> bool {
         match message {
             PBFTMessage::Request(request) => {
-                // for demo purposes, we will accept all requests
-                if !self.peer_public_keys.contains_key(&request.signer_id) {
-                    println!("Unknown client {}, accepting request", request.signer_id);
-                    return true;
-                }
-                self.verify_signed_message(request)
+                self.verify_client_message(request, request.message.client_id)
             }
             PBFTMessage::PrePrepare(pre_prepare) => self.verify_signed_message(pre_prepare),
             PBFTMessage::Prepare(prepare) => self.verify_signed_message(prepare),
@@ -100,47 +125,89 @@ impl Crypto {
             PBFTMessage::Reply(reply) => self.verify_signed_message(reply),
             PBFTMessage::ViewChange(view_change) => self.verify_signed_message(view_change),
             PBFTMessage::NewView(new_view) => self.verify_signed_message(new_view),
+            PBFTMessage::Evidence(evidence) => {
+                self.verify_signed_message(evidence.as_ref())
+                    && self.verify_evidence(&evidence.message)
+            }
+            PBFTMessage::AdminRequest(request) => {
+                self.verify_client_message(request, request.message.client_id)
+            }
+            PBFTMessage::AdminResponse(response) => self.verify_signed_message(response),
         }
     }
 }
 
-pub async fn setup_crypto_for_node(node_id: u32) -> (Crypto, HashMap<u32, Vec<u8>>) {
-    let keys_dir = Path::new("keys");
+/// Writes a fresh key pair to `<keys_dir>/<name>.key` (PKCS#8) and
+/// `<name>.pub`. Returns false without touching anything when the private key
+/// already exists.
+pub async fn generate_key_files(keys_dir: &Path, name: &str) -> io::Result<bool> {
+    let key_path = keys_dir.join(format!("{}.key", name));
+    if key_path.exists() {
+        return Ok(false);
+    }
+
+    let pkcs8 = Crypto::generate_keypair();
+    let keypair = Ed25519KeyPair::from_pkcs8(&pkcs8).expect("Generated key is valid");
+    fs::create_dir_all(keys_dir).await?;
+    fs::write(&key_path, &pkcs8).await?;
+    fs::write(
+        keys_dir.join(format!("{}.pub", name)),
+        keypair.public_key().as_ref(),
+    )
+    .await?;
+    Ok(true)
+}
 
-    let my_key_path = keys_dir.join(format!("node_{}.key", node_id));
-    let my_pub_path = keys_dir.join(format!("node_{}.pub", node_id));
+/// Reads `keys/<name>.key`, the PKCS#8 document written by `keygen`.
+pub async fn load_private_key(name: &str) -> Vec<u8> {
+    let key_path = Path::new("keys").join(format!("{}.key", name));
 
-    if !my_key_path.exists() {
+    if !key_path.exists() {
         panic!("Keys not found! Run 'cargo run --bin keygen' first");
     }
 
-    let pkcs8_bytes = fs::read(&my_key_path)
+    fs::read(&key_path)
         .await
-        .expect("Failed to read private key");
-
-    let my_keypair = Ed25519KeyPair::from_pkcs8(&pkcs8_bytes).expect("Failed to parse keypair");
+        .expect("Failed to read private key")
+}
 
-    let mut peer_public_keys = HashMap::new();
-    for peer_id in 0..4 {
-        if peer_id == node_id {
-            continue;
-        }
+/// Reads `keys/<prefix>_<id>.pub` for every id.
+pub async fn load_public_keys(prefix: &str, ids: &[u32]) -> HashMap<u32, Vec<u8>> {
+    let keys_dir = Path::new("keys");
 
-        let peer_pub_path = keys_dir.join(format!("node_{}.pub", peer_id));
+    let mut public_keys = HashMap::new();
+    for &id in ids {
+        let pub_path = keys_dir.join(format!("{}_{}.pub", prefix, id));
 
-        if !peer_pub_path.exists() {
+        if !pub_path.exists() {
             panic!(
-                "Peer {} public key not found! Run 'cargo run --bin keygen' first",
-                peer_id
+                "Public key {:?} not found! Run 'cargo run --bin keygen' first",
+                pub_path
             );
         }
 
-        let pub_key = fs::read(&peer_pub_path)
+        let pub_key = fs::read(&pub_path)
             .await
-            .expect(&format!("Failed to read public key for peer {}", peer_id));
+            .unwrap_or_else(|_| panic!("Failed to read public key {:?}", pub_path));
 
-        peer_public_keys.insert(peer_id, pub_key);
+        public_keys.insert(id, pub_key);
     }
+    public_keys
+}
+
+pub async fn setup_crypto_for_node(
+    node_id: u32,
+    replica_ids: &[u32],
+) -> (Crypto, HashMap<u32, Vec<u8>>) {
+    let pkcs8_bytes = load_private_key(&format!("node_{}", node_id)).await;
+    let my_keypair = Ed25519KeyPair::from_pkcs8(&pkcs8_bytes).expect("Failed to parse keypair");
+
+    let peer_ids: Vec<u32> = replica_ids
+        .iter()
+        .copied()
+        .filter(|&id| id != node_id)
+        .collect();
+    let peer_public_keys = load_public_keys("node", &peer_ids).await;
 
     let crypto = Crypto::new(my_keypair, node_id, peer_public_keys.clone());
 
@@ -1,8 +1,12 @@
+pub mod client;
 pub mod config;
 pub mod crypto;
 pub mod message;
+pub mod metrics;
 pub mod network;
+pub mod sim;
 pub mod state;
+pub mod trace;
 
 pub use config::*;
 pub use crypto::*;
@@ -1,10 +1,41 @@
+use ring::signature::Ed25519KeyPair;
 use simple_pbft_demo::{
-    config::node::get_node_config,
-    crypto::primitives::setup_crypto_for_node,
-    network::{cert::NodeCert, network_layer::Network},
-    state::replica::Replica,
+    config::{
+        membership::Membership,
+        node::{ClusterConfig, get_node_config, load_cluster_config},
+    },
+    crypto::primitives::{Crypto, load_private_key, load_public_keys, setup_crypto_for_node},
+    message::message_types::{ClientInfo, ReplicaInfo},
+    metrics::server::{MetricsServerConfig, spawn_metrics_server},
+    network::{
+        byzantine::ByzantineTransport,
+        cert::{NodeCert, PinnedKeys, replica_server_name},
+        network_layer::Network,
+    },
+    state::{
+        certificates::FileCertificateStore, events::format_event_line, ledger::FileLedger,
+        replica::Replica, snapshots::FileSnapshotStore,
+    },
+    trace::record::TraceRecorder,
 };
-use std::env;
+use std::{
+    collections::HashMap,
+    env,
+    fs::OpenOptions,
+    io::{LineWriter, Write},
+    path::Path,
+};
+use tracing::{Instrument, debug, error, info, info_span, warn};
+
+/// File to append commit, execution and view events to, for `invariants`.
+const EVENT_LOG_ENV: &str = "PBFT_EVENT_LOG";
+
+/// File to record a replay trace of everything the replica receives and
+/// sends to.
+const TRACE_ENV: &str = "PBFT_TRACE";
+
+/// Directory under which each node keeps its files, `data` by default.
+const DATA_DIR_ENV: &str = "PBFT_DATA_DIR";
 
 #[tokio::main]
 async fn main() {
@@ -19,59 +50,153 @@ async fn main() {
 
     let node_id: u32 = args[1].parse().unwrap();
 
-    if node_id > 3 {
+    let cluster = load_cluster_config(Path::new("cluster.toml"));
+    cluster.logging.clone().from_env().init();
+
+    run(node_id, cluster)
+        .instrument(info_span!("node", id = node_id))
+        .await;
+}
+
+async fn run(node_id: u32, cluster: ClusterConfig) {
+    let Some(config) = get_node_config(node_id, &cluster) else {
+        error!("Node is not part of the cluster config");
         std::process::exit(1);
-    }
+    };
 
-    println!("Starting node {}...", node_id);
+    info!("Starting node");
+    let (crypto, peer_pk) = setup_crypto_for_node(node_id, &cluster.replica_ids()).await;
+    let mut membership = Membership::new(
+        cluster
+            .replicas
+            .iter()
+            .map(|r| ReplicaInfo {
+                id: r.id,
+                public_key: peer_pk
+                    .get(&r.id)
+                    .cloned()
+                    .unwrap_or_else(|| crypto.get_pub_key()),
+                addr: r.addr,
+            })
+            .collect(),
+        load_public_keys("client", &cluster.clients)
+            .await
+            .into_iter()
+            .map(|(id, public_key)| ClientInfo { id, public_key })
+            .collect(),
+    )
+    .unwrap_or_else(|e| {
+        error!(error = %e, "Invalid cluster config");
+        std::process::exit(1);
+    });
+    membership.set_admins(cluster.admins.iter().copied());
 
-    let config = get_node_config(node_id);
-    let (crypto, peer_pk) = setup_crypto_for_node(node_id).await;
-    let certs = NodeCert::generate(node_id);
-    let mut network = Network::new(node_id, config.bind_addr, &certs, 4);
+    let pinned = PinnedKeys::new(
+        membership
+            .replicas()
+            .map(|r| (r.id, r.public_key.clone()))
+            .collect(),
+        membership.client_public_keys(),
+    );
+    let pkcs8 = load_private_key(&format!("node_{}", node_id)).await;
+    let certs = NodeCert::from_pkcs8(replica_server_name(node_id), &pkcs8);
+    let network = Network::new(
+        node_id,
+        config.bind_addr,
+        &certs,
+        pinned,
+        cluster.network.clone(),
+        membership.total_nodes(),
+    );
     network.spawn_acceptor();
-    println!("Node {} listening on {}", node_id, config.bind_addr);
-    let mut replica = Replica::new(node_id, 4, crypto);
+    info!(bind_addr = %config.bind_addr, "Node listening");
+    let mut replica = Replica::new(node_id, membership.clone(), crypto);
+    replica.set_metrics(network.metrics());
+    if let Some(addr) = config.metrics_addr
+        && let Err(e) =
+            spawn_metrics_server(addr, network.metrics(), MetricsServerConfig::default()).await
+    {
+        error!(%addr, error = %e, "Failed to serve metrics");
+    }
 
-    println!("Waiting for other nodes to start...");
-    tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
+    let data_dir = Path::new(&env::var(DATA_DIR_ENV).unwrap_or_else(|_| "data".to_string()))
+        .join(format!("node_{}", node_id));
+    let certificates_path = data_dir.join("certificates.bin");
+    let certificates = FileCertificateStore::open(&certificates_path).unwrap_or_else(|e| {
+        panic!(
+            "Failed to open certificate store {:?}: {}",
+            certificates_path, e
+        )
+    });
+    replica.set_certificate_store(Box::new(certificates));
+    info!(path = ?certificates_path, "Storing commit certificates");
 
-    println!("Connecting to peers...");
-    for peer in &config.peers {
-        println!("  Connecting to peer {} at {}...", peer.id, peer.addr);
-
-        let mut retries = 5;
-        loop {
-            match network
-                .connect_to_peer_with_timeout(peer.id, peer.addr)
-                .await
-            {
-                Ok(_) => {
-                    println!("Connected to peer {}", peer.id);
-                    break;
-                }
-                Err(e) => {
-                    retries -= 1;
-                    if retries == 0 {
-                        eprintln!("Failed to connect to peer {}: {:?}", peer.id, e);
-                        eprintln!("Make sure node {} is running!", peer.id);
-                        std::process::exit(1);
-                    }
-                    println!(
-                        "Retrying connection to peer {} ({} attempts left)...",
-                        peer.id, retries
-                    );
-                    tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
-                }
+    let ledger_path = data_dir.join("ledger.bin");
+    let ledger = FileLedger::open(&ledger_path)
+        .unwrap_or_else(|e| panic!("Failed to open ledger {:?}: {}", ledger_path, e));
+    replica.set_ledger(Box::new(ledger));
+    info!(path = ?ledger_path, "Appending executed requests to ledger");
+
+    let snapshot_path = data_dir.join("snapshot.bin");
+    let snapshots = FileSnapshotStore::open(&snapshot_path)
+        .unwrap_or_else(|e| panic!("Failed to open snapshot store {:?}: {}", snapshot_path, e));
+    replica.set_snapshot_store(Box::new(snapshots));
+
+    if let Ok(path) = env::var(EVENT_LOG_ENV) {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .unwrap_or_else(|e| panic!("Failed to open event log {}: {}", path, e));
+        let mut log = LineWriter::new(file);
+        replica.set_observer(Box::new(move |event| {
+            if let Err(e) = writeln!(log, "{}", format_event_line(node_id, event)) {
+                error!(error = %e, "Failed to write event log");
             }
-        }
+        }));
+        info!(%path, "Writing replica events");
     }
 
-    println!(
-        "Node {} ready! (Primary: {})",
-        node_id,
-        replica.is_primary()
+    if let Ok(path) = env::var(TRACE_ENV) {
+        let recorder = TraceRecorder::create(Path::new(&path), node_id, replica.clock())
+            .unwrap_or_else(|e| panic!("Failed to create trace {}: {}", path, e));
+        replica.set_recorder(recorder);
+        info!(%path, "Recording protocol trace");
+    }
+
+    for peer in &config.peers {
+        debug!(peer = peer.id, addr = %peer.addr, "Managing connection to peer");
+        network.add_peer(peer.id, peer.addr);
+    }
+
+    let last_executed = replica.recover(&network).await;
+    if last_executed > 0 {
+        info!(
+            last_executed,
+            "Recovered executed requests from the data directory"
+        );
+    }
+
+    // With a quorum of replicas (ourselves included) reachable the protocol
+    // can make progress; the rest join whenever their handshake completes.
+    let needed = replica.membership().quorum() as usize - 1;
+    info!(needed, "Waiting for a quorum of peers");
+    network.wait_for_peers(needed).await;
+
+    info!(
+        primary = replica.is_primary(),
+        peers = ?network.connected_peers().await,
+        "Node ready"
     );
 
-    Replica::run_replica(network, replica, config).await;
+    match cluster.byzantine_config(node_id) {
+        Some(byzantine) if !byzantine.behaviors.is_empty() => {
+            warn!(behaviors = ?byzantine.behaviors, "Node is Byzantine");
+            let keypair = Ed25519KeyPair::from_pkcs8(&pkcs8).expect("Failed to parse keypair");
+            let crypto = Crypto::new(keypair, node_id, HashMap::new());
+            let network = ByzantineTransport::new(network, byzantine.clone(), crypto, &membership);
+            Replica::run_replica(network, replica).await;
+        }
+        _ => Replica::run_replica(network, replica).await,
+    }
 }
@@ -1,18 +1,54 @@
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+};
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+use crate::crypto::{merkle::KeyProof, primitives::Crypto};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Request {
     pub operation: Vec<u8>,
     pub timestamp: u64,
     pub client_id: u64,
 }
 
+impl Request {
+    /// No-op the primary of a new view uses to fill sequence numbers that no
+    /// replica could prove prepared.
+    pub fn null() -> Self {
+        Request {
+            operation: Vec::new(),
+            timestamp: 0,
+            client_id: 0,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        self.operation.is_empty() && self.client_id == 0
+    }
+
+    /// Client and timestamp, which together identify a request.
+    pub fn id(&self) -> (u64, u64) {
+        (self.client_id, self.timestamp)
+    }
+
+    /// SHA-256 of the serialized request, as carried in pre-prepares.
+    pub fn digest(&self) -> [u8; 32] {
+        let serialized = postcard::to_allocvec(self).unwrap();
+        Sha256::digest(&serialized).into()
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct PrePrepare {
     pub view: u64,
     pub seq_num: u64,
     pub digest: [u8; 32],
-    pub request: Request,
+    /// As signed by the client, so that backups can tell the primary did
+    /// not make it up.
+    pub request: SignedMessage<Request>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -33,11 +69,15 @@ pub struct Commit {
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Reply {
-    view: u64,
-    timestamp: u64,
-    client_id: u64,
-    replica_id: u32,
-    result: Vec<u8>,
+    pub view: u64,
+    /// Sequence number the request was executed at.
+    pub seq_num: u64,
+    pub timestamp: u64,
+    pub client_id: u64,
+    pub replica_id: u32,
+    pub result: Vec<u8>,
+    /// `AppState::digest` right after executing the request.
+    pub state_digest: [u8; 32],
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -47,27 +87,273 @@ pub struct SignedMessage<T> {
     pub signer_id: u32,
 }
 
+impl SignedMessage<Request> {
+    /// The null request. No client signs it, so it carries no signature.
+    pub fn null() -> Self {
+        SignedMessage {
+            message: Request::null(),
+            signature: Vec::new(),
+            signer_id: 0,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ViewChange {
     pub new_view: u64,
+    /// Highest sequence number the sender has executed. The new view starts
+    /// from the largest such value in the quorum, less a fixed window.
+    pub last_executed: u64,
+    /// Commit certificate for `last_executed`, so that no replica can push
+    /// the new view past slots it never executed. `None` only when
+    /// `last_executed` is 0.
+    pub executed_certificate: Option<CommitCertificate>,
     pub prepared_requests: Vec<PreparedProof>,
     pub replica_id: u32,
 }
 
+/// Shows a slot prepared: the pre-prepare signed by its view's primary and
+/// matching prepares signed by enough distinct backups.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct PreparedProof {
-    pub pre_prepare: PrePrepare,
-    pub prepares: Vec<Prepare>,
+    pub pre_prepare: SignedMessage<PrePrepare>,
+    pub prepares: Vec<SignedMessage<Prepare>>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct NewView {
     pub new_view: u64,
-    pub view_change_msgs: Vec<ViewChange>,
-    pub pre_prepares: Vec<PrePrepare>,
+    pub view_change_msgs: Vec<SignedMessage<ViewChange>>,
+    /// Signed one by one, so each can later stand in a `PreparedProof`.
+    pub pre_prepares: Vec<SignedMessage<PrePrepare>>,
     pub replica_id: u32,
 }
 
+/// One replica's signature over the `Commit` a `CommitCertificate` stands for.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommitSignature {
+    pub replica_id: u32,
+    pub signature: Vec<u8>,
+}
+
+/// A quorum of signed commits for one slot. The commits only differ in who
+/// sent them, so the shared fields are stored once.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommitCertificate {
+    pub view: u64,
+    pub seq_num: u64,
+    pub digest: [u8; 32],
+    pub signatures: Vec<CommitSignature>,
+}
+
+impl CommitCertificate {
+    /// The signed commits the certificate was built from.
+    pub fn commits(&self) -> impl Iterator<Item = SignedMessage<Commit>> + '_ {
+        self.signatures.iter().map(|s| SignedMessage {
+            message: Commit {
+                view: self.view,
+                seq_num: self.seq_num,
+                digest: self.digest,
+                replica_id: s.replica_id,
+            },
+            signature: s.signature.clone(),
+            signer_id: s.replica_id,
+        })
+    }
+
+    /// Whether at least `quorum` distinct replicas with a key in
+    /// `replica_keys` validly signed the commit.
+    pub fn verify(&self, replica_keys: &HashMap<u32, Vec<u8>>, quorum: usize) -> bool {
+        let mut signers = HashSet::new();
+        for commit in self.commits() {
+            let Some(pk) = replica_keys.get(&commit.signer_id) else {
+                return false;
+            };
+            if !Crypto::verify_with_key(pk, &commit) || !signers.insert(commit.signer_id) {
+                return false;
+            }
+        }
+        signers.len() >= quorum
+    }
+}
+
+/// An executed request together with the proof that it was committed at its
+/// sequence number.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CertifiedRequest {
+    pub request: Request,
+    pub certificate: CommitCertificate,
+}
+
+impl CertifiedRequest {
+    pub fn verify(&self, replica_keys: &HashMap<u32, Vec<u8>>, quorum: usize) -> bool {
+        self.request.digest() == self.certificate.digest
+            && self.certificate.verify(replica_keys, quorum)
+    }
+}
+
+/// One executed sequence number in a replica's ledger. Each entry commits to
+/// the one before it through `prev_hash`, so rewriting any executed request
+/// changes the hash of every later entry.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LedgerEntry {
+    pub seq_num: u64,
+    /// View the replica saw the request commit in. A slot re-agreed after a
+    /// view change can commit in different views at different replicas, so
+    /// the view is kept for information but left out of `hash`.
+    pub view: u64,
+    pub request_digest: [u8; 32],
+    pub result_digest: [u8; 32],
+    /// `hash()` of the entry for `seq_num - 1`, or zeros for the first one.
+    pub prev_hash: [u8; 32],
+    /// `AppState::digest` after executing the request.
+    pub state_digest: [u8; 32],
+}
+
+impl LedgerEntry {
+    /// Hash over everything but the view, so that correct replicas build
+    /// the same chain.
+    pub fn hash(&self) -> [u8; 32] {
+        let hashed = (
+            self.seq_num,
+            self.request_digest,
+            self.result_digest,
+            self.prev_hash,
+            self.state_digest,
+        );
+        let serialized = postcard::to_allocvec(&hashed).unwrap();
+        Sha256::digest(&serialized).into()
+    }
+
+    /// Whether this entry directly follows `prev` in the chain.
+    pub fn follows(&self, prev: &LedgerEntry) -> bool {
+        self.seq_num == prev.seq_num + 1 && self.prev_hash == prev.hash()
+    }
+}
+
+/// Proof that a primary equivocated: two pre-prepares it signed for the same
+/// view and sequence number but with different digests.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Evidence {
+    pub first: SignedMessage<PrePrepare>,
+    pub second: SignedMessage<PrePrepare>,
+}
+
+impl Evidence {
+    pub fn culprit(&self) -> u32 {
+        self.first.signer_id
+    }
+
+    pub fn view(&self) -> u64 {
+        self.first.message.view
+    }
+
+    pub fn seq_num(&self) -> u64 {
+        self.first.message.seq_num
+    }
+
+    /// Whether the two messages actually conflict. Their signatures are
+    /// checked by `Crypto::verify_evidence`.
+    pub fn is_conflicting(&self) -> bool {
+        let (first, second) = (&self.first.message, &self.second.message);
+        self.first.signer_id == self.second.signer_id
+            && first.view == second.view
+            && first.seq_num == second.seq_num
+            && first.digest != second.digest
+    }
+}
+
+/// Question an operator asks, or command an operator gives, a single replica.
+/// Answered directly, outside consensus, so the answer is only as
+/// trustworthy as that replica.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AdminQuery {
+    /// Equivocation evidence the replica has collected.
+    Evidence,
+    /// The executed request at a sequence number and its commit certificate.
+    Certificate { seq_num: u64 },
+    /// Ledger entries for sequence numbers `from..=to`, at most
+    /// `MAX_LEDGER_QUERY` of them.
+    Ledger { from: u64, to: u64 },
+    /// Proof of a key's current value, or its absence, against the state
+    /// digest.
+    Proof { key: String },
+    /// What the replica is doing right now.
+    Status,
+    /// Start a view change to the next view, as if the timer had expired.
+    ForceViewChange,
+    /// Write a snapshot of the application state now.
+    Snapshot,
+}
+
+/// Most ledger entries returned for one `AdminQuery::Ledger`.
+pub const MAX_LEDGER_QUERY: u64 = 1024;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AdminRequest {
+    pub client_id: u64,
+    /// Echoed in the response, to match it to the request.
+    pub nonce: u64,
+    pub query: AdminQuery,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum AdminResult {
+    Evidence(Vec<Evidence>),
+    /// `None` if the replica has not executed that sequence number.
+    Certificate(Option<CertifiedRequest>),
+    /// The requested entries the replica has, in order.
+    Ledger(Vec<LedgerEntry>),
+    /// Taken right after executing `seq_num`.
+    Proof {
+        seq_num: u64,
+        proof: KeyProof,
+    },
+    Status(ReplicaStatus),
+    /// The view the replica is now trying to move to.
+    ViewChange {
+        new_view: u64,
+    },
+    /// The snapshot just written.
+    Snapshot {
+        seq_num: u64,
+        state_digest: [u8; 32],
+    },
+    /// The command could not be carried out.
+    Failed(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplicaStatus {
+    pub view: u64,
+    pub primary: u32,
+    pub next_seq_num: u64,
+    pub last_executed: u64,
+    /// Sequence number of the latest snapshot. There is no checkpoint
+    /// protocol, so this is only what this replica saved, not a state a
+    /// quorum vouched for.
+    pub last_snapshot: Option<u64>,
+    pub in_view_change: bool,
+    /// The view being moved to while `in_view_change`.
+    pub pending_view: Option<u64>,
+    /// Whether the outgoing connection to each peer is up, by peer id.
+    pub peers: Vec<(u32, bool)>,
+    /// Sequence numbers in the message log.
+    pub message_log: u64,
+    pub pending_requests: u64,
+    /// Messages held for a view not entered yet.
+    pub deferred: u64,
+    pub evidence: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AdminResponse {
+    pub replica_id: u32,
+    pub client_id: u64,
+    pub nonce: u64,
+    pub result: AdminResult,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum PBFTMessage {
     Request(SignedMessage<Request>),
@@ -77,4 +363,159 @@ pub enum PBFTMessage {
     Reply(SignedMessage<Reply>),
     ViewChange(SignedMessage<ViewChange>),
     NewView(SignedMessage<NewView>),
+    /// Signed by the replica reporting the evidence, not the culprit. Boxed,
+    /// as it is far larger than the other messages and rarely sent.
+    Evidence(Box<SignedMessage<Evidence>>),
+    AdminRequest(SignedMessage<AdminRequest>),
+    AdminResponse(SignedMessage<AdminResponse>),
+}
+
+/// `PBFTMessage` variant without its payload. The discriminants match the
+/// postcard variant tags, so the kind of a frame can be read off its first byte.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum MessageKind {
+    Request = 0,
+    PrePrepare = 1,
+    Prepare = 2,
+    Commit = 3,
+    Reply = 4,
+    ViewChange = 5,
+    NewView = 6,
+    Evidence = 7,
+    AdminRequest = 8,
+    AdminResponse = 9,
+}
+
+impl MessageKind {
+    pub const ALL: [MessageKind; 10] = [
+        MessageKind::Request,
+        MessageKind::PrePrepare,
+        MessageKind::Prepare,
+        MessageKind::Commit,
+        MessageKind::Reply,
+        MessageKind::ViewChange,
+        MessageKind::NewView,
+        MessageKind::Evidence,
+        MessageKind::AdminRequest,
+        MessageKind::AdminResponse,
+    ];
+
+    /// Lowercase name, as used in metric labels.
+    pub fn name(self) -> &'static str {
+        match self {
+            MessageKind::Request => "request",
+            MessageKind::PrePrepare => "pre_prepare",
+            MessageKind::Prepare => "prepare",
+            MessageKind::Commit => "commit",
+            MessageKind::Reply => "reply",
+            MessageKind::ViewChange => "view_change",
+            MessageKind::NewView => "new_view",
+            MessageKind::Evidence => "evidence",
+            MessageKind::AdminRequest => "admin_request",
+            MessageKind::AdminResponse => "admin_response",
+        }
+    }
+
+    pub fn from_tag(tag: u8) -> Option<Self> {
+        match tag {
+            0 => Some(MessageKind::Request),
+            1 => Some(MessageKind::PrePrepare),
+            2 => Some(MessageKind::Prepare),
+            3 => Some(MessageKind::Commit),
+            4 => Some(MessageKind::Reply),
+            5 => Some(MessageKind::ViewChange),
+            6 => Some(MessageKind::NewView),
+            7 => Some(MessageKind::Evidence),
+            8 => Some(MessageKind::AdminRequest),
+            9 => Some(MessageKind::AdminResponse),
+            _ => None,
+        }
+    }
+}
+
+impl PBFTMessage {
+    pub fn kind(&self) -> MessageKind {
+        match self {
+            PBFTMessage::Request(_) => MessageKind::Request,
+            PBFTMessage::PrePrepare(_) => MessageKind::PrePrepare,
+            PBFTMessage::Prepare(_) => MessageKind::Prepare,
+            PBFTMessage::Commit(_) => MessageKind::Commit,
+            PBFTMessage::Reply(_) => MessageKind::Reply,
+            PBFTMessage::ViewChange(_) => MessageKind::ViewChange,
+            PBFTMessage::NewView(_) => MessageKind::NewView,
+            PBFTMessage::Evidence(_) => MessageKind::Evidence,
+            PBFTMessage::AdminRequest(_) => MessageKind::AdminRequest,
+            PBFTMessage::AdminResponse(_) => MessageKind::AdminResponse,
+        }
+    }
+
+    pub fn signer_id(&self) -> u32 {
+        match self {
+            PBFTMessage::Request(m) => m.signer_id,
+            PBFTMessage::PrePrepare(m) => m.signer_id,
+            PBFTMessage::Prepare(m) => m.signer_id,
+            PBFTMessage::Commit(m) => m.signer_id,
+            PBFTMessage::Reply(m) => m.signer_id,
+            PBFTMessage::ViewChange(m) => m.signer_id,
+            PBFTMessage::NewView(m) => m.signer_id,
+            PBFTMessage::Evidence(m) => m.signer_id,
+            PBFTMessage::AdminRequest(m) => m.signer_id,
+            PBFTMessage::AdminResponse(m) => m.signer_id,
+        }
+    }
+
+    /// Replica id named inside the message body, for messages that carry one.
+    pub fn replica_id(&self) -> Option<u32> {
+        match self {
+            PBFTMessage::Request(_)
+            | PBFTMessage::PrePrepare(_)
+            | PBFTMessage::Evidence(_)
+            | PBFTMessage::AdminRequest(_) => None,
+            PBFTMessage::Prepare(m) => Some(m.message.replica_id),
+            PBFTMessage::Commit(m) => Some(m.message.replica_id),
+            PBFTMessage::Reply(m) => Some(m.message.replica_id),
+            PBFTMessage::ViewChange(m) => Some(m.message.replica_id),
+            PBFTMessage::NewView(m) => Some(m.message.replica_id),
+            PBFTMessage::AdminResponse(m) => Some(m.message.replica_id),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplicaInfo {
+    pub id: u32,
+    pub public_key: Vec<u8>,
+    pub addr: SocketAddr,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientInfo {
+    pub id: u32,
+    pub public_key: Vec<u8>,
+}
+
+/// Membership change ordered through consensus like any other request. It is
+/// carried in `Request::operation` and takes effect right after the sequence
+/// number it is executed at.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Reconfigure {
+    pub add: Vec<ReplicaInfo>,
+    pub remove: Vec<u32>,
+    pub add_clients: Vec<ClientInfo>,
+    pub remove_clients: Vec<u32>,
+}
+
+impl Reconfigure {
+    const OPERATION_PREFIX: &'static [u8] = b"RECONFIGURE:";
+
+    pub fn to_operation(&self) -> Vec<u8> {
+        let mut operation = Self::OPERATION_PREFIX.to_vec();
+        operation.extend(postcard::to_allocvec(self).unwrap());
+        operation
+    }
+
+    pub fn from_operation(operation: &[u8]) -> Option<Self> {
+        let payload = operation.strip_prefix(Self::OPERATION_PREFIX)?;
+        postcard::from_bytes(payload).ok()
+    }
 }
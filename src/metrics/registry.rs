@@ -0,0 +1,339 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use crate::{
+    message::message_types::MessageKind,
+    network::{cert::PeerIdentity, framing::FrameStats, inbound::QueueStats},
+};
+
+/// Upper bounds, in seconds, of the commit latency histogram buckets.
+pub const LATENCY_BUCKETS: [f64; 12] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+/// Counters per `MessageKind`, indexed by its discriminant.
+#[derive(Default)]
+pub struct KindCounters {
+    counts: [AtomicU64; MessageKind::ALL.len()],
+}
+
+impl KindCounters {
+    pub fn inc(&self, kind: MessageKind) {
+        self.counts[kind as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self, kind: MessageKind) -> u64 {
+        self.counts[kind as usize].load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Default)]
+pub struct Histogram {
+    /// Observations at or below each bound in `LATENCY_BUCKETS`, not
+    /// cumulative; `render` adds them up.
+    buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    pub fn observe(&self, value: Duration) {
+        let seconds = value.as_secs_f64();
+        if let Some(i) = LATENCY_BUCKETS.iter().position(|&b| seconds <= b) {
+            self.buckets[i].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(value.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+}
+
+/// Messages received over one authenticated connection identity.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PeerStats {
+    pub accepted: u64,
+    /// Frames that couldn't be decoded and messages not sent by their
+    /// claimed signer.
+    pub rejected: u64,
+}
+
+/// Everything a replica exposes on `/metrics`. Shared between the replica,
+/// its network and the HTTP server; every update is a single atomic store.
+#[derive(Default)]
+pub struct Metrics {
+    pub messages_received: KindCounters,
+    pub messages_sent: KindCounters,
+    pub signature_failures: AtomicU64,
+    pub requests_ordered: AtomicU64,
+    pub requests_executed: AtomicU64,
+    /// From the first message seen for a sequence number to its commit.
+    pub commit_latency: Histogram,
+    pub view: AtomicU64,
+    pub view_changes: AtomicU64,
+    pub log_size: AtomicU64,
+    pub last_executed: AtomicU64,
+    /// Inbound frames refused before decoding, by reason.
+    pub frames_rejected: FrameStats,
+    peers_connected: Mutex<BTreeMap<u32, bool>>,
+    peer_messages: Mutex<BTreeMap<PeerIdentity, PeerStats>>,
+    inbound_queues: Mutex<BTreeMap<PeerIdentity, QueueStats>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_peer_connected(&self, peer_id: u32, connected: bool) {
+        self.peers_connected
+            .lock()
+            .unwrap()
+            .insert(peer_id, connected);
+    }
+
+    pub fn remove_peer(&self, peer_id: u32) {
+        self.peers_connected.lock().unwrap().remove(&peer_id);
+    }
+
+    /// Whether the outgoing connection to each peer is up, by peer id.
+    pub fn peers_connected(&self) -> Vec<(u32, bool)> {
+        self.peers_connected
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(&id, &connected)| (id, connected))
+            .collect()
+    }
+
+    pub fn record_peer_message(&self, identity: PeerIdentity, accepted: bool) {
+        let mut stats = self.peer_messages.lock().unwrap();
+        let entry = stats.entry(identity).or_default();
+        if accepted {
+            entry.accepted += 1;
+        } else {
+            entry.rejected += 1;
+        }
+    }
+
+    /// Messages accepted and rejected, by the identity of the connection
+    /// they arrived on.
+    pub fn peer_messages(&self) -> Vec<(PeerIdentity, PeerStats)> {
+        self.peer_messages
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(&identity, &stats)| (identity, stats))
+            .collect()
+    }
+
+    pub fn set_queue_stats(&self, identity: PeerIdentity, stats: QueueStats) {
+        self.inbound_queues.lock().unwrap().insert(identity, stats);
+    }
+
+    /// The inbound queue of each connection identity that has sent anything.
+    pub fn queue_stats(&self) -> Vec<(PeerIdentity, QueueStats)> {
+        self.inbound_queues
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(&identity, &stats)| (identity, stats))
+            .collect()
+    }
+
+    /// The Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        for (name, help, counters) in [
+            (
+                "pbft_messages_received_total",
+                "Messages received, by kind.",
+                &self.messages_received,
+            ),
+            (
+                "pbft_messages_sent_total",
+                "Messages sent, by kind. A broadcast counts once per peer.",
+                &self.messages_sent,
+            ),
+        ] {
+            header(&mut out, name, help, "counter");
+            for kind in MessageKind::ALL {
+                let _ = writeln!(
+                    out,
+                    "{}{{kind=\"{}\"}} {}",
+                    name,
+                    kind.name(),
+                    counters.get(kind)
+                );
+            }
+        }
+
+        for (name, help, kind, value) in [
+            (
+                "pbft_signature_failures_total",
+                "Messages dropped for an invalid signature.",
+                "counter",
+                &self.signature_failures,
+            ),
+            (
+                "pbft_requests_ordered_total",
+                "Sequence numbers committed.",
+                "counter",
+                &self.requests_ordered,
+            ),
+            (
+                "pbft_requests_executed_total",
+                "Client requests executed.",
+                "counter",
+                &self.requests_executed,
+            ),
+            (
+                "pbft_view_changes_total",
+                "View changes this replica started.",
+                "counter",
+                &self.view_changes,
+            ),
+            ("pbft_view", "Current view.", "gauge", &self.view),
+            (
+                "pbft_log_size",
+                "Sequence numbers in the message log.",
+                "gauge",
+                &self.log_size,
+            ),
+            (
+                "pbft_last_executed",
+                "Highest sequence number executed.",
+                "gauge",
+                &self.last_executed,
+            ),
+        ] {
+            header(&mut out, name, help, kind);
+            let _ = writeln!(out, "{} {}", name, value.load(Ordering::Relaxed));
+        }
+
+        let name = "pbft_frames_rejected_total";
+        header(
+            &mut out,
+            name,
+            "Inbound frames refused before decoding, by reason.",
+            "counter",
+        );
+        let frames = self.frames_rejected.snapshot();
+        for (reason, count) in [
+            ("oversized", frames.oversized),
+            ("truncated", frames.truncated),
+            ("malformed", frames.malformed),
+            ("incompatible", frames.incompatible),
+        ] {
+            let _ = writeln!(out, "{}{{reason=\"{}\"}} {}", name, reason, count);
+        }
+
+        let name = "pbft_commit_latency_seconds";
+        header(
+            &mut out,
+            name,
+            "Time from the first message for a sequence number to its commit.",
+            "histogram",
+        );
+        let mut cumulative = 0;
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(&self.commit_latency.buckets) {
+            cumulative += count.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, cumulative);
+        }
+        let count = self.commit_latency.count();
+        let sum = self.commit_latency.sum_micros.load(Ordering::Relaxed) as f64 / 1e6;
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, count);
+        let _ = writeln!(out, "{}_sum {}", name, sum);
+        let _ = writeln!(out, "{}_count {}", name, count);
+
+        let name = "pbft_peer_connected";
+        header(
+            &mut out,
+            name,
+            "Whether the outgoing connection to a peer is up.",
+            "gauge",
+        );
+        for (peer, connected) in self.peers_connected.lock().unwrap().iter() {
+            let _ = writeln!(out, "{}{{peer=\"{}\"}} {}", name, peer, *connected as u8);
+        }
+
+        let name = "pbft_peer_messages_total";
+        header(
+            &mut out,
+            name,
+            "Messages received per connection identity, by whether they were accepted.",
+            "counter",
+        );
+        for (identity, stats) in self.peer_messages.lock().unwrap().iter() {
+            for (result, count) in [("accepted", stats.accepted), ("rejected", stats.rejected)] {
+                let _ = writeln!(
+                    out,
+                    "{}{{{},result=\"{}\"}} {}",
+                    name,
+                    identity_labels(identity),
+                    result,
+                    count
+                );
+            }
+        }
+
+        let queues = self.queue_stats();
+        let per_queue = |value: fn(&QueueStats) -> u64| -> Vec<(PeerIdentity, u64)> {
+            queues
+                .iter()
+                .map(|(id, stats)| (*id, value(stats)))
+                .collect()
+        };
+        for (name, help, kind, values) in [
+            (
+                "pbft_inbound_queue_depth",
+                "Messages waiting in a connection identity's inbound queue.",
+                "gauge",
+                per_queue(|s| s.depth as u64),
+            ),
+            (
+                "pbft_inbound_queue_high_water",
+                "Deepest a connection identity's inbound queue has been.",
+                "gauge",
+                per_queue(|s| s.high_water as u64),
+            ),
+            (
+                "pbft_inbound_dropped_total",
+                "Messages dropped because a connection identity's inbound queue was full.",
+                "counter",
+                per_queue(|s| s.dropped),
+            ),
+        ] {
+            header(&mut out, name, help, kind);
+            for (identity, value) in values {
+                let _ = writeln!(out, "{}{{{}}} {}", name, identity_labels(&identity), value);
+            }
+        }
+
+        out
+    }
+}
+
+/// `role` and `id` labels for the peer on an authenticated connection.
+fn identity_labels(identity: &PeerIdentity) -> String {
+    match identity {
+        PeerIdentity::Replica(id) => format!("role=\"replica\",id=\"{}\"", id),
+        PeerIdentity::Client(id) => format!("role=\"client\",id=\"{}\"", id),
+    }
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
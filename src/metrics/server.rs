@@ -0,0 +1,118 @@
+use std::{io, net::SocketAddr, sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::Semaphore,
+};
+use tracing::{Instrument, debug, info, warn};
+
+use crate::metrics::registry::Metrics;
+
+/// Largest request head read before giving up on a scrape.
+const MAX_REQUEST: usize = 8 * 1024;
+
+#[derive(Clone, Debug)]
+pub struct MetricsServerConfig {
+    /// Connections served at once; further ones get a 503.
+    pub max_connections: usize,
+    /// Time allowed for the whole request head to arrive, however slowly
+    /// it trickles in.
+    pub header_timeout: Duration,
+}
+
+impl Default for MetricsServerConfig {
+    fn default() -> Self {
+        MetricsServerConfig {
+            max_connections: 16,
+            header_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Serves `GET /metrics` on `addr` in the background, one request per
+/// connection, and returns the address actually bound.
+pub async fn spawn_metrics_server(
+    addr: SocketAddr,
+    metrics: Arc<Metrics>,
+    config: MetricsServerConfig,
+) -> io::Result<SocketAddr> {
+    let listener = TcpListener::bind(addr).await?;
+    let local_addr = listener.local_addr()?;
+    info!(addr = %local_addr, "Serving metrics");
+
+    let connections = Arc::new(Semaphore::new(config.max_connections));
+    tokio::spawn(
+        async move {
+            while let Ok((mut stream, peer)) = listener.accept().await {
+                let Ok(permit) = connections.clone().try_acquire_owned() else {
+                    warn!(%peer, "Too many metrics connections, refusing");
+                    tokio::spawn(async move {
+                        let _ = respond(&mut stream, "503 Service Unavailable", "").await;
+                    });
+                    continue;
+                };
+                let metrics = metrics.clone();
+                let header_timeout = config.header_timeout;
+                tokio::spawn(async move {
+                    if let Err(e) = handle_connection(stream, &metrics, header_timeout).await {
+                        debug!(%peer, error = %e, "Metrics request failed");
+                    }
+                    drop(permit);
+                });
+            }
+        }
+        .in_current_span(),
+    );
+
+    Ok(local_addr)
+}
+
+async fn handle_connection(
+    mut stream: TcpStream,
+    metrics: &Metrics,
+    header_timeout: Duration,
+) -> io::Result<()> {
+    let request = match tokio::time::timeout(header_timeout, read_head(&mut stream)).await {
+        Ok(Ok(Some(request))) => request,
+        Ok(Ok(None)) => return Ok(()),
+        Ok(Err(e)) => return Err(e),
+        Err(_) => return respond(&mut stream, "408 Request Timeout", "").await,
+    };
+    if request.len() > MAX_REQUEST {
+        return respond(&mut stream, "431 Request Header Fields Too Large", "").await;
+    }
+
+    let request_line = String::from_utf8_lossy(&request);
+    let mut parts = request_line.split_whitespace();
+    match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => respond(&mut stream, "200 OK", &metrics.render()).await,
+        (Some("GET"), _) => respond(&mut stream, "404 Not Found", "").await,
+        _ => respond(&mut stream, "405 Method Not Allowed", "").await,
+    }
+}
+
+/// Reads up to the end of the request head, stopping early once it is too
+/// long. `None` if the client hung up first.
+async fn read_head(stream: &mut TcpStream) -> io::Result<Option<Vec<u8>>> {
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() <= MAX_REQUEST {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            return Ok(None);
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+    Ok(Some(request))
+}
+
+async fn respond(stream: &mut TcpStream, status: &str, body: &str) -> io::Result<()> {
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
@@ -1,2 +1,9 @@
+pub mod byzantine;
 pub mod cert;
+pub mod framing;
+pub mod handshake;
+pub mod inbound;
 pub mod network_layer;
+pub mod outbound;
+pub mod reconnect;
+pub mod transport;
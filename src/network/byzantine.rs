@@ -0,0 +1,283 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::VecDeque, sync::Mutex};
+
+use crate::{
+    config::membership::Membership,
+    crypto::primitives::Crypto,
+    message::message_types::{PBFTMessage, SignedMessage},
+    network::transport::Transport,
+};
+
+/// Ways a faulty replica can misbehave on the wire. The replica's own state
+/// machine stays honest; only what it sends is tampered with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ByzantineBehavior {
+    /// Sends half of the backups a conflicting pre-prepare, carrying a null
+    /// request, for every sequence number it proposes.
+    Equivocate,
+    /// Withholds its pre-prepares and new-view messages.
+    SilentPrimary,
+    /// Puts a wrong digest in its pre-prepares, prepares and commits, and
+    /// signs them.
+    BogusDigest,
+    /// Corrupts the signature of everything it sends.
+    InvalidSignature,
+    /// Resends its messages from earlier views whenever it moves to a later
+    /// one.
+    ReplayOldView,
+    /// Withholds everything from the peers in `drop_to`.
+    SelectiveDrop,
+}
+
+/// Byzantine behaviours for one replica, as listed in `cluster.toml`:
+///
+/// ```toml
+/// [[byzantine]]
+/// id = 3
+/// behaviors = ["selective_drop", "bogus_digest"]
+/// drop_to = [1]
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ByzantineConfig {
+    pub id: u32,
+    #[serde(default)]
+    pub behaviors: Vec<ByzantineBehavior>,
+    /// Peers `SelectiveDrop` withholds messages from.
+    #[serde(default)]
+    pub drop_to: Vec<u32>,
+}
+
+impl ByzantineConfig {
+    pub fn new(id: u32, behaviors: Vec<ByzantineBehavior>) -> Self {
+        ByzantineConfig {
+            id,
+            behaviors,
+            drop_to: Vec::new(),
+        }
+    }
+
+    pub fn has(&self, behavior: ByzantineBehavior) -> bool {
+        self.behaviors.contains(&behavior)
+    }
+}
+
+/// Messages kept for `ReplayOldView`.
+const MAX_REPLAY: usize = 256;
+
+/// Wraps a replica's transport and applies its `ByzantineConfig` to every
+/// outgoing message. With no behaviours configured it passes everything
+/// through unchanged.
+pub struct ByzantineTransport<T> {
+    inner: T,
+    config: ByzantineConfig,
+    /// The replica's own key, to re-sign tampered messages so that only the
+    /// intended fault shows.
+    crypto: Crypto,
+    peers: Mutex<Vec<u32>>,
+    sent: Mutex<ReplayLog>,
+}
+
+#[derive(Default)]
+struct ReplayLog {
+    messages: VecDeque<(u64, PBFTMessage)>,
+    highest_view: u64,
+}
+
+impl<T: Transport> ByzantineTransport<T> {
+    pub fn new(inner: T, config: ByzantineConfig, crypto: Crypto, membership: &Membership) -> Self {
+        let transport = ByzantineTransport {
+            inner,
+            config,
+            crypto,
+            peers: Mutex::new(Vec::new()),
+            sent: Mutex::new(ReplayLog::default()),
+        };
+        transport.set_peers(membership);
+        transport
+    }
+
+    fn set_peers(&self, membership: &Membership) {
+        let mut peers: Vec<u32> = membership
+            .replicas()
+            .map(|r| r.id)
+            .filter(|&id| id != self.config.id)
+            .collect();
+        peers.sort();
+        *self.peers.lock().unwrap() = peers;
+    }
+
+    /// What `peer` gets in place of `message`, if anything.
+    fn tamper(&self, message: &PBFTMessage, peer: u32) -> Option<PBFTMessage> {
+        let config = &self.config;
+        if config.has(ByzantineBehavior::SelectiveDrop) && config.drop_to.contains(&peer) {
+            return None;
+        }
+        if config.has(ByzantineBehavior::SilentPrimary)
+            && matches!(
+                message,
+                PBFTMessage::PrePrepare(_) | PBFTMessage::NewView(_)
+            )
+        {
+            return None;
+        }
+
+        let mut message = message.clone();
+
+        if config.has(ByzantineBehavior::Equivocate)
+            && let PBFTMessage::PrePrepare(pre_prepare) = &message
+            && self.in_second_half(peer)
+        {
+            let mut conflicting = pre_prepare.message.clone();
+            conflicting.request = SignedMessage::null();
+            conflicting.digest = conflicting.request.message.digest();
+            message = PBFTMessage::PrePrepare(self.crypto.create_signed_message(conflicting));
+        }
+
+        if config.has(ByzantineBehavior::BogusDigest) {
+            message = match message {
+                PBFTMessage::PrePrepare(signed) => {
+                    let mut pre_prepare = signed.message;
+                    pre_prepare.digest = bogus(pre_prepare.digest);
+                    PBFTMessage::PrePrepare(self.crypto.create_signed_message(pre_prepare))
+                }
+                PBFTMessage::Prepare(signed) => {
+                    let mut prepare = signed.message;
+                    prepare.digest = bogus(prepare.digest);
+                    PBFTMessage::Prepare(self.crypto.create_signed_message(prepare))
+                }
+                PBFTMessage::Commit(signed) => {
+                    let mut commit = signed.message;
+                    commit.digest = bogus(commit.digest);
+                    PBFTMessage::Commit(self.crypto.create_signed_message(commit))
+                }
+                other => other,
+            };
+        }
+
+        if config.has(ByzantineBehavior::InvalidSignature) {
+            corrupt_signature(&mut message);
+        }
+
+        Some(message)
+    }
+
+    /// Backups in the upper half of the id order get the conflicting
+    /// pre-prepare; clients and unknown peers count as the lower half.
+    fn in_second_half(&self, peer: u32) -> bool {
+        let peers = self.peers.lock().unwrap();
+        peers
+            .iter()
+            .position(|&id| id == peer)
+            .is_some_and(|index| index >= peers.len() / 2)
+    }
+
+    /// Records `message` and, on the first message of a later view, returns
+    /// everything recorded from earlier views.
+    fn replays(&self, message: &PBFTMessage) -> Vec<PBFTMessage> {
+        if !self.config.has(ByzantineBehavior::ReplayOldView) {
+            return Vec::new();
+        }
+        let Some(view) = message_view(message) else {
+            return Vec::new();
+        };
+
+        let mut sent = self.sent.lock().unwrap();
+        let replays = if view > sent.highest_view {
+            sent.highest_view = view;
+            sent.messages
+                .iter()
+                .filter(|(old, _)| *old < view)
+                .map(|(_, m)| m.clone())
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        sent.messages.push_back((view, message.clone()));
+        if sent.messages.len() > MAX_REPLAY {
+            sent.messages.pop_front();
+        }
+        replays
+    }
+
+    async fn send_tampered(&self, peer: u32, message: &PBFTMessage) {
+        if let Some(message) = self.tamper(message, peer) {
+            self.inner.send_to(peer, &message).await;
+        }
+    }
+}
+
+fn message_view(message: &PBFTMessage) -> Option<u64> {
+    match message {
+        PBFTMessage::PrePrepare(m) => Some(m.message.view),
+        PBFTMessage::Prepare(m) => Some(m.message.view),
+        PBFTMessage::Commit(m) => Some(m.message.view),
+        PBFTMessage::ViewChange(m) => Some(m.message.new_view),
+        PBFTMessage::NewView(m) => Some(m.message.new_view),
+        PBFTMessage::Request(_)
+        | PBFTMessage::Reply(_)
+        | PBFTMessage::Evidence(_)
+        | PBFTMessage::AdminRequest(_)
+        | PBFTMessage::AdminResponse(_) => None,
+    }
+}
+
+fn bogus(mut digest: [u8; 32]) -> [u8; 32] {
+    for byte in &mut digest {
+        *byte ^= 0xff;
+    }
+    digest
+}
+
+fn corrupt_signature(message: &mut PBFTMessage) {
+    let signature = match message {
+        PBFTMessage::Request(m) => &mut m.signature,
+        PBFTMessage::PrePrepare(m) => &mut m.signature,
+        PBFTMessage::Prepare(m) => &mut m.signature,
+        PBFTMessage::Commit(m) => &mut m.signature,
+        PBFTMessage::Reply(m) => &mut m.signature,
+        PBFTMessage::ViewChange(m) => &mut m.signature,
+        PBFTMessage::NewView(m) => &mut m.signature,
+        PBFTMessage::Evidence(m) => &mut m.signature,
+        PBFTMessage::AdminRequest(m) => &mut m.signature,
+        PBFTMessage::AdminResponse(m) => &mut m.signature,
+    };
+    if let Some(byte) = signature.first_mut() {
+        *byte ^= 0xff;
+    }
+}
+
+impl<T: Transport + Send + Sync> Transport for ByzantineTransport<T> {
+    async fn broadcast(&self, message: &PBFTMessage) {
+        if self.config.behaviors.is_empty() {
+            return self.inner.broadcast(message).await;
+        }
+
+        let peers = self.peers.lock().unwrap().clone();
+        for replay in self.replays(message) {
+            for &peer in &peers {
+                self.send_tampered(peer, &replay).await;
+            }
+        }
+        for &peer in &peers {
+            self.send_tampered(peer, message).await;
+        }
+    }
+
+    async fn send_to(&self, peer_id: u32, message: &PBFTMessage) {
+        if self.config.behaviors.is_empty() {
+            return self.inner.send_to(peer_id, message).await;
+        }
+        self.send_tampered(peer_id, message).await;
+    }
+
+    async fn recv(&mut self) -> Option<PBFTMessage> {
+        self.inner.recv().await
+    }
+
+    async fn apply_membership(&self, membership: &Membership) {
+        self.set_peers(membership);
+        self.inner.apply_membership(membership).await
+    }
+}
@@ -1,21 +1,35 @@
-use rcgen::{CertificateParams, KeyPair, PKCS_ECDSA_P256_SHA256};
+use rcgen::{CertificateParams, KeyPair, PKCS_ED25519};
 use rustls::{
-    ClientConfig, ServerConfig, SignatureScheme,
-    pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer},
+    CertificateError, ClientConfig, DigitallySignedStruct, DistinguishedName, Error, ServerConfig,
+    SignatureScheme,
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{WebPkiSupportedAlgorithms, verify_tls12_signature, verify_tls13_signature},
+    pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName, UnixTime},
+    server::danger::{ClientCertVerified, ClientCertVerifier},
 };
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+use x509_parser::{oid_registry::OID_SIG_ED25519, parse_x509_certificate};
 
+/// TLS certificate self-signed with a node's persistent Ed25519 key, so the
+/// TLS identity is the same key that signs its PBFT messages.
 pub struct NodeCert {
     cert_der: Vec<u8>,
     key_der: Vec<u8>,
 }
 
 impl NodeCert {
-    pub fn generate(node_id: u32) -> Self {
-        let key_pair = KeyPair::generate().expect("Failed to generate key pair");
+    pub fn from_pkcs8(subject: String, pkcs8: &[u8]) -> Self {
+        let key_pair = KeyPair::from_pkcs8_der_and_sign_algo(
+            &PrivatePkcs8KeyDer::from(pkcs8.to_vec()),
+            &PKCS_ED25519,
+        )
+        .expect("Failed to load Ed25519 key pair");
 
-        let mut params = CertificateParams::new(vec![format!("node-{}", node_id)])
-            .expect("Failed to create certificate params");
+        let params =
+            CertificateParams::new(vec![subject]).expect("Failed to create certificate params");
 
         let cert = params
             .self_signed(&key_pair)
@@ -23,76 +37,260 @@ impl NodeCert {
 
         NodeCert {
             cert_der: cert.der().to_vec(),
-            key_der: key_pair.serialize_der(),
+            key_der: pkcs8.to_vec(),
+        }
+    }
+
+    fn cert_chain(&self) -> Vec<CertificateDer<'static>> {
+        vec![CertificateDer::from(self.cert_der.clone())]
+    }
+
+    fn private_key(&self) -> PrivateKeyDer<'static> {
+        PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(self.key_der.clone()))
+    }
+}
+
+/// Server name a replica's certificate is issued for and dialed as.
+pub fn replica_server_name(replica_id: u32) -> String {
+    format!("node-{}", replica_id)
+}
+
+/// Who is on the other end of a TLS session, as established by its pinned key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum PeerIdentity {
+    Replica(u32),
+    Client(u32),
+}
+
+/// Ed25519 public keys the cluster trusts, shared between the TLS verifiers
+/// and whoever updates membership.
+#[derive(Clone, Default)]
+pub struct PinnedKeys {
+    inner: Arc<RwLock<PinnedKeySet>>,
+}
+
+#[derive(Default)]
+struct PinnedKeySet {
+    replicas: HashMap<u32, Vec<u8>>,
+    clients: HashMap<u32, Vec<u8>>,
+}
+
+impl PinnedKeys {
+    pub fn new(replicas: HashMap<u32, Vec<u8>>, clients: HashMap<u32, Vec<u8>>) -> Self {
+        PinnedKeys {
+            inner: Arc::new(RwLock::new(PinnedKeySet { replicas, clients })),
+        }
+    }
+
+    pub fn set_replicas(&self, replicas: HashMap<u32, Vec<u8>>) {
+        self.inner.write().unwrap().replicas = replicas;
+    }
+
+    pub fn set_clients(&self, clients: HashMap<u32, Vec<u8>>) {
+        self.inner.write().unwrap().clients = clients;
+    }
+
+    pub fn replica_key(&self, replica_id: u32) -> Option<Vec<u8>> {
+        self.inner
+            .read()
+            .unwrap()
+            .replicas
+            .get(&replica_id)
+            .cloned()
+    }
+
+    pub fn identify(&self, public_key: &[u8]) -> Option<PeerIdentity> {
+        let keys = self.inner.read().unwrap();
+
+        if let Some((id, _)) = keys.replicas.iter().find(|(_, pk)| *pk == public_key) {
+            return Some(PeerIdentity::Replica(*id));
         }
+        keys.clients
+            .iter()
+            .find(|(_, pk)| *pk == public_key)
+            .map(|(id, _)| PeerIdentity::Client(*id))
+    }
+
+    pub fn identify_cert(&self, cert: &CertificateDer) -> Option<PeerIdentity> {
+        self.identify(&ed25519_public_key(cert)?)
     }
 }
 
-pub fn make_server_config(certs: &NodeCert) -> ServerConfig {
-    let cert_chain = vec![CertificateDer::from(certs.cert_der.clone())];
+/// The raw Ed25519 key in the certificate's
+/// `tbsCertificate.subjectPublicKeyInfo`, the key TLS checks the handshake
+/// signature against. `None` if the certificate does not parse, has trailing
+/// data or holds another kind of key.
+pub fn ed25519_public_key(cert: &CertificateDer) -> Option<Vec<u8>> {
+    let (rest, cert) = parse_x509_certificate(cert.as_ref()).ok()?;
+    if !rest.is_empty() {
+        return None;
+    }
 
-    let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(certs.key_der.clone()));
+    let spki = &cert.tbs_certificate.subject_pki;
+    let key = &spki.subject_public_key;
+    if spki.algorithm.algorithm != OID_SIG_ED25519
+        || spki.algorithm.parameters.is_some()
+        || key.unused_bits != 0
+        || key.data.len() != 32
+    {
+        return None;
+    }
+    Some(key.data.to_vec())
+}
 
+pub fn make_server_config(certs: &NodeCert, pinned: PinnedKeys) -> ServerConfig {
     ServerConfig::builder()
-        .with_no_client_auth()
-        .with_single_cert(cert_chain, key)
+        .with_client_cert_verifier(Arc::new(PinnedClientVerifier::new(pinned)))
+        .with_single_cert(certs.cert_chain(), certs.private_key())
         .expect("Invalid server config")
 }
 
-pub fn make_client_config() -> ClientConfig {
-    let mut config = ClientConfig::builder()
+pub fn make_client_config(certs: &NodeCert, pinned: PinnedKeys) -> ClientConfig {
+    ClientConfig::builder()
         .dangerous()
-        .with_custom_certificate_verifier(Arc::new(SkipVerification))
-        .with_no_client_auth();
-    config
+        .with_custom_certificate_verifier(Arc::new(PinnedServerVerifier::new(pinned)))
+        .with_client_auth_cert(certs.cert_chain(), certs.private_key())
+        .expect("Invalid client config")
 }
 
+fn supported_algs() -> WebPkiSupportedAlgorithms {
+    rustls::crypto::ring::default_provider().signature_verification_algorithms
+}
+
+/// Accepts a server only if its certificate carries the pinned key of the
+/// replica named by the server name we dialed (`node-<id>`).
 #[derive(Debug)]
-struct SkipVerification;
+struct PinnedServerVerifier {
+    pinned: PinnedKeys,
+    algs: WebPkiSupportedAlgorithms,
+}
+
+impl PinnedServerVerifier {
+    fn new(pinned: PinnedKeys) -> Self {
+        PinnedServerVerifier {
+            pinned,
+            algs: supported_algs(),
+        }
+    }
+}
 
-impl rustls::client::danger::ServerCertVerifier for SkipVerification {
+impl ServerCertVerifier for PinnedServerVerifier {
     fn verify_server_cert(
         &self,
-        _end_entity: &rustls::pki_types::CertificateDer,
-        _intermediates: &[rustls::pki_types::CertificateDer],
-        _server_name: &rustls::pki_types::ServerName,
+        end_entity: &CertificateDer,
+        _intermediates: &[CertificateDer],
+        server_name: &ServerName,
         _ocsp: &[u8],
-        _now: rustls::pki_types::UnixTime,
-    ) -> Result<rustls::client::danger::ServerCertVerified, rustls::Error> {
-        Ok(rustls::client::danger::ServerCertVerified::assertion())
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        let replica_id = match server_name {
+            ServerName::DnsName(name) => name
+                .as_ref()
+                .strip_prefix("node-")
+                .and_then(|id| id.parse::<u32>().ok()),
+            _ => None,
+        }
+        .ok_or(Error::InvalidCertificate(CertificateError::NotValidForName))?;
+
+        let presented = ed25519_public_key(end_entity)
+            .ok_or(Error::InvalidCertificate(CertificateError::BadEncoding))?;
+
+        match self.pinned.replica_key(replica_id) {
+            Some(pinned) if pinned == presented => Ok(ServerCertVerified::assertion()),
+            _ => Err(Error::InvalidCertificate(
+                CertificateError::ApplicationVerificationFailure,
+            )),
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        verify_tls12_signature(message, cert, dss, &self.algs)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        verify_tls13_signature(message, cert, dss, &self.algs)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        vec![SignatureScheme::ED25519]
+    }
+}
+
+/// Requires every client to present a certificate whose key is pinned, either
+/// as a replica or as a registered client.
+#[derive(Debug)]
+struct PinnedClientVerifier {
+    pinned: PinnedKeys,
+    algs: WebPkiSupportedAlgorithms,
+}
+
+impl PinnedClientVerifier {
+    fn new(pinned: PinnedKeys) -> Self {
+        PinnedClientVerifier {
+            pinned,
+            algs: supported_algs(),
+        }
+    }
+}
+
+impl ClientCertVerifier for PinnedClientVerifier {
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        &[]
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &CertificateDer,
+        _intermediates: &[CertificateDer],
+        _now: UnixTime,
+    ) -> Result<ClientCertVerified, Error> {
+        match self.pinned.identify_cert(end_entity) {
+            Some(_) => Ok(ClientCertVerified::assertion()),
+            None => Err(Error::InvalidCertificate(
+                CertificateError::ApplicationVerificationFailure,
+            )),
+        }
     }
 
     fn verify_tls12_signature(
         &self,
-        _: &[u8],
-        _: &rustls::pki_types::CertificateDer,
-        _: &rustls::DigitallySignedStruct,
-    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
-        Ok(rustls::client::danger::HandshakeSignatureValid::assertion())
+        message: &[u8],
+        cert: &CertificateDer,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        verify_tls12_signature(message, cert, dss, &self.algs)
     }
 
     fn verify_tls13_signature(
         &self,
-        _: &[u8],
-        _: &rustls::pki_types::CertificateDer,
-        _: &rustls::DigitallySignedStruct,
-    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
-        Ok(rustls::client::danger::HandshakeSignatureValid::assertion())
-    }
-
-    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
-        vec![
-            SignatureScheme::RSA_PKCS1_SHA256,
-            SignatureScheme::RSA_PKCS1_SHA384,
-            SignatureScheme::RSA_PKCS1_SHA512,
-            SignatureScheme::ECDSA_NISTP256_SHA256,
-            SignatureScheme::ECDSA_NISTP384_SHA384,
-            SignatureScheme::ECDSA_NISTP521_SHA512,
-            SignatureScheme::RSA_PSS_SHA256,
-            SignatureScheme::RSA_PSS_SHA384,
-            SignatureScheme::RSA_PSS_SHA512,
-            SignatureScheme::ED25519,
-            SignatureScheme::ED448,
-        ]
+        message: &[u8],
+        cert: &CertificateDer,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        verify_tls13_signature(message, cert, dss, &self.algs)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        vec![SignatureScheme::ED25519]
+    }
+}
+
+impl std::fmt::Debug for PinnedKeys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let keys = self.inner.read().unwrap();
+        f.debug_struct("PinnedKeys")
+            .field("replicas", &keys.replicas.keys())
+            .field("clients", &keys.clients.keys())
+            .finish()
     }
 }
@@ -0,0 +1,359 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt, io,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::message::message_types::{MessageKind, PBFTMessage};
+
+/// Bytes read per step while receiving a frame body. The buffer only grows as
+/// data actually arrives, so a large declared length costs nothing up front.
+const READ_CHUNK: usize = 16 * 1024;
+
+/// Start of every frame, and of the `Hello` that opens a replica connection.
+pub const MAGIC: [u8; 2] = *b"PB";
+
+/// Wire version this build writes by default.
+pub const WIRE_VERSION: u8 = 1;
+
+/// Oldest wire version this build still reads, so that replicas can be
+/// upgraded one at a time.
+pub const MIN_WIRE_VERSION: u8 = 1;
+
+/// Bytes of envelope on top of the serialized message: magic, version and
+/// cluster id (the message type is the first byte of the message itself).
+pub const ENVELOPE_LEN: u32 = 7;
+
+/// Application close code for a connection that sent a frame we cannot read.
+pub const INCOMPATIBLE_FRAME: u32 = 4;
+
+/// What a frame says about itself besides its message. A frame is
+///
+/// ```text
+/// length: u32 | magic: "PB" | version: u8 | type: u8 | cluster id: u32 | body
+/// ```
+///
+/// where `length` counts everything after itself, and the type byte followed
+/// by the body is the postcard encoding of the `PBFTMessage`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Envelope {
+    pub version: u8,
+    pub cluster_id: u32,
+}
+
+impl Envelope {
+    pub fn new(cluster_id: u32) -> Self {
+        Envelope {
+            version: WIRE_VERSION,
+            cluster_id,
+        }
+    }
+
+    pub fn is_supported(version: u8) -> bool {
+        (MIN_WIRE_VERSION..=WIRE_VERSION).contains(&version)
+    }
+}
+
+/// Upper bounds, in bytes, on the serialized size of each message type.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FrameLimits {
+    pub request: u32,
+    pub pre_prepare: u32,
+    pub prepare: u32,
+    pub commit: u32,
+    pub reply: u32,
+    pub view_change: u32,
+    pub new_view: u32,
+    pub evidence: u32,
+    pub admin_request: u32,
+    pub admin_response: u32,
+}
+
+impl Default for FrameLimits {
+    fn default() -> Self {
+        FrameLimits {
+            request: 64 * 1024,
+            pre_prepare: 64 * 1024 + 512,
+            prepare: 512,
+            commit: 512,
+            reply: 64 * 1024,
+            view_change: 4 * 1024 * 1024,
+            new_view: 8 * 1024 * 1024,
+            evidence: 2 * (64 * 1024 + 512) + 512,
+            admin_request: 512,
+            admin_response: 8 * 1024 * 1024,
+        }
+    }
+}
+
+impl FrameLimits {
+    pub fn limit(&self, kind: MessageKind) -> u32 {
+        match kind {
+            MessageKind::Request => self.request,
+            MessageKind::PrePrepare => self.pre_prepare,
+            MessageKind::Prepare => self.prepare,
+            MessageKind::Commit => self.commit,
+            MessageKind::Reply => self.reply,
+            MessageKind::ViewChange => self.view_change,
+            MessageKind::NewView => self.new_view,
+            MessageKind::Evidence => self.evidence,
+            MessageKind::AdminRequest => self.admin_request,
+            MessageKind::AdminResponse => self.admin_response,
+        }
+    }
+
+    /// Largest frame any message type may use.
+    pub fn max_frame(&self) -> u32 {
+        [
+            self.request,
+            self.pre_prepare,
+            self.prepare,
+            self.commit,
+            self.reply,
+            self.view_change,
+            self.new_view,
+            self.evidence,
+            self.admin_request,
+            self.admin_response,
+        ]
+        .into_iter()
+        .max()
+        .unwrap()
+    }
+}
+
+#[derive(Debug)]
+pub enum FrameError {
+    /// The stream ended cleanly before a new frame started.
+    Closed,
+    /// The stream ended in the middle of a frame.
+    Truncated,
+    Oversized {
+        kind: Option<MessageKind>,
+        len: u32,
+        limit: u32,
+    },
+    UnknownType(u8),
+    Malformed(postcard::Error),
+    /// Not a frame of this protocol at all.
+    BadMagic([u8; 2]),
+    UnsupportedVersion(u8),
+    WrongCluster(u32),
+    Io(io::Error),
+}
+
+impl FrameError {
+    /// Whether the sender speaks something we cannot read, so further frames
+    /// from it are pointless.
+    pub fn is_incompatible(&self) -> bool {
+        matches!(
+            self,
+            FrameError::BadMagic(_)
+                | FrameError::UnsupportedVersion(_)
+                | FrameError::WrongCluster(_)
+        )
+    }
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::Closed => write!(f, "stream closed"),
+            FrameError::Truncated => write!(f, "truncated frame"),
+            FrameError::Oversized { kind, len, limit } => write!(
+                f,
+                "{:?} frame of {} bytes exceeds limit of {}",
+                kind, len, limit
+            ),
+            FrameError::UnknownType(tag) => write!(f, "unknown message type {}", tag),
+            FrameError::Malformed(e) => write!(f, "malformed frame: {}", e),
+            FrameError::BadMagic(magic) => write!(f, "bad magic {:02x?}", magic),
+            FrameError::UnsupportedVersion(version) => write!(
+                f,
+                "unsupported wire version {} (we read {} to {})",
+                version, MIN_WIRE_VERSION, WIRE_VERSION
+            ),
+            FrameError::WrongCluster(cluster_id) => {
+                write!(f, "frame for cluster {}", cluster_id)
+            }
+            FrameError::Io(e) => write!(f, "i/o error: {}", e),
+        }
+    }
+}
+
+impl From<io::Error> for FrameError {
+    fn from(e: io::Error) -> Self {
+        if e.kind() == io::ErrorKind::UnexpectedEof {
+            FrameError::Truncated
+        } else {
+            FrameError::Io(e)
+        }
+    }
+}
+
+/// Counters of frames rejected by `read_frame`.
+#[derive(Debug, Default)]
+pub struct FrameStats {
+    oversized: AtomicU64,
+    truncated: AtomicU64,
+    malformed: AtomicU64,
+    incompatible: AtomicU64,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FrameStatsSnapshot {
+    pub oversized: u64,
+    pub truncated: u64,
+    pub malformed: u64,
+    pub incompatible: u64,
+}
+
+impl FrameStats {
+    fn record(&self, error: &FrameError) {
+        let counter = match error {
+            FrameError::Oversized { .. } => &self.oversized,
+            FrameError::Truncated => &self.truncated,
+            FrameError::UnknownType(_) | FrameError::Malformed(_) => &self.malformed,
+            FrameError::BadMagic(_)
+            | FrameError::UnsupportedVersion(_)
+            | FrameError::WrongCluster(_) => &self.incompatible,
+            FrameError::Closed | FrameError::Io(_) => return,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> FrameStatsSnapshot {
+        FrameStatsSnapshot {
+            oversized: self.oversized.load(Ordering::Relaxed),
+            truncated: self.truncated.load(Ordering::Relaxed),
+            malformed: self.malformed.load(Ordering::Relaxed),
+            incompatible: self.incompatible.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Reads one frame of cluster `cluster_id`, in any supported wire version.
+/// The declared length is checked against the overall limit before anything
+/// is read, the envelope as soon as it is in, and the length against the
+/// limit for the frame's message type before the body is read.
+pub async fn read_frame<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    cluster_id: u32,
+    limits: &FrameLimits,
+    stats: &FrameStats,
+) -> Result<PBFTMessage, FrameError> {
+    let result = read_frame_inner(reader, cluster_id, limits).await;
+    if let Err(e) = &result {
+        stats.record(e);
+    }
+    result
+}
+
+async fn read_frame_inner<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    cluster_id: u32,
+    limits: &FrameLimits,
+) -> Result<PBFTMessage, FrameError> {
+    let mut len_bytes = [0u8; 4];
+    let first = reader.read(&mut len_bytes).await?;
+    if first == 0 {
+        return Err(FrameError::Closed);
+    }
+    reader.read_exact(&mut len_bytes[first..]).await?;
+    let frame_len = u32::from_be_bytes(len_bytes);
+
+    let max_frame = limits.max_frame();
+    if frame_len > max_frame.saturating_add(ENVELOPE_LEN) {
+        return Err(FrameError::Oversized {
+            kind: None,
+            len: frame_len,
+            limit: max_frame,
+        });
+    }
+    if frame_len <= ENVELOPE_LEN {
+        return Err(FrameError::Truncated);
+    }
+    let len = frame_len - ENVELOPE_LEN;
+
+    let mut magic = [0u8; 2];
+    reader.read_exact(&mut magic).await?;
+    if magic != MAGIC {
+        return Err(FrameError::BadMagic(magic));
+    }
+    let version = reader.read_u8().await?;
+    if !Envelope::is_supported(version) {
+        return Err(FrameError::UnsupportedVersion(version));
+    }
+
+    let tag = reader.read_u8().await?;
+    let kind = MessageKind::from_tag(tag).ok_or(FrameError::UnknownType(tag))?;
+    let limit = limits.limit(kind);
+    if len > limit {
+        return Err(FrameError::Oversized {
+            kind: Some(kind),
+            len,
+            limit,
+        });
+    }
+
+    let frame_cluster = reader.read_u32().await?;
+    if frame_cluster != cluster_id {
+        return Err(FrameError::WrongCluster(frame_cluster));
+    }
+
+    let len = len as usize;
+    let mut buf = Vec::with_capacity(len.min(READ_CHUNK));
+    buf.push(tag);
+    while buf.len() < len {
+        let want = (len - buf.len()).min(READ_CHUNK);
+        let read = (&mut *reader)
+            .take(want as u64)
+            .read_to_end(&mut buf)
+            .await
+            .map_err(FrameError::from)?;
+        if read == 0 {
+            return Err(FrameError::Truncated);
+        }
+    }
+
+    postcard::from_bytes(&buf).map_err(FrameError::Malformed)
+}
+
+pub async fn write_frame<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    envelope: Envelope,
+    message: &PBFTMessage,
+) -> io::Result<()> {
+    writer.write_all(&encode_frame(envelope, message)?).await
+}
+
+/// Appends the frame for `message` to `frame`, so that several can be sent
+/// in one write.
+pub fn encode_frame_into(
+    frame: &mut Vec<u8>,
+    envelope: Envelope,
+    message: &PBFTMessage,
+) -> io::Result<()> {
+    let serialized = postcard::to_allocvec(message)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let (tag, body) = serialized
+        .split_first()
+        .expect("postcard always writes the variant tag");
+
+    frame.reserve(4 + ENVELOPE_LEN as usize + serialized.len());
+    frame.extend_from_slice(&(ENVELOPE_LEN + serialized.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&MAGIC);
+    frame.push(envelope.version);
+    frame.push(*tag);
+    frame.extend_from_slice(&envelope.cluster_id.to_be_bytes());
+    frame.extend_from_slice(body);
+    Ok(())
+}
+
+pub fn encode_frame(envelope: Envelope, message: &PBFTMessage) -> io::Result<Vec<u8>> {
+    let mut frame = Vec::new();
+    encode_frame_into(&mut frame, envelope, message)?;
+    Ok(frame)
+}
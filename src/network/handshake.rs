@@ -0,0 +1,189 @@
+use quinn::Connection;
+use serde::{Deserialize, Serialize};
+use std::{fmt, io, time::Duration};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::network::framing::{MAGIC, MIN_WIRE_VERSION, WIRE_VERSION};
+
+/// How long either side waits for the other's `Hello`.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Application close code for a connection whose handshake failed.
+pub const HANDSHAKE_FAILED: u32 = 3;
+
+/// A `Hello` is a few varints; anything longer is not one.
+const MAX_HELLO_LEN: u32 = 64;
+
+/// First thing sent on a replica-to-replica connection, by both ends: the
+/// dialing replica opens a bidirectional stream and writes its `Hello`, and
+/// the accepting one answers with its own. Both then use the newest wire
+/// version they have in common.
+///
+/// Unlike frames, a `Hello` is not versioned: later versions may only append
+/// fields, which older readers ignore.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Hello {
+    pub node_id: u32,
+    pub cluster_id: u32,
+    pub min_version: u8,
+    pub max_version: u8,
+}
+
+impl Hello {
+    pub fn new(node_id: u32, cluster_id: u32) -> Self {
+        Hello {
+            node_id,
+            cluster_id,
+            min_version: MIN_WIRE_VERSION,
+            max_version: WIRE_VERSION,
+        }
+    }
+
+    /// Checks `theirs`, received from the replica authenticated as
+    /// `expected_id` by TLS, against our own `Hello` and returns the wire
+    /// version to use with it.
+    pub fn negotiate(&self, theirs: &Hello, expected_id: u32) -> Result<u8, HandshakeError> {
+        if theirs.node_id != expected_id {
+            return Err(HandshakeError::WrongNode {
+                expected: expected_id,
+                announced: theirs.node_id,
+            });
+        }
+        if theirs.cluster_id != self.cluster_id {
+            return Err(HandshakeError::WrongCluster {
+                ours: self.cluster_id,
+                theirs: theirs.cluster_id,
+            });
+        }
+        let version = self.max_version.min(theirs.max_version);
+        if version < self.min_version.max(theirs.min_version) {
+            return Err(HandshakeError::IncompatibleVersion {
+                ours: (self.min_version, self.max_version),
+                theirs: (theirs.min_version, theirs.max_version),
+            });
+        }
+        Ok(version)
+    }
+}
+
+#[derive(Debug)]
+pub enum HandshakeError {
+    TimedOut,
+    /// The connection or stream failed, including the peer rejecting us.
+    Connection(String),
+    Malformed,
+    WrongNode {
+        expected: u32,
+        announced: u32,
+    },
+    WrongCluster {
+        ours: u32,
+        theirs: u32,
+    },
+    /// Supported wire versions, as (oldest, newest), that do not overlap.
+    IncompatibleVersion {
+        ours: (u8, u8),
+        theirs: (u8, u8),
+    },
+}
+
+impl fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HandshakeError::TimedOut => write!(f, "no hello within {:?}", HANDSHAKE_TIMEOUT),
+            HandshakeError::Connection(e) => write!(f, "{}", e),
+            HandshakeError::Malformed => write!(f, "malformed hello"),
+            HandshakeError::WrongNode {
+                expected,
+                announced,
+            } => write!(f, "replica {} announced itself as {}", expected, announced),
+            HandshakeError::WrongCluster { ours, theirs } => {
+                write!(f, "peer is in cluster {}, we are in {}", theirs, ours)
+            }
+            HandshakeError::IncompatibleVersion { ours, theirs } => write!(
+                f,
+                "incompatible wire versions {}-{} (ours are {}-{})",
+                theirs.0, theirs.1, ours.0, ours.1
+            ),
+        }
+    }
+}
+
+impl From<io::Error> for HandshakeError {
+    fn from(e: io::Error) -> Self {
+        HandshakeError::Connection(e.to_string())
+    }
+}
+
+/// Writes `length: u32 | magic | hello`, the length counting the magic.
+pub async fn write_hello<W: AsyncWrite + Unpin>(writer: &mut W, hello: &Hello) -> io::Result<()> {
+    let serialized =
+        postcard::to_allocvec(hello).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let mut frame = Vec::with_capacity(4 + MAGIC.len() + serialized.len());
+    frame.extend_from_slice(&((MAGIC.len() + serialized.len()) as u32).to_be_bytes());
+    frame.extend_from_slice(&MAGIC);
+    frame.extend_from_slice(&serialized);
+    writer.write_all(&frame).await
+}
+
+pub async fn read_hello<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Hello, HandshakeError> {
+    let len = reader.read_u32().await?;
+    if len > MAX_HELLO_LEN || (len as usize) < MAGIC.len() {
+        return Err(HandshakeError::Malformed);
+    }
+    let mut buf = vec![0u8; len as usize];
+    reader.read_exact(&mut buf).await?;
+    let (magic, hello) = buf.split_at(MAGIC.len());
+    if magic != MAGIC {
+        return Err(HandshakeError::Malformed);
+    }
+    postcard::take_from_bytes(hello)
+        .map(|(hello, _)| hello)
+        .map_err(|_| HandshakeError::Malformed)
+}
+
+/// Dialing side: sends `ours` to replica `peer_id` and checks its answer.
+/// Returns the negotiated wire version.
+pub async fn greet(
+    connection: &Connection,
+    ours: Hello,
+    peer_id: u32,
+) -> Result<u8, HandshakeError> {
+    let exchange = async {
+        let (mut send, mut recv) = connection
+            .open_bi()
+            .await
+            .map_err(|e| HandshakeError::Connection(e.to_string()))?;
+        write_hello(&mut send, &ours).await?;
+        let _ = send.finish();
+        read_hello(&mut recv).await
+    };
+    let theirs = tokio::time::timeout(HANDSHAKE_TIMEOUT, exchange)
+        .await
+        .map_err(|_| HandshakeError::TimedOut)??;
+    ours.negotiate(&theirs, peer_id)
+}
+
+/// Accepting side: reads the `Hello` of replica `peer_id` and answers with
+/// `ours` if it is acceptable, returning the negotiated wire version. The
+/// caller closes the connection otherwise.
+pub async fn answer(
+    connection: &Connection,
+    ours: Hello,
+    peer_id: u32,
+) -> Result<u8, HandshakeError> {
+    let exchange = async {
+        let (mut send, mut recv) = connection
+            .accept_bi()
+            .await
+            .map_err(|e| HandshakeError::Connection(e.to_string()))?;
+        let theirs = read_hello(&mut recv).await?;
+        let version = ours.negotiate(&theirs, peer_id)?;
+        write_hello(&mut send, &ours).await?;
+        let _ = send.finish();
+        Ok(version)
+    };
+    tokio::time::timeout(HANDSHAKE_TIMEOUT, exchange)
+        .await
+        .map_err(|_| HandshakeError::TimedOut)?
+}
@@ -0,0 +1,149 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+use tokio::sync::Notify;
+
+use crate::{
+    message::message_types::PBFTMessage, metrics::registry::Metrics, network::cert::PeerIdentity,
+};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InboundConfig {
+    /// Messages buffered per peer before new ones from it are dropped.
+    pub queue_capacity: usize,
+    /// Drops in a row after which the peer's connection is closed.
+    pub max_consecutive_drops: u64,
+    /// Uni streams read concurrently per connection.
+    pub max_concurrent_streams: usize,
+}
+
+impl Default for InboundConfig {
+    fn default() -> Self {
+        InboundConfig {
+            queue_capacity: 1024,
+            max_consecutive_drops: 256,
+            max_concurrent_streams: 32,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PushOutcome {
+    Queued,
+    Dropped,
+    /// The peer kept overflowing its queue and should be disconnected.
+    Disconnect,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct QueueStats {
+    pub depth: usize,
+    pub high_water: usize,
+    pub dropped: u64,
+}
+
+#[derive(Default)]
+struct PeerQueue {
+    messages: VecDeque<PBFTMessage>,
+    consecutive_drops: u64,
+    stats: QueueStats,
+}
+
+#[derive(Default)]
+struct InboundState {
+    queues: HashMap<PeerIdentity, PeerQueue>,
+    /// Peers with pending messages, in the order they will be served.
+    ready: VecDeque<PeerIdentity>,
+}
+
+/// Bounded per-peer inbound queues drained round-robin, so one chatty peer
+/// can neither exhaust memory nor starve the others. Every change to a
+/// queue's stats is published to `metrics`.
+pub struct InboundQueues {
+    config: InboundConfig,
+    state: Mutex<InboundState>,
+    notify: Notify,
+    metrics: Arc<Metrics>,
+}
+
+impl InboundQueues {
+    pub fn new(config: InboundConfig, metrics: Arc<Metrics>) -> Self {
+        InboundQueues {
+            config,
+            state: Mutex::new(InboundState::default()),
+            notify: Notify::new(),
+            metrics,
+        }
+    }
+
+    pub fn config(&self) -> &InboundConfig {
+        &self.config
+    }
+
+    pub fn push(&self, from: PeerIdentity, message: PBFTMessage) -> PushOutcome {
+        let mut state = self.state.lock().unwrap();
+        let queue = state.queues.entry(from).or_default();
+
+        if queue.messages.len() >= self.config.queue_capacity {
+            queue.consecutive_drops += 1;
+            queue.stats.dropped += 1;
+            self.metrics.set_queue_stats(from, queue.stats);
+            return if queue.consecutive_drops >= self.config.max_consecutive_drops {
+                PushOutcome::Disconnect
+            } else {
+                PushOutcome::Dropped
+            };
+        }
+
+        let was_empty = queue.messages.is_empty();
+        queue.consecutive_drops = 0;
+        queue.messages.push_back(message);
+        queue.stats.depth = queue.messages.len();
+        queue.stats.high_water = queue.stats.high_water.max(queue.stats.depth);
+        self.metrics.set_queue_stats(from, queue.stats);
+
+        if was_empty {
+            state.ready.push_back(from);
+        }
+        drop(state);
+
+        self.notify.notify_one();
+        PushOutcome::Queued
+    }
+
+    /// Takes the next message, one per peer in turn.
+    pub async fn recv(&self) -> PBFTMessage {
+        loop {
+            if let Some(message) = self.try_recv() {
+                return message;
+            }
+            self.notify.notified().await;
+        }
+    }
+
+    pub fn try_recv(&self) -> Option<PBFTMessage> {
+        let mut state = self.state.lock().unwrap();
+        let peer = state.ready.pop_front()?;
+        let queue = state.queues.get_mut(&peer)?;
+
+        let message = queue.messages.pop_front();
+        queue.stats.depth = queue.messages.len();
+        self.metrics.set_queue_stats(peer, queue.stats);
+        if !queue.messages.is_empty() {
+            state.ready.push_back(peer);
+        }
+        message
+    }
+
+    pub fn stats(&self) -> HashMap<PeerIdentity, QueueStats> {
+        let state = self.state.lock().unwrap();
+        state
+            .queues
+            .iter()
+            .map(|(peer, queue)| (*peer, queue.stats))
+            .collect()
+    }
+}
@@ -1,22 +1,128 @@
-use quinn::{Connection, Endpoint, RecvStream, SendStream};
-use std::{collections::HashMap, io::Error, net::SocketAddr, sync::Arc};
-use tokio::sync::{
-    RwLock,
-    mpsc::{self, UnboundedReceiver, UnboundedSender},
+use quinn::{Connection, Endpoint, RecvStream, TransportConfig};
+use rustls::pki_types::CertificateDer;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{
+        Arc,
+        atomic::{AtomicU32, Ordering},
+    },
+    time::Duration,
 };
+use tokio::{
+    sync::{RwLock, Semaphore, watch},
+    task::AbortHandle,
+};
+use tracing::{Instrument, debug, error, info, info_span, trace, warn};
 
 use crate::{
+    config::membership::Membership,
     message::message_types::PBFTMessage,
-    network::cert::{NodeCert, make_client_config, make_server_config},
+    metrics::registry::Metrics,
+    network::{
+        cert::{
+            NodeCert, PeerIdentity, PinnedKeys, make_client_config, make_server_config,
+            replica_server_name,
+        },
+        framing::{Envelope, FrameError, FrameLimits, INCOMPATIBLE_FRAME, read_frame},
+        handshake::{self, HANDSHAKE_FAILED, Hello},
+        inbound::{InboundConfig, InboundQueues, PushOutcome},
+        outbound::{OutboundConfig, OutboundStream},
+        reconnect::{Backoff, BackoffConfig},
+    },
 };
 
+/// Keep-alives stop quiet peer connections from idling out, while the short
+/// idle timeout lets a crashed peer be noticed (and redialed) quickly.
+pub const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(2);
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(6);
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkConfig {
+    /// Stamped on every frame and exchanged in the handshake, so replicas and
+    /// clients of another cluster on the same hosts are turned away.
+    pub cluster_id: u32,
+    pub frame_limits: FrameLimits,
+    pub inbound: InboundConfig,
+    pub outbound: OutboundConfig,
+    pub reconnect: BackoffConfig,
+}
+
+/// Receive-side state shared with the acceptor and per-connection tasks.
+struct Inbound {
+    pinned: PinnedKeys,
+    frame_limits: FrameLimits,
+    queues: InboundQueues,
+    /// Connections accepted from clients, used to send them replies.
+    clients: std::sync::Mutex<HashMap<u32, Peer>>,
+    metrics: Arc<Metrics>,
+    /// What we answer replicas' `Hello`s with.
+    hello: Hello,
+    outbound: OutboundConfig,
+}
+
+/// A connection we send on: ours to a replica that completed the handshake,
+/// or a client's to us.
+#[derive(Clone)]
+struct Peer {
+    connection: Connection,
+    stream: OutboundStream,
+}
+
+impl Peer {
+    fn new(connection: Connection, envelope: Envelope, inbound: &Inbound) -> Self {
+        let stream = OutboundStream::spawn(
+            connection.clone(),
+            envelope,
+            &inbound.outbound,
+            inbound.metrics.clone(),
+        );
+        Peer { connection, stream }
+    }
+}
+
+/// Outgoing connections to replicas that completed the handshake.
+struct Peers {
+    connections: RwLock<HashMap<u32, Peer>>,
+    /// How many there are, for `Network::wait_for_peers`.
+    connected: watch::Sender<usize>,
+}
+
+impl Peers {
+    async fn insert(&self, peer_id: u32, peer: Peer) {
+        let mut connections = self.connections.write().await;
+        connections.insert(peer_id, peer);
+        self.connected.send_replace(connections.len());
+    }
+
+    /// Removes the peer's connection, only if it is `stable_id` when given.
+    async fn remove(&self, peer_id: u32, stable_id: Option<usize>) -> Option<Connection> {
+        let mut connections = self.connections.write().await;
+        if stable_id.is_some_and(|id| {
+            connections
+                .get(&peer_id)
+                .is_some_and(|p| p.connection.stable_id() != id)
+        }) {
+            return None;
+        }
+        let removed = connections.remove(&peer_id);
+        self.connected.send_replace(connections.len());
+        removed.map(|p| p.connection)
+    }
+}
+
 pub struct Network {
     node_id: u32,
     endpoint: Endpoint,
-    peers: Arc<RwLock<HashMap<u32, Connection>>>,
-    tx: UnboundedSender<PBFTMessage>,
-    rx: UnboundedReceiver<PBFTMessage>,
-    total_nodes: u32,
+    pinned: PinnedKeys,
+    peers: Arc<Peers>,
+    /// Background tasks keeping each peer's outgoing connection alive.
+    managers: std::sync::Mutex<HashMap<u32, AbortHandle>>,
+    reconnect: BackoffConfig,
+    inbound: Arc<Inbound>,
+    total_nodes: AtomicU32,
 }
 
 impl Network {
@@ -24,12 +130,14 @@ impl Network {
         node_id: u32,
         bind_addr: SocketAddr,
         node_cert: &NodeCert,
+        pinned: PinnedKeys,
+        config: NetworkConfig,
         total_nodes: u32,
     ) -> Self {
-        let server_cfg = make_server_config(node_cert);
-        let client_cfg = make_client_config();
+        let server_cfg = make_server_config(node_cert, pinned.clone());
+        let client_cfg = make_client_config(node_cert, pinned.clone());
 
-        println!("Creating QUIC endpoint on {}...", bind_addr);
+        debug!(%bind_addr, "Creating QUIC endpoint");
 
         let mut endpoint = match Endpoint::server(
             quinn::ServerConfig::with_crypto(Arc::new(
@@ -39,143 +147,452 @@ impl Network {
             bind_addr,
         ) {
             Ok(ep) => {
-                println!("QUIC server started successfully on {}", bind_addr);
+                info!(%bind_addr, "QUIC server started");
                 ep
             }
             Err(e) => {
-                eprintln!("Failed to start QUIC server: {:?}", e);
+                error!(%bind_addr, error = ?e, "Failed to start QUIC server");
                 panic!("Cannot start server");
             }
         };
 
-        endpoint.set_default_client_config(quinn::ClientConfig::new(Arc::new(
+        let mut transport = TransportConfig::default();
+        transport.keep_alive_interval(Some(KEEP_ALIVE_INTERVAL));
+        transport.max_idle_timeout(Some(IDLE_TIMEOUT.try_into().unwrap()));
+        let mut quic_client_cfg = quinn::ClientConfig::new(Arc::new(
             quinn::crypto::rustls::QuicClientConfig::try_from(client_cfg)
                 .expect("Failed to create QUIC client config"),
-        )));
+        ));
+        quic_client_cfg.transport_config(Arc::new(transport));
+        endpoint.set_default_client_config(quic_client_cfg);
 
-        let (tx, rx) = mpsc::unbounded_channel();
+        let metrics = Arc::new(Metrics::new());
+        let inbound = Inbound {
+            pinned: pinned.clone(),
+            frame_limits: config.frame_limits,
+            queues: InboundQueues::new(config.inbound, metrics.clone()),
+            clients: std::sync::Mutex::new(HashMap::new()),
+            metrics,
+            hello: Hello::new(node_id, config.cluster_id),
+            outbound: config.outbound,
+        };
 
         Network {
             node_id,
             endpoint,
-            peers: Arc::new(RwLock::new(HashMap::new())),
-            tx,
-            rx,
-            total_nodes,
+            pinned,
+            peers: Arc::new(Peers {
+                connections: RwLock::new(HashMap::new()),
+                connected: watch::Sender::new(0),
+            }),
+            managers: std::sync::Mutex::new(HashMap::new()),
+            reconnect: config.reconnect,
+            inbound: Arc::new(inbound),
+            total_nodes: AtomicU32::new(total_nodes),
+        }
+    }
+
+    /// Keeps an outgoing connection to `peer_id` for as long as it remains a
+    /// peer: dials it in the background, exchanges `Hello`s, and redials with
+    /// backoff whenever the connection or handshake fails. Peers may come up
+    /// in any order; each one is used as soon as its handshake completes.
+    /// Does nothing if the peer is already managed.
+    pub fn add_peer(&self, peer_id: u32, peer_addr: SocketAddr) {
+        let mut managers = self.managers.lock().unwrap();
+        if managers.contains_key(&peer_id) {
+            return;
+        }
+
+        let task = tokio::spawn(
+            Self::maintain_connection(
+                self.endpoint.clone(),
+                self.peers.clone(),
+                self.inbound.clone(),
+                peer_id,
+                peer_addr,
+                Backoff::new(self.reconnect.clone()),
+            )
+            .instrument(info_span!("peer", id = peer_id, addr = %peer_addr)),
+        );
+        managers.insert(peer_id, task.abort_handle());
+    }
+
+    pub async fn remove_peer(&self, peer_id: u32) {
+        if let Some(task) = self.managers.lock().unwrap().remove(&peer_id) {
+            task.abort();
+        }
+        self.inbound.metrics.remove_peer(peer_id);
+        if let Some(connection) = self.peers.remove(peer_id, None).await {
+            connection.close(0u32.into(), b"Removed from membership");
         }
     }
 
-    pub async fn connect_to_peer_with_timeout(
-        &self,
+    pub async fn connected_peers(&self) -> Vec<u32> {
+        self.peers
+            .connections
+            .read()
+            .await
+            .keys()
+            .copied()
+            .collect()
+    }
+
+    /// Waits until at least `count` peers have completed the handshake.
+    pub async fn wait_for_peers(&self, count: usize) {
+        let mut connected = self.peers.connected.subscribe();
+        let _ = connected.wait_for(|&n| n >= count).await;
+    }
+
+    /// The address the endpoint is bound to.
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.endpoint.local_addr()
+    }
+
+    async fn maintain_connection(
+        endpoint: Endpoint,
+        peers: Arc<Peers>,
+        inbound: Arc<Inbound>,
         peer_id: u32,
         peer_addr: SocketAddr,
-    ) -> Result<(), String> {
-        println!("Attempting to connect to {}...", peer_addr);
+        mut backoff: Backoff,
+    ) {
+        let metrics = &inbound.metrics;
+        metrics.set_peer_connected(peer_id, false);
+        loop {
+            let connection = match Self::connect(&endpoint, peer_id, peer_addr).await {
+                Ok(connection) => connection,
+                Err(e) => {
+                    let delay = backoff.next_delay();
+                    debug!(error = %e, ?delay, "Could not reach peer, retrying");
+                    tokio::time::sleep(delay).await;
+                    continue;
+                }
+            };
+            let version = match handshake::greet(&connection, inbound.hello, peer_id).await {
+                Ok(version) => version,
+                Err(e) => {
+                    connection.close(HANDSHAKE_FAILED.into(), e.to_string().as_bytes());
+                    let delay = backoff.next_delay();
+                    warn!(error = %e, ?delay, "Handshake with peer failed, retrying");
+                    tokio::time::sleep(delay).await;
+                    continue;
+                }
+            };
 
-        let connect_future = self.endpoint.connect(peer_addr, "peer");
+            info!(version, "Connected to peer");
+            metrics.set_peer_connected(peer_id, true);
+            backoff.reset();
+            let envelope = Envelope {
+                version,
+                cluster_id: inbound.hello.cluster_id,
+            };
+            peers
+                .insert(peer_id, Peer::new(connection.clone(), envelope, &inbound))
+                .await;
 
-        let connecting = match connect_future {
-            Ok(conn) => {
-                println!("Connection initiated...");
-                conn
-            }
-            Err(e) => {
-                return Err(format!("Failed to initiate connection: {:?}", e));
-            }
-        };
+            let reason = connection.closed().await;
+            warn!(%reason, "Connection to peer lost");
+            metrics.set_peer_connected(peer_id, false);
+            peers.remove(peer_id, Some(connection.stable_id())).await;
+        }
+    }
+
+    async fn connect(
+        endpoint: &Endpoint,
+        peer_id: u32,
+        peer_addr: SocketAddr,
+    ) -> Result<Connection, String> {
+        let connecting = endpoint
+            .connect(peer_addr, &replica_server_name(peer_id))
+            .map_err(|e| format!("Failed to initiate connection: {:?}", e))?;
 
         match tokio::time::timeout(tokio::time::Duration::from_secs(5), connecting).await {
-            Ok(Ok(connection)) => {
-                println!("Connection established!");
-                let mut peers = self.peers.write().await;
-                peers.insert(peer_id, connection);
-                Ok(())
-            }
+            Ok(Ok(connection)) => Ok(connection),
             Ok(Err(e)) => Err(format!("Connection failed: {:?}", e)),
             Err(_) => Err("Connection timed out (no response from peer)".to_string()),
         }
     }
 
-    pub async fn connect_to_peer(&self, peer_id: u32, peer_addr: SocketAddr) {
-        match self.connect_to_peer_with_timeout(peer_id, peer_addr).await {
-            Ok(_) => {}
-            Err(e) => panic!("Failed to connect to peer: {}", e),
-        }
-    }
-
     pub fn spawn_acceptor(&self) {
         let endpoint = self.endpoint.clone();
-        let tx = self.tx.clone();
+        let inbound = self.inbound.clone();
+
+        tokio::spawn(
+            async move {
+                while let Some(incoming) = endpoint.accept().await {
+                    let inbound = inbound.clone();
 
-        tokio::spawn(async move {
-            while let Some(incoming) = endpoint.accept().await {
-                let connection = incoming.await.expect("Failed to accept connection");
-                let tx = tx.clone();
+                    tokio::spawn(
+                        async move {
+                            let connection = match incoming.await {
+                                Ok(connection) => connection,
+                                Err(e) => {
+                                    warn!(error = ?e, "Failed to accept connection");
+                                    return;
+                                }
+                            };
 
-                tokio::spawn(async move {
-                    println!("Connection accepted from {:?}", connection.remote_address());
-                    Self::handle_connection(connection, tx).await;
-                });
+                            let Some(identity) = Self::peer_identity(&connection, &inbound.pinned)
+                            else {
+                                warn!(
+                                    remote = %connection.remote_address(),
+                                    "Rejecting connection: unknown identity"
+                                );
+                                connection.close(1u32.into(), b"Unknown identity");
+                                return;
+                            };
+
+                            info!(
+                                remote = %connection.remote_address(),
+                                ?identity,
+                                "Connection accepted"
+                            );
+                            match identity {
+                                PeerIdentity::Client(id) => {
+                                    // Clients get replies in our newest wire version.
+                                    let peer = Peer::new(
+                                        connection.clone(),
+                                        Envelope::new(inbound.hello.cluster_id),
+                                        &inbound,
+                                    );
+                                    inbound.clients.lock().unwrap().insert(id, peer);
+                                }
+                                PeerIdentity::Replica(id) => {
+                                    tokio::spawn(
+                                        Self::answer_hello(connection.clone(), id, inbound.hello)
+                                            .in_current_span(),
+                                    );
+                                }
+                            }
+
+                            Self::handle_connection(connection.clone(), identity, inbound.clone())
+                                .await;
+
+                            if let PeerIdentity::Client(id) = identity {
+                                let mut clients = inbound.clients.lock().unwrap();
+                                if clients.get(&id).is_some_and(|p| {
+                                    p.connection.stable_id() == connection.stable_id()
+                                }) {
+                                    clients.remove(&id);
+                                }
+                            }
+                        }
+                        .in_current_span(),
+                    );
+                }
             }
-        });
+            .in_current_span(),
+        );
     }
 
-    async fn handle_connection(connection: Connection, tx: UnboundedSender<PBFTMessage>) {
-        while let Ok(mut recv_stream) = connection.accept_uni().await {
-            let inbound_tx = tx.clone();
-            tokio::spawn(async move {
-                if let Some(msg) = Self::read_message(&mut recv_stream).await {
-                    let _ = inbound_tx.send(msg);
-                }
-            });
+    /// Answers the `Hello` of a replica that dialed us, closing the connection
+    /// if it is not one we can talk to.
+    async fn answer_hello(connection: Connection, peer_id: u32, hello: Hello) {
+        match handshake::answer(&connection, hello, peer_id).await {
+            Ok(version) => debug!(peer = peer_id, version, "Answered peer hello"),
+            Err(e) => {
+                warn!(peer = peer_id, error = %e, "Rejecting peer");
+                connection.close(HANDSHAKE_FAILED.into(), e.to_string().as_bytes());
+            }
         }
     }
 
-    async fn read_message(stream: &mut RecvStream) -> Option<PBFTMessage> {
-        let mut len_bytes = [0u8; 4];
-        stream.read_exact(&mut len_bytes).await.ok()?;
-        let len = u32::from_be_bytes(len_bytes) as usize;
+    /// Maps the certificate presented during the mutual TLS handshake back to
+    /// the replica or client it was pinned for.
+    fn peer_identity(connection: &Connection, pinned: &PinnedKeys) -> Option<PeerIdentity> {
+        let certs = connection
+            .peer_identity()?
+            .downcast::<Vec<CertificateDer<'static>>>()
+            .ok()?;
+        pinned.identify_cert(certs.first()?)
+    }
 
-        let mut buf = vec![0u8; len];
-        stream.read_exact(&mut buf).await.ok()?;
+    /// Whether `identity` is allowed to have sent `message`: replicas only
+    /// send protocol messages signed by themselves, clients only their own
+    /// requests. Pre-prepares are the exception: backups relay the primary's
+    /// so that equivocation can be caught, and its signature vouches for it.
+    fn sender_matches(identity: PeerIdentity, message: &PBFTMessage) -> bool {
+        let signer_id = message.signer_id();
 
-        postcard::from_bytes(&buf).ok()
+        match (identity, message) {
+            (PeerIdentity::Client(id), PBFTMessage::Request(_) | PBFTMessage::AdminRequest(_)) => {
+                signer_id == id
+            }
+            (PeerIdentity::Client(_), _)
+            | (PeerIdentity::Replica(_), PBFTMessage::Request(_) | PBFTMessage::AdminRequest(_)) => {
+                false
+            }
+            (PeerIdentity::Replica(_), PBFTMessage::PrePrepare(_)) => true,
+            (PeerIdentity::Replica(id), _) => {
+                signer_id == id && message.replica_id().is_none_or(|r| r == id)
+            }
+        }
     }
 
-    async fn send_to(&self, peer_id: u32, message: &PBFTMessage) {
-        let peers = self.peers.read().await;
-        if let Some(connection) = peers.get(&peer_id) {
-            if let Ok(mut send_stream) = connection.open_uni().await {
-                let _ = Self::write_message(&mut send_stream, message).await;
-                let _ = send_stream.finish();
-            }
+    /// Reads frames off the connection's uni streams, at most
+    /// `max_concurrent_streams` at a time, into the peer's inbound queue.
+    /// Each stream carries any number of frames, queued in stream order.
+    async fn handle_connection(
+        connection: Connection,
+        identity: PeerIdentity,
+        inbound: Arc<Inbound>,
+    ) {
+        let streams = Arc::new(Semaphore::new(
+            inbound.queues.config().max_concurrent_streams,
+        ));
+
+        loop {
+            let Ok(permit) = streams.clone().acquire_owned().await else {
+                return;
+            };
+            let Ok(recv_stream) = connection.accept_uni().await else {
+                return;
+            };
+
+            let connection = connection.clone();
+            let inbound = inbound.clone();
+            tokio::spawn(
+                async move {
+                    let _permit = permit;
+                    Self::read_stream(recv_stream, connection, identity, inbound).await;
+                }
+                .in_current_span(),
+            );
         }
     }
 
-    pub async fn broadcast(&self, message: &PBFTMessage) {
-        let peers = self.peers.read().await;
-        for (peer_id, connection) in peers.iter() {
-            if let Ok(mut send_stream) = connection.open_uni().await {
-                let _ = Self::write_message(&mut send_stream, message).await;
-                let _ = send_stream.finish();
+    async fn read_stream(
+        mut recv_stream: RecvStream,
+        connection: Connection,
+        identity: PeerIdentity,
+        inbound: Arc<Inbound>,
+    ) {
+        loop {
+            let msg = match read_frame(
+                &mut recv_stream,
+                inbound.hello.cluster_id,
+                &inbound.frame_limits,
+                &inbound.metrics.frames_rejected,
+            )
+            .await
+            {
+                Ok(msg) => msg,
+                Err(FrameError::Closed) => return,
+                Err(e) if e.is_incompatible() => {
+                    warn!(?identity, error = %e, "Disconnecting incompatible peer");
+                    inbound.metrics.record_peer_message(identity, false);
+                    connection.close(INCOMPATIBLE_FRAME.into(), e.to_string().as_bytes());
+                    return;
+                }
+                Err(e) => {
+                    // The rest of the stream can't be framed any more; the
+                    // sender opens a new one.
+                    warn!(?identity, error = %e, "Dropping frame");
+                    inbound.metrics.record_peer_message(identity, false);
+                    let _ = recv_stream.stop(1u32.into());
+                    return;
+                }
+            };
+
+            if !Self::sender_matches(identity, &msg) {
+                warn!(
+                    ?identity,
+                    kind = ?msg.kind(),
+                    signer = msg.signer_id(),
+                    "Rejected message not sent by its signer"
+                );
+                inbound.metrics.record_peer_message(identity, false);
+                continue;
+            }
+
+            trace!(?identity, kind = ?msg.kind(), "Received message");
+            inbound.metrics.messages_received.inc(msg.kind());
+            inbound.metrics.record_peer_message(identity, true);
+            match inbound.queues.push(identity, msg) {
+                PushOutcome::Queued => {}
+                PushOutcome::Dropped => {
+                    warn!(?identity, "Inbound queue full, dropping message");
+                }
+                PushOutcome::Disconnect => {
+                    warn!(?identity, "Disconnecting: inbound queue kept overflowing");
+                    connection.close(2u32.into(), b"Inbound queue overflow");
+                    return;
+                }
             }
         }
     }
 
-    async fn write_message(stream: &mut SendStream, message: &PBFTMessage) {
-        if let Ok(serialized) = postcard::to_allocvec(message) {
-            let len = serialized.len() as u32;
+    /// Queues `message` for a replica over our connection to it, or for a
+    /// client over the connection it opened to us. Messages to the same
+    /// peer arrive in the order they were sent, or not at all.
+    pub async fn send_to(&self, peer_id: u32, message: &PBFTMessage) {
+        let stream = match self.peers.connections.read().await.get(&peer_id) {
+            Some(peer) => Some(peer.stream.clone()),
+            None => self
+                .inbound
+                .clients
+                .lock()
+                .unwrap()
+                .get(&peer_id)
+                .map(|peer| peer.stream.clone()),
+        };
+        if let Some(stream) = stream {
+            stream.send(Arc::new(message.clone()));
+        }
+    }
 
-            let _ = stream.write_all(&len.to_be_bytes()).await;
-            let _ = stream.write_all(&serialized).await;
+    /// Queues `message` for every connected replica; each peer's stream
+    /// writes it independently of the others.
+    pub async fn broadcast(&self, message: &PBFTMessage) {
+        let message = Arc::new(message.clone());
+        for peer in self.peers.connections.read().await.values() {
+            peer.stream.send(message.clone());
         }
     }
 
+    /// Counters shared with the replica and served on `/metrics`.
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.inbound.metrics.clone()
+    }
+
     pub async fn recv(&mut self) -> Option<PBFTMessage> {
-        self.rx.recv().await
+        Some(self.inbound.queues.recv().await)
     }
 
     pub fn total_nodes(&self) -> u32 {
-        4
+        self.total_nodes.load(Ordering::SeqCst)
+    }
+
+    /// Brings the peer set in line with a newly executed membership: drops
+    /// connections to removed replicas and dials added ones in the background.
+    pub async fn apply_membership(&self, membership: &Membership) {
+        self.total_nodes
+            .store(membership.total_nodes(), Ordering::SeqCst);
+        self.pinned.set_replicas(
+            membership
+                .replicas()
+                .map(|r| (r.id, r.public_key.clone()))
+                .collect(),
+        );
+        self.pinned.set_clients(membership.client_public_keys());
+
+        let removed: Vec<u32> = self
+            .managers
+            .lock()
+            .unwrap()
+            .keys()
+            .copied()
+            .filter(|id| !membership.contains(*id))
+            .collect();
+        for peer_id in removed {
+            info!(peer = peer_id, "Disconnecting removed replica");
+            self.remove_peer(peer_id).await;
+        }
+
+        for replica in membership.replicas() {
+            if replica.id != self.node_id {
+                self.add_peer(replica.id, replica.addr);
+            }
+        }
     }
 }
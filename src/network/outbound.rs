@@ -0,0 +1,130 @@
+use quinn::{Connection, SendStream};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::mpsc::{self, Receiver, Sender, error::TrySendError};
+use tracing::{Instrument, debug, warn};
+
+use crate::{
+    message::message_types::{MessageKind, PBFTMessage},
+    metrics::registry::Metrics,
+    network::framing::{Envelope, encode_frame_into},
+};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OutboundConfig {
+    /// Messages queued per connection before new ones to it are dropped.
+    pub queue_capacity: usize,
+    /// Messages that queued up while a write was in flight go out together
+    /// in one write of up to this many bytes.
+    pub max_batch_bytes: usize,
+}
+
+impl Default for OutboundConfig {
+    fn default() -> Self {
+        OutboundConfig {
+            queue_capacity: 1024,
+            max_batch_bytes: 64 * 1024,
+        }
+    }
+}
+
+/// The sending half of a connection: a single long-lived uni stream, written
+/// by a background task in the order messages were queued, so messages to one
+/// peer arrive in order. Queuing never waits on the network, which lets a
+/// broadcast reach every peer at once however slow any one of them is.
+///
+/// The task ends once the connection closes or every handle is dropped.
+#[derive(Clone)]
+pub struct OutboundStream {
+    tx: Sender<Arc<PBFTMessage>>,
+}
+
+impl OutboundStream {
+    pub fn spawn(
+        connection: Connection,
+        envelope: Envelope,
+        config: &OutboundConfig,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        let (tx, rx) = mpsc::channel(config.queue_capacity.max(1));
+        tokio::spawn(
+            write_queued(connection, envelope, rx, config.max_batch_bytes, metrics)
+                .in_current_span(),
+        );
+        OutboundStream { tx }
+    }
+
+    /// Queues `message`. Returns false if it was dropped because the queue
+    /// is full or the connection is gone.
+    pub fn send(&self, message: Arc<PBFTMessage>) -> bool {
+        match self.tx.try_send(message) {
+            Ok(()) => true,
+            Err(TrySendError::Full(message)) => {
+                warn!(kind = ?message.kind(), "Outbound queue full, dropping message");
+                false
+            }
+            Err(TrySendError::Closed(_)) => false,
+        }
+    }
+}
+
+async fn write_queued(
+    connection: Connection,
+    envelope: Envelope,
+    mut rx: Receiver<Arc<PBFTMessage>>,
+    max_batch_bytes: usize,
+    metrics: Arc<Metrics>,
+) {
+    let mut stream: Option<SendStream> = None;
+    let mut batch = Vec::new();
+    let mut kinds: Vec<MessageKind> = Vec::new();
+
+    while let Some(first) = rx.recv().await {
+        batch.clear();
+        kinds.clear();
+        let mut next = Some(first);
+        while let Some(message) = next {
+            match encode_frame_into(&mut batch, envelope, &message) {
+                Ok(()) => kinds.push(message.kind()),
+                Err(e) => warn!(kind = ?message.kind(), error = %e, "Cannot encode message"),
+            }
+            next = if batch.len() < max_batch_bytes {
+                rx.try_recv().ok()
+            } else {
+                None
+            };
+        }
+
+        let send = match stream.as_mut() {
+            Some(send) => send,
+            None => match connection.open_uni().await {
+                Ok(opened) => stream.insert(opened),
+                Err(e) => {
+                    debug!(error = %e, "Connection gone, stopping outbound stream");
+                    return;
+                }
+            },
+        };
+        match send.write_all(&batch).await {
+            Ok(()) => {
+                for &kind in &kinds {
+                    metrics.messages_sent.inc(kind);
+                }
+            }
+            Err(e) => {
+                // The batch is lost; a stream the peer stopped is replaced
+                // on the next send, a closed connection ends the task.
+                debug!(error = %e, dropped = kinds.len(), "Outbound stream failed");
+                stream = None;
+                if connection.close_reason().is_some() {
+                    return;
+                }
+            }
+        }
+    }
+
+    if let Some(mut send) = stream {
+        let _ = send.finish();
+    }
+}
@@ -0,0 +1,48 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BackoffConfig {
+    pub initial_ms: u64,
+    pub max_ms: u64,
+    pub multiplier: u32,
+}
+
+impl Default for BackoffConfig {
+    fn default() -> Self {
+        BackoffConfig {
+            initial_ms: 100,
+            max_ms: 10_000,
+            multiplier: 2,
+        }
+    }
+}
+
+/// Exponential backoff with jitter: each delay is drawn uniformly from the
+/// upper half of the current step, so peers that lost each other at the same
+/// moment don't keep retrying in lockstep.
+pub struct Backoff {
+    config: BackoffConfig,
+    current_ms: u64,
+}
+
+impl Backoff {
+    pub fn new(config: BackoffConfig) -> Self {
+        let current_ms = config.initial_ms;
+        Backoff { config, current_ms }
+    }
+
+    pub fn next_delay(&mut self) -> Duration {
+        let step = self.current_ms.clamp(1, self.config.max_ms.max(1));
+        self.current_ms = step
+            .saturating_mul(self.config.multiplier as u64)
+            .min(self.config.max_ms);
+
+        Duration::from_millis(rand::random_range(step / 2..=step))
+    }
+
+    pub fn reset(&mut self) {
+        self.current_ms = self.config.initial_ms;
+    }
+}
@@ -0,0 +1,113 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, RwLock},
+};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+use crate::{
+    config::membership::Membership, message::message_types::PBFTMessage,
+    network::network_layer::Network,
+};
+
+/// What a `Replica` needs from the network. `Network` implements it over
+/// QUIC; `MemoryTransport` over in-process channels.
+///
+/// Sends never wait for the peer. Messages from one replica to another
+/// arrive in the order they were sent, though any of them may be lost.
+pub trait Transport {
+    fn broadcast(&self, message: &PBFTMessage) -> impl Future<Output = ()> + Send;
+
+    fn send_to(&self, peer_id: u32, message: &PBFTMessage) -> impl Future<Output = ()> + Send;
+
+    fn recv(&mut self) -> impl Future<Output = Option<PBFTMessage>> + Send;
+
+    /// Called after a reconfiguration has been executed.
+    fn apply_membership(&self, _membership: &Membership) -> impl Future<Output = ()> + Send {
+        async {}
+    }
+}
+
+impl Transport for Network {
+    async fn broadcast(&self, message: &PBFTMessage) {
+        Network::broadcast(self, message).await
+    }
+
+    async fn send_to(&self, peer_id: u32, message: &PBFTMessage) {
+        Network::send_to(self, peer_id, message).await
+    }
+
+    async fn recv(&mut self) -> Option<PBFTMessage> {
+        Network::recv(self).await
+    }
+
+    async fn apply_membership(&self, membership: &Membership) {
+        Network::apply_membership(self, membership).await
+    }
+}
+
+type Mailboxes = Arc<RwLock<HashMap<u32, UnboundedSender<PBFTMessage>>>>;
+
+/// In-process switchboard connecting `MemoryTransport`s, for running a whole
+/// cluster inside one process.
+#[derive(Clone, Default)]
+pub struct MemoryNetwork {
+    mailboxes: Mailboxes,
+}
+
+impl MemoryNetwork {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn join(&self, node_id: u32) -> MemoryTransport {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.mailboxes.write().unwrap().insert(node_id, tx);
+
+        MemoryTransport {
+            node_id,
+            mailboxes: self.mailboxes.clone(),
+            rx,
+        }
+    }
+
+    /// Delivers `message` to `node_id`, e.g. to inject a client request.
+    pub fn send(&self, node_id: u32, message: PBFTMessage) {
+        if let Some(tx) = self.mailboxes.read().unwrap().get(&node_id) {
+            let _ = tx.send(message);
+        }
+    }
+}
+
+pub struct MemoryTransport {
+    node_id: u32,
+    mailboxes: Mailboxes,
+    rx: UnboundedReceiver<PBFTMessage>,
+}
+
+impl MemoryTransport {
+    pub fn try_recv(&mut self) -> Option<PBFTMessage> {
+        self.rx.try_recv().ok()
+    }
+}
+
+impl Transport for MemoryTransport {
+    async fn broadcast(&self, message: &PBFTMessage) {
+        let mailboxes = self.mailboxes.read().unwrap();
+        for (node_id, tx) in mailboxes.iter() {
+            if *node_id != self.node_id {
+                let _ = tx.send(message.clone());
+            }
+        }
+    }
+
+    async fn send_to(&self, peer_id: u32, message: &PBFTMessage) {
+        if let Some(tx) = self.mailboxes.read().unwrap().get(&peer_id) {
+            let _ = tx.send(message.clone());
+        }
+    }
+
+    async fn recv(&mut self) -> Option<PBFTMessage> {
+        self.rx.recv().await
+    }
+}
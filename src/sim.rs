@@ -0,0 +1,3 @@
+pub mod rng;
+pub mod sim_network;
+pub mod simulator;
@@ -0,0 +1,85 @@
+use std::sync::{Arc, Mutex};
+
+use crate::{
+    config::membership::Membership, message::message_types::PBFTMessage,
+    network::transport::Transport,
+};
+
+/// A message a simulated replica handed to its transport.
+#[derive(Clone, Debug)]
+pub struct Envelope {
+    pub from: u32,
+    pub to: u32,
+    pub message: PBFTMessage,
+}
+
+/// Messages sent by any replica since the simulator last collected them.
+#[derive(Clone, Default)]
+pub struct Outbox {
+    envelopes: Arc<Mutex<Vec<Envelope>>>,
+}
+
+impl Outbox {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&self, envelope: Envelope) {
+        self.envelopes.lock().unwrap().push(envelope);
+    }
+
+    pub fn take(&self) -> Vec<Envelope> {
+        std::mem::take(&mut *self.envelopes.lock().unwrap())
+    }
+}
+
+/// Transport that only records what a replica sends; the simulator decides
+/// when, whether and how often each message is delivered. Broadcasts go out
+/// in replica id order so runs stay deterministic.
+pub struct SimTransport {
+    node_id: u32,
+    replica_ids: Mutex<Vec<u32>>,
+    outbox: Outbox,
+}
+
+impl SimTransport {
+    pub fn new(node_id: u32, membership: &Membership, outbox: Outbox) -> Self {
+        SimTransport {
+            node_id,
+            replica_ids: Mutex::new(membership.replicas().map(|r| r.id).collect()),
+            outbox,
+        }
+    }
+}
+
+impl Transport for SimTransport {
+    async fn broadcast(&self, message: &PBFTMessage) {
+        let replica_ids = self.replica_ids.lock().unwrap().clone();
+        for to in replica_ids {
+            if to != self.node_id {
+                self.outbox.push(Envelope {
+                    from: self.node_id,
+                    to,
+                    message: message.clone(),
+                });
+            }
+        }
+    }
+
+    async fn send_to(&self, peer_id: u32, message: &PBFTMessage) {
+        self.outbox.push(Envelope {
+            from: self.node_id,
+            to: peer_id,
+            message: message.clone(),
+        });
+    }
+
+    /// Never used: the simulator calls `Replica::handle_message` directly.
+    async fn recv(&mut self) -> Option<PBFTMessage> {
+        None
+    }
+
+    async fn apply_membership(&self, membership: &Membership) {
+        *self.replica_ids.lock().unwrap() = membership.replicas().map(|r| r.id).collect();
+    }
+}
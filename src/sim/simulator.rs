@@ -0,0 +1,496 @@
+use ring::signature::Ed25519KeyPair;
+use sha2::{Digest, Sha256};
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap},
+    pin::pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+use crate::{
+    config::membership::Membership,
+    crypto::primitives::Crypto,
+    message::message_types::{ClientInfo, PBFTMessage, ReplicaInfo, Request},
+    network::byzantine::{ByzantineConfig, ByzantineTransport},
+    sim::{
+        rng::SimRng,
+        sim_network::{Envelope, Outbox, SimTransport},
+    },
+    state::{
+        clock::VirtualClock,
+        events::ReplicaEvent,
+        invariants::{InvariantChecker, Violation},
+        replica::Replica,
+    },
+};
+
+/// Id of the simulated client.
+pub const CLIENT_ID: u32 = 1000;
+
+/// Environment variable that overrides the seed, to replay a failing run.
+pub const SEED_ENV: &str = "PBFT_SIM_SEED";
+
+#[derive(Clone, Debug)]
+pub struct NetworkFaults {
+    /// Every copy of a message is delayed by a uniform draw from this range;
+    /// independent delays are what reorder messages.
+    pub min_delay_ms: u64,
+    pub max_delay_ms: u64,
+    pub drop_probability: f64,
+    pub duplicate_probability: f64,
+}
+
+impl Default for NetworkFaults {
+    fn default() -> Self {
+        NetworkFaults {
+            min_delay_ms: 1,
+            max_delay_ms: 20,
+            drop_probability: 0.0,
+            duplicate_probability: 0.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SimConfig {
+    pub seed: u64,
+    pub replicas: u32,
+    /// Client requests issued, one every `request_interval_ms`.
+    pub requests: u64,
+    pub request_interval_ms: u64,
+    /// The client resends a request to every replica this often until it
+    /// has f + 1 matching replies.
+    pub client_retry_ms: u64,
+    /// Replicas that neither receive nor send anything.
+    pub crashed: Vec<u32>,
+    /// Replicas that tamper with what they send.
+    pub byzantine: Vec<ByzantineConfig>,
+    pub faults: NetworkFaults,
+    pub tick_ms: u64,
+    /// Virtual time after which the run stops even if requests are pending.
+    pub max_time_ms: u64,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        SimConfig {
+            seed: 0,
+            replicas: 4,
+            requests: 10,
+            request_interval_ms: 10,
+            client_retry_ms: 500,
+            crashed: Vec::new(),
+            byzantine: Vec::new(),
+            faults: NetworkFaults::default(),
+            tick_ms: 50,
+            max_time_ms: 60_000,
+        }
+    }
+}
+
+impl SimConfig {
+    pub fn with_seed(seed: u64) -> Self {
+        SimConfig {
+            seed,
+            ..Self::default()
+        }
+    }
+}
+
+/// Seed from `PBFT_SIM_SEED`, if set.
+pub fn seed_from_env() -> Option<u64> {
+    std::env::var(SEED_ENV).ok()?.parse().ok()
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReplicaSummary {
+    pub node_id: u32,
+    pub view: u64,
+    pub last_executed: u64,
+}
+
+/// Outcome of a run. Two runs with the same config produce equal reports.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SimReport {
+    pub seed: u64,
+    /// Whether every correct replica executed every request before the
+    /// deadline.
+    pub completed: bool,
+    pub end_time_ms: u64,
+    pub delivered: u64,
+    pub dropped: u64,
+    pub duplicated: u64,
+    /// Hash over every delivery (time, sender, receiver, message), in order.
+    pub trace_digest: [u8; 32],
+    pub replicas: Vec<ReplicaSummary>,
+    /// Safety violations found by the `InvariantChecker`.
+    pub violations: Vec<Violation>,
+}
+
+enum Action {
+    Deliver(Envelope),
+    Tick(u32),
+    ClientSend(u64),
+}
+
+struct Event {
+    at_ms: u64,
+    /// Scheduling order, breaking ties between events due at the same time.
+    order: u64,
+    action: Action,
+}
+
+impl PartialEq for Event {
+    fn eq(&self, other: &Self) -> bool {
+        (self.at_ms, self.order) == (other.at_ms, other.order)
+    }
+}
+
+impl Eq for Event {}
+
+impl PartialOrd for Event {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Event {
+    // Reversed, so the `BinaryHeap` pops the earliest event first.
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.at_ms, other.order).cmp(&(self.at_ms, self.order))
+    }
+}
+
+/// Decides whether a message gets onto the simulated network at all.
+type MessageFilter = Box<dyn FnMut(&Envelope) -> bool>;
+
+/// Runs a whole cluster of `Replica`s on one thread against a virtual clock.
+/// Every source of nondeterminism (delays, drops, duplicates, event order)
+/// is drawn from a single seeded generator, so a seed fully determines a run.
+pub struct Simulator {
+    config: SimConfig,
+    rng: SimRng,
+    clock: VirtualClock,
+    replicas: BTreeMap<u32, (Replica, ByzantineTransport<SimTransport>)>,
+    outbox: Outbox,
+    events: Arc<Mutex<Vec<(u32, ReplicaEvent)>>>,
+    checker: InvariantChecker,
+    client: Crypto,
+    /// Replying replicas per request timestamp and result.
+    replies: HashMap<u64, HashMap<Vec<u8>, BTreeSet<u32>>>,
+    queue: BinaryHeap<Event>,
+    next_order: u64,
+    trace: Sha256,
+    delivered: u64,
+    dropped: u64,
+    duplicated: u64,
+    filter: Option<MessageFilter>,
+}
+
+/// Deterministic key for a simulated node, so signatures are reproducible.
+fn sim_keypair(id: u32) -> Ed25519KeyPair {
+    let seed = Sha256::digest(format!("pbft-sim-node-{}", id));
+    Ed25519KeyPair::from_seed_unchecked(&seed).unwrap()
+}
+
+/// Polls `future` once. Everything a simulated replica awaits completes
+/// immediately, so a pending future is a bug in the simulation.
+fn run_now<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let mut cx = Context::from_waker(Waker::noop());
+    match future.as_mut().poll(&mut cx) {
+        Poll::Ready(output) => output,
+        Poll::Pending => panic!("simulated replica blocked on a future"),
+    }
+}
+
+impl Simulator {
+    pub fn new(config: SimConfig) -> Self {
+        let mut client = Crypto::new(sim_keypair(CLIENT_ID), CLIENT_ID, HashMap::new());
+
+        let membership = Membership::new(
+            (0..config.replicas)
+                .map(|id| ReplicaInfo {
+                    id,
+                    public_key: Crypto::new(sim_keypair(id), id, HashMap::new()).get_pub_key(),
+                    addr: format!("127.0.0.1:{}", 5000 + id).parse().unwrap(),
+                })
+                .collect(),
+            vec![ClientInfo {
+                id: CLIENT_ID,
+                public_key: client.get_pub_key(),
+            }],
+        )
+        .expect("simulated membership is valid");
+        client.set_peer_public_keys(membership.peer_public_keys(CLIENT_ID));
+
+        let clock = VirtualClock::new();
+        let outbox = Outbox::new();
+        let events: Arc<Mutex<Vec<(u32, ReplicaEvent)>>> = Arc::default();
+        let replicas = (0..config.replicas)
+            .map(|id| {
+                let crypto = Crypto::new(sim_keypair(id), id, HashMap::new());
+                let mut replica = Replica::new(id, membership.clone(), crypto);
+                replica.set_clock(Arc::new(clock.clone()));
+                let sink = events.clone();
+                replica.set_observer(Box::new(move |event| {
+                    sink.lock().unwrap().push((id, event.clone()));
+                }));
+                let transport = ByzantineTransport::new(
+                    SimTransport::new(id, &membership, outbox.clone()),
+                    config
+                        .byzantine
+                        .iter()
+                        .find(|b| b.id == id)
+                        .cloned()
+                        .unwrap_or_else(|| ByzantineConfig::new(id, Vec::new())),
+                    Crypto::new(sim_keypair(id), id, HashMap::new()),
+                    &membership,
+                );
+                (id, (replica, transport))
+            })
+            .collect();
+
+        let mut sim = Simulator {
+            rng: SimRng::new(config.seed),
+            config,
+            clock,
+            replicas,
+            outbox,
+            events,
+            checker: InvariantChecker::new(),
+            client,
+            replies: HashMap::new(),
+            queue: BinaryHeap::new(),
+            next_order: 0,
+            trace: Sha256::new(),
+            delivered: 0,
+            dropped: 0,
+            duplicated: 0,
+            filter: None,
+        };
+
+        for id in 0..sim.config.replicas {
+            if !sim.is_crashed(id) {
+                let at_ms = sim.rng.range(1, sim.config.tick_ms);
+                sim.schedule(at_ms, Action::Tick(id));
+            }
+        }
+        for timestamp in 1..=sim.config.requests {
+            let at_ms = timestamp * sim.config.request_interval_ms;
+            sim.schedule(at_ms, Action::ClientSend(timestamp));
+        }
+
+        sim
+    }
+
+    pub fn now_ms(&self) -> u64 {
+        self.clock.now_ms()
+    }
+
+    pub fn replicas(&self) -> impl Iterator<Item = &Replica> {
+        self.replicas.values().map(|(replica, _)| replica)
+    }
+
+    pub fn checker(&self) -> &InvariantChecker {
+        &self.checker
+    }
+
+    /// Drops every message `filter` returns false for, ahead of the random
+    /// faults. For scripting a schedule the random faults would rarely hit.
+    pub fn set_filter(&mut self, filter: impl FnMut(&Envelope) -> bool + 'static) {
+        self.filter = Some(Box::new(filter));
+    }
+
+    fn is_crashed(&self, id: u32) -> bool {
+        self.config.crashed.contains(&id)
+    }
+
+    fn schedule(&mut self, at_ms: u64, action: Action) {
+        let order = self.next_order;
+        self.next_order += 1;
+        self.queue.push(Event {
+            at_ms,
+            order,
+            action,
+        });
+    }
+
+    /// Hands `envelope` to the faulty network: it may be dropped, delivered
+    /// once, or delivered twice, each copy after its own delay.
+    fn send(&mut self, envelope: Envelope) {
+        let faults = self.config.faults.clone();
+
+        if let Some(filter) = &mut self.filter
+            && !filter(&envelope)
+        {
+            self.dropped += 1;
+            return;
+        }
+        if self.rng.chance(faults.drop_probability) {
+            self.dropped += 1;
+            return;
+        }
+
+        let copies = if self.rng.chance(faults.duplicate_probability) {
+            self.duplicated += 1;
+            2
+        } else {
+            1
+        };
+
+        for _ in 0..copies {
+            let delay = self.rng.range(faults.min_delay_ms, faults.max_delay_ms);
+            self.schedule(self.now_ms() + delay, Action::Deliver(envelope.clone()));
+        }
+    }
+
+    fn flush_outbox(&mut self) {
+        for envelope in self.outbox.take() {
+            self.send(envelope);
+        }
+    }
+
+    fn record_delivery(&mut self, envelope: &Envelope) {
+        self.delivered += 1;
+        self.trace.update(self.now_ms().to_be_bytes());
+        self.trace.update(envelope.from.to_be_bytes());
+        self.trace.update(envelope.to.to_be_bytes());
+        self.trace
+            .update(postcard::to_allocvec(&envelope.message).unwrap());
+    }
+
+    /// Whether the client holds f + 1 matching replies for `timestamp`.
+    fn request_done(&self, timestamp: u64) -> bool {
+        let f = (self.config.replicas as usize - 1) / 3;
+        self.replies
+            .get(&timestamp)
+            .is_some_and(|by_result| by_result.values().any(|from| from.len() > f))
+    }
+
+    fn record_reply(&mut self, message: &PBFTMessage) {
+        if let PBFTMessage::Reply(reply) = message
+            && reply.signer_id == reply.message.replica_id
+            && self.client.verify_signed_message(reply)
+        {
+            let reply = &reply.message;
+            self.replies
+                .entry(reply.timestamp)
+                .or_default()
+                .entry(reply.result.clone())
+                .or_default()
+                .insert(reply.replica_id);
+        }
+    }
+
+    fn is_byzantine(&self, id: u32) -> bool {
+        self.config
+            .byzantine
+            .iter()
+            .any(|b| b.id == id && !b.behaviors.is_empty())
+    }
+
+    fn all_executed(&self) -> bool {
+        self.replicas()
+            .filter(|replica| {
+                !self.is_crashed(replica.node_id()) && !self.is_byzantine(replica.node_id())
+            })
+            .all(|replica| {
+                (1..=self.config.requests).all(|ts| replica.has_executed(CLIENT_ID as u64, ts))
+            })
+    }
+
+    /// Processes the next event. Returns false once the run is over.
+    pub fn step(&mut self) -> bool {
+        if self.all_executed() {
+            return false;
+        }
+
+        let Some(event) = self.queue.pop() else {
+            return false;
+        };
+        if event.at_ms > self.config.max_time_ms {
+            return false;
+        }
+        self.clock.set_ms(event.at_ms);
+
+        match event.action {
+            Action::Deliver(envelope) => {
+                if self.is_crashed(envelope.to) {
+                    return true;
+                }
+                self.record_delivery(&envelope);
+                if envelope.to == CLIENT_ID {
+                    self.record_reply(&envelope.message);
+                } else if let Some((replica, transport)) = self.replicas.get_mut(&envelope.to) {
+                    run_now(replica.handle_message(envelope.message, transport));
+                }
+            }
+            Action::Tick(id) => {
+                if let Some((replica, transport)) = self.replicas.get_mut(&id) {
+                    run_now(replica.tick(transport));
+                }
+                let next = self.now_ms() + self.config.tick_ms;
+                self.schedule(next, Action::Tick(id));
+            }
+            Action::ClientSend(timestamp) => {
+                if self.request_done(timestamp) {
+                    return true;
+                }
+                let request = Request {
+                    operation: format!("PUT:key{}:value{}", timestamp % 4, timestamp).into_bytes(),
+                    timestamp,
+                    client_id: CLIENT_ID as u64,
+                };
+                let message = PBFTMessage::Request(self.client.create_signed_message(request));
+                for to in 0..self.config.replicas {
+                    self.send(Envelope {
+                        from: CLIENT_ID,
+                        to,
+                        message: message.clone(),
+                    });
+                }
+                let retry = self.now_ms() + self.config.client_retry_ms;
+                self.schedule(retry, Action::ClientSend(timestamp));
+            }
+        }
+
+        self.flush_outbox();
+        self.check_events();
+        true
+    }
+
+    fn check_events(&mut self) {
+        let events = std::mem::take(&mut *self.events.lock().unwrap());
+        for (node_id, event) in &events {
+            self.checker.observe(*node_id, event);
+        }
+    }
+
+    pub fn run(mut self) -> SimReport {
+        while self.step() {}
+        self.report()
+    }
+
+    pub fn report(&self) -> SimReport {
+        SimReport {
+            seed: self.config.seed,
+            completed: self.all_executed(),
+            end_time_ms: self.now_ms(),
+            delivered: self.delivered,
+            dropped: self.dropped,
+            duplicated: self.duplicated,
+            trace_digest: self.trace.clone().finalize().into(),
+            replicas: self
+                .replicas()
+                .map(|replica| ReplicaSummary {
+                    node_id: replica.node_id(),
+                    view: replica.view(),
+                    last_executed: replica.last_executed(),
+                })
+                .collect(),
+            violations: self.checker.violations().to_vec(),
+        }
+    }
+}
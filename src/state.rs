@@ -1,2 +1,9 @@
 pub mod app_state;
+pub mod certificates;
+pub mod clock;
+pub mod events;
+pub mod invariants;
+pub mod ledger;
+pub mod records;
 pub mod replica;
+pub mod snapshots;
@@ -1,16 +1,51 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+
+use crate::crypto::merkle::{self, KeyProof};
 
 pub struct AppState {
-    store: HashMap<String, String>,
+    store: BTreeMap<String, String>,
+}
+
+impl Default for AppState {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl AppState {
     pub fn new() -> Self {
         AppState {
-            store: HashMap::new(),
+            store: BTreeMap::new(),
         }
     }
 
+    /// Merkle root over the store's entries in key order, so replicas with
+    /// the same contents get the same digest.
+    pub fn digest(&self) -> [u8; 32] {
+        let leaves: Vec<[u8; 32]> = self
+            .store
+            .iter()
+            .map(|(k, v)| merkle::leaf_hash(k, v))
+            .collect();
+        merkle::root(&leaves)
+    }
+
+    /// The store's entries in key order.
+    pub fn entries(&self) -> Vec<(String, String)> {
+        self.store
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect()
+    }
+
+    /// Proof of `key`'s current value, or its absence, against `digest()`.
+    pub fn prove(&self, key: &str) -> KeyProof {
+        KeyProof::build(
+            self.store.iter().map(|(k, v)| (k.as_str(), v.as_str())),
+            key,
+        )
+    }
+
     pub fn execute(&mut self, operation: &[u8]) -> Vec<u8> {
         let op_str = String::from_utf8_lossy(operation);
 
@@ -26,8 +61,18 @@ impl AppState {
                 return value.as_bytes().to_vec();
             }
             return b"NOT_FOUND".to_vec();
+        } else if let Some(cas) = op_str.strip_prefix("CAS:") {
+            let parts: Vec<&str> = cas.split(':').collect();
+            if parts.len() == 3 {
+                if self.store.get(parts[0]).map(String::as_str) != Some(parts[1]) {
+                    return b"CAS_FAILED".to_vec();
+                }
+                self.store
+                    .insert(parts[0].to_string(), parts[2].to_string());
+                return b"OK".to_vec();
+            }
         }
 
-        return b"INVALID_OPERATION".to_vec();
+        b"INVALID_OPERATION".to_vec()
     }
 }
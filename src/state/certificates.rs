@@ -0,0 +1,79 @@
+use std::{collections::BTreeMap, io, path::Path};
+use tracing::error;
+
+use crate::{message::message_types::CertifiedRequest, state::records::RecordFile};
+
+/// Where a replica keeps the certified request for every sequence number it
+/// executed, so they can be served to auditors later.
+pub trait CertificateStore: Send {
+    /// Stores `entry` unless one is already stored for its sequence number.
+    /// Any quorum certifies the slot equally well, and a restarted replica
+    /// executing its history again must not store it twice.
+    fn put(&mut self, entry: &CertifiedRequest);
+
+    fn get(&self, seq_num: u64) -> Option<CertifiedRequest>;
+}
+
+#[derive(Default)]
+pub struct MemoryCertificateStore {
+    entries: BTreeMap<u64, CertifiedRequest>,
+}
+
+impl MemoryCertificateStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl CertificateStore for MemoryCertificateStore {
+    fn put(&mut self, entry: &CertifiedRequest) {
+        self.entries
+            .entry(entry.certificate.seq_num)
+            .or_insert_with(|| entry.clone());
+    }
+
+    fn get(&self, seq_num: u64) -> Option<CertifiedRequest> {
+        self.entries.get(&seq_num).cloned()
+    }
+}
+
+/// Certificates kept in a `RecordFile`, with an index from sequence number
+/// to offset rebuilt when the file is opened. Only the first record for each
+/// sequence number counts.
+pub struct FileCertificateStore {
+    file: RecordFile,
+    index: BTreeMap<u64, u64>,
+}
+
+impl FileCertificateStore {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let (file, records) = RecordFile::open::<CertifiedRequest>(path)?;
+        let mut index = BTreeMap::new();
+        for (offset, entry) in records {
+            index.entry(entry.certificate.seq_num).or_insert(offset);
+        }
+        Ok(FileCertificateStore { file, index })
+    }
+}
+
+impl CertificateStore for FileCertificateStore {
+    fn put(&mut self, entry: &CertifiedRequest) {
+        if self.index.contains_key(&entry.certificate.seq_num) {
+            return;
+        }
+        match self.file.append(entry) {
+            Ok(offset) => {
+                self.index.insert(entry.certificate.seq_num, offset);
+            }
+            Err(e) => error!(
+                seq_num = entry.certificate.seq_num,
+                error = %e,
+                "Failed to store commit certificate"
+            ),
+        }
+    }
+
+    fn get(&self, seq_num: u64) -> Option<CertifiedRequest> {
+        self.file.read(*self.index.get(&seq_num)?)
+    }
+}
@@ -0,0 +1,70 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+use tokio::time::Instant;
+
+/// Source of time for the replica's timers, so a simulator can drive them
+/// instead of the wall clock.
+pub trait Clock: Send + Sync {
+    /// Time elapsed since a fixed, arbitrary origin.
+    fn now(&self) -> Duration;
+}
+
+pub struct SystemClock {
+    origin: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> Self {
+        SystemClock {
+            origin: Instant::now(),
+        }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.origin.elapsed()
+    }
+}
+
+/// Clock that only moves when told to. Clones share the same time.
+#[derive(Clone, Default)]
+pub struct VirtualClock {
+    now_nanos: Arc<AtomicU64>,
+}
+
+impl VirtualClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&self, now: Duration) {
+        self.now_nanos
+            .store(now.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub fn set_ms(&self, ms: u64) {
+        self.set(Duration::from_millis(ms));
+    }
+
+    pub fn now_ms(&self) -> u64 {
+        self.now().as_millis() as u64
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> Duration {
+        Duration::from_nanos(self.now_nanos.load(Ordering::Relaxed))
+    }
+}
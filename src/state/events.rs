@@ -0,0 +1,105 @@
+use std::{fmt, str::FromStr};
+
+/// Something a replica did that matters for safety. Emitted through
+/// `Replica::set_observer`, and written one per line to the event log.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ReplicaEvent {
+    ViewEntered {
+        view: u64,
+    },
+    Committed {
+        seq_num: u64,
+        view: u64,
+        digest: [u8; 32],
+    },
+    Executed {
+        seq_num: u64,
+        digest: [u8; 32],
+    },
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// First bytes of a digest, enough to tell requests apart in logs.
+pub fn digest_prefix(digest: &[u8; 32]) -> String {
+    to_hex(&digest[..4])
+}
+
+pub fn digest_from_hex(hex: &str) -> Option<[u8; 32]> {
+    if hex.len() != 64 {
+        return None;
+    }
+    let mut digest = [0u8; 32];
+    for (i, byte) in digest.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(2 * i..2 * i + 2)?, 16).ok()?;
+    }
+    Some(digest)
+}
+
+impl fmt::Display for ReplicaEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplicaEvent::ViewEntered { view } => write!(f, "view {}", view),
+            ReplicaEvent::Committed {
+                seq_num,
+                view,
+                digest,
+            } => write!(f, "committed {} {} {}", seq_num, view, to_hex(digest)),
+            ReplicaEvent::Executed { seq_num, digest } => {
+                write!(f, "executed {} {}", seq_num, to_hex(digest))
+            }
+        }
+    }
+}
+
+impl FromStr for ReplicaEvent {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let number = |i: usize| -> Result<u64, String> {
+            fields
+                .get(i)
+                .and_then(|f| f.parse().ok())
+                .ok_or_else(|| format!("bad field {} in {:?}", i, line))
+        };
+        let digest = |i: usize| -> Result<[u8; 32], String> {
+            fields
+                .get(i)
+                .and_then(|f| digest_from_hex(f))
+                .ok_or_else(|| format!("bad digest in {:?}", line))
+        };
+
+        match fields.first() {
+            Some(&"view") => Ok(ReplicaEvent::ViewEntered { view: number(1)? }),
+            Some(&"committed") => Ok(ReplicaEvent::Committed {
+                seq_num: number(1)?,
+                view: number(2)?,
+                digest: digest(3)?,
+            }),
+            Some(&"executed") => Ok(ReplicaEvent::Executed {
+                seq_num: number(1)?,
+                digest: digest(2)?,
+            }),
+            _ => Err(format!("unknown event {:?}", line)),
+        }
+    }
+}
+
+/// Event log line: the replica id followed by the event.
+pub fn format_event_line(node_id: u32, event: &ReplicaEvent) -> String {
+    format!("{} {}", node_id, event)
+}
+
+pub fn parse_event_line(line: &str) -> Result<(u32, ReplicaEvent), String> {
+    let (node_id, event) = line
+        .trim()
+        .split_once(' ')
+        .ok_or_else(|| format!("malformed event line {:?}", line))?;
+    let node_id = node_id
+        .parse()
+        .map_err(|_| format!("bad replica id in {:?}", line))?;
+    Ok((node_id, event.parse()?))
+}
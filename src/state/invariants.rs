@@ -0,0 +1,194 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+};
+
+use crate::state::events::{ReplicaEvent, to_hex};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Violation {
+    /// Two replicas committed different requests at the same sequence number.
+    ConflictingCommit {
+        seq_num: u64,
+        first: (u32, [u8; 32]),
+        second: (u32, [u8; 32]),
+    },
+    /// Two replicas executed different requests at the same sequence number.
+    DivergentExecution {
+        seq_num: u64,
+        first: (u32, [u8; 32]),
+        second: (u32, [u8; 32]),
+    },
+    /// A replica executed out of order or skipped a sequence number.
+    ExecutionGap {
+        node_id: u32,
+        expected: u64,
+        got: u64,
+    },
+    /// A replica executed something other than what it committed.
+    ExecutedUncommitted {
+        node_id: u32,
+        seq_num: u64,
+    },
+    ViewRegressed {
+        node_id: u32,
+        from: u64,
+        to: u64,
+    },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::ConflictingCommit {
+                seq_num,
+                first,
+                second,
+            } => write!(
+                f,
+                "seq {} committed as {} by {} and as {} by {}",
+                seq_num,
+                to_hex(&first.1),
+                first.0,
+                to_hex(&second.1),
+                second.0
+            ),
+            Violation::DivergentExecution {
+                seq_num,
+                first,
+                second,
+            } => write!(
+                f,
+                "seq {} executed as {} by {} and as {} by {}",
+                seq_num,
+                to_hex(&first.1),
+                first.0,
+                to_hex(&second.1),
+                second.0
+            ),
+            Violation::ExecutionGap {
+                node_id,
+                expected,
+                got,
+            } => write!(
+                f,
+                "replica {} executed seq {} when {} was next",
+                node_id, got, expected
+            ),
+            Violation::ExecutedUncommitted { node_id, seq_num } => write!(
+                f,
+                "replica {} executed seq {} without committing it",
+                node_id, seq_num
+            ),
+            Violation::ViewRegressed { node_id, from, to } => {
+                write!(f, "replica {} went from view {} to {}", node_id, from, to)
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct ReplicaTrack {
+    view: u64,
+    last_executed: u64,
+    committed: HashMap<u64, [u8; 32]>,
+}
+
+/// Checks PBFT safety over the events of correct replicas:
+/// - no two replicas commit different digests at the same sequence number,
+/// - every replica executes the same digest at each sequence number, in
+///   order and without gaps, and only what it committed,
+/// - no replica's view goes backwards.
+///
+/// Feed it events from the simulator or from event logs scraped off real
+/// nodes; events of Byzantine replicas must be left out.
+#[derive(Default)]
+pub struct InvariantChecker {
+    replicas: HashMap<u32, ReplicaTrack>,
+    committed: BTreeMap<u64, (u32, [u8; 32])>,
+    executed: BTreeMap<u64, (u32, [u8; 32])>,
+    violations: Vec<Violation>,
+}
+
+impl InvariantChecker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records one event of `node_id`, in the order the replica emitted it.
+    pub fn observe(&mut self, node_id: u32, event: &ReplicaEvent) {
+        let track = self.replicas.entry(node_id).or_default();
+
+        match *event {
+            ReplicaEvent::ViewEntered { view } => {
+                if view <= track.view {
+                    self.violations.push(Violation::ViewRegressed {
+                        node_id,
+                        from: track.view,
+                        to: view,
+                    });
+                }
+                track.view = track.view.max(view);
+            }
+            ReplicaEvent::Committed {
+                seq_num, digest, ..
+            } => {
+                track.committed.insert(seq_num, digest);
+                match self.committed.get(&seq_num) {
+                    Some(&first) if first.1 != digest => {
+                        self.violations.push(Violation::ConflictingCommit {
+                            seq_num,
+                            first,
+                            second: (node_id, digest),
+                        });
+                    }
+                    Some(_) => {}
+                    None => {
+                        self.committed.insert(seq_num, (node_id, digest));
+                    }
+                }
+            }
+            ReplicaEvent::Executed { seq_num, digest } => {
+                if seq_num != track.last_executed + 1 {
+                    self.violations.push(Violation::ExecutionGap {
+                        node_id,
+                        expected: track.last_executed + 1,
+                        got: seq_num,
+                    });
+                }
+                track.last_executed = track.last_executed.max(seq_num);
+
+                if track.committed.get(&seq_num) != Some(&digest) {
+                    self.violations
+                        .push(Violation::ExecutedUncommitted { node_id, seq_num });
+                }
+
+                match self.executed.get(&seq_num) {
+                    Some(&first) if first.1 != digest => {
+                        self.violations.push(Violation::DivergentExecution {
+                            seq_num,
+                            first,
+                            second: (node_id, digest),
+                        });
+                    }
+                    Some(_) => {}
+                    None => {
+                        self.executed.insert(seq_num, (node_id, digest));
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn violations(&self) -> &[Violation] {
+        &self.violations
+    }
+
+    pub fn check(&self) -> Result<(), Vec<Violation>> {
+        if self.violations.is_empty() {
+            Ok(())
+        } else {
+            Err(self.violations.clone())
+        }
+    }
+}
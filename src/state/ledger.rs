@@ -0,0 +1,87 @@
+use std::{collections::BTreeMap, io, path::Path};
+use tracing::error;
+
+use crate::{message::message_types::LedgerEntry, state::records::RecordFile};
+
+/// `prev_hash` of the first entry.
+pub const GENESIS_HASH: [u8; 32] = [0; 32];
+
+/// Where a replica appends a `LedgerEntry` for every sequence number it
+/// executes.
+pub trait LedgerStore: Send {
+    /// Appends `entry`. Callers never append a sequence number twice.
+    fn append(&mut self, entry: &LedgerEntry);
+
+    fn get(&self, seq_num: u64) -> Option<LedgerEntry>;
+}
+
+#[derive(Default)]
+pub struct MemoryLedger {
+    entries: BTreeMap<u64, LedgerEntry>,
+}
+
+impl MemoryLedger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl LedgerStore for MemoryLedger {
+    fn append(&mut self, entry: &LedgerEntry) {
+        self.entries.insert(entry.seq_num, entry.clone());
+    }
+
+    fn get(&self, seq_num: u64) -> Option<LedgerEntry> {
+        self.entries.get(&seq_num).cloned()
+    }
+}
+
+/// Ledger kept in a `RecordFile`, so it survives restarts.
+pub struct FileLedger {
+    file: RecordFile,
+    index: BTreeMap<u64, u64>,
+}
+
+impl FileLedger {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let (file, records) = RecordFile::open::<LedgerEntry>(path)?;
+        let index = records
+            .into_iter()
+            .map(|(offset, entry)| (entry.seq_num, offset))
+            .collect();
+        Ok(FileLedger { file, index })
+    }
+}
+
+impl LedgerStore for FileLedger {
+    fn append(&mut self, entry: &LedgerEntry) {
+        match self.file.append(entry) {
+            Ok(offset) => {
+                self.index.insert(entry.seq_num, offset);
+            }
+            Err(e) => error!(seq_num = entry.seq_num, error = %e, "Failed to append ledger entry"),
+        }
+    }
+
+    fn get(&self, seq_num: u64) -> Option<LedgerEntry> {
+        self.file.read(*self.index.get(&seq_num)?)
+    }
+}
+
+/// Checks that `entries` form an unbroken chain, starting from the genesis
+/// hash if the first one is sequence number 1. Returns the sequence number
+/// of the first entry that does not follow its predecessor.
+pub fn verify_chain(entries: &[LedgerEntry]) -> Result<(), u64> {
+    if let Some(first) = entries.first()
+        && first.seq_num == 1
+        && first.prev_hash != GENESIS_HASH
+    {
+        return Err(1);
+    }
+    for pair in entries.windows(2) {
+        if !pair[1].follows(&pair[0]) {
+            return Err(pair[1].seq_num);
+        }
+    }
+    Ok(())
+}
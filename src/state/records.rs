@@ -0,0 +1,136 @@
+use serde::{Serialize, de::DeserializeOwned};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+use tracing::warn;
+
+/// Largest record body `RecordFile` writes or reads. A longer declared
+/// length can only come from a damaged file.
+pub const MAX_RECORD_LEN: u32 = 16 << 20;
+
+/// Append-only file of u32 length-prefixed postcard records, shared by the
+/// on-disk stores. A record cut short by a crash is dropped on open; any
+/// other damage is an error.
+pub struct RecordFile {
+    file: File,
+}
+
+/// What `read_record` found at an offset.
+enum Slot<T> {
+    /// A record and its length on disk.
+    Record(T, u64),
+    End,
+    /// A record running past the end of the file, as left by a crash
+    /// mid-append.
+    Torn,
+}
+
+impl RecordFile {
+    /// Opens or creates the file at `path` and returns every complete record
+    /// in it with its offset. A torn record at the end is cut off.
+    pub fn open<T: DeserializeOwned>(path: &Path) -> io::Result<(Self, Vec<(u64, T)>)> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+
+        let mut records = Vec::new();
+        let mut offset = 0;
+        loop {
+            match read_record(&mut file, offset)? {
+                Slot::Record(record, len) => {
+                    records.push((offset, record));
+                    offset += len;
+                }
+                Slot::End => break,
+                Slot::Torn => {
+                    warn!(?path, offset, "Dropping record torn by a crash");
+                    file.set_len(offset)?;
+                    break;
+                }
+            }
+        }
+
+        Ok((RecordFile { file }, records))
+    }
+
+    /// Appends `record` and returns its offset.
+    pub fn append<T: Serialize>(&mut self, record: &T) -> io::Result<u64> {
+        let body = postcard::to_allocvec(record).map_err(io::Error::other)?;
+        if body.len() > MAX_RECORD_LEN as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("record of {} bytes is too large", body.len()),
+            ));
+        }
+        let offset = self.file.seek(SeekFrom::End(0))?;
+        let mut bytes = (body.len() as u32).to_be_bytes().to_vec();
+        bytes.extend(&body);
+        self.file.write_all(&bytes)?;
+        Ok(offset)
+    }
+
+    /// Every complete record in the file at `path`, without modifying it.
+    pub fn read_all<T: DeserializeOwned>(path: &Path) -> io::Result<Vec<T>> {
+        let mut file = File::open(path)?;
+        let mut records = Vec::new();
+        let mut offset = 0;
+        while let Slot::Record(record, len) = read_record(&mut file, offset)? {
+            records.push(record);
+            offset += len;
+        }
+        Ok(records)
+    }
+
+    pub fn read<T: DeserializeOwned>(&self, offset: u64) -> Option<T> {
+        let mut file = self.file.try_clone().ok()?;
+        match read_record(&mut file, offset).ok()? {
+            Slot::Record(record, _) => Some(record),
+            Slot::End | Slot::Torn => None,
+        }
+    }
+}
+
+/// Reads the record at `offset`. Its declared length is checked against
+/// `MAX_RECORD_LEN` and what is left of the file before anything is
+/// allocated for it.
+fn read_record<T: DeserializeOwned>(file: &mut File, offset: u64) -> io::Result<Slot<T>> {
+    let remaining = file.metadata()?.len().saturating_sub(offset);
+    if remaining == 0 {
+        return Ok(Slot::End);
+    }
+    if remaining < 4 {
+        return Ok(Slot::Torn);
+    }
+
+    file.seek(SeekFrom::Start(offset))?;
+    let mut len = [0u8; 4];
+    file.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len);
+    if len > MAX_RECORD_LEN {
+        return Err(corrupt(offset, format!("declares {} bytes", len)));
+    }
+    if 4 + len as u64 > remaining {
+        return Ok(Slot::Torn);
+    }
+
+    let mut body = vec![0u8; len as usize];
+    file.read_exact(&mut body)?;
+    match postcard::from_bytes(&body) {
+        Ok(record) => Ok(Slot::Record(record, 4 + len as u64)),
+        Err(e) => Err(corrupt(offset, e)),
+    }
+}
+
+fn corrupt(offset: u64, reason: impl std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("corrupt record at offset {}: {}", offset, reason),
+    )
+}
@@ -1,69 +1,156 @@
+use serde::Serialize;
 use sha2::{Digest, Sha256};
 use std::{
-    collections::{HashMap, HashSet},
-    hash::Hash,
+    collections::{BTreeMap, HashMap, HashSet},
+    io,
+    sync::{Arc, atomic::Ordering},
     time::Duration,
 };
-use tokio::time::Instant;
+use tracing::{Instrument, debug, info, info_span, warn};
 
 use crate::{
-    config::node::NodeConfig,
-    crypto::primitives::Crypto,
-    message::message_types::{Commit, PBFTMessage, PrePrepare, Prepare, Request, SignedMessage},
-    message_types::{PreparedProof, ViewChange},
-    network::network_layer::Network,
-    state::app_state::AppState,
+    config::membership::Membership,
+    crypto::{merkle::KeyProof, primitives::Crypto},
+    message::message_types::{
+        AdminQuery, AdminRequest, AdminResponse, AdminResult, CertifiedRequest, Commit,
+        CommitCertificate, CommitSignature, Evidence, LedgerEntry, MAX_LEDGER_QUERY, NewView,
+        PBFTMessage, PrePrepare, Prepare, PreparedProof, Reconfigure, ReplicaStatus, Reply,
+        Request, SignedMessage, ViewChange,
+    },
+    metrics::registry::Metrics,
+    network::transport::Transport,
+    state::{
+        app_state::AppState,
+        certificates::{CertificateStore, MemoryCertificateStore},
+        clock::{Clock, SystemClock},
+        events::{ReplicaEvent, digest_prefix},
+        ledger::{GENESIS_HASH, LedgerStore, MemoryLedger},
+        snapshots::{MemorySnapshotStore, Snapshot, SnapshotStore},
+    },
+    trace::record::{RecordingTransport, TraceEvent, TraceRecorder},
 };
 
 pub struct Replica {
     node_id: u32,
-    f: u32,
+    membership: Membership,
+    /// Memberships replaced by reconfiguration, by the last sequence number
+    /// each of them ordered.
+    retired_memberships: BTreeMap<u64, Membership>,
     view: u64,
     next_seq_num: u64,
     message_log: HashMap<u64, MessageLog>,
-    executed_req: HashSet<u64>,
+    executed_req: HashSet<(u64, u64)>,
     last_executed: u64,
     crypto: Crypto,
     app_state: AppState,
+    clock: Arc<dyn Clock>,
+    /// Client requests seen but not yet executed, by `Request::id`. A backup
+    /// keeps its view-change timer running while this is non-empty.
+    pending_requests: BTreeMap<(u64, u64), SignedMessage<Request>>,
+    /// Last reply sent to each client, resent if the request is retried.
+    last_replies: HashMap<u64, SignedMessage<Reply>>,
+    /// Replies produced by execution and not yet sent.
+    outgoing_replies: Vec<SignedMessage<Reply>>,
+    /// Normal-case messages for a view this replica has not entered yet.
+    deferred: Vec<PBFTMessage>,
     // view change
-    view_change_timer: Option<Instant>,
+    view_change_timer: Option<Duration>,
     view_change_timeout: Duration,
     in_view_change: bool,
-    view_change_msgs: HashMap<u64, HashMap<u32, ViewChange>>,
+    /// View this replica is trying to move to while `in_view_change`.
+    pending_view: u64,
+    /// Highest last executed slot reported in the view changes that started
+    /// the current view.
+    view_entered_at: u64,
+    view_change_msgs: HashMap<u64, BTreeMap<u32, SignedMessage<ViewChange>>>,
+    /// Equivocation proofs collected so far, oldest first.
+    evidence: Vec<Evidence>,
+    /// Executed requests with their commit certificates.
+    certificates: Box<dyn CertificateStore>,
+    /// Hash chain over everything executed, one entry per sequence number.
+    ledger: Box<dyn LedgerStore>,
+    /// Snapshots taken on an operator's request.
+    snapshots: Box<dyn SnapshotStore>,
+    /// Sequence number of the latest snapshot in `snapshots`.
+    last_snapshot: Option<u64>,
+    metrics: Arc<Metrics>,
+    /// Records what the replica receives and sends, when tracing is on.
+    recorder: Option<TraceRecorder>,
+    /// Set when evidence convicts the current primary; `handle_message`
+    /// then moves on to the next view.
+    primary_faulty: bool,
+    /// Set when a reconfiguration executes; `handle_message` then starts
+    /// the new epoch's first view.
+    epoch_changed: bool,
+    observer: Option<Observer>,
 }
 
+/// Callback receiving the replica's safety-relevant events.
+pub type Observer = Box<dyn FnMut(&ReplicaEvent) + Send>;
+
 pub struct MessageLog {
     request: Option<Request>,
     pre_prepare: Option<PrePrepare>,
-    prepares: HashMap<u32, Prepare>,
-    commits: HashMap<u32, Commit>,
+    /// The primary's signed pre-prepare, kept in case it equivocates and
+    /// for the slot's `PreparedProof`.
+    signed_pre_prepare: Option<SignedMessage<PrePrepare>>,
+    /// Signed, so that they can prove the slot prepared in a view change.
+    prepares: HashMap<u32, SignedMessage<Prepare>>,
+    /// Signed, so a quorum of them can be kept as the slot's certificate.
+    commits: HashMap<u32, SignedMessage<Commit>>,
     prepared: bool,
     committed: bool,
+    /// Certificate from the latest view the slot prepared in. Unlike the
+    /// votes above it outlives view changes, so later view changes still
+    /// carry the slot.
+    proof: Option<PreparedProof>,
+    /// When the first message for the slot arrived, for the commit latency.
+    created_at: Duration,
 }
 
 impl MessageLog {
-    fn new() -> Self {
+    fn new(created_at: Duration) -> Self {
         MessageLog {
             request: None,
             pre_prepare: None,
+            signed_pre_prepare: None,
             prepares: HashMap::new(),
             commits: HashMap::new(),
             prepared: false,
             committed: false,
+            proof: None,
+            created_at,
         }
     }
 }
 
+/// Upper bound on `Replica::deferred`, so a peer running ahead cannot make
+/// it grow without limit.
+const MAX_DEFERRED: usize = 4096;
+
+/// How far below its last executed slot a replica still reports prepared
+/// slots in a view change. Replicas more than this far behind the quorum
+/// are not caught up by the new view.
+const VIEW_CHANGE_WINDOW: u64 = 256;
+
+/// Upper bound on `Replica::evidence`.
+const MAX_EVIDENCE: usize = 1024;
+
+/// How often `run_replica` checks the view-change timer.
+const TICK_INTERVAL: Duration = Duration::from_millis(100);
+
 impl Replica {
-    pub fn new(node_id: u32, total_nodes: u32, crypto: Crypto) -> Self {
-        assert!(total_nodes >= 4);
-        assert!((total_nodes - 1) % 3 == 0);
+    pub fn new(node_id: u32, membership: Membership, mut crypto: Crypto) -> Self {
+        assert!(membership.total_nodes() >= 4);
+        assert!(membership.contains(node_id));
 
-        let f = (total_nodes - 1) / 3;
+        crypto.set_peer_public_keys(membership.peer_public_keys(node_id));
+        crypto.set_client_public_keys(membership.client_public_keys());
 
         Replica {
             node_id,
-            f,
+            membership,
+            retired_memberships: BTreeMap::new(),
             view: 0,
             next_seq_num: 1,
             message_log: HashMap::new(),
@@ -71,121 +158,586 @@ impl Replica {
             last_executed: 0,
             crypto,
             app_state: AppState::new(),
+            clock: Arc::new(SystemClock::new()),
+            pending_requests: BTreeMap::new(),
+            last_replies: HashMap::new(),
+            outgoing_replies: Vec::new(),
+            deferred: Vec::new(),
             view_change_timer: None,
             view_change_timeout: Duration::from_millis(1000),
             in_view_change: false,
+            pending_view: 0,
+            view_entered_at: 0,
             view_change_msgs: HashMap::new(),
+            evidence: Vec::new(),
+            certificates: Box::new(MemoryCertificateStore::new()),
+            ledger: Box::new(MemoryLedger::new()),
+            snapshots: Box::new(MemorySnapshotStore::new()),
+            last_snapshot: None,
+            metrics: Arc::new(Metrics::new()),
+            recorder: None,
+            primary_faulty: false,
+            epoch_changed: false,
+            observer: None,
+        }
+    }
+
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    pub fn set_certificate_store(&mut self, certificates: Box<dyn CertificateStore>) {
+        self.certificates = certificates;
+    }
+
+    pub fn set_ledger(&mut self, ledger: Box<dyn LedgerStore>) {
+        self.ledger = ledger;
+    }
+
+    pub fn set_snapshot_store(&mut self, snapshots: Box<dyn SnapshotStore>) {
+        self.last_snapshot = snapshots.latest().map(|s| s.seq_num);
+        self.snapshots = snapshots;
+    }
+
+    pub fn set_metrics(&mut self, metrics: Arc<Metrics>) {
+        self.metrics = metrics;
+    }
+
+    pub fn set_recorder(&mut self, recorder: TraceRecorder) {
+        self.recorder = Some(recorder);
+    }
+
+    /// The clock the replica's timers run on.
+    pub fn clock(&self) -> &dyn Clock {
+        self.clock.as_ref()
+    }
+
+    pub fn set_observer(&mut self, observer: Observer) {
+        self.observer = Some(observer);
+    }
+
+    fn emit(&mut self, event: ReplicaEvent) {
+        if let Some(observer) = &mut self.observer {
+            observer(&event);
         }
     }
 
     fn start_timer(&mut self) {
-        self.view_change_timer = Some(Instant::now());
+        self.view_change_timer = Some(self.clock.now());
     }
 
     fn stop_timer(&mut self) {
         self.view_change_timer = None;
     }
 
-    fn check_timeout(&mut self) -> bool {
-        if let Some(timer) = self.view_change_timer {
-            if timer.elapsed() > self.view_change_timeout {
-                return true;
-            }
+    fn check_timeout(&self) -> bool {
+        if let Some(started) = self.view_change_timer
+            && self.clock.now().saturating_sub(started) > self.view_change_timeout
+        {
+            return true;
         }
         false
     }
 
-    async fn trigger_view_change(&mut self, network: &Network) {
-        if self.in_view_change {
+    /// Drives the view-change timer; call it periodically. A backup that has
+    /// waited too long for a request to execute asks for the next view, and
+    /// one whose view change stalls moves on to the view after that.
+    pub async fn tick<T: Transport + Sync>(&mut self, network: &T) {
+        if !self.check_timeout() {
             return;
         }
 
-        let new_view = self.view + 1;
+        match self.recorder.clone() {
+            Some(recorder) => {
+                recorder.record(self.clock.now(), TraceEvent::Timeout);
+                let network = RecordingTransport::new(network, recorder, self.clock.clone());
+                self.force_view_change(&network).await;
+            }
+            None => {
+                self.force_view_change(network).await;
+            }
+        }
+    }
 
-        println!("Triggering view change to {}", new_view);
+    /// Asks for the view after the current or pending one, as when the timer
+    /// expires, and returns it. The view only changes once a quorum asks too.
+    pub async fn force_view_change<T: Transport>(&mut self, network: &T) -> u64 {
+        let next_view = if self.in_view_change {
+            self.pending_view + 1
+        } else {
+            self.view + 1
+        };
+        self.trigger_view_change(next_view, network).await;
+        next_view
+    }
 
-        self.in_view_change = true;
-        self.stop_timer();
+    async fn trigger_view_change<T: Transport>(&mut self, new_view: u64, network: &T) {
+        if new_view <= self.view || (self.in_view_change && new_view <= self.pending_view) {
+            return;
+        }
+
+        info!(new_view, "Triggering view change");
 
-        let prepared_reqs = self.collect_prepared_requests();
+        self.in_view_change = true;
+        self.pending_view = new_view;
+        self.metrics.view_changes.fetch_add(1, Ordering::Relaxed);
+        // Restarted rather than stopped: if no NewView shows up in time, the
+        // next tick moves on to the following view.
+        self.start_timer();
 
         let view_change = ViewChange {
             new_view,
-            prepared_requests: prepared_reqs,
+            last_executed: self.last_executed,
+            executed_certificate: self
+                .certificates
+                .get(self.last_executed)
+                .map(|entry| entry.certificate),
+            prepared_requests: self.collect_prepared_requests(),
             replica_id: self.node_id,
         };
 
-        let signed_view_change = self.crypto.create_signed_message(view_change.clone());
+        let signed_view_change = self.crypto.create_signed_message(view_change);
         network
-            .broadcast(&PBFTMessage::ViewChange(signed_view_change))
+            .broadcast(&PBFTMessage::ViewChange(signed_view_change.clone()))
             .await;
 
         self.view_change_msgs
             .entry(new_view)
-            .or_insert_with(HashMap::new)
-            .insert(self.node_id, view_change);
+            .or_default()
+            .insert(self.node_id, signed_view_change);
 
-        println!("View change sent for view {}", new_view);
+        debug!(new_view, "View change sent");
+
+        self.try_send_new_view(new_view, network).await;
     }
 
+    /// Prepared certificates for every slot within `VIEW_CHANGE_WINDOW` of the
+    /// last executed one or above it, by sequence number.
     fn collect_prepared_requests(&self) -> Vec<PreparedProof> {
-        let mut proofs = Vec::new();
-
-        for (seq_num, log) in &self.message_log {
-            if log.prepared && !log.committed {
-                if let Some(pre) = &log.pre_prepare {
-                    let prepares: Vec<Prepare> = log.prepares.values().map(|p| p.clone()).collect();
-
-                    if prepares.len() >= (2 * self.f) as usize {
-                        proofs.push(PreparedProof {
-                            pre_prepare: pre.clone(),
-                            prepares,
-                        })
+        let low = self.last_executed.saturating_sub(VIEW_CHANGE_WINDOW);
+        let mut proofs: Vec<PreparedProof> = self
+            .message_log
+            .iter()
+            .filter(|(seq_num, _)| **seq_num > low)
+            .filter_map(|(_, log)| log.proof.clone())
+            .collect();
+        proofs.sort_by_key(|p| p.pre_prepare.message.seq_num);
+        proofs
+    }
+
+    async fn handle_view_change<T: Transport>(
+        &mut self,
+        signed_view_change: SignedMessage<ViewChange>,
+        network: &T,
+    ) {
+        let view_change = &signed_view_change.message;
+        let new_view = view_change.new_view;
+
+        if signed_view_change.signer_id != view_change.replica_id || new_view <= self.view {
+            return;
+        }
+
+        if !self.verify_view_change(&signed_view_change) {
+            warn!(
+                new_view,
+                from = view_change.replica_id,
+                "Ignoring view change with an unproven claim"
+            );
+            return;
+        }
+
+        let votes = self.view_change_msgs.entry(new_view).or_default();
+        votes.insert(view_change.replica_id, signed_view_change);
+        let votes = votes.len();
+
+        debug!(new_view, votes, "Received view change");
+
+        // f + 1 replicas asking for a view include a correct one, so join
+        // them rather than wait for our own timer.
+        if votes > self.membership.f() as usize {
+            self.trigger_view_change(new_view, network).await;
+        }
+
+        self.try_send_new_view(new_view, network).await;
+    }
+
+    /// Whether everything `signed` claims is backed by signatures: its
+    /// `last_executed` by a commit certificate, and each prepared slot by a
+    /// `PreparedProof` from a view before the one it asks for. Its own
+    /// signature is checked by the caller.
+    fn verify_view_change(&self, signed: &SignedMessage<ViewChange>) -> bool {
+        let view_change = &signed.message;
+        if signed.signer_id != view_change.replica_id {
+            return false;
+        }
+
+        let executed_proven = match &view_change.executed_certificate {
+            None => view_change.last_executed == 0,
+            Some(certificate) => {
+                let membership = self.membership_at(certificate.seq_num);
+                certificate.seq_num == view_change.last_executed
+                    && certificate.verify(
+                        &membership.replica_public_keys(),
+                        membership.quorum() as usize,
+                    )
+            }
+        };
+
+        executed_proven
+            && view_change
+                .prepared_requests
+                .iter()
+                .all(|proof| self.verify_prepared_proof(proof, view_change.new_view))
+    }
+
+    /// Whether `proof` holds a pre-prepare signed by the primary of its view,
+    /// which must precede `new_view`, and a quorum less one of prepares for
+    /// the same slot and digest signed by distinct backups.
+    fn verify_prepared_proof(&self, proof: &PreparedProof, new_view: u64) -> bool {
+        let pre = &proof.pre_prepare.message;
+        let membership = self.membership_at(pre.seq_num);
+        let primary = membership.primary(pre.view);
+
+        if pre.view >= new_view
+            || proof.pre_prepare.signer_id != primary
+            || pre.request.message.digest() != pre.digest
+            || !self.is_authentic(&pre.request)
+            || !Self::signed_by_member(membership, &proof.pre_prepare)
+        {
+            return false;
+        }
+
+        let mut backups = HashSet::new();
+        for signed in &proof.prepares {
+            let prepare = &signed.message;
+            let matches = (prepare.view, prepare.seq_num, prepare.digest)
+                == (pre.view, pre.seq_num, pre.digest);
+            if !matches
+                || signed.signer_id != prepare.replica_id
+                || prepare.replica_id == primary
+                || !backups.insert(prepare.replica_id)
+                || !Self::signed_by_member(membership, signed)
+            {
+                return false;
+            }
+        }
+        backups.len() >= (membership.quorum() - 1) as usize
+    }
+
+    /// The membership that ordered `seq_num`.
+    fn membership_at(&self, seq_num: u64) -> &Membership {
+        self.retired_memberships
+            .range(seq_num..)
+            .next()
+            .map_or(&self.membership, |(_, membership)| membership)
+    }
+
+    fn signed_by_member<T: Serialize>(membership: &Membership, signed: &SignedMessage<T>) -> bool {
+        membership
+            .get(signed.signer_id)
+            .is_some_and(|r| Crypto::verify_with_key(&r.public_key, signed))
+    }
+
+    /// As primary of `new_view`, announces it once a quorum asked for it.
+    async fn try_send_new_view<T: Transport>(&mut self, new_view: u64, network: &T) {
+        if self.membership.primary(new_view) != self.node_id || new_view <= self.view {
+            return;
+        }
+
+        let Some(votes) = self.view_change_msgs.get(&new_view) else {
+            return;
+        };
+        if votes.len() < self.membership.quorum() as usize {
+            return;
+        }
+
+        let view_change_msgs: Vec<SignedMessage<ViewChange>> = votes.values().cloned().collect();
+        let pre_prepares = self
+            .new_view_pre_prepares(new_view, &view_change_msgs)
+            .into_iter()
+            .map(|pre| self.crypto.create_signed_message(pre))
+            .collect();
+
+        let new_view_msg = NewView {
+            new_view,
+            view_change_msgs,
+            pre_prepares,
+            replica_id: self.node_id,
+        };
+
+        let signed_new_view = self.crypto.create_signed_message(new_view_msg.clone());
+        network
+            .broadcast(&PBFTMessage::NewView(signed_new_view))
+            .await;
+
+        info!(new_view, "Primary: broadcasted new view");
+
+        self.enter_view(new_view_msg, network).await;
+    }
+
+    /// Slots up to this one are left out of a new view. Every view change
+    /// reports the slots above it, so no committed slot above it can be
+    /// missing from a quorum of them.
+    fn new_view_low(view_change_msgs: &[SignedMessage<ViewChange>]) -> u64 {
+        Self::highest_executed(view_change_msgs).saturating_sub(VIEW_CHANGE_WINDOW)
+    }
+
+    fn highest_executed(view_change_msgs: &[SignedMessage<ViewChange>]) -> u64 {
+        view_change_msgs
+            .iter()
+            .map(|vc| vc.message.last_executed)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Pre-prepares a new primary issues for `new_view`: for every slot above
+    /// `new_view_low`, the request prepared in the latest view, or a null
+    /// request where none was. Deterministic, so backups can recompute it to
+    /// check the primary.
+    fn new_view_pre_prepares(
+        &self,
+        new_view: u64,
+        view_change_msgs: &[SignedMessage<ViewChange>],
+    ) -> Vec<PrePrepare> {
+        let low = Self::new_view_low(view_change_msgs);
+
+        let mut chosen: BTreeMap<u64, &PrePrepare> = BTreeMap::new();
+        for vc in view_change_msgs {
+            for proof in &vc.message.prepared_requests {
+                let pre = &proof.pre_prepare.message;
+                if pre.seq_num <= low {
+                    continue;
+                }
+                match chosen.get(&pre.seq_num) {
+                    Some(current) if current.view >= pre.view => {}
+                    _ => {
+                        chosen.insert(pre.seq_num, pre);
                     }
                 }
             }
         }
-        proofs
+
+        let high = chosen.keys().next_back().copied().unwrap_or(low);
+
+        (low + 1..=high)
+            .map(|seq_num| {
+                let request = chosen
+                    .get(&seq_num)
+                    .map(|pre| pre.request.clone())
+                    .unwrap_or_else(SignedMessage::null);
+                PrePrepare {
+                    view: new_view,
+                    seq_num,
+                    digest: request.message.digest(),
+                    request,
+                }
+            })
+            .collect()
     }
 
-    fn total_nodes(&self) -> u32 {
-        3 * self.f + 1
+    async fn handle_new_view<T: Transport>(
+        &mut self,
+        signed_new_view: SignedMessage<NewView>,
+        network: &T,
+    ) {
+        let new_view = signed_new_view.message;
+
+        if new_view.new_view <= self.view {
+            return;
+        }
+
+        if signed_new_view.signer_id != self.membership.primary(new_view.new_view) {
+            warn!(
+                new_view = new_view.new_view,
+                signer = signed_new_view.signer_id,
+                "New view not from its primary"
+            );
+            return;
+        }
+
+        let mut voters = HashSet::new();
+        for vc in &new_view.view_change_msgs {
+            if vc.message.new_view != new_view.new_view
+                || !self.crypto.verify_signed_message(vc)
+                || !self.verify_view_change(vc)
+            {
+                warn!(
+                    new_view = new_view.new_view,
+                    "New view carries an invalid view change"
+                );
+                return;
+            }
+            voters.insert(vc.signer_id);
+        }
+        if voters.len() < self.membership.quorum() as usize {
+            warn!(new_view = new_view.new_view, "New view lacks a quorum");
+            return;
+        }
+
+        let expected = self.new_view_pre_prepares(new_view.new_view, &new_view.view_change_msgs);
+        let matches = expected.len() == new_view.pre_prepares.len()
+            && expected.iter().zip(&new_view.pre_prepares).all(|(a, b)| {
+                let b_pre = &b.message;
+                a.seq_num == b_pre.seq_num
+                    && a.digest == b_pre.digest
+                    && a.view == b_pre.view
+                    && b_pre.request.message.digest() == b_pre.digest
+                    && self.is_authentic(&b_pre.request)
+                    && b.signer_id == signed_new_view.signer_id
+                    && self.crypto.verify_signed_message(b)
+            });
+        if !matches {
+            warn!(
+                new_view = new_view.new_view,
+                "New view pre-prepares do not follow from its view changes"
+            );
+            return;
+        }
+
+        self.enter_view(new_view, network).await;
+    }
+
+    async fn enter_view<T: Transport>(&mut self, new_view: NewView, network: &T) {
+        self.view = new_view.new_view;
+        self.metrics.view.store(self.view, Ordering::Relaxed);
+        self.in_view_change = false;
+        self.view_entered_at = Self::highest_executed(&new_view.view_change_msgs);
+        self.stop_timer();
+        self.view_change_msgs
+            .retain(|view, _| *view > new_view.new_view);
+
+        info!(
+            view = self.view,
+            primary = self.get_primary(),
+            "Entered view"
+        );
+        self.emit(ReplicaEvent::ViewEntered { view: self.view });
+
+        // Whatever was in flight but not committed is either re-proposed
+        // below or abandoned, except for its prepared proof: a quorum may
+        // have committed the slot, so later view changes must still carry it.
+        let last_executed = self.last_executed;
+        self.message_log.retain(|seq_num, log| {
+            if *seq_num <= last_executed || log.committed {
+                return true;
+            }
+            let Some(proof) = log.proof.take() else {
+                return false;
+            };
+            *log = MessageLog {
+                proof: Some(proof),
+                ..MessageLog::new(log.created_at)
+            };
+            true
+        });
+
+        // Every slot in the new view is agreed on again, including ones this
+        // replica already executed, so that replicas behind it can finish
+        // them. Nothing is executed twice: execution only moves forward.
+        let is_primary = self.is_primary();
+        let low = Self::new_view_low(&new_view.view_change_msgs);
+        self.next_seq_num = self.next_seq_num.max(low + 1);
+
+        for signed_pre in new_view.pre_prepares {
+            let pre = signed_pre.message.clone();
+            let seq_num = pre.seq_num;
+            self.next_seq_num = self.next_seq_num.max(seq_num + 1);
+
+            let proof = self.message_log.remove(&seq_num).and_then(|log| log.proof);
+            let log = self.get_or_create_log(seq_num);
+            log.request = Some(pre.request.message.clone());
+            log.pre_prepare = Some(pre.clone());
+            log.signed_pre_prepare = Some(signed_pre);
+            log.proof = proof;
+
+            if !is_primary {
+                self.send_prepare(&pre, network).await;
+            }
+        }
+        self.next_seq_num = self.next_seq_num.max(self.last_executed + 1);
+
+        if is_primary {
+            let unassigned: Vec<SignedMessage<Request>> = self
+                .pending_requests
+                .values()
+                .filter(|req| !self.is_assigned(req.message.id()))
+                .cloned()
+                .collect();
+            for req in unassigned {
+                self.propose(req, network).await;
+            }
+        } else if !self.pending_requests.is_empty() {
+            self.start_timer();
+        }
+
+        let deferred = std::mem::take(&mut self.deferred);
+        for msg in deferred {
+            self.handle_normal_case(msg, network).await;
+        }
+
+        self.try_execute_committed(network).await;
+    }
+
+    /// Matching prepares (besides the pre-prepare) needed to become prepared.
+    fn prepared_threshold(&self) -> usize {
+        (self.membership.quorum() - 1) as usize
     }
 
     pub fn is_primary(&self) -> bool {
-        self.node_id as u64 == self.view % (self.total_nodes() as u64)
+        self.node_id == self.get_primary()
     }
 
     fn get_primary(&self) -> u32 {
-        (self.view % (self.total_nodes() as u64)) as u32
+        self.membership.primary(self.view)
     }
 
     fn get_or_create_log(&mut self, seq_num: u64) -> &mut MessageLog {
+        let now = self.clock.now();
         self.message_log
             .entry(seq_num)
-            .or_insert_with(MessageLog::new)
+            .or_insert_with(|| MessageLog::new(now))
     }
 
-    fn check_prepared(&mut self, seq_num: u64, digest: &[u8; 32]) {
-        let log = self.message_log.get_mut(&seq_num).unwrap();
+    fn check_prepared(&mut self, seq_num: u64) -> bool {
+        let threshold = self.prepared_threshold();
+        let Some(log) = self.message_log.get_mut(&seq_num) else {
+            return false;
+        };
 
         if log.prepared {
-            return;
+            return true;
         }
 
-        let matching_prepares = log
+        let Some(pre) = &log.signed_pre_prepare else {
+            return false;
+        };
+
+        // The primary's pre-prepare stands in for its prepare.
+        let primary = self.membership.primary(pre.message.view);
+        let mut prepares: Vec<SignedMessage<Prepare>> = log
             .prepares
             .values()
-            .filter(|p| &p.digest == digest)
-            .count();
-
-        if matching_prepares >= 2 * self.f as usize {
+            .filter(|p| p.message.digest == pre.message.digest && p.message.replica_id != primary)
+            .cloned()
+            .collect();
+
+        if prepares.len() >= threshold {
+            prepares.sort_by_key(|p| p.message.replica_id);
+            log.proof = Some(PreparedProof {
+                pre_prepare: pre.clone(),
+                prepares,
+            });
             log.prepared = true;
         }
+
+        log.prepared
     }
 
-    fn check_committed(&mut self, seq_num: u64, digest: &[u8; 32]) -> bool {
-        let log = self.message_log.get_mut(&seq_num).unwrap();
+    fn check_committed(&mut self, seq_num: u64) -> bool {
+        let quorum = self.membership.quorum() as usize;
+        let Some(log) = self.message_log.get_mut(&seq_num) else {
+            return false;
+        };
 
         if log.committed {
             return true;
@@ -195,9 +747,17 @@ impl Replica {
             return false;
         }
 
-        let matching_commits = log.commits.values().filter(|p| &p.digest == digest).count();
+        let Some(pre) = &log.pre_prepare else {
+            return false;
+        };
+
+        let matching_commits = log
+            .commits
+            .values()
+            .filter(|c| c.message.digest == pre.digest)
+            .count();
 
-        if matching_commits == (2 * self.f + 1) as usize {
+        if matching_commits >= quorum {
             log.committed = true;
             return true;
         }
@@ -205,53 +765,314 @@ impl Replica {
         false
     }
 
+    /// Moves `seq_num` as far through prepare, commit and execution as the
+    /// messages logged for it allow. Prepares and commits may arrive before
+    /// the pre-prepare, so every handler calls this after logging.
+    async fn advance<T: Transport>(&mut self, seq_num: u64, network: &T) {
+        if !self.check_prepared(seq_num) {
+            return;
+        }
+
+        let log = self.message_log.get_mut(&seq_num).unwrap();
+        let was_committed = log.committed;
+
+        if !log.commits.contains_key(&self.node_id) {
+            let commit = Commit {
+                view: self.view,
+                seq_num,
+                digest: log.pre_prepare.as_ref().unwrap().digest,
+                replica_id: self.node_id,
+            };
+
+            let digest = commit.digest;
+            let signed_commit = self.crypto.create_signed_message(commit);
+            log.commits.insert(self.node_id, signed_commit.clone());
+            network.broadcast(&PBFTMessage::Commit(signed_commit)).await;
+
+            debug!(seq_num, digest = %digest_prefix(&digest), "Prepared, sent commit");
+        }
+
+        if self.check_committed(seq_num) && !was_committed {
+            let log = &self.message_log[&seq_num];
+            // Slots re-agreed after a view change were ordered already.
+            if seq_num > self.last_executed {
+                self.metrics
+                    .requests_ordered
+                    .fetch_add(1, Ordering::Relaxed);
+                self.metrics
+                    .commit_latency
+                    .observe(self.clock.now().saturating_sub(log.created_at));
+            }
+            let pre = log.pre_prepare.as_ref().unwrap();
+            info!(
+                seq_num,
+                view = pre.view,
+                digest = %digest_prefix(&pre.digest),
+                "Committed"
+            );
+
+            let event = ReplicaEvent::Committed {
+                seq_num,
+                view: pre.view,
+                digest: pre.digest,
+            };
+            self.emit(event);
+
+            self.try_execute_committed(network).await;
+        }
+    }
+
     fn execute_request(&mut self, seq_num: u64) -> Option<Vec<u8>> {
         if seq_num != self.last_executed + 1 {
             return None;
         }
 
         let log = self.message_log.get(&seq_num)?;
-        let req = log.request.as_ref()?;
+        let req = log.request.clone()?;
+        let pre = log.pre_prepare.as_ref()?;
+        let (view, digest) = (pre.view, pre.digest);
+        let certificate = Self::commit_certificate(log, pre, self.membership.quorum() as usize);
+        self.certificates.put(&CertifiedRequest {
+            request: req.clone(),
+            certificate,
+        });
+        self.emit(ReplicaEvent::Executed { seq_num, digest });
+        Some(self.apply_request(seq_num, view, digest, &req))
+    }
 
-        if self.executed_req.contains(&req.timestamp) {
-            return None;
+    /// Runs `req`, committed at `seq_num` in `view`, against the replicated
+    /// state and queues the reply for its client.
+    fn apply_request(
+        &mut self,
+        seq_num: u64,
+        view: u64,
+        digest: [u8; 32],
+        req: &Request,
+    ) -> Vec<u8> {
+        // A null request, or a request that was already executed at an
+        // earlier slot, still uses up its sequence number.
+        if req.is_null() || self.executed_req.contains(&req.id()) {
+            self.set_last_executed(seq_num);
+            self.append_ledger(seq_num, view, digest, &[]);
+            return Vec::new();
         }
 
-        let result = self.app_state.execute(&req.operation);
+        let result = match Reconfigure::from_operation(&req.operation) {
+            Some(reconfigure) => self.apply_reconfigure(seq_num, req.client_id, &reconfigure),
+            None => self.app_state.execute(&req.operation),
+        };
 
-        self.executed_req.insert(req.timestamp);
+        self.executed_req.insert(req.id());
+        self.pending_requests.remove(&req.id());
+        self.set_last_executed(seq_num);
+        self.metrics
+            .requests_executed
+            .fetch_add(1, Ordering::Relaxed);
+        let entry = self.append_ledger(seq_num, view, digest, &result);
+
+        let reply = Reply {
+            view: self.view,
+            seq_num,
+            timestamp: req.timestamp,
+            client_id: req.client_id,
+            replica_id: self.node_id,
+            result: result.clone(),
+            state_digest: entry.state_digest,
+        };
+        let signed_reply = self.crypto.create_signed_message(reply);
+        self.last_replies
+            .insert(req.client_id, signed_reply.clone());
+        self.outgoing_replies.push(signed_reply);
+
+        result
+    }
+
+    /// Executes again the certified requests in the certificate store, from
+    /// `last_executed + 1` up to the first missing or invalid one, so a
+    /// replica restarted on its data directory resumes where it stopped.
+    /// Clients are not answered again. Returns the new `last_executed`.
+    pub async fn recover<T: Transport>(&mut self, network: &T) -> u64 {
+        let epoch = self.membership.epoch();
+        while let Some(entry) = self.certificates.get(self.last_executed + 1) {
+            let certificate = &entry.certificate;
+            let keys = self.membership.replica_public_keys();
+            if !entry.verify(&keys, self.membership.quorum() as usize) {
+                warn!(
+                    seq_num = certificate.seq_num,
+                    "Stored certificate does not verify; recovery stops here"
+                );
+                break;
+            }
+            self.apply_request(
+                certificate.seq_num,
+                certificate.view,
+                certificate.digest,
+                &entry.request,
+            );
+        }
+        self.outgoing_replies.clear();
+
+        if self.membership.epoch() != epoch {
+            network.apply_membership(&self.membership).await;
+        }
+        self.last_executed
+    }
+
+    fn set_last_executed(&mut self, seq_num: u64) {
         self.last_executed = seq_num;
-        Some(result)
+        self.metrics.last_executed.store(seq_num, Ordering::Relaxed);
     }
 
-    fn compute_digest(&self, req: &Request) -> [u8; 32] {
-        let serialized = postcard::to_allocvec(req).unwrap();
-        let mut hasher = Sha256::new();
+    /// Adds the ledger entry for `seq_num`, chained to the one before it. A
+    /// replica restarted on an existing ledger executes its history again in
+    /// `recover`; entries it already has are kept, and a mismatch is reported.
+    fn append_ledger(
+        &mut self,
+        seq_num: u64,
+        view: u64,
+        request_digest: [u8; 32],
+        result: &[u8],
+    ) -> LedgerEntry {
+        let prev_hash = self
+            .ledger
+            .get(seq_num - 1)
+            .map_or(GENESIS_HASH, |prev| prev.hash());
+        let entry = LedgerEntry {
+            seq_num,
+            view,
+            request_digest,
+            result_digest: Sha256::digest(result).into(),
+            prev_hash,
+            state_digest: self.app_state.digest(),
+        };
+
+        match self.ledger.get(seq_num) {
+            None => self.ledger.append(&entry),
+            Some(existing) if existing.hash() == entry.hash() => {}
+            Some(_) => warn!(
+                seq_num,
+                "Ledger entry does not match the one on disk; keeping the old one"
+            ),
+        }
+        entry
+    }
 
-        hasher.update(&serialized);
+    /// The quorum of commits matching `pre` from a committed slot, ordered by
+    /// replica id.
+    fn commit_certificate(log: &MessageLog, pre: &PrePrepare, quorum: usize) -> CommitCertificate {
+        let mut signatures: Vec<CommitSignature> = log
+            .commits
+            .values()
+            .filter(|c| c.message.digest == pre.digest)
+            .map(|c| CommitSignature {
+                replica_id: c.message.replica_id,
+                signature: c.signature.clone(),
+            })
+            .collect();
+        signatures.sort_by_key(|s| s.replica_id);
+        signatures.truncate(quorum);
+
+        CommitCertificate {
+            view: pre.view,
+            seq_num: pre.seq_num,
+            digest: pre.digest,
+            signatures,
+        }
+    }
 
-        let res = hasher.finalize();
-        let mut digest = [0u8; 32];
+    /// Switches to the membership produced by `reconfigure`. Every correct
+    /// replica executes it at the same `seq_num`, so all of them move to the
+    /// new epoch at the same point in the sequence. Only admin clients may
+    /// reconfigure; anyone else's request is executed as a rejection.
+    fn apply_reconfigure(
+        &mut self,
+        seq_num: u64,
+        client_id: u64,
+        reconfigure: &Reconfigure,
+    ) -> Vec<u8> {
+        let membership = match u32::try_from(client_id) {
+            Ok(id) if self.membership.is_admin(id) => self.membership.apply(reconfigure),
+            _ => Err(format!("client {} is not an admin", client_id)),
+        };
+        match membership {
+            Ok(membership) => {
+                self.crypto
+                    .set_peer_public_keys(membership.peer_public_keys(self.node_id));
+                self.crypto
+                    .set_client_public_keys(membership.client_public_keys());
+                let retired = std::mem::replace(&mut self.membership, membership);
+                self.retired_memberships.insert(seq_num, retired);
+
+                info!(
+                    epoch = self.membership.epoch(),
+                    seq_num,
+                    replicas = self.membership.total_nodes(),
+                    quorum = self.membership.quorum(),
+                    "Membership epoch in effect"
+                );
+                if !self.membership.contains(self.node_id) {
+                    warn!("This replica was removed from the membership");
+                }
 
-        digest.copy_from_slice(&res);
-        digest
+                b"OK".to_vec()
+            }
+            Err(e) => {
+                warn!(seq_num, error = %e, "Rejected reconfiguration");
+                format!("RECONFIGURE_REJECTED:{}", e).into_bytes()
+            }
+        }
     }
 
-    async fn handle_request(&mut self, signed_req: SignedMessage<Request>, network: &Network) {
-        if !self.is_primary() {
+    async fn handle_request<T: Transport>(
+        &mut self,
+        signed_req: SignedMessage<Request>,
+        network: &T,
+    ) {
+        let req = &signed_req.message;
+
+        if self.executed_req.contains(&req.id()) {
+            // The client is retrying, so the reply was probably lost.
+            if let Some(reply) = self.last_replies.get(&req.client_id)
+                && reply.message.timestamp == req.timestamp
+            {
+                network
+                    .send_to(req.client_id as u32, &PBFTMessage::Reply(reply.clone()))
+                    .await;
+            }
             return;
         }
 
-        let req = signed_req.message;
+        if !self.is_primary() || self.in_view_change {
+            // Remember it so a stalled primary is noticed and the request
+            // can be re-proposed after a view change.
+            self.pending_requests.entry(req.id()).or_insert(signed_req);
+            if self.view_change_timer.is_none() {
+                self.start_timer();
+            }
+            return;
+        }
 
-        if self.executed_req.contains(&req.timestamp) {
+        if self.is_assigned(req.id()) {
             return;
         }
 
+        self.propose(signed_req, network).await;
+    }
+
+    /// Whether some logged pre-prepare already carries the request.
+    fn is_assigned(&self, id: (u64, u64)) -> bool {
+        self.message_log.values().any(|log| {
+            log.request
+                .as_ref()
+                .is_some_and(|req| !req.is_null() && req.id() == id)
+        })
+    }
+
+    async fn propose<T: Transport>(&mut self, req: SignedMessage<Request>, network: &T) {
         let seq_num = self.next_seq_num;
         self.next_seq_num += 1;
 
-        let digest = self.compute_digest(&req);
+        let digest = req.message.digest();
 
         let pre_prepare = PrePrepare {
             view: self.view,
@@ -262,32 +1083,191 @@ impl Replica {
 
         let signed_pre_prepare = self.crypto.create_signed_message(pre_prepare.clone());
         network
-            .broadcast(&PBFTMessage::PrePrepare(signed_pre_prepare))
+            .broadcast(&PBFTMessage::PrePrepare(signed_pre_prepare.clone()))
             .await;
 
+        self.pending_requests.insert(req.message.id(), req.clone());
+
         let log = self.get_or_create_log(seq_num);
-        log.request = Some(req);
+        log.request = Some(req.message);
         log.pre_prepare = Some(pre_prepare);
+        log.signed_pre_prepare = Some(signed_pre_prepare);
 
-        println!("Primary: broadcasted pre-prepare for seq {}", seq_num);
+        info!(seq_num, digest = %digest_prefix(&digest), "Primary: broadcasted pre-prepare");
     }
 
-    async fn handle_pre_prepare(
+    async fn handle_pre_prepare<T: Transport>(
         &mut self,
         signed_pre_prepare: SignedMessage<PrePrepare>,
-        network: &Network,
+        network: &T,
     ) {
-        let pre = signed_pre_prepare.message;
+        if let Some(evidence) = self.conflicting_pre_prepare(&signed_pre_prepare) {
+            self.report_evidence(evidence, network).await;
+            return;
+        }
+
+        let pre = signed_pre_prepare.message.clone();
 
-        if !self.validate_pre_prepare(&pre, signed_pre_prepare.signer_id, network) {
-            println!("Pre-prepare invalid");
+        if !self.validate_pre_prepare(&pre, signed_pre_prepare.signer_id) {
+            debug!(seq_num = pre.seq_num, "Pre-prepare invalid");
             return;
         }
 
-        // let log = self.get_or_create_log(pre.seq_num);
-        // log.request = Some(pre.request.clone());
-        // log.pre_prepare = Some(pre.clone());
+        if self
+            .message_log
+            .get(&pre.seq_num)
+            .is_some_and(|log| log.pre_prepare.is_some())
+        {
+            return;
+        }
+
+        let log = self.get_or_create_log(pre.seq_num);
+        log.request = Some(pre.request.message.clone());
+        log.pre_prepare = Some(pre.clone());
+        log.signed_pre_prepare = Some(signed_pre_prepare);
+
+        let req = &pre.request.message;
+        if !req.is_null() && !self.executed_req.contains(&req.id()) {
+            self.pending_requests
+                .entry(req.id())
+                .or_insert_with(|| pre.request.clone());
+            if self.view_change_timer.is_none() {
+                self.start_timer();
+            }
+        }
 
+        self.send_prepare(&pre, network).await;
+        self.advance(pre.seq_num, network).await;
+    }
+
+    /// Evidence against the primary if `signed` conflicts with the
+    /// pre-prepare it already sent for the same view and sequence number.
+    fn conflicting_pre_prepare(&self, signed: &SignedMessage<PrePrepare>) -> Option<Evidence> {
+        let existing = self
+            .message_log
+            .get(&signed.message.seq_num)?
+            .signed_pre_prepare
+            .as_ref()?;
+
+        let evidence = Evidence {
+            first: existing.clone(),
+            second: signed.clone(),
+        };
+        evidence.is_conflicting().then_some(evidence)
+    }
+
+    /// A backup whose prepare disagrees with our pre-prepare may have been
+    /// sent a different one. Showing it ours lets it catch the primary
+    /// equivocating.
+    async fn relay_pre_prepare<T: Transport>(&self, prepare: &Prepare, from: u32, network: &T) {
+        if let Some(log) = self.message_log.get(&prepare.seq_num)
+            && let Some(signed) = &log.signed_pre_prepare
+            && signed.message.view == prepare.view
+            && signed.message.digest != prepare.digest
+        {
+            network
+                .send_to(from, &PBFTMessage::PrePrepare(signed.clone()))
+                .await;
+        }
+    }
+
+    /// Records evidence seen for the first time and passes it on to the other
+    /// replicas. If it convicts the current primary, the view is abandoned.
+    async fn report_evidence<T: Transport>(&mut self, evidence: Evidence, network: &T) {
+        let known = self.evidence.iter().any(|e| {
+            e.culprit() == evidence.culprit()
+                && e.view() == evidence.view()
+                && e.seq_num() == evidence.seq_num()
+        });
+        if known {
+            return;
+        }
+
+        warn!(
+            culprit = evidence.culprit(),
+            view = evidence.view(),
+            seq_num = evidence.seq_num(),
+            "Replica equivocated"
+        );
+
+        let signed_evidence = self.crypto.create_signed_message(evidence.clone());
+        network
+            .broadcast(&PBFTMessage::Evidence(Box::new(signed_evidence)))
+            .await;
+
+        if evidence.view() == self.view && evidence.culprit() == self.get_primary() {
+            self.primary_faulty = true;
+        }
+        if self.evidence.len() < MAX_EVIDENCE {
+            self.evidence.push(evidence);
+        }
+    }
+
+    async fn handle_admin_request<T: Transport>(
+        &mut self,
+        signed_request: SignedMessage<AdminRequest>,
+        network: &T,
+    ) {
+        let request = signed_request.message;
+        let is_admin =
+            u32::try_from(request.client_id).is_ok_and(|id| self.membership.is_admin(id));
+        let result = match request.query {
+            // Queries only read state; anything that acts on the replica
+            // needs an admin from `cluster.toml`.
+            AdminQuery::ForceViewChange | AdminQuery::Snapshot if !is_admin => {
+                warn!(
+                    client_id = request.client_id,
+                    query = ?request.query,
+                    "Refused admin command from a non-admin client"
+                );
+                AdminResult::Failed(format!("client {} is not an admin", request.client_id))
+            }
+            AdminQuery::Evidence => AdminResult::Evidence(self.evidence.clone()),
+            AdminQuery::Certificate { seq_num } => {
+                AdminResult::Certificate(self.certificates.get(seq_num))
+            }
+            AdminQuery::Ledger { from, to } => {
+                let to = to.min(from.saturating_add(MAX_LEDGER_QUERY - 1));
+                AdminResult::Ledger((from..=to).filter_map(|s| self.ledger.get(s)).collect())
+            }
+            AdminQuery::Proof { key } => AdminResult::Proof {
+                seq_num: self.last_executed,
+                proof: self.app_state.prove(&key),
+            },
+            AdminQuery::Status => AdminResult::Status(self.status()),
+            AdminQuery::ForceViewChange => {
+                warn!(
+                    client_id = request.client_id,
+                    "View change forced by operator"
+                );
+                AdminResult::ViewChange {
+                    new_view: self.force_view_change(network).await,
+                }
+            }
+            AdminQuery::Snapshot => match self.take_snapshot() {
+                Ok(snapshot) => AdminResult::Snapshot {
+                    seq_num: snapshot.seq_num,
+                    state_digest: snapshot.state_digest,
+                },
+                Err(e) => AdminResult::Failed(format!("Failed to save snapshot: {}", e)),
+            },
+        };
+
+        let response = self.crypto.create_signed_message(AdminResponse {
+            replica_id: self.node_id,
+            client_id: request.client_id,
+            nonce: request.nonce,
+            result,
+        });
+        network
+            .send_to(
+                request.client_id as u32,
+                &PBFTMessage::AdminResponse(response),
+            )
+            .await;
+    }
+
+    async fn send_prepare<T: Transport>(&mut self, pre: &PrePrepare, network: &T) {
         let node_id = self.node_id;
         let prepare = Prepare {
             view: pre.view,
@@ -296,25 +1276,36 @@ impl Replica {
             replica_id: node_id,
         };
 
-        let signed_prepare = self.crypto.create_signed_message(prepare.clone());
+        let signed_prepare = self.crypto.create_signed_message(prepare);
 
         network
-            .broadcast(&PBFTMessage::Prepare(signed_prepare))
+            .broadcast(&PBFTMessage::Prepare(signed_prepare.clone()))
             .await;
 
         let log = self.get_or_create_log(pre.seq_num);
-        log.request = Some(pre.request.clone());
-        log.pre_prepare = Some(pre.clone());
-
-        log.prepares.insert(node_id, prepare);
+        log.prepares.insert(node_id, signed_prepare);
 
-        println!("Backup: sent prepare for seq {}", pre.seq_num);
+        debug!(
+            seq_num = pre.seq_num,
+            digest = %digest_prefix(&pre.digest),
+            "Backup: sent prepare"
+        );
     }
 
-    async fn handle_prepare(&mut self, signed_prepare: SignedMessage<Prepare>, network: &Network) {
-        let prepare = signed_prepare.message;
+    async fn handle_prepare<T: Transport>(
+        &mut self,
+        signed_prepare: SignedMessage<Prepare>,
+        network: &T,
+    ) {
+        let prepare = signed_prepare.message.clone();
+
+        if signed_prepare.signer_id != prepare.replica_id {
+            return;
+        }
 
         if !self.validate_prepare(&prepare) {
+            self.relay_pre_prepare(&prepare, signed_prepare.signer_id, network)
+                .await;
             return;
         }
 
@@ -324,40 +1315,27 @@ impl Replica {
             return;
         }
 
-        log.prepares.insert(prepare.replica_id, prepare.clone());
+        log.prepares.insert(prepare.replica_id, signed_prepare);
 
-        println!(
-            "Received prepare from {} for seq {} (total: {})",
-            prepare.replica_id,
-            prepare.seq_num,
-            log.prepares.len()
+        debug!(
+            from = prepare.replica_id,
+            seq_num = prepare.seq_num,
+            digest = %digest_prefix(&prepare.digest),
+            total = log.prepares.len(),
+            "Received prepare"
         );
 
-        self.check_prepared(prepare.seq_num, &prepare.digest);
-
-        let log = self.message_log.get_mut(&prepare.seq_num).unwrap();
-
-        if log.prepared && !log.commits.contains_key(&self.node_id) {
-            let commit = Commit {
-                view: prepare.view,
-                seq_num: prepare.seq_num,
-                digest: prepare.digest,
-                replica_id: self.node_id,
-            };
-
-            let signed_commit = self.crypto.create_signed_message(commit.clone());
-            network.broadcast(&PBFTMessage::Commit(signed_commit)).await;
-
-            log.commits.insert(self.node_id, commit);
-
-            println!("Prepared! Sent commit for seq {}", prepare.seq_num);
-        }
+        self.advance(prepare.seq_num, network).await;
     }
 
-    async fn handle_commit(&mut self, signed_commit: SignedMessage<Commit>, network: &Network) {
-        let commit = signed_commit.message;
+    async fn handle_commit<T: Transport>(
+        &mut self,
+        signed_commit: SignedMessage<Commit>,
+        network: &T,
+    ) {
+        let commit = signed_commit.message.clone();
 
-        if !self.validate_commit(&commit) {
+        if signed_commit.signer_id != commit.replica_id || !self.validate_commit(&commit) {
             return;
         }
 
@@ -367,79 +1345,94 @@ impl Replica {
             return;
         }
 
-        log.commits.insert(commit.replica_id, commit.clone());
+        log.commits.insert(commit.replica_id, signed_commit);
 
-        println!(
-            "Received commit from {} for seq {} (total: {})",
-            commit.replica_id,
-            commit.seq_num,
-            log.commits.len()
+        debug!(
+            from = commit.replica_id,
+            seq_num = commit.seq_num,
+            digest = %digest_prefix(&commit.digest),
+            total = log.commits.len(),
+            "Received commit"
         );
 
-        if self.check_committed(commit.seq_num, &commit.digest) {
-            println!("Committed seq {}!", commit.seq_num);
-
-            self.try_execute_up_to(commit.seq_num);
-        }
+        self.advance(commit.seq_num, network).await;
     }
 
-    fn validate_pre_prepare(
-        &mut self,
-        pre_prepare: &PrePrepare,
-        signer_id: u32,
-        network: &Network,
-    ) -> bool {
+    fn validate_pre_prepare(&mut self, pre_prepare: &PrePrepare, signer_id: u32) -> bool {
         let expected_primary = self.get_primary();
 
         if signer_id != expected_primary {
-            println!(
-                "Pre-prepare not from primary (expected {}, got {})",
-                expected_primary, signer_id
+            warn!(
+                expected = expected_primary,
+                signer = signer_id,
+                "Pre-prepare not from primary"
             );
             return false;
         }
 
         if pre_prepare.view != self.view {
-            println!(
-                "Pre-prepare view mismatch (expected {}, got {})",
-                self.view, pre_prepare.view
+            debug!(
+                expected = self.view,
+                got = pre_prepare.view,
+                "Pre-prepare view mismatch"
+            );
+            return false;
+        }
+
+        if !self.is_authentic(&pre_prepare.request) {
+            warn!(
+                seq_num = pre_prepare.seq_num,
+                client = pre_prepare.request.message.client_id,
+                "Pre-prepare carries a request its client did not sign"
             );
             return false;
         }
 
-        let digest = self.compute_digest(&pre_prepare.request);
+        let digest = pre_prepare.request.message.digest();
         if digest != pre_prepare.digest {
-            println!(
-                "Pre-prepare digest mismatch (expected {:?}, got {:?})",
-                digest, pre_prepare.digest
+            warn!(
+                seq_num = pre_prepare.seq_num,
+                expected = %digest_prefix(&digest),
+                got = %digest_prefix(&pre_prepare.digest),
+                "Pre-prepare digest mismatch"
             );
             return false;
         }
 
-        if let Some(log) = self.message_log.get(&pre_prepare.seq_num) {
-            if let Some(curr) = &log.pre_prepare {
-                if curr.digest != pre_prepare.digest {
-                    println!("Conflicting pre-prepare for seq {}", pre_prepare.seq_num);
-                    return false;
-                }
-            }
+        if let Some(log) = self.message_log.get(&pre_prepare.seq_num)
+            && let Some(curr) = &log.pre_prepare
+            && curr.digest != pre_prepare.digest
+        {
+            warn!(seq_num = pre_prepare.seq_num, "Conflicting pre-prepare");
+            return false;
         }
 
         true
     }
 
+    /// Whether `request` is the null request or signed by the registered
+    /// client it names.
+    fn is_authentic(&self, request: &SignedMessage<Request>) -> bool {
+        request.message.is_null()
+            || self
+                .crypto
+                .verify_client_message(request, request.message.client_id)
+    }
+
     fn validate_prepare(&self, prepare: &Prepare) -> bool {
         if prepare.view != self.view {
             return false;
         }
 
-        if let Some(log) = self.message_log.get(&prepare.seq_num) {
-            if let Some(pre) = &log.pre_prepare {
-                return pre.digest == prepare.digest;
-            }
+        // Without a pre-prepare yet the prepare is kept; `check_prepared`
+        // only counts the ones matching the pre-prepare once it arrives.
+        if let Some(log) = self.message_log.get(&prepare.seq_num)
+            && let Some(pre) = &log.pre_prepare
+        {
+            return pre.digest == prepare.digest;
         }
 
-        false
+        true
     }
 
     fn validate_commit(&self, commit: &Commit) -> bool {
@@ -447,22 +1440,26 @@ impl Replica {
             return false;
         }
 
-        if let Some(log) = self.message_log.get(&commit.seq_num) {
-            if let Some(pre) = &log.pre_prepare {
-                if pre.digest != commit.digest {
-                    println!("Commit digest mismatch for seq {}", commit.seq_num);
-                    return false;
-                }
-            }
+        if let Some(log) = self.message_log.get(&commit.seq_num)
+            && let Some(pre) = &log.pre_prepare
+            && pre.digest != commit.digest
+        {
+            debug!(seq_num = commit.seq_num, "Commit digest mismatch");
+            return false;
         }
 
         true
     }
 
-    fn try_execute_up_to(&mut self, target_seq: u64) {
+    /// Executes committed requests in order, for as long as the next
+    /// sequence number is committed. Slots can commit out of order, so this
+    /// may run past the one that just committed.
+    async fn try_execute_committed<T: Transport>(&mut self, network: &T) {
         let mut seq = self.last_executed + 1;
+        let first = seq;
+        let epoch = self.membership.epoch();
 
-        while seq <= target_seq {
+        loop {
             let is_committed = self
                 .message_log
                 .get(&seq)
@@ -474,56 +1471,254 @@ impl Replica {
             }
 
             if let Some(res) = self.execute_request(seq) {
-                println!(
-                    "Executed seq {}: result = {:?}",
-                    seq,
-                    String::from_utf8_lossy(&res)
+                info!(
+                    seq_num = seq,
+                    result = %String::from_utf8_lossy(&res),
+                    "Executed"
                 );
             } else {
-                println!("Failed to execute seq {}", seq);
+                warn!(seq_num = seq, "Failed to execute");
                 break;
             }
 
             seq += 1;
         }
+
+        for reply in std::mem::take(&mut self.outgoing_replies) {
+            let client_id = reply.message.client_id as u32;
+            network.send_to(client_id, &PBFTMessage::Reply(reply)).await;
+        }
+
+        if seq > first {
+            if self.pending_requests.is_empty() {
+                self.stop_timer();
+            } else if !self.is_primary() {
+                // Progress was made; give the rest a full timeout again.
+                self.start_timer();
+            }
+        }
+
+        if self.membership.epoch() != epoch {
+            network.apply_membership(&self.membership).await;
+            self.epoch_changed = true;
+        }
     }
 
-    pub async fn run_replica(mut network: Network, mut replica: Replica, mut config: NodeConfig) {
-        network.spawn_acceptor();
+    /// The primary may change with the membership, and the new one does
+    /// not know where the sequence stands, so a new epoch starts with a view
+    /// change. Replicas whose view began after the reconfiguration executed
+    /// are already past that.
+    async fn start_epoch_view<T: Transport>(&mut self, network: &T) {
+        let reconfigured_at = self
+            .retired_memberships
+            .keys()
+            .next_back()
+            .copied()
+            .unwrap_or(0);
+        if self.in_view_change
+            || self.view_entered_at >= reconfigured_at
+            || !self.membership.contains(self.node_id)
+        {
+            return;
+        }
+        self.trigger_view_change(self.view + 1, network).await;
+    }
 
-        for peer in config.peers {
-            network.connect_to_peer(peer.id, peer.addr).await;
+    pub fn node_id(&self) -> u32 {
+        self.node_id
+    }
+
+    pub fn view(&self) -> u64 {
+        self.view
+    }
+
+    pub fn last_executed(&self) -> u64 {
+        self.last_executed
+    }
+
+    pub fn membership(&self) -> &Membership {
+        &self.membership
+    }
+
+    /// The request executed at `seq_num`, with its commit certificate.
+    pub fn certificate(&self, seq_num: u64) -> Option<CertifiedRequest> {
+        self.certificates.get(seq_num)
+    }
+
+    /// Proof of `key`'s value against the state after `last_executed`.
+    pub fn prove(&self, key: &str) -> KeyProof {
+        self.app_state.prove(key)
+    }
+
+    /// The ledger entry for `seq_num`, if it has been executed.
+    pub fn ledger_entry(&self, seq_num: u64) -> Option<LedgerEntry> {
+        self.ledger.get(seq_num)
+    }
+
+    pub fn status(&self) -> ReplicaStatus {
+        ReplicaStatus {
+            view: self.view,
+            primary: self.get_primary(),
+            next_seq_num: self.next_seq_num,
+            last_executed: self.last_executed,
+            last_snapshot: self.last_snapshot,
+            in_view_change: self.in_view_change,
+            pending_view: self.in_view_change.then_some(self.pending_view),
+            peers: self.metrics.peers_connected(),
+            message_log: self.message_log.len() as u64,
+            pending_requests: self.pending_requests.len() as u64,
+            deferred: self.deferred.len() as u64,
+            evidence: self.evidence.len() as u64,
         }
+    }
 
-        println!(
-            "Replica {} started (primary: {})",
-            replica.node_id,
-            replica.is_primary()
+    /// Saves the state after `last_executed` to the snapshot store.
+    pub fn take_snapshot(&mut self) -> io::Result<Snapshot> {
+        let snapshot = Snapshot {
+            seq_num: self.last_executed,
+            state_digest: self.app_state.digest(),
+            entries: self.app_state.entries(),
+        };
+        self.snapshots.save(&snapshot)?;
+        self.last_snapshot = Some(snapshot.seq_num);
+        info!(
+            seq_num = snapshot.seq_num,
+            state = %digest_prefix(&snapshot.state_digest),
+            "Saved snapshot"
         );
+        Ok(snapshot)
+    }
 
-        loop {
-            if let Some(msg) = network.recv().await {
-                if !replica.crypto.verify_pbft_message(&msg) {
-                    continue;
-                }
+    /// Equivocation evidence collected so far.
+    pub fn evidence(&self) -> &[Evidence] {
+        &self.evidence
+    }
 
-                match msg {
-                    PBFTMessage::Request(req) => {
-                        replica.handle_request(req, &network).await;
-                    }
-                    PBFTMessage::PrePrepare(pp) => {
-                        replica.handle_pre_prepare(pp, &network).await;
-                    }
-                    PBFTMessage::Prepare(p) => {
-                        replica.handle_prepare(p, &network).await;
-                    }
-                    PBFTMessage::Commit(c) => {
-                        replica.handle_commit(c, &network).await;
-                    }
-                    PBFTMessage::Reply(_) => {}
-                    PBFTMessage::NewView(_) => {}
-                    PBFTMessage::ViewChange(_) => {}
-                }
+    /// Whether the request `timestamp` of `client_id` has been executed.
+    pub fn has_executed(&self, client_id: u64, timestamp: u64) -> bool {
+        self.executed_req.contains(&(client_id, timestamp))
+    }
+
+    /// Verifies `msg` and dispatches it to its handler.
+    pub async fn handle_message<T: Transport + Sync>(&mut self, msg: PBFTMessage, network: &T) {
+        let span = info_span!(
+            "message",
+            kind = ?msg.kind(),
+            from = msg.signer_id(),
+            view = self.view
+        );
+        match self.recorder.clone() {
+            Some(recorder) => {
+                recorder.record(self.clock.now(), TraceEvent::Received(msg.clone()));
+                let network = RecordingTransport::new(network, recorder, self.clock.clone());
+                self.process_message(msg, &network).instrument(span).await;
+            }
+            None => self.process_message(msg, network).instrument(span).await,
+        }
+        self.metrics
+            .log_size
+            .store(self.message_log.len() as u64, Ordering::Relaxed);
+    }
+
+    async fn process_message<T: Transport>(&mut self, msg: PBFTMessage, network: &T) {
+        if !self.crypto.verify_pbft_message(&msg) {
+            debug!("Dropped message with an invalid signature");
+            self.metrics
+                .signature_failures
+                .fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+
+        if let Some(view) = Self::normal_case_view(&msg) {
+            if view > self.view {
+                self.defer(msg);
+                return;
+            }
+            if self.in_view_change {
+                return;
+            }
+        }
+
+        match msg {
+            PBFTMessage::Request(req) => {
+                self.handle_request(req, network).await;
+            }
+            PBFTMessage::PrePrepare(_) | PBFTMessage::Prepare(_) | PBFTMessage::Commit(_) => {
+                self.handle_normal_case(msg, network).await;
+            }
+            PBFTMessage::Reply(_) => {}
+            PBFTMessage::ViewChange(vc) => {
+                self.handle_view_change(vc, network).await;
+            }
+            PBFTMessage::NewView(nv) => {
+                self.handle_new_view(nv, network).await;
+            }
+            PBFTMessage::Evidence(evidence) => {
+                self.report_evidence(evidence.message, network).await;
+            }
+            PBFTMessage::AdminRequest(request) => {
+                self.handle_admin_request(request, network).await;
+            }
+            PBFTMessage::AdminResponse(_) => {}
+        }
+
+        if std::mem::take(&mut self.epoch_changed) {
+            self.start_epoch_view(network).await;
+        }
+        if std::mem::take(&mut self.primary_faulty) && !self.in_view_change {
+            self.trigger_view_change(self.view + 1, network).await;
+        }
+    }
+
+    /// Keeps `msg` for a view this replica has not entered yet, dropping it once
+    /// `MAX_DEFERRED` messages are waiting.
+    fn defer(&mut self, msg: PBFTMessage) {
+        if self.deferred.len() < MAX_DEFERRED {
+            self.deferred.push(msg);
+        }
+    }
+
+    fn normal_case_view(msg: &PBFTMessage) -> Option<u64> {
+        match msg {
+            PBFTMessage::PrePrepare(pp) => Some(pp.message.view),
+            PBFTMessage::Prepare(p) => Some(p.message.view),
+            PBFTMessage::Commit(c) => Some(c.message.view),
+            _ => None,
+        }
+    }
+
+    async fn handle_normal_case<T: Transport>(&mut self, msg: PBFTMessage, network: &T) {
+        if Self::normal_case_view(&msg).is_some_and(|view| view > self.view) {
+            self.defer(msg);
+            return;
+        }
+
+        match msg {
+            PBFTMessage::PrePrepare(pp) => {
+                self.handle_pre_prepare(pp, network).await;
+            }
+            PBFTMessage::Prepare(p) => {
+                self.handle_prepare(p, network).await;
+            }
+            PBFTMessage::Commit(c) => {
+                self.handle_commit(c, network).await;
+            }
+            _ => {}
+        }
+    }
+
+    pub async fn run_replica<T: Transport + Sync>(mut network: T, mut replica: Replica) {
+        info!(primary = replica.is_primary(), "Replica started");
+
+        let mut ticker = tokio::time::interval(TICK_INTERVAL);
+
+        loop {
+            tokio::select! {
+                msg = network.recv() => match msg {
+                    Some(msg) => replica.handle_message(msg, &network).await,
+                    None => break,
+                },
+                _ = ticker.tick() => replica.tick(&network).await,
             }
         }
     }
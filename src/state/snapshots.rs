@@ -0,0 +1,70 @@
+use serde::{Deserialize, Serialize};
+use std::{fs, io, path::PathBuf};
+
+/// The application state right after executing `seq_num`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub seq_num: u64,
+    pub state_digest: [u8; 32],
+    /// The store's entries in key order.
+    pub entries: Vec<(String, String)>,
+}
+
+/// Where a replica keeps its latest snapshot. Older ones are replaced.
+pub trait SnapshotStore: Send {
+    fn save(&mut self, snapshot: &Snapshot) -> io::Result<()>;
+
+    fn latest(&self) -> Option<Snapshot>;
+}
+
+#[derive(Default)]
+pub struct MemorySnapshotStore {
+    latest: Option<Snapshot>,
+}
+
+impl MemorySnapshotStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl SnapshotStore for MemorySnapshotStore {
+    fn save(&mut self, snapshot: &Snapshot) -> io::Result<()> {
+        self.latest = Some(snapshot.clone());
+        Ok(())
+    }
+
+    fn latest(&self) -> Option<Snapshot> {
+        self.latest.clone()
+    }
+}
+
+/// Snapshot kept in a single postcard file. A new one is written next to it
+/// and renamed over it, so a crash leaves either the old or the new one.
+pub struct FileSnapshotStore {
+    path: PathBuf,
+}
+
+impl FileSnapshotStore {
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        Ok(FileSnapshotStore { path })
+    }
+}
+
+impl SnapshotStore for FileSnapshotStore {
+    fn save(&mut self, snapshot: &Snapshot) -> io::Result<()> {
+        let bytes = postcard::to_allocvec(snapshot).map_err(io::Error::other)?;
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, bytes)?;
+        fs::rename(&tmp, &self.path)
+    }
+
+    fn latest(&self) -> Option<Snapshot> {
+        let bytes = fs::read(&self.path).ok()?;
+        postcard::from_bytes(&bytes).ok()
+    }
+}
@@ -0,0 +1,4 @@
+pub mod diagram;
+pub mod record;
+pub mod replay;
+pub mod timeline;
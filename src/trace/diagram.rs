@@ -0,0 +1,637 @@
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt::Write,
+};
+
+use crate::{
+    message::message_types::{MessageKind, PBFTMessage},
+    trace::record::{TraceEvent, TraceRecord},
+};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Participant {
+    pub id: u32,
+    pub is_client: bool,
+}
+
+impl Participant {
+    /// Short identifier used in the diagram sources.
+    pub fn alias(&self) -> String {
+        if self.is_client {
+            format!("C{}", self.id)
+        } else {
+            format!("R{}", self.id)
+        }
+    }
+
+    pub fn name(&self) -> String {
+        if self.is_client {
+            format!("Client {}", self.id)
+        } else {
+            format!("Replica {}", self.id)
+        }
+    }
+}
+
+/// One message from one participant to another. Times are microseconds
+/// since the Unix epoch, as recorded by the traces.
+#[derive(Clone, Debug)]
+pub struct Arrow {
+    pub from: u32,
+    pub to: u32,
+    pub kind: MessageKind,
+    pub label: String,
+    /// `None` if the sender was not traced.
+    pub sent_at: Option<u64>,
+    /// `None` if the receiver was not traced or never got the message.
+    pub received_at: Option<u64>,
+    /// Sent to a traced replica whose trace does not show it arriving.
+    pub lost: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QuorumKind {
+    /// The replica saw the pre-prepare and enough matching prepares, and sent
+    /// its commit.
+    Prepared,
+    /// The replica saw a quorum of matching commits.
+    Committed,
+    /// The new primary gathered a quorum of view changes and sent the new
+    /// view.
+    NewView,
+}
+
+/// A point where a replica had heard from enough others to move on.
+#[derive(Clone, Debug)]
+pub struct Quorum {
+    pub replica: u32,
+    pub kind: QuorumKind,
+    pub view: u64,
+    pub seq_num: Option<u64>,
+    pub at: u64,
+    /// Replicas whose messages made up the quorum.
+    pub signers: Vec<u32>,
+}
+
+impl Quorum {
+    pub fn label(&self) -> String {
+        let signers: Vec<String> = self.signers.iter().map(|s| format!("R{}", s)).collect();
+        let signers = signers.join(", ");
+        match (self.kind, self.seq_num) {
+            (QuorumKind::Prepared, Some(seq_num)) => format!(
+                "prepared n={} in v={} with the pre-prepare and prepares from {}",
+                seq_num, self.view, signers
+            ),
+            (QuorumKind::Committed, Some(seq_num)) => format!(
+                "committed n={} in v={} with commits from {}",
+                seq_num, self.view, signers
+            ),
+            _ => format!(
+                "entering v={} with view changes from {}",
+                self.view, signers
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Step {
+    Message(Arrow),
+    Quorum(Quorum),
+}
+
+impl Step {
+    pub fn at(&self) -> u64 {
+        match self {
+            Step::Message(arrow) => arrow.sent_at.or(arrow.received_at).unwrap_or(0),
+            Step::Quorum(quorum) => quorum.at,
+        }
+    }
+}
+
+/// What a group of steps is about.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Topic {
+    /// Agreeing on a sequence number in a view. A slot carried into a new
+    /// view is agreed on again there.
+    Slot { view: u64, seq_num: u64 },
+    /// Moving to a view.
+    View(u64),
+    /// Requests no traced replica assigned a sequence number to.
+    Unordered,
+}
+
+impl Topic {
+    pub fn title(&self) -> String {
+        match self {
+            Topic::Slot { view, seq_num } => {
+                format!("Sequence number {} in view {}", seq_num, view)
+            }
+            Topic::View(view) => format!("View change to view {}", view),
+            Topic::Unordered => "Requests not ordered".to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Section {
+    pub topic: Topic,
+    /// In the order they happened.
+    pub steps: Vec<Step>,
+}
+
+/// The message flow of a run, merged from the traces of its replicas.
+#[derive(Clone, Debug, Default)]
+pub struct Diagram {
+    /// Replicas first, then clients, each by id.
+    pub participants: Vec<Participant>,
+    /// Ordered by their first step.
+    pub sections: Vec<Section>,
+}
+
+struct Sent {
+    from: u32,
+    to: Option<u32>,
+    at: u64,
+    message: PBFTMessage,
+    delivered: BTreeSet<u32>,
+}
+
+struct Received {
+    node_id: u32,
+    at: u64,
+    hash: [u8; 32],
+    message: PBFTMessage,
+}
+
+fn message_hash(message: &PBFTMessage) -> [u8; 32] {
+    Sha256::digest(postcard::to_allocvec(message).unwrap()).into()
+}
+
+fn is_protocol_message(message: &PBFTMessage) -> bool {
+    !matches!(
+        message,
+        PBFTMessage::AdminRequest(_) | PBFTMessage::AdminResponse(_)
+    )
+}
+
+impl Diagram {
+    /// Merges `traces`, one per replica, into a diagram. `quorum` is the
+    /// cluster's quorum size, used to tell when a replica committed. Admin
+    /// messages are left out.
+    pub fn build(traces: &[Vec<TraceRecord>], quorum: usize) -> Self {
+        let mut traced = BTreeSet::new();
+        let mut sent: Vec<Sent> = Vec::new();
+        let mut sent_by_hash: HashMap<[u8; 32], Vec<usize>> = HashMap::new();
+        let mut received: Vec<Received> = Vec::new();
+        let mut quorums = Vec::new();
+
+        for trace in traces {
+            let Some(TraceEvent::Start { node_id }) = trace.first().map(|r| &r.event) else {
+                continue;
+            };
+            traced.insert(*node_id);
+            quorums.extend(find_quorums(*node_id, trace, quorum));
+
+            for record in trace {
+                match &record.event {
+                    TraceEvent::Received(message) if is_protocol_message(message) => {
+                        received.push(Received {
+                            node_id: *node_id,
+                            at: record.unix_micros,
+                            hash: message_hash(message),
+                            message: message.clone(),
+                        });
+                    }
+                    TraceEvent::Sent { to, message } if is_protocol_message(message) => {
+                        sent_by_hash
+                            .entry(message_hash(message))
+                            .or_default()
+                            .push(sent.len());
+                        sent.push(Sent {
+                            from: *node_id,
+                            to: *to,
+                            at: record.unix_micros,
+                            message: message.clone(),
+                            delivered: BTreeSet::new(),
+                        });
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let request_slots = request_slots(&received, &sent);
+        let mut arrows = Vec::new();
+        for r in &received {
+            // The earliest matching send not yet paired with this receiver.
+            let send = sent_by_hash.get(&r.hash).and_then(|indices| {
+                indices
+                    .iter()
+                    .copied()
+                    .filter(|&i| {
+                        let s = &sent[i];
+                        s.from != r.node_id
+                            && s.to.is_none_or(|to| to == r.node_id)
+                            && !s.delivered.contains(&r.node_id)
+                    })
+                    .min_by_key(|&i| sent[i].at)
+            });
+            let (from, sent_at) = match send {
+                Some(i) => {
+                    sent[i].delivered.insert(r.node_id);
+                    (sent[i].from, Some(sent[i].at))
+                }
+                None => (r.message.signer_id(), None),
+            };
+            let arrow = Arrow {
+                from,
+                to: r.node_id,
+                kind: r.message.kind(),
+                label: label(&r.message),
+                sent_at,
+                received_at: Some(r.at),
+                lost: false,
+            };
+            arrows.push((topic(&r.message, &request_slots), arrow));
+        }
+        for s in &sent {
+            let recipients: Vec<u32> = match s.to {
+                Some(to) => vec![to],
+                None => traced.iter().copied().filter(|&id| id != s.from).collect(),
+            };
+            for to in recipients {
+                if s.delivered.contains(&to) {
+                    continue;
+                }
+                let arrow = Arrow {
+                    from: s.from,
+                    to,
+                    kind: s.message.kind(),
+                    label: label(&s.message),
+                    sent_at: Some(s.at),
+                    received_at: None,
+                    lost: traced.contains(&to),
+                };
+                arrows.push((topic(&s.message, &request_slots), arrow));
+            }
+        }
+
+        let participants = participants(&traced, arrows.iter().map(|(_, arrow)| arrow));
+        let mut sections: BTreeMap<Topic, Vec<Step>> = BTreeMap::new();
+        for (topic, arrow) in arrows {
+            sections
+                .entry(topic)
+                .or_default()
+                .push(Step::Message(arrow));
+        }
+        for quorum in quorums {
+            let topic = match quorum.seq_num {
+                Some(seq_num) => Topic::Slot {
+                    view: quorum.view,
+                    seq_num,
+                },
+                None => Topic::View(quorum.view),
+            };
+            sections
+                .entry(topic)
+                .or_default()
+                .push(Step::Quorum(quorum));
+        }
+
+        let mut sections: Vec<Section> = sections
+            .into_iter()
+            .map(|(topic, mut steps)| {
+                steps.sort_by_key(Step::at);
+                Section { topic, steps }
+            })
+            .collect();
+        sections.sort_by_key(|s| s.steps.first().map_or(0, Step::at));
+
+        Diagram {
+            participants,
+            sections,
+        }
+    }
+
+    /// Earliest time anything happened, to show the others relative to it.
+    pub fn start(&self) -> u64 {
+        self.sections
+            .iter()
+            .flat_map(|s| &s.steps)
+            .flat_map(|step| match step {
+                Step::Message(arrow) => [arrow.sent_at, arrow.received_at],
+                Step::Quorum(quorum) => [Some(quorum.at), None],
+            })
+            .flatten()
+            .min()
+            .unwrap_or(0)
+    }
+
+    pub fn participant(&self, id: u32) -> Option<&Participant> {
+        self.participants.iter().find(|p| p.id == id)
+    }
+
+    fn alias(&self, id: u32) -> String {
+        self.participant(id)
+            .map_or_else(|| format!("R{}", id), Participant::alias)
+    }
+
+    pub fn to_mermaid(&self) -> String {
+        let mut out = String::from("sequenceDiagram\n");
+        for p in &self.participants {
+            let _ = writeln!(out, "    participant {} as {}", p.alias(), p.name());
+        }
+        let span = match (self.participants.first(), self.participants.last()) {
+            (Some(first), Some(last)) if first != last => {
+                format!("{},{}", first.alias(), last.alias())
+            }
+            (Some(only), _) => only.alias(),
+            _ => return out,
+        };
+
+        for section in &self.sections {
+            let _ = writeln!(out, "    Note over {}: {}", span, section.topic.title());
+            for step in &section.steps {
+                match step {
+                    Step::Message(arrow) => {
+                        let _ = writeln!(
+                            out,
+                            "    {}{}{}: {}{}",
+                            self.alias(arrow.from),
+                            if arrow.lost { "-x" } else { "->>" },
+                            self.alias(arrow.to),
+                            arrow.label,
+                            if arrow.lost { " (lost)" } else { "" }
+                        );
+                    }
+                    Step::Quorum(quorum) => {
+                        let (r, g, b) = quorum_rgb(quorum.kind);
+                        let _ = writeln!(out, "    rect rgb({}, {}, {})", r, g, b);
+                        let _ = writeln!(
+                            out,
+                            "    Note over {}: {}",
+                            self.alias(quorum.replica),
+                            quorum.label()
+                        );
+                        let _ = writeln!(out, "    end");
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    pub fn to_plantuml(&self) -> String {
+        let mut out = String::from("@startuml\n");
+        for p in &self.participants {
+            let _ = writeln!(out, "participant \"{}\" as {}", p.name(), p.alias());
+        }
+        for section in &self.sections {
+            let _ = writeln!(out, "== {} ==", section.topic.title());
+            for step in &section.steps {
+                match step {
+                    Step::Message(arrow) => {
+                        let _ = writeln!(
+                            out,
+                            "{} -[{}]>{} {} : {}{}",
+                            self.alias(arrow.from),
+                            kind_color(arrow.kind),
+                            if arrow.lost { "x" } else { "" },
+                            self.alias(arrow.to),
+                            arrow.label,
+                            if arrow.lost { " (lost)" } else { "" }
+                        );
+                    }
+                    Step::Quorum(quorum) => {
+                        let _ = writeln!(
+                            out,
+                            "hnote over {} #{} : {}",
+                            self.alias(quorum.replica),
+                            quorum_hex(quorum.kind),
+                            quorum.label()
+                        );
+                    }
+                }
+            }
+        }
+        out.push_str("@enduml\n");
+        out
+    }
+}
+
+/// Short description of a message, safe to embed in Mermaid and PlantUML.
+pub fn label(message: &PBFTMessage) -> String {
+    match message {
+        PBFTMessage::Request(m) => format!(
+            "Request(c={}, t={})",
+            m.message.client_id, m.message.timestamp
+        ),
+        PBFTMessage::PrePrepare(m) => {
+            format!("PrePrepare(v={}, n={})", m.message.view, m.message.seq_num)
+        }
+        PBFTMessage::Prepare(m) => {
+            format!("Prepare(v={}, n={})", m.message.view, m.message.seq_num)
+        }
+        PBFTMessage::Commit(m) => {
+            format!("Commit(v={}, n={})", m.message.view, m.message.seq_num)
+        }
+        PBFTMessage::Reply(m) => {
+            format!("Reply(n={}, t={})", m.message.seq_num, m.message.timestamp)
+        }
+        PBFTMessage::ViewChange(m) => format!("ViewChange(v={})", m.message.new_view),
+        PBFTMessage::NewView(m) => format!("NewView(v={})", m.message.new_view),
+        PBFTMessage::Evidence(m) => format!(
+            "Evidence(culprit={}, n={})",
+            m.message.culprit(),
+            m.message.seq_num()
+        ),
+        PBFTMessage::AdminRequest(_) => "AdminRequest".to_string(),
+        PBFTMessage::AdminResponse(_) => "AdminResponse".to_string(),
+    }
+}
+
+/// Color for arrows of `kind`, as a hex RGB string with a leading `#`.
+pub fn kind_color(kind: MessageKind) -> &'static str {
+    match kind {
+        MessageKind::Request => "#6b7280",
+        MessageKind::PrePrepare => "#7c3aed",
+        MessageKind::Prepare => "#2563eb",
+        MessageKind::Commit => "#059669",
+        MessageKind::Reply => "#d97706",
+        MessageKind::ViewChange | MessageKind::NewView => "#dc2626",
+        _ => "#111827",
+    }
+}
+
+/// Background of quorum notes, as a hex RGB string without the `#`.
+pub fn quorum_hex(kind: QuorumKind) -> &'static str {
+    match kind {
+        QuorumKind::Prepared => "dbeafe",
+        QuorumKind::Committed => "d1fae5",
+        QuorumKind::NewView => "fee2e2",
+    }
+}
+
+/// The `quorum_hex` colors as RGB channels, for Mermaid's `rect rgb(...)`.
+const fn quorum_rgb(kind: QuorumKind) -> (u8, u8, u8) {
+    match kind {
+        QuorumKind::Prepared => (0xdb, 0xea, 0xfe),
+        QuorumKind::Committed => (0xd1, 0xfa, 0xe5),
+        QuorumKind::NewView => (0xfe, 0xe2, 0xe2),
+    }
+}
+
+fn topic(message: &PBFTMessage, request_slots: &HashMap<(u64, u64), Topic>) -> Topic {
+    let slot = |view, seq_num| Topic::Slot { view, seq_num };
+    match message {
+        PBFTMessage::Request(m) => request_slots
+            .get(&m.message.id())
+            .copied()
+            .unwrap_or(Topic::Unordered),
+        PBFTMessage::PrePrepare(m) => slot(m.message.view, m.message.seq_num),
+        PBFTMessage::Prepare(m) => slot(m.message.view, m.message.seq_num),
+        PBFTMessage::Commit(m) => slot(m.message.view, m.message.seq_num),
+        PBFTMessage::Reply(m) => slot(m.message.view, m.message.seq_num),
+        PBFTMessage::ViewChange(m) => Topic::View(m.message.new_view),
+        PBFTMessage::NewView(m) => Topic::View(m.message.new_view),
+        PBFTMessage::Evidence(m) => slot(m.message.view(), m.message.seq_num()),
+        PBFTMessage::AdminRequest(_) | PBFTMessage::AdminResponse(_) => Topic::Unordered,
+    }
+}
+
+/// Slot of each request, by `Request::id`, from the first pre-prepare
+/// carrying it.
+fn request_slots(received: &[Received], sent: &[Sent]) -> HashMap<(u64, u64), Topic> {
+    let mut slots = HashMap::new();
+    let messages = received
+        .iter()
+        .map(|r| &r.message)
+        .chain(sent.iter().map(|s| &s.message));
+    for message in messages {
+        if let PBFTMessage::PrePrepare(m) = message {
+            slots
+                .entry(m.message.request.message.id())
+                .or_insert(Topic::Slot {
+                    view: m.message.view,
+                    seq_num: m.message.seq_num,
+                });
+        }
+    }
+    slots
+}
+
+fn participants<'a>(
+    traced: &BTreeSet<u32>,
+    arrows: impl Iterator<Item = &'a Arrow>,
+) -> Vec<Participant> {
+    let mut replicas = traced.clone();
+    let mut clients = BTreeSet::new();
+    for arrow in arrows {
+        match arrow.kind {
+            MessageKind::Request if !traced.contains(&arrow.from) => {
+                clients.insert(arrow.from);
+            }
+            MessageKind::Reply if !traced.contains(&arrow.to) => {
+                clients.insert(arrow.to);
+            }
+            _ => {
+                replicas.insert(arrow.from);
+                if !traced.contains(&arrow.to) && arrow.kind != MessageKind::Reply {
+                    replicas.insert(arrow.to);
+                }
+            }
+        }
+    }
+    replicas.retain(|id| !clients.contains(id));
+
+    replicas
+        .into_iter()
+        .map(|id| Participant {
+            id,
+            is_client: false,
+        })
+        .chain(clients.into_iter().map(|id| Participant {
+            id,
+            is_client: true,
+        }))
+        .collect()
+}
+
+/// Quorums `node_id` reached, read off its own trace: it prepared when it
+/// sent its commit, committed once it also had a quorum of matching commits,
+/// and gathered a view-change quorum when it sent a new view.
+fn find_quorums(node_id: u32, trace: &[TraceRecord], quorum: usize) -> Vec<Quorum> {
+    type Slot = (u64, u64, [u8; 32]);
+    let mut prepares: HashMap<Slot, BTreeSet<u32>> = HashMap::new();
+    let mut commits: HashMap<Slot, BTreeSet<u32>> = HashMap::new();
+    let mut committed: BTreeSet<Slot> = BTreeSet::new();
+    let mut found = Vec::new();
+
+    for record in trace {
+        let (message, own) = match &record.event {
+            TraceEvent::Received(message) => (message, false),
+            TraceEvent::Sent { message, .. } => (message, true),
+            _ => continue,
+        };
+        let slot = match message {
+            PBFTMessage::Prepare(m) => {
+                let slot = (m.message.view, m.message.seq_num, m.message.digest);
+                prepares.entry(slot).or_default().insert(m.signer_id);
+                continue;
+            }
+            PBFTMessage::Commit(m) => {
+                let slot = (m.message.view, m.message.seq_num, m.message.digest);
+                let signers = commits.entry(slot).or_default();
+                // A broadcast is recorded once, so the own commit is new.
+                if own && signers.insert(node_id) {
+                    found.push(Quorum {
+                        replica: node_id,
+                        kind: QuorumKind::Prepared,
+                        view: slot.0,
+                        seq_num: Some(slot.1),
+                        at: record.unix_micros,
+                        signers: prepares
+                            .get(&slot)
+                            .map(|s| s.iter().copied().collect())
+                            .unwrap_or_default(),
+                    });
+                } else {
+                    signers.insert(m.signer_id);
+                }
+                slot
+            }
+            PBFTMessage::NewView(m) if own => {
+                found.push(Quorum {
+                    replica: node_id,
+                    kind: QuorumKind::NewView,
+                    view: m.message.new_view,
+                    seq_num: None,
+                    at: record.unix_micros,
+                    signers: m
+                        .message
+                        .view_change_msgs
+                        .iter()
+                        .map(|v| v.signer_id)
+                        .collect(),
+                });
+                continue;
+            }
+            _ => continue,
+        };
+
+        let signers = &commits[&slot];
+        if signers.contains(&node_id) && signers.len() >= quorum && committed.insert(slot) {
+            found.push(Quorum {
+                replica: node_id,
+                kind: QuorumKind::Committed,
+                view: slot.0,
+                seq_num: Some(slot.1),
+                at: record.unix_micros,
+                signers: signers.iter().copied().collect(),
+            });
+        }
+    }
+    found
+}
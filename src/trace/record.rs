@@ -0,0 +1,130 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    fs, io,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tracing::error;
+
+use crate::{
+    config::membership::Membership,
+    message::message_types::PBFTMessage,
+    network::transport::Transport,
+    state::{clock::Clock, records::RecordFile},
+};
+
+/// One thing that happened to a replica, in the order it happened.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TraceRecord {
+    /// The replica's clock, which replay feeds back to it.
+    pub at: Duration,
+    /// Wall-clock time, to line up the traces of different replicas.
+    pub unix_micros: u64,
+    pub event: TraceEvent,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum TraceEvent {
+    /// First record of every trace.
+    Start { node_id: u32 },
+    /// A message handed to the replica, whether or not it was valid.
+    Received(PBFTMessage),
+    /// The view-change timer expired.
+    Timeout,
+    /// A message the replica sent; `to` is `None` for a broadcast.
+    Sent {
+        to: Option<u32>,
+        message: PBFTMessage,
+    },
+}
+
+/// Appends `TraceRecord`s to a file. Clones write to the same file.
+#[derive(Clone)]
+pub struct TraceRecorder {
+    file: Arc<Mutex<RecordFile>>,
+}
+
+impl TraceRecorder {
+    /// Starts a new trace at `path` for `node_id`, replacing any earlier one.
+    pub fn create(path: &Path, node_id: u32, clock: &dyn Clock) -> io::Result<Self> {
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        let (file, _) = RecordFile::open::<TraceRecord>(path)?;
+        let recorder = TraceRecorder {
+            file: Arc::new(Mutex::new(file)),
+        };
+        recorder.record(clock.now(), TraceEvent::Start { node_id });
+        Ok(recorder)
+    }
+
+    pub fn record(&self, at: Duration, event: TraceEvent) {
+        let unix_micros = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+        let record = TraceRecord {
+            at,
+            unix_micros,
+            event,
+        };
+        if let Err(e) = self.file.lock().unwrap().append(&record) {
+            error!(error = %e, "Failed to write trace record");
+        }
+    }
+}
+
+/// Every complete record of the trace at `path`.
+pub fn read_trace(path: &Path) -> io::Result<Vec<TraceRecord>> {
+    RecordFile::read_all(path)
+}
+
+/// Records what a replica's handlers send before passing it on. Only lives
+/// for one handler call, so it never receives.
+pub(crate) struct RecordingTransport<'a, T> {
+    inner: &'a T,
+    recorder: TraceRecorder,
+    clock: Arc<dyn Clock>,
+}
+
+impl<'a, T> RecordingTransport<'a, T> {
+    pub(crate) fn new(inner: &'a T, recorder: TraceRecorder, clock: Arc<dyn Clock>) -> Self {
+        RecordingTransport {
+            inner,
+            recorder,
+            clock,
+        }
+    }
+
+    fn record_sent(&self, to: Option<u32>, message: &PBFTMessage) {
+        self.recorder.record(
+            self.clock.now(),
+            TraceEvent::Sent {
+                to,
+                message: message.clone(),
+            },
+        );
+    }
+}
+
+impl<T: Transport + Sync> Transport for RecordingTransport<'_, T> {
+    async fn broadcast(&self, message: &PBFTMessage) {
+        self.record_sent(None, message);
+        self.inner.broadcast(message).await
+    }
+
+    async fn send_to(&self, peer_id: u32, message: &PBFTMessage) {
+        self.record_sent(Some(peer_id), message);
+        self.inner.send_to(peer_id, message).await
+    }
+
+    async fn recv(&mut self) -> Option<PBFTMessage> {
+        None
+    }
+
+    async fn apply_membership(&self, membership: &Membership) {
+        self.inner.apply_membership(membership).await
+    }
+}
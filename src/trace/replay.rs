@@ -0,0 +1,105 @@
+use std::sync::{Arc, Mutex};
+
+use crate::{
+    message::message_types::PBFTMessage,
+    network::transport::Transport,
+    state::{clock::VirtualClock, replica::Replica},
+    trace::record::{TraceEvent, TraceRecord},
+};
+
+/// Messages sent in response to one event, with their destination; `None`
+/// for a broadcast.
+pub type SentMessages = Vec<(Option<u32>, PBFTMessage)>;
+
+/// An event after which the replayed replica sent something other than what
+/// the trace recorded.
+#[derive(Debug)]
+pub struct Divergence {
+    /// Index of the `Received` or `Timeout` record in the trace.
+    pub index: usize,
+    pub recorded: SentMessages,
+    pub replayed: SentMessages,
+}
+
+#[derive(Debug, Default)]
+pub struct ReplayReport {
+    /// `Received` and `Timeout` records fed to the replica.
+    pub events: usize,
+    pub divergences: Vec<Divergence>,
+}
+
+/// Collects what the replica sends instead of sending it.
+#[derive(Default)]
+struct ReplayTransport {
+    sent: Mutex<SentMessages>,
+}
+
+impl ReplayTransport {
+    fn take(&self) -> SentMessages {
+        std::mem::take(&mut self.sent.lock().unwrap())
+    }
+}
+
+impl Transport for ReplayTransport {
+    async fn broadcast(&self, message: &PBFTMessage) {
+        self.sent.lock().unwrap().push((None, message.clone()));
+    }
+
+    async fn send_to(&self, peer_id: u32, message: &PBFTMessage) {
+        self.sent
+            .lock()
+            .unwrap()
+            .push((Some(peer_id), message.clone()));
+    }
+
+    async fn recv(&mut self) -> Option<PBFTMessage> {
+        None
+    }
+}
+
+/// Feeds `records` to `replica`, which should be freshly created with the
+/// recorded replica's id, key and initial membership. Its clock is replaced
+/// by one that shows each record's time while the record is handled.
+pub async fn replay(replica: &mut Replica, records: &[TraceRecord]) -> ReplayReport {
+    let clock = VirtualClock::new();
+    replica.set_clock(Arc::new(clock.clone()));
+    let transport = ReplayTransport::default();
+    let mut report = ReplayReport::default();
+
+    for (index, record) in records.iter().enumerate() {
+        clock.set(record.at);
+        match &record.event {
+            TraceEvent::Received(message) => {
+                replica.handle_message(message.clone(), &transport).await;
+            }
+            TraceEvent::Timeout => replica.tick(&transport).await,
+            TraceEvent::Start { .. } | TraceEvent::Sent { .. } => continue,
+        }
+        report.events += 1;
+
+        let recorded: SentMessages = records[index + 1..]
+            .iter()
+            .map_while(|r| match &r.event {
+                TraceEvent::Sent { to, message } => Some((*to, message.clone())),
+                _ => None,
+            })
+            .collect();
+        let replayed = transport.take();
+        if !same_messages(&recorded, &replayed) {
+            report.divergences.push(Divergence {
+                index,
+                recorded,
+                replayed,
+            });
+        }
+    }
+    report
+}
+
+/// Compares by encoding, as signatures are deterministic.
+fn same_messages(a: &SentMessages, b: &SentMessages) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .zip(b)
+            .all(|(x, y)| postcard::to_allocvec(x).ok() == postcard::to_allocvec(y).ok())
+}
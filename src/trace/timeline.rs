@@ -0,0 +1,202 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+};
+
+use crate::{
+    message::message_types::MessageKind,
+    trace::diagram::{Diagram, QuorumKind, Step, kind_color, quorum_hex},
+};
+
+const MARGIN: u64 = 40;
+const LANE_WIDTH: u64 = 180;
+const HEADER: u64 = 60;
+const ROW: u64 = 24;
+
+/// Renders `diagram` as a self-contained HTML page with an SVG timeline: one
+/// lane per participant, time flowing down. Every distinct timestamp gets its
+/// own row, so idle gaps do not stretch the page; hovering over an arrow or
+/// quorum shows its actual time.
+pub fn to_html(diagram: &Diagram) -> String {
+    let start = diagram.start();
+    let rows: BTreeMap<u64, u64> = diagram
+        .sections
+        .iter()
+        .flat_map(|s| &s.steps)
+        .flat_map(|step| match step {
+            Step::Message(arrow) => [arrow.sent_at, arrow.received_at],
+            Step::Quorum(quorum) => [Some(quorum.at), None],
+        })
+        .flatten()
+        .collect::<BTreeSet<u64>>()
+        .into_iter()
+        .enumerate()
+        .map(|(row, at)| (at, row as u64))
+        .collect();
+    let y = |at: u64| HEADER + ROW * (rows[&at] + 1);
+    let lanes: BTreeMap<u32, u64> = diagram
+        .participants
+        .iter()
+        .enumerate()
+        .map(|(i, p)| (p.id, MARGIN + LANE_WIDTH / 2 + LANE_WIDTH * i as u64))
+        .collect();
+    let x = |id: u32| lanes.get(&id).copied().unwrap_or(MARGIN);
+
+    let width = 2 * MARGIN + LANE_WIDTH * diagram.participants.len().max(1) as u64;
+    let height = HEADER + ROW * (rows.len() as u64 + 2);
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}">"#,
+        w = width,
+        h = height
+    );
+    svg.push_str(
+        r#"<defs><marker id="arrow" viewBox="0 0 10 10" refX="10" refY="5" markerWidth="7" markerHeight="7" orient="auto-start-reverse"><path d="M 0 0 L 10 5 L 0 10 z" fill="context-stroke"/></marker></defs>"#,
+    );
+    svg.push('\n');
+
+    for p in &diagram.participants {
+        let lane = x(p.id);
+        let _ = writeln!(
+            svg,
+            r#"<line class="lane" x1="{x}" y1="{top}" x2="{x}" y2="{bottom}"/><text class="name" x="{x}" y="{name_y}">{name}</text>"#,
+            x = lane,
+            top = HEADER,
+            bottom = height - ROW,
+            name_y = HEADER - 20,
+            name = escape(&p.name())
+        );
+    }
+
+    for section in &diagram.sections {
+        for step in &section.steps {
+            match step {
+                Step::Message(arrow) => {
+                    let sent = arrow.sent_at.or(arrow.received_at).unwrap_or(start);
+                    let (x1, y1) = (x(arrow.from), y(sent));
+                    let (mut x2, y2) = (x(arrow.to), arrow.received_at.map_or(y1, y));
+                    if arrow.lost {
+                        x2 = (x1 + x2) / 2;
+                    }
+                    let _ = writeln!(
+                        svg,
+                        r#"<g class="message{lost}"><title>{title}</title><line x1="{x1}" y1="{y1}" x2="{x2}" y2="{y2}" stroke="{color}" marker-end="url(#arrow)"/><text x="{tx}" y="{ty}" fill="{color}">{label}</text></g>"#,
+                        lost = if arrow.lost { " lost" } else { "" },
+                        title = escape(&format!(
+                            "{} from {} to {}{}, sent {}, received {}",
+                            arrow.label,
+                            arrow.from,
+                            arrow.to,
+                            if arrow.lost { " (lost)" } else { "" },
+                            relative(arrow.sent_at, start),
+                            relative(arrow.received_at, start)
+                        )),
+                        color = kind_color(arrow.kind),
+                        tx = (x1 + x2) / 2,
+                        ty = (y1 + y2) / 2 - 3,
+                        label = escape(&arrow.label)
+                    );
+                }
+                Step::Quorum(quorum) => {
+                    let short = match quorum.kind {
+                        QuorumKind::Prepared => "prepared",
+                        QuorumKind::Committed => "committed",
+                        QuorumKind::NewView => "new view",
+                    };
+                    let _ = writeln!(
+                        svg,
+                        r##"<g class="quorum"><title>{title}</title><rect x="{rx}" y="{ry}" width="{rw}" height="{rh}" rx="4" fill="#{fill}"/><text x="{x}" y="{ty}">{short}</text></g>"##,
+                        title = escape(&format!(
+                            "Replica {} {}, at {}",
+                            quorum.replica,
+                            quorum.label(),
+                            relative(Some(quorum.at), start)
+                        )),
+                        rx = x(quorum.replica) - 36,
+                        ry = y(quorum.at) - ROW / 2 + 3,
+                        rw = 72,
+                        rh = ROW - 6,
+                        fill = quorum_hex(quorum.kind),
+                        x = x(quorum.replica),
+                        ty = y(quorum.at) + 4,
+                        short = short
+                    );
+                }
+            }
+        }
+    }
+    svg.push_str("</svg>\n");
+
+    let mut legend = String::new();
+    for kind in [
+        MessageKind::Request,
+        MessageKind::PrePrepare,
+        MessageKind::Prepare,
+        MessageKind::Commit,
+        MessageKind::Reply,
+        MessageKind::ViewChange,
+    ] {
+        let _ = write!(
+            legend,
+            r#"<span style="color: {}">&#9632; {}</span> "#,
+            kind_color(kind),
+            kind.name()
+        );
+    }
+    for (kind, name) in [
+        (QuorumKind::Prepared, "prepared"),
+        (QuorumKind::Committed, "committed"),
+        (QuorumKind::NewView, "new view"),
+    ] {
+        let _ = write!(
+            legend,
+            r##"<span class="tag" style="background: #{}">{}</span> "##,
+            quorum_hex(kind),
+            name
+        );
+    }
+
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>PBFT message flow</title>
+<style>
+body {{ font-family: sans-serif; margin: 20px; }}
+.legend span {{ margin-right: 12px; }}
+.tag {{ padding: 1px 6px; border-radius: 4px; }}
+.lane {{ stroke: #d1d5db; stroke-width: 2; }}
+.name {{ font-weight: bold; text-anchor: middle; }}
+.message line {{ stroke-width: 1.5; }}
+.message.lost line {{ stroke-dasharray: 4 3; }}
+.message text {{ font-size: 10px; text-anchor: middle; }}
+.message:hover line {{ stroke-width: 3; }}
+.quorum text {{ font-size: 11px; text-anchor: middle; }}
+</style>
+</head>
+<body>
+<h1>PBFT message flow</h1>
+<p class="legend">{legend}</p>
+{svg}</body>
+</html>
+"#,
+        legend = legend,
+        svg = svg
+    )
+}
+
+fn relative(at: Option<u64>, start: u64) -> String {
+    match at {
+        Some(at) => format!("+{:.3} ms", at.saturating_sub(start) as f64 / 1000.0),
+        None => "unknown".to_string(),
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,234 @@
+mod common;
+
+use common::{CLIENT_ID, crypto, generate_keys, membership, replicas, run_until_quiet};
+use simple_pbft_demo::{
+    crypto::primitives::Crypto,
+    message::message_types::{AdminQuery, AdminRequest, AdminResult, PBFTMessage, Request},
+    network::transport::{MemoryNetwork, MemoryTransport},
+    state::{
+        replica::Replica,
+        snapshots::{FileSnapshotStore, Snapshot, SnapshotStore},
+    },
+};
+
+/// A registered client that is not an admin.
+const OUTSIDER_ID: u32 = 101;
+
+struct Cluster {
+    network: MemoryNetwork,
+    replicas: Vec<(Replica, MemoryTransport)>,
+    client: Crypto,
+    client_inbox: MemoryTransport,
+    outsider: Crypto,
+    outsider_inbox: MemoryTransport,
+    nonce: u64,
+}
+
+impl Cluster {
+    fn new(n: u32) -> Self {
+        let replica_keys = generate_keys(n);
+        let client = crypto(CLIENT_ID, &Crypto::generate_keypair());
+        let outsider = crypto(OUTSIDER_ID, &Crypto::generate_keypair());
+        let mut membership = membership(
+            &replica_keys,
+            &[(CLIENT_ID, &client), (OUTSIDER_ID, &outsider)],
+        );
+        membership.set_admins([CLIENT_ID]);
+
+        let network = MemoryNetwork::new();
+        let replicas = replicas(&network, &membership, &replica_keys);
+        let client_inbox = network.join(CLIENT_ID);
+        let outsider_inbox = network.join(OUTSIDER_ID);
+
+        Cluster {
+            network,
+            replicas,
+            client,
+            client_inbox,
+            outsider,
+            outsider_inbox,
+            nonce: 0,
+        }
+    }
+
+    async fn run_until_quiet(&mut self) {
+        run_until_quiet(&mut self.replicas).await;
+    }
+
+    async fn invoke(&mut self, operation: &str) {
+        self.invoke_at(0, operation).await;
+    }
+
+    async fn invoke_at(&mut self, primary: u32, operation: &str) {
+        self.nonce += 1;
+        let request = Request {
+            operation: operation.as_bytes().to_vec(),
+            timestamp: self.nonce,
+            client_id: CLIENT_ID as u64,
+        };
+        self.network.send(
+            primary,
+            PBFTMessage::Request(self.client.create_signed_message(request)),
+        );
+        self.run_until_quiet().await;
+    }
+
+    /// Sends `query` to `replica_id` as the admin client and returns its
+    /// answer.
+    async fn admin(&mut self, replica_id: u32, query: AdminQuery) -> AdminResult {
+        self.admin_as(CLIENT_ID, replica_id, query).await
+    }
+
+    async fn admin_as(
+        &mut self,
+        client_id: u32,
+        replica_id: u32,
+        query: AdminQuery,
+    ) -> AdminResult {
+        self.nonce += 1;
+        let request = AdminRequest {
+            client_id: client_id as u64,
+            nonce: self.nonce,
+            query,
+        };
+        let client = if client_id == CLIENT_ID {
+            &self.client
+        } else {
+            &self.outsider
+        };
+        self.network.send(
+            replica_id,
+            PBFTMessage::AdminRequest(client.create_signed_message(request)),
+        );
+        self.run_until_quiet().await;
+
+        let inbox = if client_id == CLIENT_ID {
+            &mut self.client_inbox
+        } else {
+            &mut self.outsider_inbox
+        };
+        while let Some(msg) = inbox.try_recv() {
+            if let PBFTMessage::AdminResponse(response) = msg
+                && response.message.nonce == self.nonce
+            {
+                assert_eq!(response.signer_id, replica_id);
+                return response.message.result;
+            }
+        }
+        panic!("replica {} did not answer", replica_id);
+    }
+}
+
+#[tokio::test]
+async fn status_reports_progress_and_snapshots() {
+    let mut cluster = Cluster::new(4);
+    cluster.invoke("PUT:a:1").await;
+    cluster.invoke("PUT:b:2").await;
+
+    let AdminResult::Status(status) = cluster.admin(0, AdminQuery::Status).await else {
+        panic!("expected a status");
+    };
+    assert_eq!(status.view, 0);
+    assert_eq!(status.primary, 0);
+    assert_eq!(status.next_seq_num, 3);
+    assert_eq!(status.last_executed, 2);
+    assert_eq!(status.last_snapshot, None);
+    assert!(!status.in_view_change);
+    assert_eq!(status.pending_view, None);
+    assert_eq!(status.message_log, 2);
+    assert_eq!(status.pending_requests, 0);
+
+    let AdminResult::Snapshot {
+        seq_num,
+        state_digest,
+    } = cluster.admin(2, AdminQuery::Snapshot).await
+    else {
+        panic!("expected a snapshot");
+    };
+    assert_eq!(seq_num, 2);
+    let entry = cluster.replicas[2].0.ledger_entry(2).unwrap();
+    assert_eq!(state_digest, entry.state_digest);
+
+    let AdminResult::Status(status) = cluster.admin(2, AdminQuery::Status).await else {
+        panic!("expected a status");
+    };
+    assert_eq!(status.last_snapshot, Some(2));
+}
+
+#[tokio::test]
+async fn forced_view_change_takes_a_quorum() {
+    let mut cluster = Cluster::new(4);
+    cluster.invoke("PUT:a:1").await;
+
+    let result = cluster.admin(1, AdminQuery::ForceViewChange).await;
+    assert!(matches!(result, AdminResult::ViewChange { new_view: 1 }));
+    let AdminResult::Status(status) = cluster.admin(1, AdminQuery::Status).await else {
+        panic!("expected a status");
+    };
+    assert!(status.in_view_change);
+    assert_eq!(status.pending_view, Some(1));
+    assert_eq!(cluster.replicas[0].0.view(), 0);
+
+    // f + 1 replicas asking is enough for the others to join in.
+    cluster.admin(2, AdminQuery::ForceViewChange).await;
+    for (replica, _) in &cluster.replicas {
+        assert_eq!(replica.view(), 1, "replica {}", replica.node_id());
+    }
+    let AdminResult::Status(status) = cluster.admin(0, AdminQuery::Status).await else {
+        panic!("expected a status");
+    };
+    assert_eq!(status.primary, 1);
+    assert!(!status.in_view_change);
+
+    cluster.invoke_at(1, "PUT:b:2").await;
+    for (replica, _) in &cluster.replicas {
+        assert_eq!(replica.last_executed(), 2, "replica {}", replica.node_id());
+    }
+}
+
+#[tokio::test]
+async fn only_admins_may_act_on_a_replica() {
+    let mut cluster = Cluster::new(4);
+    cluster.invoke("PUT:a:1").await;
+
+    for query in [AdminQuery::ForceViewChange, AdminQuery::Snapshot] {
+        let result = cluster.admin_as(OUTSIDER_ID, 1, query.clone()).await;
+        assert!(
+            matches!(&result, AdminResult::Failed(reason) if reason.contains("not an admin")),
+            "{:?} answered {:?}",
+            query,
+            result
+        );
+    }
+    let AdminResult::Status(status) = cluster.admin_as(OUTSIDER_ID, 1, AdminQuery::Status).await
+    else {
+        panic!("read-only queries stay open");
+    };
+    assert!(!status.in_view_change);
+    assert_eq!(status.last_snapshot, None);
+
+    let result = cluster.admin(1, AdminQuery::ForceViewChange).await;
+    assert!(matches!(result, AdminResult::ViewChange { new_view: 1 }));
+}
+
+#[test]
+fn file_snapshot_store_keeps_the_latest() {
+    let dir = std::env::temp_dir().join(format!("pbft-snapshots-{}", std::process::id()));
+    let path = dir.join("snapshot.bin");
+    let _ = std::fs::remove_file(&path);
+
+    let mut store = FileSnapshotStore::open(&path).unwrap();
+    assert_eq!(store.latest(), None);
+
+    let mut snapshot = Snapshot {
+        seq_num: 3,
+        state_digest: [1; 32],
+        entries: vec![("a".to_string(), "1".to_string())],
+    };
+    store.save(&snapshot).unwrap();
+    snapshot.seq_num = 5;
+    store.save(&snapshot).unwrap();
+
+    let reopened = FileSnapshotStore::open(&path).unwrap();
+    assert_eq!(reopened.latest(), Some(snapshot));
+}
@@ -0,0 +1,557 @@
+mod common;
+
+use common::{CLIENT_ID, run_until_quiet};
+use ring::signature::Ed25519KeyPair;
+use simple_pbft_demo::{
+    config::{membership::Membership, node::ClusterConfig},
+    crypto::primitives::Crypto,
+    message::message_types::{
+        ClientInfo, Commit, CommitCertificate, CommitSignature, Evidence, NewView, PBFTMessage,
+        PrePrepare, Prepare, PreparedProof, Reconfigure, ReplicaInfo, Request, SignedMessage,
+        ViewChange,
+    },
+    metrics::registry::Metrics,
+    network::{
+        byzantine::{ByzantineBehavior, ByzantineConfig, ByzantineTransport},
+        transport::{MemoryNetwork, MemoryTransport, Transport},
+    },
+    sim::simulator::{SEED_ENV, SimConfig, SimReport, Simulator, seed_from_env},
+    state::replica::Replica,
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, atomic::Ordering},
+};
+
+fn seeds(default: &[u64]) -> Vec<u64> {
+    match seed_from_env() {
+        Some(seed) => vec![seed],
+        None => default.to_vec(),
+    }
+}
+
+fn run_with(byzantine: ByzantineConfig, seed: u64) -> SimReport {
+    let config = SimConfig {
+        byzantine: vec![byzantine],
+        ..SimConfig::with_seed(seed)
+    };
+    Simulator::new(config).run()
+}
+
+/// One faulty replica out of four: the others must stay consistent and
+/// execute every request.
+fn assert_tolerated(byzantine: ByzantineConfig) {
+    for seed in seeds(&[30, 31]) {
+        let report = run_with(byzantine.clone(), seed);
+        assert!(
+            report.completed,
+            "{:?} with {}={}: {:?}",
+            byzantine.behaviors, SEED_ENV, seed, report
+        );
+        assert!(report.violations.is_empty(), "{:?}", report);
+    }
+}
+
+#[test]
+fn equivocating_primary_is_tolerated() {
+    assert_tolerated(ByzantineConfig::new(0, vec![ByzantineBehavior::Equivocate]));
+}
+
+#[test]
+fn equivocating_primary_is_convicted_by_every_correct_replica() {
+    for seed in seeds(&[30, 31]) {
+        let config = SimConfig {
+            byzantine: vec![ByzantineConfig::new(0, vec![ByzantineBehavior::Equivocate])],
+            ..SimConfig::with_seed(seed)
+        };
+        let mut sim = Simulator::new(config);
+        while sim.step() {}
+
+        for replica in sim.replicas().filter(|r| r.node_id() != 0) {
+            let evidence = replica.evidence();
+            assert!(!evidence.is_empty(), "replay with {}={}", SEED_ENV, seed);
+            assert!(
+                evidence
+                    .iter()
+                    .all(|e| e.culprit() == 0 && e.is_conflicting())
+            );
+            assert!(replica.view() >= 1);
+        }
+    }
+}
+
+#[test]
+fn silent_primary_is_replaced() {
+    let byzantine = ByzantineConfig::new(0, vec![ByzantineBehavior::SilentPrimary]);
+    assert_tolerated(byzantine.clone());
+
+    let report = run_with(byzantine, 30);
+    for replica in report.replicas.iter().filter(|r| r.node_id != 0) {
+        assert!(replica.view >= 1, "{:?}", report);
+    }
+}
+
+#[test]
+fn bogus_digests_are_tolerated() {
+    assert_tolerated(ByzantineConfig::new(
+        0,
+        vec![ByzantineBehavior::BogusDigest],
+    ));
+    assert_tolerated(ByzantineConfig::new(
+        2,
+        vec![ByzantineBehavior::BogusDigest],
+    ));
+}
+
+#[test]
+fn invalid_signatures_are_tolerated() {
+    assert_tolerated(ByzantineConfig::new(
+        0,
+        vec![ByzantineBehavior::InvalidSignature],
+    ));
+    assert_tolerated(ByzantineConfig::new(
+        3,
+        vec![ByzantineBehavior::InvalidSignature],
+    ));
+}
+
+#[test]
+fn old_view_replays_are_tolerated() {
+    assert_tolerated(ByzantineConfig::new(
+        0,
+        vec![
+            ByzantineBehavior::SilentPrimary,
+            ByzantineBehavior::ReplayOldView,
+        ],
+    ));
+}
+
+#[test]
+fn selective_drop_is_tolerated() {
+    assert_tolerated(ByzantineConfig {
+        drop_to: vec![1],
+        ..ByzantineConfig::new(3, vec![ByzantineBehavior::SelectiveDrop])
+    });
+}
+
+fn keypair(id: u32) -> Ed25519KeyPair {
+    Ed25519KeyPair::from_seed_unchecked(&[id as u8; 32]).unwrap()
+}
+
+#[tokio::test]
+async fn equivocation_sends_conflicting_pre_prepares() {
+    let membership = Membership::new(
+        (0..4)
+            .map(|id| ReplicaInfo {
+                id,
+                public_key: Crypto::new(keypair(id), id, HashMap::new()).get_pub_key(),
+                addr: format!("127.0.0.1:{}", 5000 + id).parse().unwrap(),
+            })
+            .collect(),
+        Vec::new(),
+    )
+    .unwrap();
+    let network = MemoryNetwork::new();
+    let crypto = Crypto::new(keypair(0), 0, HashMap::new());
+    let primary = ByzantineTransport::new(
+        network.join(0),
+        ByzantineConfig::new(0, vec![ByzantineBehavior::Equivocate]),
+        Crypto::new(keypair(0), 0, HashMap::new()),
+        &membership,
+    );
+    let mut backups: Vec<_> = (1..4).map(|id| network.join(id)).collect();
+
+    let request = signed_request(Request {
+        operation: b"PUT:k:v".to_vec(),
+        timestamp: 1,
+        client_id: CLIENT_ID as u64,
+    });
+    let pre_prepare = PrePrepare {
+        view: 0,
+        seq_num: 1,
+        digest: request.message.digest(),
+        request,
+    };
+    primary
+        .broadcast(&PBFTMessage::PrePrepare(
+            crypto.create_signed_message(pre_prepare.clone()),
+        ))
+        .await;
+
+    let verifier = Crypto::new(keypair(1), 1, membership.peer_public_keys(1));
+    let digests: Vec<[u8; 32]> = backups
+        .iter_mut()
+        .map(|backup| match backup.try_recv() {
+            Some(PBFTMessage::PrePrepare(signed)) => {
+                assert!(verifier.verify_signed_message(&signed));
+                assert_eq!(
+                    signed.message.digest,
+                    signed.message.request.message.digest()
+                );
+                signed.message.digest
+            }
+            other => panic!("expected a pre-prepare, got {:?}", other),
+        })
+        .collect();
+
+    assert_eq!(digests[0], pre_prepare.digest);
+    assert_eq!(digests[1], Request::null().digest());
+    assert_eq!(digests[2], Request::null().digest());
+}
+
+#[test]
+fn evidence_must_be_signed_and_conflicting() {
+    let primary = Crypto::new(keypair(0), 0, HashMap::new());
+    let verifier = Crypto::new(keypair(1), 1, HashMap::from([(0, primary.get_pub_key())]));
+    let pre_prepare = |request: SignedMessage<Request>| PrePrepare {
+        view: 0,
+        seq_num: 7,
+        digest: request.message.digest(),
+        request,
+    };
+    let a = primary.create_signed_message(pre_prepare(SignedMessage::null()));
+    let b = primary.create_signed_message(pre_prepare(signed_request(Request {
+        operation: b"PUT:k:v".to_vec(),
+        timestamp: 1,
+        client_id: CLIENT_ID as u64,
+    })));
+
+    let evidence = Evidence {
+        first: a.clone(),
+        second: b.clone(),
+    };
+    assert!(verifier.verify_evidence(&evidence));
+    assert_eq!(evidence.culprit(), 0);
+
+    let same = Evidence {
+        first: a.clone(),
+        second: a.clone(),
+    };
+    assert!(!verifier.verify_evidence(&same));
+
+    let mut forged = b;
+    forged.signature[0] ^= 0xff;
+    let forged = Evidence {
+        first: a,
+        second: forged,
+    };
+    assert!(!verifier.verify_evidence(&forged));
+}
+
+#[test]
+fn byzantine_nodes_are_read_from_cluster_config() {
+    let cluster: ClusterConfig = toml::from_str(
+        r#"
+        replicas = [
+            { id = 0, addr = "127.0.0.1:5000" },
+            { id = 1, addr = "127.0.0.1:5001" },
+        ]
+
+        [[byzantine]]
+        id = 1
+        behaviors = ["selective_drop", "invalid_signature"]
+        drop_to = [0]
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(cluster.byzantine_config(0), None);
+    assert_eq!(
+        cluster.byzantine_config(1),
+        Some(&ByzantineConfig {
+            id: 1,
+            behaviors: vec![
+                ByzantineBehavior::SelectiveDrop,
+                ByzantineBehavior::InvalidSignature,
+            ],
+            drop_to: vec![0],
+        })
+    );
+}
+
+fn signer(id: u32) -> Crypto {
+    Crypto::new(keypair(id), id, HashMap::new())
+}
+
+fn signed_request(request: Request) -> SignedMessage<Request> {
+    signer(CLIENT_ID).create_signed_message(request)
+}
+
+/// Four replicas over an in-process network, all but `byzantine` run by a
+/// `Replica`; the test speaks for `byzantine` with its real key. The client
+/// is an admin.
+fn cluster_with_byzantine(byzantine: u32) -> (MemoryNetwork, Vec<(Replica, MemoryTransport)>) {
+    let mut membership = Membership::new(
+        (0..4)
+            .map(|id| ReplicaInfo {
+                id,
+                public_key: signer(id).get_pub_key(),
+                addr: format!("127.0.0.1:{}", 5000 + id).parse().unwrap(),
+            })
+            .collect(),
+        vec![ClientInfo {
+            id: CLIENT_ID,
+            public_key: signer(CLIENT_ID).get_pub_key(),
+        }],
+    )
+    .unwrap();
+    membership.set_admins([CLIENT_ID]);
+
+    let network = MemoryNetwork::new();
+    let _byzantine_mailbox = network.join(byzantine);
+    let replicas = (0..4)
+        .filter(|&id| id != byzantine)
+        .map(|id| {
+            (
+                Replica::new(id, membership.clone(), signer(id)),
+                network.join(id),
+            )
+        })
+        .collect();
+    (network, replicas)
+}
+
+fn evil_request() -> Request {
+    Request {
+        operation: b"PUT:owner:mallory".to_vec(),
+        timestamp: 1,
+        client_id: CLIENT_ID as u64,
+    }
+}
+
+/// A proof that `evil_request` prepared at seq 1 of view 0, made up by
+/// `forger` alone: the primary's and backups' signatures are all its own.
+fn forged_proof(forger: &Crypto) -> PreparedProof {
+    let request = signed_request(evil_request());
+    let digest = request.message.digest();
+    let mut pre_prepare = forger.create_signed_message(PrePrepare {
+        view: 0,
+        seq_num: 1,
+        digest,
+        request,
+    });
+    pre_prepare.signer_id = 0;
+    let prepares = [1, 2]
+        .into_iter()
+        .map(|replica_id| {
+            let mut prepare = forger.create_signed_message(Prepare {
+                view: 0,
+                seq_num: 1,
+                digest,
+                replica_id,
+            });
+            prepare.signer_id = replica_id;
+            prepare
+        })
+        .collect();
+    PreparedProof {
+        pre_prepare,
+        prepares,
+    }
+}
+
+fn view_change(from: u32, last_executed: u64, proofs: Vec<PreparedProof>) -> ViewChange {
+    ViewChange {
+        new_view: 1,
+        last_executed,
+        executed_certificate: None,
+        prepared_requests: proofs,
+        replica_id: from,
+    }
+}
+
+fn assert_not_executed(replicas: &[(Replica, MemoryTransport)]) {
+    for (replica, _) in replicas {
+        assert!(
+            !replica.has_executed(CLIENT_ID as u64, 1),
+            "replica {} executed the forged request",
+            replica.node_id()
+        );
+    }
+}
+
+#[tokio::test]
+async fn view_change_with_forged_prepared_proof_is_not_counted() {
+    let (network, mut replicas) = cluster_with_byzantine(3);
+    let forged = signer(3).create_signed_message(view_change(3, 0, vec![forged_proof(&signer(3))]));
+    for id in 0..3 {
+        network.send(id, PBFTMessage::ViewChange(forged.clone()));
+    }
+
+    // Replicas 0 and 2 time out on primary 0. Had the forged view change
+    // been counted, the new primary would have re-proposed the request in
+    // it.
+    for index in [0, 2] {
+        let (replica, transport) = &mut replicas[index];
+        replica.force_view_change(transport).await;
+    }
+    run_until_quiet(&mut replicas).await;
+
+    for (replica, _) in &replicas {
+        assert_eq!(replica.view(), 1, "replica {}", replica.node_id());
+        assert_eq!(replica.last_executed(), 0);
+    }
+    assert_not_executed(&replicas);
+}
+
+#[tokio::test]
+async fn view_change_must_prove_its_last_executed() {
+    let (network, mut replicas) = cluster_with_byzantine(3);
+    let byzantine = signer(3);
+
+    // A certificate for a slot nobody executed, signed by the liar alone.
+    let mut claims = view_change(3, 1000, Vec::new());
+    let unbacked = byzantine.create_signed_message(claims.clone());
+    let digest = Request::null().digest();
+    claims.executed_certificate = Some(CommitCertificate {
+        view: 0,
+        seq_num: 1000,
+        digest,
+        signatures: vec![CommitSignature {
+            replica_id: 3,
+            signature: byzantine
+                .create_signed_message(Commit {
+                    view: 0,
+                    seq_num: 1000,
+                    digest,
+                    replica_id: 3,
+                })
+                .signature,
+        }],
+    });
+    let undercertified = byzantine.create_signed_message(claims);
+    for id in 0..3 {
+        network.send(id, PBFTMessage::ViewChange(unbacked.clone()));
+        network.send(id, PBFTMessage::ViewChange(undercertified.clone()));
+    }
+
+    for index in [0, 2] {
+        let (replica, transport) = &mut replicas[index];
+        replica.force_view_change(transport).await;
+    }
+    run_until_quiet(&mut replicas).await;
+
+    // Had the claim been believed, the new primary would number requests
+    // from 745 on and none of them could ever execute.
+    let request = Request {
+        operation: b"PUT:k:v".to_vec(),
+        timestamp: 2,
+        client_id: CLIENT_ID as u64,
+    };
+    network.send(
+        1,
+        PBFTMessage::Request(signer(CLIENT_ID).create_signed_message(request)),
+    );
+    run_until_quiet(&mut replicas).await;
+
+    for (replica, _) in &replicas {
+        assert_eq!(replica.view(), 1, "replica {}", replica.node_id());
+        assert_eq!(replica.last_executed(), 1, "replica {}", replica.node_id());
+    }
+}
+
+#[tokio::test]
+async fn new_view_carrying_a_forged_prepared_proof_is_refused() {
+    // Replica 1, primary of view 1, builds its new view on view changes
+    // from 0 and 2 plus one of its own with a made-up proof.
+    let (network, mut replicas) = cluster_with_byzantine(1);
+    let primary = signer(1);
+    let view_change_msgs = vec![
+        signer(0).create_signed_message(view_change(0, 0, Vec::new())),
+        primary.create_signed_message(view_change(1, 0, vec![forged_proof(&primary)])),
+        signer(2).create_signed_message(view_change(2, 0, Vec::new())),
+    ];
+    let request = signed_request(evil_request());
+    let pre_prepare = primary.create_signed_message(PrePrepare {
+        view: 1,
+        seq_num: 1,
+        digest: request.message.digest(),
+        request,
+    });
+    let new_view = primary.create_signed_message(NewView {
+        new_view: 1,
+        view_change_msgs,
+        pre_prepares: vec![pre_prepare],
+        replica_id: 1,
+    });
+    for id in [0, 2, 3] {
+        network.send(id, PBFTMessage::NewView(new_view.clone()));
+    }
+    run_until_quiet(&mut replicas).await;
+
+    for (replica, _) in &replicas {
+        assert_eq!(replica.view(), 0, "replica {}", replica.node_id());
+    }
+    assert_not_executed(&replicas);
+}
+
+#[tokio::test]
+async fn primary_cannot_forge_a_client_request() {
+    let add_replica = Reconfigure {
+        add: vec![ReplicaInfo {
+            id: 4,
+            public_key: vec![4u8; 32],
+            addr: "127.0.0.1:5004".parse().unwrap(),
+        }],
+        ..Reconfigure::default()
+    };
+    let request = Request {
+        operation: add_replica.to_operation(),
+        timestamp: 1,
+        client_id: CLIENT_ID as u64,
+    };
+    let genuine = signed_request(request.clone());
+    // Primary 0 signs the admin's request itself and puts the admin's id on
+    // it.
+    let mut forged = signer(0).create_signed_message(request);
+    forged.signer_id = CLIENT_ID;
+
+    for (request, epoch, executed) in [(forged, 0, 0), (genuine, 1, 1)] {
+        let (network, mut replicas) = cluster_with_byzantine(0);
+        let pre_prepare = signer(0).create_signed_message(PrePrepare {
+            view: 0,
+            seq_num: 1,
+            digest: request.message.digest(),
+            request,
+        });
+        for id in 1..4 {
+            network.send(id, PBFTMessage::PrePrepare(pre_prepare.clone()));
+        }
+        run_until_quiet(&mut replicas).await;
+
+        for (replica, _) in &replicas {
+            assert_eq!(replica.last_executed(), executed);
+            assert_eq!(replica.membership().epoch(), epoch);
+        }
+    }
+}
+
+#[tokio::test]
+async fn null_slots_are_not_counted_as_executed_requests() {
+    let (network, mut replicas) = cluster_with_byzantine(0);
+    let metrics: Vec<Arc<Metrics>> = replicas
+        .iter_mut()
+        .map(|(replica, _)| {
+            let metrics = Arc::new(Metrics::new());
+            replica.set_metrics(metrics.clone());
+            metrics
+        })
+        .collect();
+
+    let pre_prepare = signer(0).create_signed_message(PrePrepare {
+        view: 0,
+        seq_num: 1,
+        digest: Request::null().digest(),
+        request: SignedMessage::null(),
+    });
+    for id in 1..4 {
+        network.send(id, PBFTMessage::PrePrepare(pre_prepare.clone()));
+    }
+    run_until_quiet(&mut replicas).await;
+
+    for (metrics, (replica, _)) in metrics.iter().zip(&replicas) {
+        assert_eq!(replica.last_executed(), 1);
+        assert_eq!(metrics.last_executed.load(Ordering::Relaxed), 1);
+        assert_eq!(metrics.requests_executed.load(Ordering::Relaxed), 0);
+    }
+}
@@ -0,0 +1,153 @@
+mod common;
+
+use common::install_crypto_provider;
+use quinn::{Connecting, Endpoint};
+use rcgen::{CertificateParams, CustomExtension, KeyPair, PKCS_ED25519, SerialNumber};
+use rustls::pki_types::{CertificateDer, PrivatePkcs8KeyDer};
+use simple_pbft_demo::{
+    crypto::primitives::Crypto,
+    network::{
+        cert::{
+            NodeCert, PeerIdentity, PinnedKeys, ed25519_public_key, make_client_config,
+            replica_server_name,
+        },
+        network_layer::{Network, NetworkConfig},
+    },
+};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+/// DER prefix of an Ed25519 `SubjectPublicKeyInfo`, as a forger would plant it.
+const ED25519_SPKI_PREFIX: [u8; 12] = [
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+
+fn public_key(pkcs8: &[u8]) -> Vec<u8> {
+    let keypair = ring::signature::Ed25519KeyPair::from_pkcs8(pkcs8).unwrap();
+    Crypto::new(keypair, 0, HashMap::new()).get_pub_key()
+}
+
+fn key_pair(pkcs8: &[u8]) -> KeyPair {
+    KeyPair::from_pkcs8_der_and_sign_algo(&PrivatePkcs8KeyDer::from(pkcs8.to_vec()), &PKCS_ED25519)
+        .unwrap()
+}
+
+fn self_signed(params: CertificateParams, key_pair: &KeyPair) -> CertificateDer<'static> {
+    params.self_signed(key_pair).unwrap().der().clone()
+}
+
+/// Certificate for the attacker's own key that mentions `victim`'s key both
+/// before its real `SubjectPublicKeyInfo` (in the serial number) and after
+/// it (in an extension).
+fn forged_cert(attacker_pkcs8: &[u8], victim: &[u8]) -> CertificateDer<'static> {
+    let mut planted = ED25519_SPKI_PREFIX.to_vec();
+    planted.extend_from_slice(victim);
+
+    let mut params = CertificateParams::new(vec![replica_server_name(0)]).unwrap();
+    params.serial_number = Some(SerialNumber::from_slice(&planted));
+    params
+        .custom_extensions
+        .push(CustomExtension::from_oid_content(
+            &[1, 3, 6, 1, 4, 1, 99999, 1],
+            planted,
+        ));
+    self_signed(params, &key_pair(attacker_pkcs8))
+}
+
+#[test]
+fn key_is_read_from_the_subject_public_key_info() {
+    let victim = Crypto::generate_keypair();
+    let attacker = Crypto::generate_keypair();
+    let pinned = PinnedKeys::new(HashMap::from([(0, public_key(&victim))]), HashMap::new());
+
+    let genuine = self_signed(
+        CertificateParams::new(vec![replica_server_name(0)]).unwrap(),
+        &key_pair(&victim),
+    );
+    assert_eq!(ed25519_public_key(&genuine), Some(public_key(&victim)));
+    assert_eq!(
+        pinned.identify_cert(&genuine),
+        Some(PeerIdentity::Replica(0))
+    );
+
+    let forged = forged_cert(&attacker, &public_key(&victim));
+    assert_eq!(ed25519_public_key(&forged), Some(public_key(&attacker)));
+    assert_eq!(pinned.identify_cert(&forged), None);
+}
+
+#[test]
+fn malformed_and_non_ed25519_certificates_have_no_key() {
+    let ecdsa = KeyPair::generate().unwrap();
+    let cert = self_signed(
+        CertificateParams::new(vec!["node-0".into()]).unwrap(),
+        &ecdsa,
+    );
+    assert_eq!(ed25519_public_key(&cert), None);
+
+    let genuine = self_signed(
+        CertificateParams::new(vec!["node-0".into()]).unwrap(),
+        &key_pair(&Crypto::generate_keypair()),
+    );
+    let mut trailing = genuine.to_vec();
+    trailing.push(0);
+    assert_eq!(ed25519_public_key(&CertificateDer::from(trailing)), None);
+    assert_eq!(
+        ed25519_public_key(&CertificateDer::from(genuine[..genuine.len() - 1].to_vec())),
+        None
+    );
+}
+
+/// Whether the TLS handshake, or the connection right after it, fails. Waits
+/// well under `HANDSHAKE_TIMEOUT`, so only a TLS rejection counts.
+async fn rejected(connecting: Connecting) -> bool {
+    match connecting.await {
+        Err(_) => true,
+        Ok(connection) => tokio::time::timeout(Duration::from_secs(2), connection.closed())
+            .await
+            .is_ok(),
+    }
+}
+
+#[tokio::test]
+async fn replicas_accept_only_pinned_keys_under_their_own_name() {
+    install_crypto_provider();
+    let pkcs8: Vec<Vec<u8>> = (0..2).map(|_| Crypto::generate_keypair()).collect();
+    let pinned = PinnedKeys::new(
+        (0..2)
+            .map(|id| (id, public_key(&pkcs8[id as usize])))
+            .collect(),
+        HashMap::new(),
+    );
+    let replica = Network::new(
+        0,
+        "127.0.0.1:0".parse().unwrap(),
+        &NodeCert::from_pkcs8(replica_server_name(0), &pkcs8[0]),
+        pinned.clone(),
+        NetworkConfig::default(),
+        2,
+    );
+    replica.spawn_acceptor();
+    let addr = replica.local_addr().unwrap();
+
+    let dial = |cert: NodeCert, server_name: String| {
+        let mut endpoint = Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+        endpoint.set_default_client_config(quinn::ClientConfig::new(Arc::new(
+            quinn::crypto::rustls::QuicClientConfig::try_from(make_client_config(
+                &cert,
+                pinned.clone(),
+            ))
+            .unwrap(),
+        )));
+        endpoint.connect(addr, &server_name).unwrap()
+    };
+
+    // Replica 0's key does not match the one pinned for node-1.
+    let replica_1 = || NodeCert::from_pkcs8(replica_server_name(1), &pkcs8[1]);
+    assert!(rejected(dial(replica_1(), replica_server_name(1))).await);
+
+    // A client certificate whose key nobody pinned.
+    let stranger = NodeCert::from_pkcs8(replica_server_name(1), &Crypto::generate_keypair());
+    assert!(rejected(dial(stranger, replica_server_name(0))).await);
+
+    let accepted = dial(replica_1(), replica_server_name(0)).await.unwrap();
+    assert!(accepted.close_reason().is_none());
+}
@@ -0,0 +1,93 @@
+mod common;
+
+use common::temp_path;
+use simple_pbft_demo::{
+    message::message_types::{CertifiedRequest, CommitCertificate, CommitSignature, Request},
+    state::certificates::{CertificateStore, FileCertificateStore},
+};
+use std::{fs::OpenOptions, io::Write};
+
+fn entry(seq_num: u64, value: &str) -> CertifiedRequest {
+    let request = Request {
+        operation: format!("PUT:k:{}", value).into_bytes(),
+        timestamp: seq_num,
+        client_id: 100,
+    };
+    CertifiedRequest {
+        certificate: CommitCertificate {
+            view: 0,
+            seq_num,
+            digest: request.digest(),
+            signatures: vec![CommitSignature {
+                replica_id: 1,
+                signature: vec![seq_num as u8; 64],
+            }],
+        },
+        request,
+    }
+}
+
+#[test]
+fn certificates_survive_reopening() {
+    let path = temp_path("certificates", "reopen.bin");
+
+    let mut store = FileCertificateStore::open(&path).unwrap();
+    store.put(&entry(1, "a"));
+    store.put(&entry(2, "b"));
+    store.put(&entry(2, "c"));
+    assert_eq!(store.get(1), Some(entry(1, "a")));
+    drop(store);
+
+    let store = FileCertificateStore::open(&path).unwrap();
+    assert_eq!(store.get(1), Some(entry(1, "a")));
+    assert_eq!(store.get(2), Some(entry(2, "b")), "first record wins");
+    assert_eq!(store.get(3), None);
+}
+
+#[test]
+fn restart_does_not_store_certificates_again() {
+    let path = temp_path("certificates", "restart.bin");
+
+    let mut store = FileCertificateStore::open(&path).unwrap();
+    for seq_num in 1..=3 {
+        store.put(&entry(seq_num, "a"));
+    }
+    drop(store);
+    let size = std::fs::metadata(&path).unwrap().len();
+
+    // A restarted replica executes everything again from the start.
+    for _ in 0..2 {
+        let mut store = FileCertificateStore::open(&path).unwrap();
+        for seq_num in 1..=3 {
+            store.put(&entry(seq_num, "a"));
+        }
+    }
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), size);
+
+    let mut store = FileCertificateStore::open(&path).unwrap();
+    store.put(&entry(4, "a"));
+    assert!(std::fs::metadata(&path).unwrap().len() > size);
+    assert_eq!(store.get(3), Some(entry(3, "a")));
+    assert_eq!(store.get(4), Some(entry(4, "a")));
+}
+
+#[test]
+fn partial_record_at_the_end_is_dropped() {
+    let path = temp_path("certificates", "partial.bin");
+
+    let mut store = FileCertificateStore::open(&path).unwrap();
+    store.put(&entry(1, "a"));
+    drop(store);
+
+    let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(&[0, 0, 1, 0, 42, 42]).unwrap();
+    drop(file);
+
+    let mut store = FileCertificateStore::open(&path).unwrap();
+    assert_eq!(store.get(1), Some(entry(1, "a")));
+    store.put(&entry(2, "b"));
+    drop(store);
+
+    let store = FileCertificateStore::open(&path).unwrap();
+    assert_eq!(store.get(2), Some(entry(2, "b")));
+}
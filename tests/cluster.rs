@@ -0,0 +1,43 @@
+use simple_pbft_demo::{
+    config::node::{ClusterConfig, load_cluster_config},
+    crypto::primitives::generate_key_files,
+};
+use std::{env, fs};
+
+#[test]
+fn local_cluster_numbers_ports_by_replica() {
+    let cluster = ClusterConfig::local(7);
+
+    assert_eq!(cluster.replica_ids(), (0..7).collect::<Vec<_>>());
+    assert_eq!(cluster.replicas[6].addr, "127.0.0.1:5006".parse().unwrap());
+    assert_eq!(
+        cluster.replicas[6].metrics_addr,
+        Some("127.0.0.1:9006".parse().unwrap())
+    );
+    assert_eq!(
+        ClusterConfig::default().replica_ids(),
+        ClusterConfig::local(4).replica_ids()
+    );
+
+    let path = env::temp_dir().join(format!("pbft-cluster-{}.toml", std::process::id()));
+    fs::write(&path, toml::to_string(&cluster).unwrap()).unwrap();
+    let loaded = load_cluster_config(&path);
+    fs::remove_file(&path).unwrap();
+    assert_eq!(loaded.replica_ids(), cluster.replica_ids());
+    assert_eq!(loaded.clients, cluster.clients);
+}
+
+#[tokio::test]
+async fn key_files_are_generated_once() {
+    let dir = env::temp_dir().join(format!("pbft-keys-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+
+    assert!(generate_key_files(&dir, "node_0").await.unwrap());
+    let key = fs::read(dir.join("node_0.key")).unwrap();
+    let public = fs::read(dir.join("node_0.pub")).unwrap();
+    assert_eq!(public.len(), 32);
+
+    assert!(!generate_key_files(&dir, "node_0").await.unwrap());
+    assert_eq!(fs::read(dir.join("node_0.key")).unwrap(), key);
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,97 @@
+//! Fixtures shared by the integration tests. Each test binary uses only some
+//! of them.
+#![allow(dead_code)]
+
+use ring::signature::Ed25519KeyPair;
+use simple_pbft_demo::{
+    config::membership::Membership,
+    crypto::primitives::Crypto,
+    message::message_types::{ClientInfo, ReplicaInfo},
+    network::transport::{MemoryNetwork, MemoryTransport},
+    state::replica::Replica,
+};
+use std::{collections::HashMap, path::PathBuf};
+
+pub const CLIENT_ID: u32 = 100;
+
+pub fn install_crypto_provider() {
+    let _ = rustls::crypto::ring::default_provider().install_default();
+}
+
+pub fn crypto(id: u32, pkcs8: &[u8]) -> Crypto {
+    Crypto::new(
+        Ed25519KeyPair::from_pkcs8(pkcs8).unwrap(),
+        id,
+        HashMap::new(),
+    )
+}
+
+/// A path named `name` in this process's directory for `suite`, with any file
+/// left there by an earlier run removed.
+pub fn temp_path(suite: &str, name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("pbft-{}-{}", suite, std::process::id()));
+    let path = dir.join(name);
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+pub fn generate_keys(n: u32) -> Vec<Vec<u8>> {
+    (0..n).map(|_| Crypto::generate_keypair()).collect()
+}
+
+/// Membership of one replica per key in `replica_keys`, numbered from 0, and
+/// the given `(id, key)` clients.
+pub fn membership(replica_keys: &[Vec<u8>], clients: &[(u32, &Crypto)]) -> Membership {
+    Membership::new(
+        replica_keys
+            .iter()
+            .enumerate()
+            .map(|(id, key)| ReplicaInfo {
+                id: id as u32,
+                public_key: crypto(id as u32, key).get_pub_key(),
+                addr: format!("127.0.0.1:{}", 5000 + id).parse().unwrap(),
+            })
+            .collect(),
+        clients
+            .iter()
+            .map(|(id, client)| ClientInfo {
+                id: *id,
+                public_key: client.get_pub_key(),
+            })
+            .collect(),
+    )
+    .unwrap()
+}
+
+/// One replica per key in `replica_keys`, each joined to `network`.
+pub fn replicas(
+    network: &MemoryNetwork,
+    membership: &Membership,
+    replica_keys: &[Vec<u8>],
+) -> Vec<(Replica, MemoryTransport)> {
+    replica_keys
+        .iter()
+        .enumerate()
+        .map(|(id, key)| {
+            let id = id as u32;
+            let replica = Replica::new(id, membership.clone(), crypto(id, key));
+            (replica, network.join(id))
+        })
+        .collect()
+}
+
+/// Delivers queued messages until every mailbox is empty.
+pub async fn run_until_quiet(replicas: &mut [(Replica, MemoryTransport)]) {
+    loop {
+        let mut delivered = false;
+        for (replica, transport) in replicas.iter_mut() {
+            while let Some(msg) = transport.try_recv() {
+                replica.handle_message(msg, transport).await;
+                delivered = true;
+            }
+        }
+        if !delivered {
+            return;
+        }
+    }
+}
@@ -0,0 +1,134 @@
+mod common;
+
+use common::{CLIENT_ID, crypto, generate_keys, membership, replicas, run_until_quiet, temp_path};
+use simple_pbft_demo::{
+    crypto::primitives::Crypto,
+    message::message_types::{MessageKind, PBFTMessage, Request},
+    network::transport::MemoryNetwork,
+    trace::{
+        diagram::{Diagram, QuorumKind, Step, Topic},
+        record::{TraceEvent, TraceRecord, TraceRecorder, read_trace},
+        timeline::to_html,
+    },
+};
+use std::path::PathBuf;
+
+/// Traces of a 4 replica cluster ordering one request.
+async fn traces(name: &str) -> Vec<Vec<TraceRecord>> {
+    let replica_keys = generate_keys(4);
+    let client = crypto(CLIENT_ID, &Crypto::generate_keypair());
+    let membership = membership(&replica_keys, &[(CLIENT_ID, &client)]);
+
+    let network = MemoryNetwork::new();
+    let paths: Vec<PathBuf> = (0..4)
+        .map(|id| temp_path("diagram", &format!("{}-{}.bin", name, id)))
+        .collect();
+    let mut replicas = replicas(&network, &membership, &replica_keys);
+    for (replica, _) in &mut replicas {
+        let id = replica.node_id();
+        let recorder = TraceRecorder::create(&paths[id as usize], id, replica.clock()).unwrap();
+        replica.set_recorder(recorder);
+    }
+    let _client_inbox = network.join(CLIENT_ID);
+
+    let request = Request {
+        operation: b"PUT:a:1".to_vec(),
+        timestamp: 1,
+        client_id: CLIENT_ID as u64,
+    };
+    network.send(
+        0,
+        PBFTMessage::Request(client.create_signed_message(request)),
+    );
+    run_until_quiet(&mut replicas).await;
+
+    paths.iter().map(|path| read_trace(path).unwrap()).collect()
+}
+
+#[tokio::test]
+async fn diagram_shows_every_phase_and_quorum() {
+    let diagram = Diagram::build(&traces("phases").await, 3);
+
+    let aliases: Vec<String> = diagram.participants.iter().map(|p| p.alias()).collect();
+    assert_eq!(aliases, ["R0", "R1", "R2", "R3", "C100"]);
+    assert_eq!(diagram.sections.len(), 1);
+    let section = &diagram.sections[0];
+    assert_eq!(
+        section.topic,
+        Topic::Slot {
+            view: 0,
+            seq_num: 1
+        }
+    );
+
+    let count = |kind: MessageKind| {
+        section
+            .steps
+            .iter()
+            .filter(|s| matches!(s, Step::Message(a) if a.kind == kind && !a.lost))
+            .count()
+    };
+    assert_eq!(count(MessageKind::Request), 1);
+    assert_eq!(count(MessageKind::PrePrepare), 3);
+    assert_eq!(count(MessageKind::Prepare), 9);
+    assert_eq!(count(MessageKind::Commit), 12);
+    assert_eq!(count(MessageKind::Reply), 4);
+
+    for replica in 0..4 {
+        let quorums: Vec<_> = section
+            .steps
+            .iter()
+            .filter_map(|s| match s {
+                Step::Quorum(q) if q.replica == replica => Some(q),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(quorums.len(), 2, "replica {}", replica);
+        assert_eq!(quorums[0].kind, QuorumKind::Prepared);
+        assert_eq!(quorums[1].kind, QuorumKind::Committed);
+        assert!(quorums[1].signers.len() >= 3);
+        assert!(quorums[1].signers.contains(&replica));
+    }
+
+    let mermaid = diagram.to_mermaid();
+    assert!(mermaid.starts_with("sequenceDiagram\n"));
+    assert!(mermaid.contains("    participant C100 as Client 100\n"));
+    assert!(mermaid.contains("    C100->>R0: Request(c=100, t=1)\n"));
+    assert!(mermaid.contains("    Note over R0,C100: Sequence number 1 in view 0\n"));
+    assert!(mermaid.contains("    rect rgb(219, 234, 254)\n"));
+
+    let plantuml = diagram.to_plantuml();
+    assert!(plantuml.starts_with("@startuml\n"));
+    assert!(plantuml.ends_with("@enduml\n"));
+    assert!(plantuml.contains("== Sequence number 1 in view 0 =="));
+
+    let html = to_html(&diagram);
+    assert!(html.starts_with("<!DOCTYPE html>"));
+    assert_eq!(html.matches(r#"<g class="quorum">"#).count(), 8);
+}
+
+#[tokio::test]
+async fn messages_missing_from_the_receiver_trace_are_lost() {
+    let mut traces = traces("lost").await;
+    traces[2].retain(
+        |r| !matches!(&r.event, TraceEvent::Received(PBFTMessage::Commit(m)) if m.signer_id == 1),
+    );
+
+    let diagram = Diagram::build(&traces, 3);
+    let lost: Vec<_> = diagram.sections[0]
+        .steps
+        .iter()
+        .filter_map(|s| match s {
+            Step::Message(a) if a.lost => Some(a),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(lost.len(), 1);
+    assert_eq!((lost[0].from, lost[0].to), (1, 2));
+    assert_eq!(lost[0].kind, MessageKind::Commit);
+    assert!(
+        diagram
+            .to_mermaid()
+            .contains("    R1-xR2: Commit(v=0, n=1) (lost)\n")
+    );
+}
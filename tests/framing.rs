@@ -0,0 +1,211 @@
+use simple_pbft_demo::{
+    message::message_types::{MessageKind, PBFTMessage, Prepare, Request, SignedMessage},
+    network::framing::{
+        Envelope, FrameError, FrameLimits, FrameStats, MAGIC, WIRE_VERSION, read_frame, write_frame,
+    },
+};
+
+fn prepare() -> PBFTMessage {
+    PBFTMessage::Prepare(SignedMessage {
+        message: Prepare {
+            view: 0,
+            seq_num: 1,
+            digest: [7u8; 32],
+            replica_id: 2,
+        },
+        signature: vec![1u8; 64],
+        signer_id: 2,
+    })
+}
+
+fn request(operation_len: usize) -> PBFTMessage {
+    PBFTMessage::Request(SignedMessage {
+        message: Request {
+            operation: vec![b'x'; operation_len],
+            timestamp: 1,
+            client_id: 100,
+        },
+        signature: vec![1u8; 64],
+        signer_id: 100,
+    })
+}
+
+const CLUSTER_ID: u32 = 7;
+
+async fn encode(message: &PBFTMessage) -> Vec<u8> {
+    let mut buf = Vec::new();
+    write_frame(&mut buf, Envelope::new(CLUSTER_ID), message)
+        .await
+        .unwrap();
+    buf
+}
+
+#[tokio::test]
+async fn round_trips_valid_frames() {
+    let mut bytes = encode(&prepare()).await;
+    bytes.extend(encode(&request(32)).await);
+    let stats = FrameStats::default();
+    let mut reader = bytes.as_slice();
+
+    let first = read_frame(&mut reader, CLUSTER_ID, &FrameLimits::default(), &stats).await;
+    assert_eq!(first.unwrap().kind(), MessageKind::Prepare);
+    let second = read_frame(&mut reader, CLUSTER_ID, &FrameLimits::default(), &stats).await;
+    assert_eq!(second.unwrap().kind(), MessageKind::Request);
+    let end = read_frame(&mut reader, CLUSTER_ID, &FrameLimits::default(), &stats).await;
+    assert!(matches!(end, Err(FrameError::Closed)));
+}
+
+#[tokio::test]
+async fn rejects_declared_length_above_every_limit() {
+    // Only the length prefix is sent: the frame must be refused on the length
+    // alone, without waiting for (or allocating) a 4 GiB body.
+    let bytes = u32::MAX.to_be_bytes();
+    let stats = FrameStats::default();
+
+    let result = read_frame(
+        &mut bytes.as_slice(),
+        CLUSTER_ID,
+        &FrameLimits::default(),
+        &stats,
+    )
+    .await;
+
+    assert!(matches!(
+        result,
+        Err(FrameError::Oversized { kind: None, .. })
+    ));
+    assert_eq!(stats.snapshot().oversized, 1);
+}
+
+#[tokio::test]
+async fn limits_near_u32_max_do_not_overflow() {
+    let unlimited = FrameLimits {
+        request: u32::MAX,
+        pre_prepare: u32::MAX,
+        prepare: u32::MAX,
+        commit: u32::MAX,
+        reply: u32::MAX,
+        view_change: u32::MAX,
+        new_view: u32::MAX,
+        evidence: u32::MAX,
+        admin_request: u32::MAX,
+        admin_response: u32::MAX,
+    };
+    let bytes = encode(&prepare()).await;
+    let stats = FrameStats::default();
+
+    let result = read_frame(&mut bytes.as_slice(), CLUSTER_ID, &unlimited, &stats).await;
+    assert_eq!(result.unwrap().kind(), MessageKind::Prepare);
+}
+
+#[tokio::test]
+async fn rejects_frame_above_its_type_limit() {
+    let limits = FrameLimits {
+        request: 128,
+        ..FrameLimits::default()
+    };
+    let bytes = encode(&request(1024)).await;
+    let stats = FrameStats::default();
+
+    let result = read_frame(&mut bytes.as_slice(), CLUSTER_ID, &limits, &stats).await;
+
+    assert!(matches!(
+        result,
+        Err(FrameError::Oversized {
+            kind: Some(MessageKind::Request),
+            ..
+        })
+    ));
+    assert_eq!(stats.snapshot().oversized, 1);
+}
+
+#[tokio::test]
+async fn rejects_truncated_frames() {
+    let full = encode(&prepare()).await;
+    let stats = FrameStats::default();
+
+    for cut in [2, 4, 5, full.len() - 1] {
+        let result = read_frame(
+            &mut &full[..cut],
+            CLUSTER_ID,
+            &FrameLimits::default(),
+            &stats,
+        )
+        .await;
+        assert!(
+            matches!(result, Err(FrameError::Truncated)),
+            "cut at {}",
+            cut
+        );
+    }
+    assert_eq!(stats.snapshot().truncated, 4);
+}
+
+#[tokio::test]
+async fn rejects_unknown_message_type() {
+    let mut bytes = vec![0, 0, 0, 10];
+    bytes.extend(MAGIC);
+    bytes.extend([WIRE_VERSION, 0xff]);
+    bytes.extend(CLUSTER_ID.to_be_bytes());
+    bytes.extend([0, 0]);
+    let stats = FrameStats::default();
+
+    let result = read_frame(
+        &mut bytes.as_slice(),
+        CLUSTER_ID,
+        &FrameLimits::default(),
+        &stats,
+    )
+    .await;
+
+    assert!(matches!(result, Err(FrameError::UnknownType(0xff))));
+    assert_eq!(stats.snapshot().malformed, 1);
+}
+
+#[tokio::test]
+async fn frame_carries_envelope_ahead_of_message() {
+    let bytes = encode(&prepare()).await;
+
+    assert_eq!(&bytes[4..6], &MAGIC);
+    assert_eq!(bytes[6], WIRE_VERSION);
+    assert_eq!(bytes[7], 2, "type byte of a Prepare");
+    assert_eq!(&bytes[8..12], &CLUSTER_ID.to_be_bytes());
+}
+
+#[tokio::test]
+async fn rejects_incompatible_envelopes() {
+    let valid = encode(&prepare()).await;
+    let stats = FrameStats::default();
+
+    let mut bad_magic = valid.clone();
+    bad_magic[4] = b'X';
+    let mut future_version = valid.clone();
+    future_version[6] = WIRE_VERSION + 1;
+    let mut other_cluster = valid.clone();
+    other_cluster[11] ^= 1;
+
+    let read = |bytes: Vec<u8>| {
+        let stats = &stats;
+        async move {
+            read_frame(
+                &mut bytes.as_slice(),
+                CLUSTER_ID,
+                &FrameLimits::default(),
+                stats,
+            )
+            .await
+        }
+    };
+    assert!(matches!(
+        read(bad_magic).await,
+        Err(FrameError::BadMagic([b'X', b'B']))
+    ));
+    assert!(matches!(
+        read(future_version).await,
+        Err(FrameError::UnsupportedVersion(v)) if v == WIRE_VERSION + 1
+    ));
+    let result = read(other_cluster).await;
+    assert!(matches!(result, Err(FrameError::WrongCluster(6))));
+    assert!(result.unwrap_err().is_incompatible());
+    assert_eq!(stats.snapshot().incompatible, 3);
+}
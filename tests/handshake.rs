@@ -0,0 +1,259 @@
+mod common;
+
+use common::install_crypto_provider;
+use quinn::{Connection, ConnectionError, Endpoint};
+use simple_pbft_demo::{
+    crypto::primitives::Crypto,
+    message::message_types::{PBFTMessage, Prepare, SignedMessage},
+    network::{
+        cert::{NodeCert, PinnedKeys, make_client_config, replica_server_name},
+        framing::{Envelope, MIN_WIRE_VERSION, WIRE_VERSION, write_frame},
+        handshake::{HANDSHAKE_FAILED, HandshakeError, Hello, read_hello, write_hello},
+        network_layer::{Network, NetworkConfig},
+    },
+};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+struct Keys {
+    pkcs8: Vec<Vec<u8>>,
+    pinned: PinnedKeys,
+}
+
+fn keys(n: u32) -> Keys {
+    let pkcs8: Vec<Vec<u8>> = (0..n).map(|_| Crypto::generate_keypair()).collect();
+    let public = pkcs8
+        .iter()
+        .enumerate()
+        .map(|(id, key)| {
+            let keypair = ring::signature::Ed25519KeyPair::from_pkcs8(key).unwrap();
+            (
+                id as u32,
+                Crypto::new(keypair, id as u32, HashMap::new()).get_pub_key(),
+            )
+        })
+        .collect();
+    Keys {
+        pkcs8,
+        pinned: PinnedKeys::new(public, HashMap::new()),
+    }
+}
+
+fn network(id: u32, keys: &Keys) -> Network {
+    let cert = NodeCert::from_pkcs8(replica_server_name(id), &keys.pkcs8[id as usize]);
+    Network::new(
+        id,
+        "127.0.0.1:0".parse().unwrap(),
+        &cert,
+        keys.pinned.clone(),
+        NetworkConfig::default(),
+        keys.pkcs8.len() as u32,
+    )
+}
+
+#[tokio::test]
+async fn hello_round_trips_and_tolerates_appended_fields() {
+    let mut bytes = Vec::new();
+    write_hello(&mut bytes, &Hello::new(2, 7)).await.unwrap();
+    assert_eq!(
+        read_hello(&mut bytes.as_slice()).await.unwrap(),
+        Hello::new(2, 7)
+    );
+
+    // A later version's Hello with an extra field.
+    let len = u32::from_be_bytes(bytes[..4].try_into().unwrap());
+    bytes[..4].copy_from_slice(&(len + 1).to_be_bytes());
+    bytes.push(42);
+    assert_eq!(
+        read_hello(&mut bytes.as_slice()).await.unwrap(),
+        Hello::new(2, 7)
+    );
+
+    let oversized = [0u8, 0, 1, 0];
+    assert!(matches!(
+        read_hello(&mut oversized.as_slice()).await,
+        Err(HandshakeError::Malformed)
+    ));
+}
+
+#[test]
+fn negotiation_picks_newest_common_version() {
+    let ours = Hello {
+        node_id: 0,
+        cluster_id: 7,
+        min_version: 2,
+        max_version: 4,
+    };
+    let older = Hello {
+        node_id: 1,
+        min_version: 1,
+        max_version: 3,
+        ..ours
+    };
+    let newer = Hello {
+        node_id: 1,
+        min_version: 3,
+        max_version: 6,
+        ..ours
+    };
+    assert_eq!(ours.negotiate(&older, 1).unwrap(), 3);
+    assert_eq!(ours.negotiate(&newer, 1).unwrap(), 4);
+    assert_eq!(
+        Hello::new(0, 7).negotiate(&Hello::new(1, 7), 1).unwrap(),
+        WIRE_VERSION
+    );
+
+    let ancient = Hello {
+        min_version: 1,
+        max_version: 1,
+        ..older
+    };
+    assert!(matches!(
+        ours.negotiate(&ancient, 1),
+        Err(HandshakeError::IncompatibleVersion {
+            ours: (2, 4),
+            theirs: (1, 1)
+        })
+    ));
+    assert!(matches!(
+        ours.negotiate(&older, 2),
+        Err(HandshakeError::WrongNode {
+            expected: 2,
+            announced: 1
+        })
+    ));
+    let elsewhere = Hello {
+        cluster_id: 8,
+        ..older
+    };
+    assert!(matches!(
+        ours.negotiate(&elsewhere, 1),
+        Err(HandshakeError::WrongCluster { ours: 7, theirs: 8 })
+    ));
+}
+
+#[tokio::test]
+async fn replicas_become_ready_as_peers_appear() {
+    install_crypto_provider();
+    let keys = keys(3);
+    let networks: Vec<Network> = (0..3).map(|id| network(id, &keys)).collect();
+    for network in &networks {
+        network.spawn_acceptor();
+    }
+
+    // Only replicas 0 and 1 know about each other at first.
+    networks[0].add_peer(1, networks[1].local_addr().unwrap());
+    networks[1].add_peer(0, networks[0].local_addr().unwrap());
+    tokio::time::timeout(Duration::from_secs(5), networks[0].wait_for_peers(1))
+        .await
+        .expect("replica 1 never completed the handshake");
+    assert_eq!(networks[0].connected_peers().await, vec![1]);
+
+    // A late replica is picked up without restarting anything.
+    networks[0].add_peer(2, networks[2].local_addr().unwrap());
+    tokio::time::timeout(Duration::from_secs(5), networks[0].wait_for_peers(2))
+        .await
+        .expect("late replica 2 never joined");
+    let mut peers = networks[0].connected_peers().await;
+    peers.sort();
+    assert_eq!(peers, vec![1, 2]);
+}
+
+/// Connects to `replica` as replica `id`, without the `Network` handshake.
+async fn dial_as(id: u32, replica: &Network, keys: &Keys) -> (Endpoint, Connection) {
+    let cert = NodeCert::from_pkcs8(replica_server_name(id), &keys.pkcs8[id as usize]);
+    let mut endpoint = Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+    endpoint.set_default_client_config(quinn::ClientConfig::new(Arc::new(
+        quinn::crypto::rustls::QuicClientConfig::try_from(make_client_config(
+            &cert,
+            keys.pinned.clone(),
+        ))
+        .unwrap(),
+    )));
+    let connection = endpoint
+        .connect(replica.local_addr().unwrap(), &replica_server_name(0))
+        .unwrap()
+        .await
+        .unwrap();
+    (endpoint, connection)
+}
+
+#[tokio::test]
+async fn newer_peer_falls_back_to_our_version() {
+    install_crypto_provider();
+    let keys = keys(2);
+    let mut replica = network(0, &keys);
+    replica.spawn_acceptor();
+
+    // Replica 1 running a build that also knows the next wire version.
+    let (_endpoint, connection) = dial_as(1, &replica, &keys).await;
+    let (mut send, mut recv) = connection.open_bi().await.unwrap();
+    let newer = Hello {
+        min_version: MIN_WIRE_VERSION,
+        max_version: WIRE_VERSION + 1,
+        ..Hello::new(1, 0)
+    };
+    write_hello(&mut send, &newer).await.unwrap();
+    send.finish().unwrap();
+
+    let answer = read_hello(&mut recv).await.unwrap();
+    assert_eq!(answer, Hello::new(0, 0));
+    let version = newer.negotiate(&answer, 0).unwrap();
+    assert_eq!(version, WIRE_VERSION);
+    assert_eq!(answer.negotiate(&newer, 1).unwrap(), version);
+
+    // Frames at the agreed version get through.
+    let prepare = PBFTMessage::Prepare(SignedMessage {
+        message: Prepare {
+            view: 0,
+            seq_num: 1,
+            digest: [7u8; 32],
+            replica_id: 1,
+        },
+        signature: vec![0u8; 64],
+        signer_id: 1,
+    });
+    let mut stream = connection.open_uni().await.unwrap();
+    let envelope = Envelope {
+        version,
+        ..Envelope::new(0)
+    };
+    write_frame(&mut stream, envelope, &prepare).await.unwrap();
+    stream.finish().unwrap();
+    let received = tokio::time::timeout(Duration::from_secs(5), replica.recv())
+        .await
+        .expect("frame at the negotiated version was not delivered")
+        .unwrap();
+    assert!(matches!(received, PBFTMessage::Prepare(p) if p.message.seq_num == 1));
+}
+
+#[tokio::test]
+async fn incompatible_peer_is_rejected() {
+    install_crypto_provider();
+    let keys = keys(2);
+    let replica = network(0, &keys);
+    replica.spawn_acceptor();
+
+    // Replica 1 only speaking wire versions replica 0 does not know yet.
+    let (_endpoint, connection) = dial_as(1, &replica, &keys).await;
+
+    let (mut send, mut recv) = connection.open_bi().await.unwrap();
+    let hello = Hello {
+        min_version: WIRE_VERSION + 1,
+        max_version: WIRE_VERSION + 2,
+        ..Hello::new(1, 0)
+    };
+    write_hello(&mut send, &hello).await.unwrap();
+    send.finish().unwrap();
+
+    assert!(read_hello(&mut recv).await.is_err());
+    match tokio::time::timeout(Duration::from_secs(5), connection.closed())
+        .await
+        .unwrap()
+    {
+        ConnectionError::ApplicationClosed(close) => {
+            assert_eq!(close.error_code, HANDSHAKE_FAILED.into());
+            assert!(String::from_utf8_lossy(&close.reason).contains("incompatible"));
+        }
+        other => panic!("unexpected close: {:?}", other),
+    }
+}
@@ -0,0 +1,108 @@
+use simple_pbft_demo::{
+    message::message_types::{PBFTMessage, Prepare, SignedMessage},
+    metrics::registry::Metrics,
+    network::{
+        cert::PeerIdentity,
+        inbound::{InboundConfig, InboundQueues, PushOutcome, QueueStats},
+    },
+};
+use std::sync::Arc;
+
+const A: PeerIdentity = PeerIdentity::Replica(1);
+const B: PeerIdentity = PeerIdentity::Replica(2);
+const C: PeerIdentity = PeerIdentity::Client(100);
+
+fn message(seq_num: u64) -> PBFTMessage {
+    PBFTMessage::Prepare(SignedMessage {
+        message: Prepare {
+            view: 0,
+            seq_num,
+            digest: [0u8; 32],
+            replica_id: 1,
+        },
+        signature: vec![0u8; 64],
+        signer_id: 1,
+    })
+}
+
+fn seq_num(message: PBFTMessage) -> u64 {
+    match message {
+        PBFTMessage::Prepare(p) => p.message.seq_num,
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+fn queues(queue_capacity: usize, max_consecutive_drops: u64) -> (InboundQueues, Arc<Metrics>) {
+    let metrics = Arc::new(Metrics::new());
+    let config = InboundConfig {
+        queue_capacity,
+        max_consecutive_drops,
+        ..InboundConfig::default()
+    };
+    (InboundQueues::new(config, metrics.clone()), metrics)
+}
+
+fn drain(queues: &InboundQueues) -> Vec<u64> {
+    std::iter::from_fn(|| queues.try_recv())
+        .map(seq_num)
+        .collect()
+}
+
+#[test]
+fn peers_are_served_round_robin() {
+    let (queues, _) = queues(16, 16);
+    // A floods before B and C get a word in.
+    for seq in 1..=4 {
+        queues.push(A, message(seq));
+    }
+    queues.push(B, message(10));
+    queues.push(B, message(11));
+    queues.push(C, message(20));
+
+    assert_eq!(drain(&queues), vec![1, 10, 20, 2, 11, 3, 4]);
+}
+
+#[test]
+fn full_queue_drops_the_newest_message() {
+    let (queues, metrics) = queues(2, 16);
+    assert_eq!(queues.push(A, message(1)), PushOutcome::Queued);
+    assert_eq!(queues.push(A, message(2)), PushOutcome::Queued);
+    assert_eq!(queues.push(A, message(3)), PushOutcome::Dropped);
+    // Another peer's queue is unaffected.
+    assert_eq!(queues.push(B, message(10)), PushOutcome::Queued);
+
+    let full = QueueStats {
+        depth: 2,
+        high_water: 2,
+        dropped: 1,
+    };
+    assert_eq!(queues.stats()[&A], full);
+    assert_eq!(metrics.queue_stats()[0], (A, full));
+
+    assert_eq!(drain(&queues), vec![1, 10, 2]);
+    assert_eq!(queues.stats()[&A].depth, 0);
+    assert_eq!(metrics.queue_stats()[0].1.depth, 0);
+}
+
+#[test]
+fn peer_that_keeps_overflowing_is_disconnected() {
+    let (queues, metrics) = queues(1, 3);
+    assert_eq!(queues.push(A, message(1)), PushOutcome::Queued);
+    assert_eq!(queues.push(A, message(2)), PushOutcome::Dropped);
+    assert_eq!(queues.push(A, message(3)), PushOutcome::Dropped);
+    assert_eq!(queues.push(A, message(4)), PushOutcome::Disconnect);
+
+    // Room again resets the count of drops in a row.
+    assert_eq!(drain(&queues), vec![1]);
+    assert_eq!(queues.push(A, message(5)), PushOutcome::Queued);
+    assert_eq!(queues.push(A, message(6)), PushOutcome::Dropped);
+    assert_eq!(queues.push(A, message(7)), PushOutcome::Dropped);
+    assert_eq!(queues.push(A, message(8)), PushOutcome::Disconnect);
+
+    assert!(
+        metrics
+            .render()
+            .lines()
+            .any(|line| line == "pbft_inbound_dropped_total{role=\"replica\",id=\"1\"} 6")
+    );
+}
@@ -0,0 +1,107 @@
+use simple_pbft_demo::state::{
+    events::{ReplicaEvent, format_event_line, parse_event_line},
+    invariants::{InvariantChecker, Violation},
+};
+
+fn committed(seq_num: u64, digest: u8) -> ReplicaEvent {
+    ReplicaEvent::Committed {
+        seq_num,
+        view: 0,
+        digest: [digest; 32],
+    }
+}
+
+fn executed(seq_num: u64, digest: u8) -> ReplicaEvent {
+    ReplicaEvent::Executed {
+        seq_num,
+        digest: [digest; 32],
+    }
+}
+
+#[test]
+fn agreeing_replicas_pass() {
+    let mut checker = InvariantChecker::new();
+    for node in 0..4 {
+        checker.observe(node, &committed(1, 7));
+        checker.observe(node, &executed(1, 7));
+        checker.observe(node, &ReplicaEvent::ViewEntered { view: 1 });
+        checker.observe(node, &committed(2, 8));
+        checker.observe(node, &executed(2, 8));
+    }
+    assert_eq!(checker.check(), Ok(()));
+}
+
+#[test]
+fn conflicting_commits_are_reported() {
+    let mut checker = InvariantChecker::new();
+    checker.observe(0, &committed(1, 7));
+    checker.observe(1, &committed(1, 9));
+
+    assert!(matches!(
+        checker.violations(),
+        [Violation::ConflictingCommit { seq_num: 1, .. }]
+    ));
+}
+
+#[test]
+fn divergent_or_gapped_execution_is_reported() {
+    let mut checker = InvariantChecker::new();
+    checker.observe(0, &committed(1, 7));
+    checker.observe(0, &executed(1, 7));
+    checker.observe(1, &committed(1, 9));
+    checker.observe(1, &executed(1, 9));
+    checker.observe(2, &committed(3, 7));
+    checker.observe(2, &executed(3, 7));
+    checker.observe(3, &executed(1, 7));
+
+    let violations = checker.violations();
+    assert!(
+        violations
+            .iter()
+            .any(|v| matches!(v, Violation::DivergentExecution { seq_num: 1, .. }))
+    );
+    assert!(violations.iter().any(|v| matches!(
+        v,
+        Violation::ExecutionGap {
+            node_id: 2,
+            expected: 1,
+            got: 3
+        }
+    )));
+    assert!(violations.iter().any(|v| matches!(
+        v,
+        Violation::ExecutedUncommitted {
+            node_id: 3,
+            seq_num: 1
+        }
+    )));
+}
+
+#[test]
+fn view_going_backwards_is_reported() {
+    let mut checker = InvariantChecker::new();
+    checker.observe(0, &ReplicaEvent::ViewEntered { view: 2 });
+    checker.observe(0, &ReplicaEvent::ViewEntered { view: 1 });
+
+    assert_eq!(
+        checker.violations(),
+        [Violation::ViewRegressed {
+            node_id: 0,
+            from: 2,
+            to: 1
+        }]
+    );
+}
+
+#[test]
+fn event_lines_round_trip() {
+    for event in [
+        ReplicaEvent::ViewEntered { view: 3 },
+        committed(5, 0xab),
+        executed(5, 0xab),
+    ] {
+        let line = format_event_line(2, &event);
+        assert_eq!(parse_event_line(&line), Ok((2, event)));
+    }
+    assert!(parse_event_line("2 committed 5").is_err());
+}
@@ -0,0 +1,238 @@
+mod common;
+
+use common::{CLIENT_ID, crypto, generate_keys, membership, replicas, run_until_quiet, temp_path};
+use simple_pbft_demo::{
+    crypto::primitives::Crypto,
+    message::message_types::{LedgerEntry, PBFTMessage, Request},
+    network::transport::MemoryNetwork,
+    sim::simulator::{NetworkFaults, SEED_ENV, SimConfig, Simulator, seed_from_env},
+    state::{
+        certificates::FileCertificateStore,
+        ledger::{FileLedger, GENESIS_HASH, LedgerStore, verify_chain},
+        replica::Replica,
+    },
+};
+use std::{
+    fs::OpenOptions,
+    io::{ErrorKind, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+fn chain(len: u64) -> Vec<LedgerEntry> {
+    let mut entries: Vec<LedgerEntry> = Vec::new();
+    for seq_num in 1..=len {
+        let prev_hash = entries.last().map_or(GENESIS_HASH, |e| e.hash());
+        entries.push(LedgerEntry {
+            seq_num,
+            view: 0,
+            request_digest: [seq_num as u8; 32],
+            result_digest: [1; 32],
+            prev_hash,
+            state_digest: [2; 32],
+        });
+    }
+    entries
+}
+
+#[test]
+fn correct_replicas_build_the_same_chain() {
+    let seed = seed_from_env().unwrap_or(40);
+    let config = SimConfig {
+        faults: NetworkFaults {
+            drop_probability: 0.05,
+            ..NetworkFaults::default()
+        },
+        ..SimConfig::with_seed(seed)
+    };
+    let mut sim = Simulator::new(config);
+    while sim.step() {}
+
+    let chains: Vec<Vec<LedgerEntry>> = sim
+        .replicas()
+        .map(|r| {
+            (1..=r.last_executed())
+                .map(|s| r.ledger_entry(s).expect("every executed seq has an entry"))
+                .collect()
+        })
+        .collect();
+
+    let longest = chains.iter().max_by_key(|c| c.len()).unwrap();
+    assert!(!longest.is_empty());
+    for chain in &chains {
+        assert_eq!(verify_chain(chain), Ok(()));
+        for (entry, other) in chain.iter().zip(longest) {
+            assert_eq!(
+                entry.hash(),
+                other.hash(),
+                "replay with {}={}",
+                SEED_ENV,
+                seed
+            );
+        }
+    }
+}
+
+#[test]
+fn view_is_not_part_of_the_hash() {
+    let entry = chain(1).remove(0);
+    let later_view = LedgerEntry {
+        view: 3,
+        ..entry.clone()
+    };
+    assert_eq!(entry.hash(), later_view.hash());
+
+    let other_result = LedgerEntry {
+        result_digest: [9; 32],
+        ..entry.clone()
+    };
+    assert_ne!(entry.hash(), other_result.hash());
+}
+
+#[test]
+fn rewritten_entry_breaks_the_chain() {
+    let mut entries = chain(5);
+    assert_eq!(verify_chain(&entries), Ok(()));
+
+    entries[2].request_digest = [0xff; 32];
+    assert_eq!(verify_chain(&entries), Err(4));
+
+    // Re-linking the next entry only moves the break along.
+    entries[3].prev_hash = entries[2].hash();
+    assert_eq!(verify_chain(&entries), Err(5));
+
+    let mut forged_start = chain(2);
+    forged_start[0].prev_hash = [7; 32];
+    assert_eq!(verify_chain(&forged_start), Err(1));
+}
+
+fn write_ledger(path: &Path, entries: &[LedgerEntry]) {
+    let mut ledger = FileLedger::open(path).unwrap();
+    for entry in entries {
+        ledger.append(entry);
+    }
+}
+
+#[test]
+fn file_ledger_survives_reopening() {
+    let path = temp_path("ledger", "ledger.bin");
+
+    let entries = chain(3);
+    let mut ledger = FileLedger::open(&path).unwrap();
+    for entry in &entries {
+        ledger.append(entry);
+    }
+    drop(ledger);
+
+    let ledger = FileLedger::open(&path).unwrap();
+    let reopened: Vec<LedgerEntry> = (1..=3).map(|s| ledger.get(s).unwrap()).collect();
+    assert_eq!(reopened, entries);
+    assert_eq!(ledger.get(4), None);
+}
+
+#[test]
+fn torn_record_at_the_end_is_truncated() {
+    let path = temp_path("ledger", "torn.bin");
+    let entries = chain(2);
+    write_ledger(&path, &entries);
+    let len = std::fs::metadata(&path).unwrap().len();
+
+    let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(&[0, 0, 1, 0, 42]).unwrap();
+    drop(file);
+
+    let ledger = FileLedger::open(&path).unwrap();
+    assert_eq!(ledger.get(2), Some(entries[1].clone()));
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), len);
+}
+
+#[test]
+fn corruption_before_the_end_is_an_error() {
+    let path = temp_path("ledger", "corrupt.bin");
+    let entries = chain(3);
+    write_ledger(&path, &entries);
+    let len = std::fs::metadata(&path).unwrap().len();
+
+    // Shrink the first record's length so its body no longer decodes.
+    let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+    file.seek(SeekFrom::Start(0)).unwrap();
+    file.write_all(&1u32.to_be_bytes()).unwrap();
+    drop(file);
+
+    let err = FileLedger::open(&path)
+        .err()
+        .expect("corruption was ignored");
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+    // Nothing after the damage was thrown away.
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), len);
+}
+
+#[test]
+fn oversized_length_is_an_error() {
+    let path = temp_path("ledger", "oversized.bin");
+    write_ledger(&path, &chain(1));
+
+    let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(&u32::MAX.to_be_bytes()).unwrap();
+    file.write_all(&[0; 64]).unwrap();
+    drop(file);
+
+    let err = FileLedger::open(&path).err().expect("length was trusted");
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+}
+
+#[tokio::test]
+async fn restarted_replica_recovers_from_its_data_directory() {
+    const RESTARTED: u32 = 1;
+    let certificates_path = temp_path("ledger", "restart-certificates.bin");
+    let ledger_path = temp_path("ledger", "restart-ledger.bin");
+    let replica_keys = generate_keys(4);
+    let client = crypto(CLIENT_ID, &Crypto::generate_keypair());
+    let membership = membership(&replica_keys, &[(CLIENT_ID, &client)]);
+    let start = |replica: &mut Replica| {
+        replica.set_certificate_store(Box::new(
+            FileCertificateStore::open(&certificates_path).unwrap(),
+        ));
+        replica.set_ledger(Box::new(FileLedger::open(&ledger_path).unwrap()));
+    };
+
+    let network = MemoryNetwork::new();
+    let mut replicas = replicas(&network, &membership, &replica_keys);
+    start(&mut replicas[RESTARTED as usize].0);
+    let send = |timestamp: u64| {
+        let request = Request {
+            operation: format!("PUT:k{}:{}", timestamp, timestamp).into_bytes(),
+            timestamp,
+            client_id: CLIENT_ID as u64,
+        };
+        network.send(
+            0,
+            PBFTMessage::Request(client.create_signed_message(request)),
+        );
+    };
+    for timestamp in 1..=3 {
+        send(timestamp);
+        run_until_quiet(&mut replicas).await;
+    }
+
+    let mut restarted = Replica::new(
+        RESTARTED,
+        membership.clone(),
+        crypto(RESTARTED, &replica_keys[RESTARTED as usize]),
+    );
+    start(&mut restarted);
+    let (crashed, transport) = &mut replicas[RESTARTED as usize];
+    assert_eq!(restarted.recover(transport).await, 3);
+    assert_eq!(restarted.prove("k3"), crashed.prove("k3"));
+    *crashed = restarted;
+
+    // It carries on from there rather than from the start.
+    send(4);
+    run_until_quiet(&mut replicas).await;
+    let restarted = &replicas[RESTARTED as usize].0;
+    assert_eq!(restarted.last_executed(), 4);
+    let entries: Vec<LedgerEntry> = (1..=4)
+        .map(|seq_num| restarted.ledger_entry(seq_num).unwrap())
+        .collect();
+    assert_eq!(verify_chain(&entries), Ok(()));
+    assert_eq!(entries[3], replicas[0].0.ledger_entry(4).unwrap());
+}
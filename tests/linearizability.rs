@@ -0,0 +1,141 @@
+use simple_pbft_demo::client::{
+    history::{HistoryEntry, KvOp, KvResult},
+    linearizability::check,
+};
+
+fn put(key: &str, value: &str) -> KvOp {
+    KvOp::Put {
+        key: key.to_string(),
+        value: value.to_string(),
+    }
+}
+
+fn get(key: &str) -> KvOp {
+    KvOp::Get {
+        key: key.to_string(),
+    }
+}
+
+fn cas(key: &str, expected: &str, new: &str) -> KvOp {
+    KvOp::Cas {
+        key: key.to_string(),
+        expected: expected.to_string(),
+        new: new.to_string(),
+    }
+}
+
+fn value(v: &str) -> KvResult {
+    KvResult::Value(Some(v.to_string()))
+}
+
+fn done(
+    client_id: u32,
+    op: KvOp,
+    invoke_us: u64,
+    complete_us: u64,
+    result: KvResult,
+) -> HistoryEntry {
+    HistoryEntry {
+        client_id,
+        op,
+        invoke_us,
+        complete: Some((complete_us, result)),
+    }
+}
+
+fn timed_out(client_id: u32, op: KvOp, invoke_us: u64) -> HistoryEntry {
+    HistoryEntry {
+        client_id,
+        op,
+        invoke_us,
+        complete: None,
+    }
+}
+
+#[test]
+fn sequential_history_passes() {
+    let history = vec![
+        done(1, get("k"), 0, 1, KvResult::Value(None)),
+        done(1, put("k", "a"), 2, 3, KvResult::Ok),
+        done(1, cas("k", "a", "b"), 4, 5, KvResult::Ok),
+        done(1, cas("k", "a", "c"), 6, 7, KvResult::CasFailed),
+        done(1, get("k"), 8, 9, value("b")),
+    ];
+    assert!(check(&history).is_ok());
+}
+
+#[test]
+fn concurrent_writes_may_take_effect_in_either_order() {
+    let history = vec![
+        done(1, put("k", "a"), 0, 10, KvResult::Ok),
+        done(2, put("k", "b"), 1, 9, KvResult::Ok),
+        done(3, get("k"), 11, 12, value("a")),
+        done(3, get("k"), 13, 14, value("a")),
+    ];
+    assert!(check(&history).is_ok());
+}
+
+#[test]
+fn stale_read_is_rejected() {
+    let history = vec![
+        done(1, put("k", "a"), 0, 1, KvResult::Ok),
+        done(1, put("k", "b"), 2, 3, KvResult::Ok),
+        done(2, get("k"), 4, 5, value("a")),
+    ];
+    let err = check(&history).unwrap_err();
+    assert_eq!(err.key, "k");
+    assert_eq!(err.entries.len(), 3);
+}
+
+#[test]
+fn two_successful_cas_from_the_same_value_are_rejected() {
+    let history = vec![
+        done(1, put("k", "a"), 0, 1, KvResult::Ok),
+        done(2, cas("k", "a", "b"), 2, 6, KvResult::Ok),
+        done(3, cas("k", "a", "c"), 3, 7, KvResult::Ok),
+    ];
+    assert!(check(&history).is_err());
+}
+
+#[test]
+fn operation_without_result_may_or_may_not_take_effect() {
+    let took_effect = vec![
+        timed_out(1, put("k", "a"), 0),
+        done(2, get("k"), 5, 6, value("a")),
+    ];
+    assert!(check(&took_effect).is_ok());
+
+    let never_happened = vec![
+        timed_out(1, put("k", "a"), 0),
+        done(2, get("k"), 5, 6, KvResult::Value(None)),
+    ];
+    assert!(check(&never_happened).is_ok());
+
+    // It cannot take effect before it was invoked.
+    let too_early = vec![
+        done(2, get("k"), 0, 1, value("a")),
+        timed_out(1, put("k", "a"), 2),
+    ];
+    assert!(check(&too_early).is_err());
+}
+
+#[test]
+fn keys_are_checked_independently() {
+    let history = vec![
+        done(1, put("x", "a"), 0, 1, KvResult::Ok),
+        done(2, put("y", "b"), 0, 1, KvResult::Ok),
+        done(1, get("y"), 2, 3, value("b")),
+        done(2, get("x"), 2, 3, value("b")),
+    ];
+    assert_eq!(check(&history).unwrap_err().key, "x");
+}
+
+#[test]
+fn operations_round_trip_through_app_state_format() {
+    let op = cas("k", "a", "b");
+    assert_eq!(op.to_operation(), b"CAS:k:a:b".to_vec());
+    assert_eq!(op.parse_result(b"OK"), KvResult::Ok);
+    assert_eq!(op.parse_result(b"CAS_FAILED"), KvResult::CasFailed);
+    assert_eq!(get("k").parse_result(b"NOT_FOUND"), KvResult::Value(None));
+    assert_eq!(get("k").parse_result(b"v"), value("v"));
+}
@@ -0,0 +1,83 @@
+use simple_pbft_demo::config::{
+    logging::{LOG_FORMAT_ENV, LOG_LEVEL_ENV, LogFormat, LoggingConfig},
+    node::ClusterConfig,
+};
+
+fn parse(toml: &str) -> ClusterConfig {
+    toml::from_str(toml).unwrap()
+}
+
+#[test]
+fn logging_section_is_optional() {
+    let cluster = parse(
+        r#"
+        replicas = [{ id = 0, addr = "127.0.0.1:5000" }]
+        "#,
+    );
+    assert_eq!(cluster.logging, LoggingConfig::default());
+    assert_eq!(cluster.logging.level, "info");
+    assert_eq!(cluster.logging.format, LogFormat::Text);
+}
+
+#[test]
+fn logging_level_and_format_are_read_from_cluster_config() {
+    let cluster = parse(
+        r#"
+        replicas = [{ id = 0, addr = "127.0.0.1:5000" }]
+
+        [logging]
+        level = "debug,simple_pbft_demo::network=warn"
+        format = "json"
+        "#,
+    );
+    assert_eq!(
+        cluster.logging.level,
+        "debug,simple_pbft_demo::network=warn"
+    );
+    assert_eq!(cluster.logging.format, LogFormat::Json);
+}
+
+#[test]
+fn environment_overrides_cluster_config() {
+    let file = LoggingConfig {
+        level: "debug".to_string(),
+        format: LogFormat::Json,
+    };
+
+    // SAFETY: no other test in this binary reads or writes the environment.
+    unsafe {
+        std::env::set_var(LOG_LEVEL_ENV, "warn,simple_pbft_demo::network=trace");
+        std::env::set_var(LOG_FORMAT_ENV, "text");
+    }
+    let overridden = file.clone().from_env();
+    assert_eq!(overridden.level, "warn,simple_pbft_demo::network=trace");
+    assert_eq!(overridden.format, LogFormat::Text);
+
+    // An unknown format is ignored rather than guessed at.
+    unsafe {
+        std::env::remove_var(LOG_LEVEL_ENV);
+        std::env::set_var(LOG_FORMAT_ENV, "yaml");
+    }
+    assert_eq!(file.clone().from_env(), file);
+
+    unsafe { std::env::remove_var(LOG_FORMAT_ENV) };
+    assert_eq!(file.clone().from_env(), file);
+}
+
+#[test]
+fn invalid_level_directives_are_rejected() {
+    let config = |level: &str| LoggingConfig {
+        level: level.to_string(),
+        ..LoggingConfig::default()
+    };
+    assert!(
+        config("info,simple_pbft_demo::network=debug")
+            .filter()
+            .is_ok()
+    );
+
+    let err = config("info,simple_pbft_demo::network=loud")
+        .filter()
+        .expect_err("bad level accepted");
+    assert!(err.contains("simple_pbft_demo::network=loud"), "{}", err);
+}
@@ -0,0 +1,138 @@
+use simple_pbft_demo::{
+    config::membership::Membership,
+    message::message_types::{ClientInfo, Reconfigure, ReplicaInfo},
+};
+
+fn replica(id: u32) -> ReplicaInfo {
+    ReplicaInfo {
+        id,
+        public_key: vec![id as u8; 32],
+        addr: format!("127.0.0.1:{}", 5000 + id).parse().unwrap(),
+    }
+}
+
+fn client(id: u32) -> ClientInfo {
+    ClientInfo {
+        id,
+        public_key: vec![id as u8; 32],
+    }
+}
+
+fn membership(n: u32) -> Membership {
+    Membership::new((0..n).map(replica).collect(), vec![client(100)]).unwrap()
+}
+
+#[test]
+fn new_rejects_ids_listed_twice() {
+    assert!(Membership::new(vec![replica(0), replica(1), replica(1)], Vec::new()).is_err());
+    assert!(Membership::new((0..4).map(replica).collect(), vec![client(7), client(7)]).is_err());
+    assert!(Membership::new((0..4).map(replica).collect(), vec![client(2)]).is_err());
+}
+
+#[test]
+fn new_rejects_too_few_replicas() {
+    for n in 0..4 {
+        let err = Membership::new((0..n).map(replica).collect(), vec![client(100)]).unwrap_err();
+        assert!(err.contains("cannot tolerate a fault"), "{}", err);
+    }
+    assert_eq!(membership(4).f(), 1);
+}
+
+#[test]
+fn apply_bumps_the_epoch_and_resizes_quorums() {
+    let initial = membership(4);
+    assert_eq!((initial.epoch(), initial.f(), initial.quorum()), (0, 1, 3));
+
+    let grown = initial
+        .apply(&Reconfigure {
+            add: (4..7).map(replica).collect(),
+            ..Reconfigure::default()
+        })
+        .unwrap();
+    assert_eq!(grown.epoch(), 1);
+    assert_eq!((grown.total_nodes(), grown.f(), grown.quorum()), (7, 2, 5));
+    assert_eq!(grown.primary(6), 6);
+    assert_eq!(grown.peer_public_keys(0).len(), 6);
+
+    let replaced = grown
+        .apply(&Reconfigure {
+            remove: vec![0, 1, 2],
+            add: vec![replica(9)],
+            ..Reconfigure::default()
+        })
+        .unwrap();
+    assert_eq!(replaced.epoch(), 2);
+    assert_eq!((replaced.total_nodes(), replaced.quorum()), (5, 4));
+    assert!(!replaced.contains(0) && replaced.contains(9));
+    assert_eq!(replaced.primary(0), 3);
+
+    // The original is left as it was.
+    assert_eq!((initial.epoch(), initial.total_nodes()), (0, 4));
+}
+
+#[test]
+fn apply_rejects_invalid_changes() {
+    let current = membership(4);
+    let rejected = |reconfigure: Reconfigure| current.apply(&reconfigure).unwrap_err();
+
+    assert!(
+        rejected(Reconfigure {
+            remove: vec![9],
+            ..Reconfigure::default()
+        })
+        .contains("not a member")
+    );
+    assert!(
+        rejected(Reconfigure {
+            add: vec![replica(3)],
+            ..Reconfigure::default()
+        })
+        .contains("already a member")
+    );
+    assert!(
+        rejected(Reconfigure {
+            remove: vec![3],
+            ..Reconfigure::default()
+        })
+        .contains("cannot tolerate a fault")
+    );
+    assert!(
+        rejected(Reconfigure {
+            add_clients: vec![client(1)],
+            ..Reconfigure::default()
+        })
+        .contains("already registered")
+    );
+    assert!(
+        rejected(Reconfigure {
+            add: vec![replica(100)],
+            ..Reconfigure::default()
+        })
+        .contains("registered as a client")
+    );
+}
+
+#[test]
+fn admins_must_be_registered_and_survive_reconfiguration() {
+    let mut initial = membership(4);
+    initial.set_admins([100, 7]);
+    assert!(initial.is_admin(100));
+    assert!(!initial.is_admin(7), "not a registered client");
+    assert!(!initial.is_admin(0), "replicas are not admins");
+
+    let next = initial
+        .apply(&Reconfigure {
+            add_clients: vec![client(7)],
+            ..Reconfigure::default()
+        })
+        .unwrap();
+    assert!(next.is_admin(100) && next.is_admin(7));
+
+    let removed = next
+        .apply(&Reconfigure {
+            remove_clients: vec![100],
+            ..Reconfigure::default()
+        })
+        .unwrap();
+    assert!(!removed.is_admin(100));
+}
@@ -0,0 +1,208 @@
+mod common;
+
+use common::{CLIENT_ID, crypto, generate_keys, membership, replicas, run_until_quiet};
+use simple_pbft_demo::{
+    crypto::primitives::Crypto,
+    message::message_types::{PBFTMessage, Reconfigure, ReplicaInfo, Request},
+    metrics::registry::Metrics,
+    network::transport::{MemoryNetwork, MemoryTransport},
+    state::replica::Replica,
+};
+use std::sync::{Arc, atomic::Ordering};
+
+fn cluster(n: u32) -> (MemoryNetwork, Vec<(Replica, MemoryTransport)>, Crypto) {
+    cluster_with_admins(n, &[])
+}
+
+fn cluster_with_admins(
+    n: u32,
+    admins: &[u32],
+) -> (MemoryNetwork, Vec<(Replica, MemoryTransport)>, Crypto) {
+    let replica_keys = generate_keys(n);
+    let client = crypto(CLIENT_ID, &Crypto::generate_keypair());
+    let mut membership = membership(&replica_keys, &[(CLIENT_ID, &client)]);
+    membership.set_admins(admins.iter().copied());
+
+    let network = MemoryNetwork::new();
+    let replicas = replicas(&network, &membership, &replica_keys);
+    (network, replicas, client)
+}
+
+#[tokio::test]
+async fn four_replicas_execute_a_request_in_process() {
+    let (network, mut replicas, client) = cluster(4);
+
+    let request = Request {
+        operation: b"PUT:name:Alice".to_vec(),
+        timestamp: 1,
+        client_id: CLIENT_ID as u64,
+    };
+    network.send(
+        0,
+        PBFTMessage::Request(client.create_signed_message(request)),
+    );
+
+    run_until_quiet(&mut replicas).await;
+
+    for (replica, _) in &replicas {
+        assert_eq!(replica.last_executed(), 1, "replica {}", replica.node_id());
+    }
+}
+
+#[tokio::test]
+async fn executed_requests_carry_verifiable_commit_certificates() {
+    let (network, mut replicas, client) = cluster(4);
+
+    let request = Request {
+        operation: b"PUT:name:Alice".to_vec(),
+        timestamp: 1,
+        client_id: CLIENT_ID as u64,
+    };
+    network.send(
+        0,
+        PBFTMessage::Request(client.create_signed_message(request.clone())),
+    );
+    run_until_quiet(&mut replicas).await;
+
+    let membership = replicas[0].0.membership().clone();
+    let keys = membership.replica_public_keys();
+    let quorum = membership.quorum() as usize;
+
+    for (replica, _) in &replicas {
+        let entry = replica.certificate(1).expect("certificate for seq 1");
+        assert_eq!(entry.request, request);
+        assert_eq!(entry.certificate.signatures.len(), quorum);
+        assert!(entry.verify(&keys, quorum), "replica {}", replica.node_id());
+        assert!(replica.certificate(2).is_none());
+
+        let mut altered = entry.clone();
+        altered.request.operation = b"PUT:name:Mallory".to_vec();
+        assert!(!altered.verify(&keys, quorum));
+
+        let mut forged = entry.clone();
+        forged.certificate.signatures[0].signature[0] ^= 0xff;
+        assert!(!forged.verify(&keys, quorum));
+
+        let mut repeated = entry.clone();
+        let first = repeated.certificate.signatures[0].clone();
+        repeated.certificate.signatures[1] = first;
+        assert!(!repeated.verify(&keys, quorum));
+    }
+}
+
+#[tokio::test]
+async fn replicas_report_ordering_progress_as_metrics() {
+    let (network, mut replicas, client) = cluster(4);
+    let metrics: Vec<Arc<Metrics>> = replicas
+        .iter_mut()
+        .map(|(replica, _)| {
+            let metrics = Arc::new(Metrics::new());
+            replica.set_metrics(metrics.clone());
+            metrics
+        })
+        .collect();
+
+    for timestamp in 1..=2 {
+        let request = Request {
+            operation: format!("PUT:k:{}", timestamp).into_bytes(),
+            timestamp,
+            client_id: CLIENT_ID as u64,
+        };
+        network.send(
+            0,
+            PBFTMessage::Request(client.create_signed_message(request)),
+        );
+        run_until_quiet(&mut replicas).await;
+    }
+
+    for metrics in &metrics {
+        assert_eq!(metrics.requests_ordered.load(Ordering::Relaxed), 2);
+        assert_eq!(metrics.requests_executed.load(Ordering::Relaxed), 2);
+        assert_eq!(metrics.last_executed.load(Ordering::Relaxed), 2);
+        assert_eq!(metrics.log_size.load(Ordering::Relaxed), 2);
+        assert_eq!(metrics.commit_latency.count(), 2);
+        assert_eq!(metrics.signature_failures.load(Ordering::Relaxed), 0);
+    }
+}
+
+#[tokio::test]
+async fn only_admins_can_reconfigure() {
+    let add_replica = Reconfigure {
+        add: vec![ReplicaInfo {
+            id: 4,
+            public_key: vec![4u8; 32],
+            addr: "127.0.0.1:5004".parse().unwrap(),
+        }],
+        ..Reconfigure::default()
+    };
+
+    for (admins, epoch, size) in [(&[][..], 0, 4), (&[CLIENT_ID][..], 1, 5)] {
+        let (network, mut replicas, client) = cluster_with_admins(4, admins);
+        let request = Request {
+            operation: add_replica.to_operation(),
+            timestamp: 1,
+            client_id: CLIENT_ID as u64,
+        };
+        network.send(
+            0,
+            PBFTMessage::Request(client.create_signed_message(request)),
+        );
+        run_until_quiet(&mut replicas).await;
+
+        for (replica, _) in &replicas {
+            // Ordered and executed either way, but only applied for an admin.
+            assert_eq!(replica.last_executed(), 1);
+            let membership = replica.membership();
+            assert_eq!(
+                (membership.epoch(), membership.total_nodes()),
+                (epoch, size),
+                "admins {:?}, replica {}",
+                admins,
+                replica.node_id()
+            );
+        }
+    }
+}
+
+#[tokio::test]
+async fn removing_the_primary_starts_a_new_view() {
+    let (network, mut replicas, client) = cluster_with_admins(5, &[CLIENT_ID]);
+    let remove_primary = Reconfigure {
+        remove: vec![0],
+        ..Reconfigure::default()
+    };
+    let request = Request {
+        operation: remove_primary.to_operation(),
+        timestamp: 1,
+        client_id: CLIENT_ID as u64,
+    };
+    network.send(
+        0,
+        PBFTMessage::Request(client.create_signed_message(request)),
+    );
+    run_until_quiet(&mut replicas).await;
+
+    // No timer runs here: the new primary must take over straight away and
+    // number requests after the reconfiguration.
+    let remaining = &replicas[1..];
+    for (replica, _) in remaining {
+        assert_eq!(replica.membership().epoch(), 1);
+        assert_eq!(replica.view(), 1, "replica {}", replica.node_id());
+    }
+    let primary = remaining[0].0.membership().primary(1);
+    assert_ne!(primary, 0);
+
+    let request = Request {
+        operation: b"PUT:k:v".to_vec(),
+        timestamp: 2,
+        client_id: CLIENT_ID as u64,
+    };
+    network.send(
+        primary,
+        PBFTMessage::Request(client.create_signed_message(request)),
+    );
+    run_until_quiet(&mut replicas).await;
+    for (replica, _) in &replicas[1..] {
+        assert_eq!(replica.last_executed(), 2, "replica {}", replica.node_id());
+    }
+}
@@ -0,0 +1,214 @@
+use simple_pbft_demo::{
+    message::message_types::MessageKind,
+    metrics::{
+        registry::Metrics,
+        server::{MetricsServerConfig, spawn_metrics_server},
+    },
+    network::framing::{FrameLimits, read_frame},
+};
+use std::{
+    sync::{Arc, atomic::Ordering},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+async fn get(addr: std::net::SocketAddr, request: &str) -> String {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+#[test]
+fn render_uses_prometheus_text_format() {
+    let metrics = Metrics::new();
+    metrics.messages_received.inc(MessageKind::Prepare);
+    metrics.messages_received.inc(MessageKind::Prepare);
+    metrics.messages_sent.inc(MessageKind::Commit);
+    metrics.view.store(3, Ordering::Relaxed);
+    metrics.set_peer_connected(1, true);
+    metrics.set_peer_connected(2, false);
+    metrics.remove_peer(2);
+
+    let text = metrics.render();
+    let lines: Vec<&str> = text.lines().collect();
+    for expected in [
+        "# TYPE pbft_messages_received_total counter",
+        "pbft_messages_received_total{kind=\"prepare\"} 2",
+        "pbft_messages_received_total{kind=\"commit\"} 0",
+        "pbft_messages_sent_total{kind=\"commit\"} 1",
+        "# TYPE pbft_view gauge",
+        "pbft_view 3",
+        "pbft_peer_connected{peer=\"1\"} 1",
+    ] {
+        assert!(
+            lines.contains(&expected),
+            "missing {:?} in\n{}",
+            expected,
+            text
+        );
+    }
+    assert!(!text.contains("peer=\"2\""));
+}
+
+#[test]
+fn latency_buckets_are_cumulative() {
+    let metrics = Metrics::new();
+    metrics.commit_latency.observe(Duration::from_millis(2));
+    metrics.commit_latency.observe(Duration::from_millis(30));
+    metrics.commit_latency.observe(Duration::from_secs(10));
+
+    let text = metrics.render();
+    let lines: Vec<&str> = text.lines().collect();
+    for expected in [
+        "pbft_commit_latency_seconds_bucket{le=\"0.001\"} 0",
+        "pbft_commit_latency_seconds_bucket{le=\"0.0025\"} 1",
+        "pbft_commit_latency_seconds_bucket{le=\"0.05\"} 2",
+        "pbft_commit_latency_seconds_bucket{le=\"5\"} 2",
+        "pbft_commit_latency_seconds_bucket{le=\"+Inf\"} 3",
+        "pbft_commit_latency_seconds_count 3",
+        "pbft_commit_latency_seconds_sum 10.032",
+    ] {
+        assert!(
+            lines.contains(&expected),
+            "missing {:?} in\n{}",
+            expected,
+            text
+        );
+    }
+}
+
+#[tokio::test]
+async fn rejected_frames_are_counted_by_reason() {
+    let metrics = Metrics::new();
+    let limits = FrameLimits::default();
+    let oversized = u32::MAX.to_be_bytes();
+    let truncated = [0u8, 0, 0, 16, 1];
+    for bytes in [&oversized[..], &truncated[..], &truncated[..]] {
+        let mut reader = bytes;
+        assert!(
+            read_frame(&mut reader, 0, &limits, &metrics.frames_rejected)
+                .await
+                .is_err()
+        );
+    }
+
+    let text = metrics.render();
+    let lines: Vec<&str> = text.lines().collect();
+    for expected in [
+        "# TYPE pbft_frames_rejected_total counter",
+        "pbft_frames_rejected_total{reason=\"oversized\"} 1",
+        "pbft_frames_rejected_total{reason=\"truncated\"} 2",
+        "pbft_frames_rejected_total{reason=\"malformed\"} 0",
+        "pbft_frames_rejected_total{reason=\"incompatible\"} 0",
+    ] {
+        assert!(
+            lines.contains(&expected),
+            "missing {:?} in\n{}",
+            expected,
+            text
+        );
+    }
+}
+
+#[tokio::test]
+async fn server_answers_scrapes() {
+    let metrics = Arc::new(Metrics::new());
+    let addr = spawn_metrics_server(
+        "127.0.0.1:0".parse().unwrap(),
+        metrics.clone(),
+        MetricsServerConfig::default(),
+    )
+    .await
+    .unwrap();
+    metrics.requests_executed.store(7, Ordering::Relaxed);
+
+    let response = get(addr, "GET /metrics HTTP/1.1\r\nHost: test\r\n\r\n").await;
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(response.contains("\npbft_requests_executed_total 7\n"));
+
+    let response = get(addr, "GET / HTTP/1.1\r\n\r\n").await;
+    assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    let response = get(addr, "POST /metrics HTTP/1.1\r\n\r\n").await;
+    assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+}
+
+async fn server(config: MetricsServerConfig) -> std::net::SocketAddr {
+    spawn_metrics_server(
+        "127.0.0.1:0".parse().unwrap(),
+        Arc::new(Metrics::new()),
+        config,
+    )
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn server_limits_concurrent_connections() {
+    let addr = server(MetricsServerConfig {
+        max_connections: 2,
+        ..MetricsServerConfig::default()
+    })
+    .await;
+
+    let mut idle = Vec::new();
+    for _ in 0..2 {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"GET /metrics").await.unwrap();
+        idle.push(stream);
+    }
+    let response = get(addr, "GET /metrics HTTP/1.1\r\n\r\n").await;
+    assert!(
+        response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"),
+        "{}",
+        response
+    );
+
+    // Hanging up frees the slot.
+    drop(idle.pop());
+    let mut response = String::new();
+    for _ in 0..50 {
+        response = get(addr, "GET /metrics HTTP/1.1\r\n\r\n").await;
+        if response.starts_with("HTTP/1.1 200 OK\r\n") {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+}
+
+#[tokio::test]
+async fn slow_request_head_times_out() {
+    let addr = server(MetricsServerConfig {
+        header_timeout: Duration::from_millis(300),
+        ..MetricsServerConfig::default()
+    })
+    .await;
+
+    // Each byte arrives well within the timeout, the whole head doesn't.
+    let (mut reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+    let started = tokio::time::Instant::now();
+    tokio::spawn(async move {
+        for byte in b"GET /metrics HTTP/1.1\r\nX: "
+            .iter()
+            .chain(std::iter::repeat(&b'x'))
+        {
+            if writer.write_all(&[*byte]).await.is_err() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    });
+    let mut response = String::new();
+    reader.read_to_string(&mut response).await.unwrap();
+    assert!(
+        response.starts_with("HTTP/1.1 408 Request Timeout\r\n"),
+        "{}",
+        response
+    );
+    assert!(started.elapsed() < Duration::from_secs(2));
+}
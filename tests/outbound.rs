@@ -0,0 +1,160 @@
+mod common;
+
+use common::install_crypto_provider;
+use simple_pbft_demo::{
+    crypto::primitives::Crypto,
+    message::message_types::{MessageKind, PBFTMessage, Prepare, SignedMessage},
+    network::{
+        cert::{NodeCert, PinnedKeys, replica_server_name},
+        network_layer::{Network, NetworkConfig},
+        outbound::OutboundConfig,
+    },
+};
+use std::{collections::HashMap, time::Duration};
+
+const MESSAGES: u64 = 500;
+
+fn prepare(replica_id: u32, seq_num: u64) -> PBFTMessage {
+    PBFTMessage::Prepare(SignedMessage {
+        message: Prepare {
+            view: 0,
+            seq_num,
+            digest: [7u8; 32],
+            replica_id,
+        },
+        signature: vec![1u8; 64],
+        signer_id: replica_id,
+    })
+}
+
+fn seq_num(message: &PBFTMessage) -> u64 {
+    match message {
+        PBFTMessage::Prepare(prepare) => prepare.message.seq_num,
+        other => panic!("unexpected {:?}", other.kind()),
+    }
+}
+
+/// `n` replicas on ephemeral localhost ports, all connected to each other.
+async fn cluster(n: u32, outbound: OutboundConfig) -> Vec<Network> {
+    install_crypto_provider();
+    let pkcs8: Vec<Vec<u8>> = (0..n).map(|_| Crypto::generate_keypair()).collect();
+    let public = pkcs8
+        .iter()
+        .enumerate()
+        .map(|(id, key)| {
+            let keypair = ring::signature::Ed25519KeyPair::from_pkcs8(key).unwrap();
+            (
+                id as u32,
+                Crypto::new(keypair, id as u32, HashMap::new()).get_pub_key(),
+            )
+        })
+        .collect();
+    let pinned = PinnedKeys::new(public, HashMap::new());
+    let config = NetworkConfig {
+        outbound,
+        ..NetworkConfig::default()
+    };
+
+    let networks: Vec<Network> = (0..n)
+        .map(|id| {
+            let cert = NodeCert::from_pkcs8(replica_server_name(id), &pkcs8[id as usize]);
+            Network::new(
+                id,
+                "127.0.0.1:0".parse().unwrap(),
+                &cert,
+                pinned.clone(),
+                config.clone(),
+                n,
+            )
+        })
+        .collect();
+    for network in &networks {
+        network.spawn_acceptor();
+    }
+    for (id, network) in networks.iter().enumerate() {
+        for (peer, other) in networks.iter().enumerate() {
+            if peer != id {
+                network.add_peer(peer as u32, other.local_addr().unwrap());
+            }
+        }
+    }
+    for network in &networks {
+        tokio::time::timeout(
+            Duration::from_secs(5),
+            network.wait_for_peers(n as usize - 1),
+        )
+        .await
+        .expect("replicas never connected");
+    }
+    networks
+}
+
+async fn receive(network: &mut Network, count: u64) -> Vec<u64> {
+    let mut received = Vec::new();
+    while (received.len() as u64) < count {
+        let message = tokio::time::timeout(Duration::from_secs(5), network.recv())
+            .await
+            .expect("messages went missing")
+            .unwrap();
+        received.push(seq_num(&message));
+    }
+    received
+}
+
+/// Prepares `network` has written out, once the count reaches `expected`;
+/// the counter is bumped just after the write completes.
+async fn prepares_sent(network: &Network, expected: u64) -> u64 {
+    let metrics = network.metrics();
+    for _ in 0..50 {
+        if metrics.messages_sent.get(MessageKind::Prepare) >= expected {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    metrics.messages_sent.get(MessageKind::Prepare)
+}
+
+#[tokio::test]
+async fn messages_to_a_peer_arrive_in_order() {
+    for max_batch_bytes in [1, 64 * 1024] {
+        let mut networks = cluster(
+            2,
+            OutboundConfig {
+                max_batch_bytes,
+                ..OutboundConfig::default()
+            },
+        )
+        .await;
+
+        for seq_num in 1..=MESSAGES {
+            networks[0].send_to(1, &prepare(0, seq_num)).await;
+        }
+
+        let received = receive(&mut networks[1], MESSAGES).await;
+        assert_eq!(
+            received,
+            (1..=MESSAGES).collect::<Vec<_>>(),
+            "batches of up to {} bytes",
+            max_batch_bytes
+        );
+        assert_eq!(prepares_sent(&networks[0], MESSAGES).await, MESSAGES);
+    }
+}
+
+#[tokio::test]
+async fn broadcast_reaches_every_peer_in_order() {
+    let mut networks = cluster(3, OutboundConfig::default()).await;
+
+    for seq_num in 1..=MESSAGES {
+        networks[0].broadcast(&prepare(0, seq_num)).await;
+    }
+
+    for peer in &mut networks[1..] {
+        let received = receive(peer, MESSAGES).await;
+        assert_eq!(received, (1..=MESSAGES).collect::<Vec<_>>());
+    }
+    assert_eq!(
+        prepares_sent(&networks[0], 2 * MESSAGES).await,
+        2 * MESSAGES
+    );
+}
@@ -0,0 +1,70 @@
+use simple_pbft_demo::network::reconnect::{Backoff, BackoffConfig};
+use std::time::Duration;
+
+fn backoff(initial_ms: u64, max_ms: u64, multiplier: u32) -> Backoff {
+    Backoff::new(BackoffConfig {
+        initial_ms,
+        max_ms,
+        multiplier,
+    })
+}
+
+fn millis(delay: Duration) -> u64 {
+    delay.as_millis() as u64
+}
+
+#[test]
+fn delays_grow_geometrically_up_to_the_cap() {
+    let mut backoff = backoff(100, 1_000, 2);
+    for step in [100, 200, 400, 800, 1_000, 1_000, 1_000] {
+        let delay = millis(backoff.next_delay());
+        assert!(
+            (step / 2..=step).contains(&delay),
+            "{} ms outside the step of {} ms",
+            delay,
+            step
+        );
+    }
+}
+
+#[test]
+fn jitter_stays_in_the_upper_half_of_the_step() {
+    let delays: Vec<u64> = (0..200)
+        .map(|_| millis(backoff(1_000, 10_000, 2).next_delay()))
+        .collect();
+
+    assert!(delays.iter().all(|d| (500..=1_000).contains(d)));
+    let distinct: std::collections::BTreeSet<_> = delays.iter().collect();
+    assert!(
+        distinct.len() > 10,
+        "delays are not jittered: {:?}",
+        distinct
+    );
+}
+
+#[test]
+fn reset_after_success_starts_over() {
+    let mut backoff = backoff(100, 10_000, 3);
+    for _ in 0..5 {
+        backoff.next_delay();
+    }
+    assert!(millis(backoff.next_delay()) > 100);
+
+    backoff.reset();
+    assert!(millis(backoff.next_delay()) <= 100);
+}
+
+#[test]
+fn extreme_configs_neither_overflow_nor_stall() {
+    let mut huge = backoff(u64::MAX / 2, u64::MAX, u32::MAX);
+    for _ in 0..3 {
+        assert!(millis(huge.next_delay()) >= u64::MAX / 4);
+    }
+
+    // An initial delay above the cap is held to the cap.
+    let mut capped = backoff(5_000, 1_000, 2);
+    assert!(millis(capped.next_delay()) <= 1_000);
+
+    let mut zero = backoff(0, 0, 0);
+    assert!(millis(zero.next_delay()) <= 1);
+}
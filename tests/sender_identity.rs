@@ -0,0 +1,189 @@
+mod common;
+
+use common::{CLIENT_ID, install_crypto_provider};
+use quinn::Endpoint;
+use simple_pbft_demo::{
+    crypto::primitives::Crypto,
+    message::message_types::{Commit, PBFTMessage, Prepare, Request, SignedMessage},
+    metrics::registry::PeerStats,
+    network::{
+        cert::{NodeCert, PeerIdentity, PinnedKeys, make_client_config, replica_server_name},
+        framing::{Envelope, write_frame},
+        network_layer::{Network, NetworkConfig},
+    },
+};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+fn public_key(pkcs8: &[u8]) -> Vec<u8> {
+    let keypair = ring::signature::Ed25519KeyPair::from_pkcs8(pkcs8).unwrap();
+    Crypto::new(keypair, 0, HashMap::new()).get_pub_key()
+}
+
+/// Keys for replicas 0..3 and client `CLIENT_ID`, in that order.
+fn keys() -> (Vec<Vec<u8>>, PinnedKeys) {
+    let pkcs8: Vec<Vec<u8>> = (0..4).map(|_| Crypto::generate_keypair()).collect();
+    let pinned = PinnedKeys::new(
+        (0..3)
+            .map(|id| (id, public_key(&pkcs8[id as usize])))
+            .collect(),
+        HashMap::from([(CLIENT_ID, public_key(&pkcs8[3]))]),
+    );
+    (pkcs8, pinned)
+}
+
+fn network(id: u32, pkcs8: &[Vec<u8>], pinned: &PinnedKeys) -> Network {
+    let network = Network::new(
+        id,
+        "127.0.0.1:0".parse().unwrap(),
+        &NodeCert::from_pkcs8(replica_server_name(id), &pkcs8[id as usize]),
+        pinned.clone(),
+        NetworkConfig::default(),
+        3,
+    );
+    network.spawn_acceptor();
+    network
+}
+
+/// Signatures are checked by the replica, not the network, so any bytes do.
+fn signed<T>(message: T, signer_id: u32) -> SignedMessage<T> {
+    SignedMessage {
+        message,
+        signature: vec![0u8; 64],
+        signer_id,
+    }
+}
+
+fn prepare(replica_id: u32, signer_id: u32) -> PBFTMessage {
+    PBFTMessage::Prepare(signed(
+        Prepare {
+            view: 0,
+            seq_num: 1,
+            digest: [7u8; 32],
+            replica_id,
+        },
+        signer_id,
+    ))
+}
+
+fn request(client_id: u32, signer_id: u32) -> PBFTMessage {
+    PBFTMessage::Request(signed(
+        Request {
+            operation: b"op".to_vec(),
+            timestamp: 1,
+            client_id: client_id.into(),
+        },
+        signer_id,
+    ))
+}
+
+async fn next(network: &mut Network) -> PBFTMessage {
+    tokio::time::timeout(Duration::from_secs(5), network.recv())
+        .await
+        .expect("nothing was delivered")
+        .unwrap()
+}
+
+fn stats(network: &Network, identity: PeerIdentity) -> PeerStats {
+    network
+        .metrics()
+        .peer_messages()
+        .into_iter()
+        .find(|(i, _)| *i == identity)
+        .map_or_else(PeerStats::default, |(_, stats)| stats)
+}
+
+#[tokio::test]
+async fn replica_connection_cannot_speak_for_others() {
+    install_crypto_provider();
+    let (pkcs8, pinned) = keys();
+    let mut receiver = network(0, &pkcs8, &pinned);
+    let sender = network(1, &pkcs8, &pinned);
+    sender.add_peer(0, receiver.local_addr().unwrap());
+    tokio::time::timeout(Duration::from_secs(5), sender.wait_for_peers(1))
+        .await
+        .expect("replica 1 never connected");
+
+    // Everything goes over one ordered stream, so by the time the genuine
+    // message arrives the others have been looked at.
+    for forged in [
+        prepare(2, 2),
+        prepare(2, 1),
+        PBFTMessage::Commit(signed(
+            Commit {
+                view: 0,
+                seq_num: 1,
+                digest: [7u8; 32],
+                replica_id: 1,
+            },
+            2,
+        )),
+        request(CLIENT_ID, CLIENT_ID),
+    ] {
+        sender.send_to(0, &forged).await;
+    }
+    sender.send_to(0, &prepare(1, 1)).await;
+
+    match next(&mut receiver).await {
+        PBFTMessage::Prepare(p) => assert_eq!((p.message.replica_id, p.signer_id), (1, 1)),
+        other => panic!("forged message delivered: {:?}", other),
+    }
+    assert_eq!(
+        stats(&receiver, PeerIdentity::Replica(1)),
+        PeerStats {
+            accepted: 1,
+            rejected: 4
+        }
+    );
+    assert!(
+        receiver
+            .metrics()
+            .render()
+            .contains("pbft_peer_messages_total{role=\"replica\",id=\"1\",result=\"rejected\"} 4")
+    );
+}
+
+#[tokio::test]
+async fn client_connection_carries_only_its_own_requests() {
+    install_crypto_provider();
+    let (pkcs8, pinned) = keys();
+    let mut receiver = network(0, &pkcs8, &pinned);
+
+    let cert = NodeCert::from_pkcs8("client-100".to_string(), &pkcs8[3]);
+    let mut endpoint = Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+    endpoint.set_default_client_config(quinn::ClientConfig::new(Arc::new(
+        quinn::crypto::rustls::QuicClientConfig::try_from(make_client_config(
+            &cert,
+            pinned.clone(),
+        ))
+        .unwrap(),
+    )));
+    let connection = endpoint
+        .connect(receiver.local_addr().unwrap(), &replica_server_name(0))
+        .unwrap()
+        .await
+        .unwrap();
+
+    let mut stream = connection.open_uni().await.unwrap();
+    let envelope = Envelope::new(NetworkConfig::default().cluster_id);
+    for message in [
+        request(101, 101),
+        request(CLIENT_ID, 101),
+        prepare(1, 1),
+        request(CLIENT_ID, CLIENT_ID),
+    ] {
+        write_frame(&mut stream, envelope, &message).await.unwrap();
+    }
+    stream.finish().unwrap();
+
+    match next(&mut receiver).await {
+        PBFTMessage::Request(r) => assert_eq!(r.signer_id, CLIENT_ID),
+        other => panic!("forged message delivered: {:?}", other),
+    }
+    assert_eq!(
+        stats(&receiver, PeerIdentity::Client(CLIENT_ID)),
+        PeerStats {
+            accepted: 1,
+            rejected: 3
+        }
+    );
+}
@@ -0,0 +1,115 @@
+use simple_pbft_demo::{
+    message::message_types::{PBFTMessage, Request},
+    sim::simulator::{NetworkFaults, SEED_ENV, SimConfig, Simulator, seed_from_env},
+};
+
+/// Seeds to try, or only the one in `PBFT_SIM_SEED` when replaying.
+fn seeds(default: &[u64]) -> Vec<u64> {
+    match seed_from_env() {
+        Some(seed) => vec![seed],
+        None => default.to_vec(),
+    }
+}
+
+fn faulty(seed: u64) -> SimConfig {
+    SimConfig {
+        faults: NetworkFaults {
+            min_delay_ms: 1,
+            max_delay_ms: 50,
+            drop_probability: 0.05,
+            duplicate_probability: 0.1,
+        },
+        ..SimConfig::with_seed(seed)
+    }
+}
+
+#[test]
+fn same_seed_replays_the_same_run() {
+    for seed in seeds(&[1, 2, 3]) {
+        let first = Simulator::new(faulty(seed)).run();
+        let second = Simulator::new(faulty(seed)).run();
+        assert_eq!(first, second, "replay with {}={}", SEED_ENV, seed);
+        assert!(first.violations.is_empty(), "{:?}", first);
+    }
+}
+
+#[test]
+fn different_seeds_schedule_differently() {
+    let a = Simulator::new(faulty(1)).run();
+    let b = Simulator::new(faulty(2)).run();
+    assert_ne!(a.trace_digest, b.trace_digest);
+}
+
+#[test]
+fn reordering_network_executes_every_request() {
+    for seed in seeds(&[10, 11, 12, 13]) {
+        let config = SimConfig {
+            faults: NetworkFaults {
+                duplicate_probability: 0.2,
+                ..NetworkFaults::default()
+            },
+            ..SimConfig::with_seed(seed)
+        };
+        let requests = config.requests;
+        let report = Simulator::new(config).run();
+
+        assert!(report.completed, "replay with {}={}", SEED_ENV, seed);
+        assert!(report.violations.is_empty(), "{:?}", report);
+        for replica in &report.replicas {
+            assert!(replica.last_executed >= requests, "{:?}", report);
+        }
+    }
+}
+
+#[test]
+fn crashed_primary_is_replaced_by_a_view_change() {
+    for seed in seeds(&[20, 21]) {
+        let config = SimConfig {
+            crashed: vec![0],
+            ..SimConfig::with_seed(seed)
+        };
+        let report = Simulator::new(config).run();
+
+        assert!(report.completed, "replay with {}={}", SEED_ENV, seed);
+        assert!(report.violations.is_empty(), "{:?}", report);
+        for replica in report.replicas.iter().filter(|r| r.node_id != 0) {
+            assert!(replica.view >= 1, "{:?}", report);
+        }
+    }
+}
+
+#[test]
+fn prepared_slot_survives_two_view_changes() {
+    let config = SimConfig {
+        requests: 1,
+        ..SimConfig::with_seed(34)
+    };
+    let mut sim = Simulator::new(config);
+    // Only replica 3 collects the view-0 commits and executes the request;
+    // it then drops out before the view changes. The others prepared the
+    // slot, re-propose it in view 1, fail to prepare it again there and
+    // move on to view 2, which must still carry it.
+    sim.set_filter(|envelope| match &envelope.message {
+        PBFTMessage::Request(_) => true,
+        PBFTMessage::Commit(c) if c.message.view == 0 => envelope.to == 3,
+        PBFTMessage::PrePrepare(p) if p.message.view == 0 => true,
+        PBFTMessage::Prepare(p) if p.message.view == 0 => true,
+        _ if envelope.from == 3 || envelope.to == 3 => false,
+        PBFTMessage::Prepare(p) => p.message.view != 1,
+        _ => true,
+    });
+    while sim.step() {}
+
+    let report = sim.report();
+    assert!(report.violations.is_empty(), "{:?}", report);
+    assert!(report.completed, "{:?}", report);
+    let executed: Vec<[u8; 32]> = sim
+        .replicas()
+        .map(|r| r.ledger_entry(1).expect("slot 1 executed").request_digest)
+        .collect();
+    assert_ne!(executed[3], Request::null().digest());
+    assert!(executed.iter().all(|d| *d == executed[3]), "{:?}", report);
+    for replica in sim.replicas().filter(|r| r.node_id() != 3) {
+        assert!(replica.view() >= 2, "{:?}", report);
+    }
+}
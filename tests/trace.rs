@@ -0,0 +1,121 @@
+mod common;
+
+use common::{CLIENT_ID, crypto, generate_keys, membership, replicas, run_until_quiet, temp_path};
+use simple_pbft_demo::{
+    crypto::primitives::Crypto,
+    message::message_types::{PBFTMessage, Request},
+    network::transport::MemoryNetwork,
+    state::{clock::VirtualClock, replica::Replica},
+    trace::{
+        record::{TraceEvent, TraceRecorder, read_trace},
+        replay::replay,
+    },
+};
+use std::{path::Path, sync::Arc};
+
+const TRACED: u32 = 2;
+
+/// Runs a 4 replica cluster with `TRACED` recording to `path`, through a few
+/// requests and a view change forced by timeouts. Returns a fresh replica
+/// with the traced one's key and membership, and the traced one.
+async fn record(path: &Path) -> (Replica, Replica) {
+    let replica_keys = generate_keys(4);
+    let client = crypto(CLIENT_ID, &Crypto::generate_keypair());
+    let membership = membership(&replica_keys, &[(CLIENT_ID, &client)]);
+
+    let clock = VirtualClock::new();
+    let network = MemoryNetwork::new();
+    let mut replicas = replicas(&network, &membership, &replica_keys);
+    for (replica, _) in &mut replicas {
+        replica.set_clock(Arc::new(clock.clone()));
+    }
+    let recorder = TraceRecorder::create(path, TRACED, &clock).unwrap();
+    replicas[TRACED as usize].0.set_recorder(recorder);
+
+    for timestamp in 1..=3 {
+        clock.set_ms(timestamp * 10);
+        let request = Request {
+            operation: format!("PUT:k{}:{}", timestamp, timestamp).into_bytes(),
+            timestamp,
+            client_id: CLIENT_ID as u64,
+        };
+        // The last request only reaches the backups, so their timers expire.
+        let to: &[u32] = if timestamp < 3 { &[0] } else { &[1, 2, 3] };
+        for &id in to {
+            network.send(
+                id,
+                PBFTMessage::Request(client.create_signed_message(request.clone())),
+            );
+        }
+        run_until_quiet(&mut replicas).await;
+    }
+
+    clock.set_ms(5_000);
+    for (replica, transport) in replicas.iter_mut().skip(1) {
+        replica.tick(transport).await;
+    }
+    run_until_quiet(&mut replicas).await;
+
+    let fresh = Replica::new(
+        TRACED,
+        membership,
+        crypto(TRACED, &replica_keys[TRACED as usize]),
+    );
+    (fresh, replicas.swap_remove(TRACED as usize).0)
+}
+
+#[tokio::test]
+async fn replay_reproduces_the_recorded_replica() {
+    let path = temp_path("trace", "reproduce.bin");
+    let (mut fresh, traced) = record(&path).await;
+    assert_eq!(traced.view(), 1);
+    assert_eq!(traced.last_executed(), 3);
+
+    let records = read_trace(&path).unwrap();
+    assert!(matches!(
+        records[0].event,
+        TraceEvent::Start { node_id: TRACED }
+    ));
+    assert!(
+        records
+            .iter()
+            .any(|r| matches!(r.event, TraceEvent::Timeout))
+    );
+    assert!(
+        records
+            .iter()
+            .any(|r| matches!(r.event, TraceEvent::Sent { to: None, .. }))
+    );
+
+    let report = replay(&mut fresh, &records).await;
+    assert!(report.divergences.is_empty(), "{:?}", report.divergences);
+    assert_eq!(fresh.view(), traced.view());
+    assert_eq!(fresh.last_executed(), traced.last_executed());
+    for seq_num in 1..=3 {
+        assert_eq!(
+            fresh.ledger_entry(seq_num).unwrap().hash(),
+            traced.ledger_entry(seq_num).unwrap().hash()
+        );
+    }
+}
+
+#[tokio::test]
+async fn replay_reports_where_the_replica_diverged() {
+    let path = temp_path("trace", "diverge.bin");
+    let (mut fresh, _) = record(&path).await;
+    let mut records = read_trace(&path).unwrap();
+
+    // In place of the primary's first pre-prepare the replica sees a timer
+    // that has not expired yet, so it sends nothing where it sent a prepare.
+    let index = records
+        .iter()
+        .position(|r| matches!(&r.event, TraceEvent::Received(PBFTMessage::PrePrepare(_))))
+        .unwrap();
+    records[index].event = TraceEvent::Timeout;
+
+    let report = replay(&mut fresh, &records).await;
+    let first = &report.divergences[0];
+    assert_eq!(first.index, index);
+    assert!(!first.recorded.is_empty());
+    assert!(first.replayed.is_empty());
+}
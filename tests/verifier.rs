@@ -0,0 +1,197 @@
+mod common;
+
+use common::{CLIENT_ID, crypto, generate_keys, membership, replicas, run_until_quiet};
+use simple_pbft_demo::{
+    client::verifier::{Verifier, VerifyError},
+    crypto::{
+        merkle::{self, KeyProof},
+        primitives::Crypto,
+    },
+    message::message_types::{PBFTMessage, Reply, Request, SignedMessage},
+    network::transport::{MemoryNetwork, MemoryTransport},
+    state::replica::Replica,
+};
+
+struct Cluster {
+    network: MemoryNetwork,
+    replicas: Vec<(Replica, MemoryTransport)>,
+    client: Crypto,
+    client_inbox: MemoryTransport,
+    timestamp: u64,
+}
+
+impl Cluster {
+    fn new(n: u32) -> Self {
+        let replica_keys = generate_keys(n);
+        let client = crypto(CLIENT_ID, &Crypto::generate_keypair());
+        let membership = membership(&replica_keys, &[(CLIENT_ID, &client)]);
+
+        let network = MemoryNetwork::new();
+        let replicas = replicas(&network, &membership, &replica_keys);
+        let client_inbox = network.join(CLIENT_ID);
+
+        Cluster {
+            network,
+            replicas,
+            client,
+            client_inbox,
+            timestamp: 0,
+        }
+    }
+
+    fn verifier(&self) -> Verifier {
+        Verifier::from_membership(self.replicas[0].0.membership())
+    }
+
+    /// Orders `operation` and returns every reply the client got for it.
+    async fn invoke(&mut self, operation: &str) -> Vec<SignedMessage<Reply>> {
+        self.timestamp += 1;
+        let request = Request {
+            operation: operation.as_bytes().to_vec(),
+            timestamp: self.timestamp,
+            client_id: CLIENT_ID as u64,
+        };
+        self.network.send(
+            0,
+            PBFTMessage::Request(self.client.create_signed_message(request)),
+        );
+        run_until_quiet(&mut self.replicas).await;
+
+        let mut replies = Vec::new();
+        while let Some(msg) = self.client_inbox.try_recv() {
+            if let PBFTMessage::Reply(reply) = msg
+                && reply.message.timestamp == self.timestamp
+            {
+                replies.push(reply);
+            }
+        }
+        replies
+    }
+}
+
+#[tokio::test]
+async fn read_is_verified_from_replies_and_one_proof() {
+    let mut cluster = Cluster::new(4);
+    cluster.invoke("PUT:a:1").await;
+    cluster.invoke("PUT:c:3").await;
+    let replies = cluster.invoke("PUT:b:2").await;
+    let verifier = cluster.verifier();
+
+    let agreed = verifier.verify_replies(&replies).unwrap();
+    assert_eq!(agreed.seq_num, 3);
+    assert_eq!(agreed.result, b"OK".to_vec());
+
+    // A single replica is enough to serve the proofs.
+    let replica = &cluster.replicas[2].0;
+    assert_eq!(
+        verifier.verify_read(&replies, &replica.prove("b")),
+        Ok(Some("2".to_string()))
+    );
+    assert_eq!(
+        verifier.verify_read(&replies, &replica.prove("bb")),
+        Ok(None)
+    );
+
+    let mut lie = replica.prove("b");
+    lie.value = Some("9".to_string());
+    lie.leaves[0].value = "9".to_string();
+    assert_eq!(
+        verifier.verify_read(&replies, &lie),
+        Err(VerifyError::InvalidProof)
+    );
+
+    let certificate = replica.certificate(3).unwrap();
+    assert_eq!(verifier.verify_certificate(&certificate), Ok(()));
+    let mut forged = certificate;
+    forged.request.operation = b"PUT:b:9".to_vec();
+    assert_eq!(
+        verifier.verify_certificate(&forged),
+        Err(VerifyError::InvalidCertificate)
+    );
+}
+
+#[tokio::test]
+async fn proof_must_be_for_the_agreed_state() {
+    let mut cluster = Cluster::new(4);
+    let replies = cluster.invoke("PUT:a:1").await;
+    cluster.invoke("PUT:a:2").await;
+    let verifier = cluster.verifier();
+
+    // A replica that moved on proves the key under its newer state.
+    let newer = cluster.replicas[1].0.prove("a");
+    assert_eq!(newer.value, Some("2".to_string()));
+    assert_eq!(
+        verifier.verify_read(&replies, &newer),
+        Err(VerifyError::ProofForOtherState)
+    );
+
+    let mut relabelled = newer;
+    relabelled.root = verifier.verify_replies(&replies).unwrap().state_digest;
+    assert_eq!(
+        verifier.verify_read(&replies, &relabelled),
+        Err(VerifyError::InvalidProof)
+    );
+}
+
+#[tokio::test]
+async fn f_replies_or_forged_ones_are_not_enough() {
+    let mut cluster = Cluster::new(4);
+    let replies = cluster.invoke("PUT:a:1").await;
+    let verifier = cluster.verifier();
+    assert_eq!(replies.len(), 4);
+
+    let needed = Err(VerifyError::NotEnoughReplies {
+        matching: 1,
+        needed: 2,
+    });
+    assert_eq!(verifier.verify_replies(&replies[..1]), needed);
+
+    // The same replica twice counts once.
+    let repeated = vec![replies[0].clone(), replies[0].clone()];
+    assert_eq!(verifier.verify_replies(&repeated), needed);
+
+    let mut forged = replies[1].clone();
+    forged.message.state_digest = [7; 32];
+    assert_eq!(
+        verifier.verify_replies(&[replies[0].clone(), forged]),
+        needed
+    );
+
+    assert!(verifier.verify_replies(&replies[..2]).is_ok());
+}
+
+#[test]
+fn proofs_cover_every_key_and_gap() {
+    for size in 0..10 {
+        let entries: Vec<(String, String)> = (0..size)
+            .map(|i| (format!("k{:02}", i * 2), format!("v{}", i)))
+            .collect();
+        let leaves: Vec<[u8; 32]> = entries
+            .iter()
+            .map(|(k, v)| merkle::leaf_hash(k, v))
+            .collect();
+        let root = merkle::root(&leaves);
+        let prove =
+            |key: &str| KeyProof::build(entries.iter().map(|(k, v)| (k.as_str(), v.as_str())), key);
+
+        for i in 0..size {
+            let proof = prove(&format!("k{:02}", i * 2));
+            assert_eq!(proof.value, Some(format!("v{}", i)));
+            assert!(proof.verify(&root), "size {} key {}", size, i);
+
+            let absent = prove(&format!("k{:02}", i * 2 + 1));
+            assert_eq!(absent.value, None);
+            assert!(absent.verify(&root), "size {} gap {}", size, i);
+        }
+        let before_all = prove("a");
+        assert!(before_all.verify(&root), "size {}", size);
+
+        // Dropping a neighbour would let a present key pass as absent.
+        if size >= 3 {
+            let mut gap = prove("k03");
+            gap.leaves.remove(0);
+            assert!(!gap.verify(&root));
+        }
+        assert!(!prove("k00").verify(&[1; 32]));
+    }
+}